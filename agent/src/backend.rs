@@ -0,0 +1,699 @@
+use crate::{
+    config::AgentConfig,
+    control::{ContainerSummary, ControlCommand, ControlRequest, ControlResponse},
+    mount,
+    package::{self, PulledPackage},
+    reconcile::{self, ReconcilerHandle},
+    supervisor::{RunRecord, RunState, Supervisor},
+};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot, watch},
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+
+pub mod proto {
+    tonic::include_proto!("containers.v1");
+}
+
+use proto::{
+    agent_message, agent_service_client::AgentServiceClient, backend_message, command_request,
+    AgentHeartbeat, AgentMessage, CommandAck, CommandRequest, CommandResult, ContainerSync,
+    DesiredContainer, HostedContainer, MountStatus, RegisterAgentRequest, RunStatus, SyncReport,
+};
+
+/// Espera entre reconexiones; se duplica en cada fallo hasta el máximo.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Órdenes recientes que se recuerdan para reconocer los reenvíos del backend.
+const REMEMBERED_COMMANDS: usize = 256;
+
+/// Vínculo del agent con el plano de control: se registra con su inventario,
+/// mantiene un stream de heartbeat con las ejecuciones y ejecuta las órdenes que
+/// llegan por él, reconectando si cae.
+pub struct BackendLink {
+    endpoint: String,
+    agent_id: String,
+    hostname: String,
+    capabilities: Vec<String>,
+    inventory: watch::Receiver<Vec<ContainerSummary>>,
+    supervisor: Supervisor,
+    /// Canal hacia el bucle del agent, el mismo que usa el API local.
+    control: mpsc::Sender<ControlCommand>,
+    packages_dir: PathBuf,
+    /// Recibe el estado deseado que manda el backend y publica su progreso.
+    reconciler: ReconcilerHandle,
+    /// Sobrevive a las reconexiones: una orden reenviada no se ejecuta dos veces.
+    handled: Arc<Mutex<HandledCommands>>,
+    state: watch::Receiver<LinkState>,
+}
+
+/// Fase del vínculo durante el apagado del agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkState {
+    Open,
+    /// Sigue enviando heartbeats, pero ya no acepta órdenes ni estado deseado.
+    Closing,
+    /// Envía un último heartbeat con el estado final y cierra el stream.
+    Finishing,
+}
+
+/// Tarea del vínculo con el backend, arrancada con [`BackendLink::spawn`].
+pub struct LinkHandle {
+    state: watch::Sender<LinkState>,
+    task: JoinHandle<()>,
+}
+
+impl LinkHandle {
+    /// Deja de aceptar órdenes y estado deseado; las que lleguen se quedan sin
+    /// confirmar y el backend las vuelve a encolar al cerrarse el stream.
+    pub fn close(&self) {
+        self.state.send_replace(LinkState::Closing);
+    }
+
+    /// Envía al backend el estado final (ejecuciones terminadas, contenedores
+    /// sin preparar) y cierra el stream. Pasado `timeout` se corta igualmente.
+    pub async fn finish(mut self, timeout: Duration) {
+        self.state.send_replace(LinkState::Finishing);
+        if tokio::time::timeout(timeout, &mut self.task).await.is_err() {
+            warn!(timeout = ?timeout, "El backend no recibió el estado final a tiempo");
+            self.task.abort();
+        }
+    }
+}
+
+impl Drop for LinkHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Órdenes ya recibidas por `command_id`, con su resultado cuando terminan.
+#[derive(Default)]
+struct HandledCommands {
+    order: VecDeque<String>,
+    results: HashMap<String, Option<CommandResult>>,
+}
+
+impl HandledCommands {
+    /// Registra la orden si es nueva y devuelve `None`; si ya se recibió,
+    /// devuelve su resultado o `Some(None)` mientras sigue en curso.
+    fn begin(&mut self, command_id: &str) -> Option<Option<CommandResult>> {
+        if let Some(result) = self.results.get(command_id) {
+            return Some(result.clone());
+        }
+        if self.order.len() >= REMEMBERED_COMMANDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.results.remove(&oldest);
+            }
+        }
+        self.order.push_back(command_id.to_string());
+        self.results.insert(command_id.to_string(), None);
+        None
+    }
+
+    fn finish(&mut self, result: &CommandResult) {
+        if let Some(slot) = self.results.get_mut(&result.command_id) {
+            *slot = Some(result.clone());
+        }
+    }
+}
+
+impl BackendLink {
+    pub fn new(
+        endpoint: String,
+        config: &AgentConfig,
+        inventory: watch::Receiver<Vec<ContainerSummary>>,
+        supervisor: Supervisor,
+        control: mpsc::Sender<ControlCommand>,
+        reconciler: ReconcilerHandle,
+    ) -> Self {
+        // `spawn` lo sustituye por el receptor de su `LinkHandle`.
+        let (_, state) = watch::channel(LinkState::Open);
+        let hostname = hostname();
+        Self {
+            endpoint,
+            agent_id: config
+                .backend
+                .agent_id
+                .clone()
+                .unwrap_or_else(|| hostname.clone()),
+            hostname,
+            capabilities: capabilities(config),
+            inventory,
+            supervisor,
+            control,
+            packages_dir: config.packages_dir.clone(),
+            reconciler,
+            handled: Arc::default(),
+            state,
+        }
+    }
+
+    /// Arranca la conexión en su propia tarea.
+    pub fn spawn(mut self) -> LinkHandle {
+        let (state, receiver) = watch::channel(LinkState::Open);
+        self.state = receiver;
+        LinkHandle {
+            state,
+            task: tokio::spawn(self.run()),
+        }
+    }
+
+    /// Mantiene la conexión hasta que se aborte la tarea o se cierre con
+    /// [`LinkHandle::finish`].
+    async fn run(mut self) {
+        let mut backoff = RECONNECT_BACKOFF;
+        loop {
+            let result = self.session().await;
+            if *self.state.borrow() == LinkState::Finishing {
+                return;
+            }
+            match result {
+                Ok(()) => {
+                    info!(
+                        endpoint = self.endpoint.as_str(),
+                        "El backend cerró el heartbeat"
+                    );
+                    backoff = RECONNECT_BACKOFF;
+                }
+                Err(err) => warn!(
+                    endpoint = self.endpoint.as_str(),
+                    error = format!("{err:#}"),
+                    retry_in = ?backoff,
+                    "Sin conexión con el backend"
+                ),
+            }
+            // Sin conexión no hay a quién enviar el estado final.
+            let finishing = self.state.wait_for(|state| *state == LinkState::Finishing);
+            if tokio::time::timeout(backoff, finishing).await.is_ok() {
+                return;
+            }
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    async fn session(&mut self) -> Result<()> {
+        let mut client = AgentServiceClient::connect(self.endpoint.clone())
+            .await
+            .context("No se pudo conectar con el backend")?;
+        // Suscribirse antes de registrar para no perder cambios intermedios.
+        let mut runs = self.supervisor.subscribe();
+        let containers = hosted(&self.inventory.borrow_and_update());
+        let registered = client
+            .register(RegisterAgentRequest {
+                agent_id: self.agent_id.clone(),
+                hostname: self.hostname.clone(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                capabilities: self.capabilities.clone(),
+                containers,
+            })
+            .await
+            .context("El backend rechazó el registro")?
+            .into_inner();
+        let interval = Duration::from_secs(registered.heartbeat_interval_secs.max(1) as u64);
+        info!(
+            agent_id = self.agent_id.as_str(),
+            interval = ?interval,
+            "Agent registrado en el backend"
+        );
+
+        let (outbound, rx) = mpsc::channel(16);
+        let mut inbound = client
+            .heartbeat(ReceiverStream::new(rx))
+            .await
+            .context("No se pudo abrir el stream de heartbeat")?
+            .into_inner();
+
+        // El backend conserva el último informe; se repite al reconectar.
+        let mut reports = self.reconciler.reports();
+        let report = sync_report(&reports.borrow_and_update());
+        if outbound.send(report).await.is_err() {
+            return Ok(());
+        }
+
+        let mut ticker = tokio::time::interval(interval);
+        let mut changed = BTreeSet::new();
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                result = self.state.changed() => {
+                    if result.is_err() {
+                        return Ok(());
+                    }
+                    if *self.state.borrow() != LinkState::Finishing {
+                        continue;
+                    }
+                    // Los cambios de estado ya emitidos entran en el último heartbeat.
+                    while let Ok(run) = runs.try_recv() {
+                        changed.insert(run.run_id);
+                    }
+                    let heartbeat = self.heartbeat(&changed);
+                    if outbound.send(heartbeat).await.is_ok() {
+                        // El backend termina su lado del stream cuando ha
+                        // procesado todo lo enviado.
+                        drop(outbound);
+                        while let Ok(Some(_)) = inbound.message().await {}
+                    }
+                    info!("Estado final enviado al backend");
+                    return Ok(());
+                }
+                result = reports.changed() => {
+                    if result.is_err() {
+                        return Ok(());
+                    }
+                    let report = sync_report(&reports.borrow_and_update());
+                    if outbound.send(report).await.is_err() {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                result = self.inventory.changed() => {
+                    if result.is_err() {
+                        return Ok(());
+                    }
+                }
+                event = runs.recv() => match event {
+                    Ok(run) => {
+                        changed.insert(run.run_id);
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Se perdieron cambios de estado de ejecuciones");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                },
+                message = inbound.message() => match message.context("Heartbeat interrumpido")? {
+                    Some(message) => {
+                        if *self.state.borrow() != LinkState::Open {
+                            continue;
+                        }
+                        match message.payload {
+                            Some(backend_message::Payload::Command(command)) => {
+                                self.accept(command, &outbound).await;
+                            }
+                            Some(backend_message::Payload::Desired(desired)) => {
+                                self.reconciler.set_desired(
+                                    desired.containers.into_iter().map(Into::into).collect(),
+                                );
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+                    None => return Ok(()),
+                },
+            }
+
+            let message = self.heartbeat(&changed);
+            changed.clear();
+            if outbound.send(message).await.is_err() {
+                return Ok(());
+            }
+            ticker.reset();
+        }
+    }
+
+    fn heartbeat(&mut self, changed: &BTreeSet<String>) -> AgentMessage {
+        let heartbeat = AgentHeartbeat {
+            agent_id: self.agent_id.clone(),
+            containers: hosted(&self.inventory.borrow_and_update()),
+            runs: heartbeat_runs(&self.supervisor.list(), changed),
+        };
+        AgentMessage {
+            payload: Some(agent_message::Payload::Heartbeat(heartbeat)),
+        }
+    }
+
+    /// Confirma la orden y la ejecuta aparte; el resultado vuelve por el mismo
+    /// stream cuando termina, sin bloquear los heartbeats. Una orden repetida
+    /// solo se confirma, y se reenvía su resultado si ya lo tiene.
+    async fn accept(&self, command: CommandRequest, outbound: &mpsc::Sender<AgentMessage>) {
+        let command_id = command.command_id.clone();
+        let previous = self.handled.lock().unwrap().begin(&command_id);
+        info!(
+            command_id = command_id.as_str(),
+            repeated = previous.is_some(),
+            "Orden recibida del backend"
+        );
+        let ack = AgentMessage {
+            payload: Some(agent_message::Payload::Ack(CommandAck {
+                command_id: command_id.clone(),
+            })),
+        };
+        if outbound.send(ack).await.is_err() {
+            return;
+        }
+        match previous {
+            Some(Some(result)) => {
+                let message = AgentMessage {
+                    payload: Some(agent_message::Payload::Result(result)),
+                };
+                let _ = outbound.send(message).await;
+                return;
+            }
+            Some(None) => return,
+            None => {}
+        }
+
+        let control = self.control.clone();
+        let packages_dir = self.packages_dir.clone();
+        let handled = self.handled.clone();
+        let outbound = outbound.clone();
+        tokio::spawn(async move {
+            let result = match execute(command, &control, &packages_dir).await {
+                Ok(response) => command_result(&command_id, &response),
+                Err(err) => CommandResult {
+                    command_id: command_id.clone(),
+                    success: false,
+                    message: format!("{err:#}"),
+                    output: String::new(),
+                },
+            };
+            if !result.success {
+                warn!(
+                    command_id = command_id.as_str(),
+                    error = result.message.as_str(),
+                    "La orden del backend falló"
+                );
+            }
+            handled.lock().unwrap().finish(&result);
+            let message = AgentMessage {
+                payload: Some(agent_message::Payload::Result(result)),
+            };
+            // Si el stream cayó, el backend la reenviará y se responde entonces.
+            let _ = outbound.send(message).await;
+        });
+    }
+}
+
+/// Respuesta de una orden: la del API local o, para las descargas, el paquete.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum CommandOutput {
+    Control(ControlResponse),
+    Package(PulledPackage),
+}
+
+async fn execute(
+    command: CommandRequest,
+    control: &mpsc::Sender<ControlCommand>,
+    packages_dir: &Path,
+) -> Result<CommandOutput> {
+    let request = match command.action.context("Orden sin acción")? {
+        command_request::Action::Pull(pull) => {
+            return package::pull(packages_dir, &pull.container_id, &pull.url, &pull.sha256)
+                .await
+                .map(CommandOutput::Package)
+        }
+        action => control_request(action)?,
+    };
+    let (reply, response) = oneshot::channel();
+    control
+        .send(ControlCommand { request, reply })
+        .await
+        .map_err(|_| anyhow!("El agent se está apagando"))?;
+    let response = response
+        .await
+        .map_err(|_| anyhow!("El agent no respondió a la orden"))?;
+    Ok(CommandOutput::Control(response))
+}
+
+/// Las órdenes que no descargan nada son peticiones del API local.
+fn control_request(action: command_request::Action) -> Result<ControlRequest> {
+    let request = match action {
+        command_request::Action::Launch(launch) => ControlRequest::Launch {
+            container_id: launch.container_id,
+            profile: launch.profile,
+            executable: None,
+            args: launch.args,
+        },
+        command_request::Action::Stop(stop) => ControlRequest::Stop {
+            run_id: stop.run_id,
+            timeout_ms: stop.timeout_ms,
+        },
+        command_request::Action::Refresh(_) => ControlRequest::Refresh,
+        command_request::Action::Diagnostics(_) => ControlRequest::Diagnostics,
+        command_request::Action::Pull(_) => {
+            return Err(anyhow!("Las descargas no pasan por el API local"))
+        }
+    };
+    Ok(request)
+}
+
+fn command_result(command_id: &str, output: &CommandOutput) -> CommandResult {
+    let (success, message) = match output {
+        CommandOutput::Control(ControlResponse::Error { message }) => (false, message.clone()),
+        CommandOutput::Control(ControlResponse::Launched { run_id }) => {
+            (true, format!("Ejecución {run_id} iniciada"))
+        }
+        CommandOutput::Control(_) => (true, String::new()),
+        CommandOutput::Package(package) => (
+            true,
+            format!("Paquete descargado en {}", package.path.display()),
+        ),
+    };
+    CommandResult {
+        command_id: command_id.to_string(),
+        success,
+        message,
+        output: serde_json::to_string(output).unwrap_or_default(),
+    }
+}
+
+/// Ejecuciones activas más las que cambiaron desde el heartbeat anterior, para
+/// que el backend vea también cómo terminaron.
+fn heartbeat_runs(runs: &[RunRecord], changed: &BTreeSet<String>) -> Vec<RunStatus> {
+    runs.iter()
+        .filter(|run| !run.state.is_terminal() || changed.contains(&run.run_id))
+        .map(RunStatus::from)
+        .collect()
+}
+
+fn sync_report(containers: &[reconcile::ContainerSync]) -> AgentMessage {
+    AgentMessage {
+        payload: Some(agent_message::Payload::Sync(SyncReport {
+            containers: containers.iter().map(ContainerSync::from).collect(),
+        })),
+    }
+}
+
+fn hosted(containers: &[ContainerSummary]) -> Vec<HostedContainer> {
+    containers
+        .iter()
+        .map(|container| HostedContainer {
+            id: container.id.clone(),
+            name: container.name.clone(),
+            version: container.version.clone().unwrap_or_default(),
+            prepared: container.prepared,
+            mount: container.mount.as_ref().map(MountStatus::from),
+            error: container.error.clone().unwrap_or_default(),
+        })
+        .collect()
+}
+
+impl From<&mount::MountStatus> for MountStatus {
+    fn from(status: &mount::MountStatus) -> Self {
+        MountStatus {
+            state: status.state.as_str().to_string(),
+            provider: status.provider.clone().unwrap_or_default(),
+            mount_point: status
+                .mount_point
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            attempts: status.attempts,
+            error: status.error.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<DesiredContainer> for reconcile::DesiredContainer {
+    fn from(desired: DesiredContainer) -> Self {
+        Self {
+            container_id: desired.container_id,
+            version: Some(desired.version).filter(|version| !version.is_empty()),
+            package_url: desired.package_url,
+            sha256: desired.sha256,
+        }
+    }
+}
+
+impl From<&reconcile::ContainerSync> for ContainerSync {
+    fn from(sync: &reconcile::ContainerSync) -> Self {
+        ContainerSync {
+            container_id: sync.container_id.clone(),
+            status: sync.status.as_str().to_string(),
+            version: sync.version.clone().unwrap_or_default(),
+            message: sync.message.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&RunRecord> for RunStatus {
+    fn from(run: &RunRecord) -> Self {
+        let state = match run.state {
+            RunState::Running => "running",
+            RunState::Restarting => "restarting",
+            RunState::Stopping => "stopping",
+            RunState::Exited => "exited",
+            RunState::Failed => "failed",
+            RunState::Stopped => "stopped",
+        };
+        RunStatus {
+            run_id: run.run_id.clone(),
+            container_id: run.container_id.clone(),
+            state: state.to_string(),
+            pid: run.pid,
+            started_at_ms: run.started_at_ms,
+            ended_at_ms: run.ended_at_ms,
+            exit_code: run.exit_code,
+            signal: run.signal,
+            restarts: run.restarts,
+        }
+    }
+}
+
+fn capabilities(config: &AgentConfig) -> Vec<String> {
+    let mut capabilities = vec!["launch", "supervise", "logs", "pull", "reconcile"];
+    if config.mount.enabled {
+        capabilities.push("mount");
+    }
+    if cfg!(feature = "native-hooks") {
+        capabilities.push("native-hooks");
+    }
+    capabilities.into_iter().map(String::from).collect()
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(id: &str, state: RunState) -> RunRecord {
+        RunRecord {
+            run_id: id.into(),
+            container_id: "demo".into(),
+            executable: "bin/app".into(),
+            args: vec![],
+            state,
+            pid: Some(7),
+            started_at_ms: 1,
+            ended_at_ms: state.is_terminal().then_some(2),
+            exit_code: state.is_terminal().then_some(0),
+            signal: None,
+            restarts: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn heartbeats_carry_active_and_recently_finished_runs() {
+        let runs = [
+            run("a", RunState::Running),
+            run("b", RunState::Exited),
+            run("c", RunState::Stopped),
+        ];
+        let changed = BTreeSet::from(["c".to_string()]);
+
+        let statuses = heartbeat_runs(&runs, &changed);
+        let ids: Vec<_> = statuses.iter().map(|run| run.run_id.as_str()).collect();
+        assert_eq!(ids, ["a", "c"]);
+        assert_eq!(statuses[0].state, "running");
+        assert_eq!(statuses[1].state, "stopped");
+        assert_eq!(statuses[1].ended_at_ms, Some(2));
+    }
+
+    fn command(action: command_request::Action) -> CommandRequest {
+        CommandRequest {
+            command_id: "cmd-1".into(),
+            action: Some(action),
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_go_through_the_control_channel() {
+        let (control, mut requests) = mpsc::channel(4);
+        tokio::spawn(async move {
+            while let Some(ControlCommand { request, reply }) = requests.recv().await {
+                let response = match request {
+                    ControlRequest::Launch { profile, .. } => ControlResponse::Launched {
+                        run_id: format!("run-{}", profile.unwrap()),
+                    },
+                    other => ControlResponse::error(format!("no soportado: {other:?}")),
+                };
+                let _ = reply.send(response);
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+
+        let launch = command(command_request::Action::Launch(proto::LaunchProfile {
+            container_id: "demo".into(),
+            profile: Some("safe".into()),
+            args: vec![],
+        }));
+        let output = execute(launch, &control, dir.path()).await.unwrap();
+        let result = command_result("cmd-1", &output);
+        assert!(result.success);
+        assert_eq!(result.message, "Ejecución run-safe iniciada");
+        assert_eq!(result.output, r#"{"type":"launched","run_id":"run-safe"}"#);
+
+        let refresh = command(command_request::Action::Refresh(proto::RefreshRegistry {}));
+        let result = command_result(
+            "cmd-2",
+            &execute(refresh, &control, dir.path()).await.unwrap(),
+        );
+        assert!(!result.success);
+        assert_eq!(result.message, "no soportado: Refresh");
+    }
+
+    #[test]
+    fn repeated_commands_return_the_stored_result() {
+        let mut handled = HandledCommands::default();
+        assert!(handled.begin("cmd-1").is_none());
+        assert_eq!(handled.begin("cmd-1"), Some(None));
+
+        let result = CommandResult {
+            command_id: "cmd-1".into(),
+            success: true,
+            message: "Ejecución run-1 iniciada".into(),
+            output: String::new(),
+        };
+        handled.finish(&result);
+        assert_eq!(handled.begin("cmd-1"), Some(Some(result)));
+
+        for i in 0..REMEMBERED_COMMANDS {
+            handled.begin(&format!("other-{i}"));
+        }
+        assert!(handled.begin("cmd-1").is_none());
+    }
+
+    #[test]
+    fn pulls_are_not_control_requests() {
+        let pull = command_request::Action::Pull(proto::PullPackage {
+            container_id: "demo".into(),
+            url: "https://example.com/demo.ctnr".into(),
+            sha256: "00".repeat(32),
+        });
+        assert!(control_request(pull).is_err());
+    }
+}
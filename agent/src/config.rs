@@ -0,0 +1,379 @@
+use crate::{
+    control::{self, ControlAccess},
+    logs::LogConfig,
+    mount::MountProviderKind,
+    supervisor::DEFAULT_STOP_TIMEOUT,
+};
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::{
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+const DEFAULT_LOG: &str = "agent=info,tracing=info";
+
+/// Carpeta de datos del agent, de la que cuelgan las rutas por defecto. Es
+/// absoluta para no depender del directorio de trabajo (`System32` en un
+/// servicio de Windows): `AGENT_DATA_DIR`, `%ProgramData%\ctnr` en Windows y
+/// `$XDG_DATA_HOME/ctnr` (o `~/.local/share/ctnr`, o `/var/lib/ctnr`) en el resto.
+pub fn default_data_dir() -> PathBuf {
+    data_dir_from(|key| env::var_os(key))
+}
+
+fn data_dir_from(var: impl Fn(&str) -> Option<OsString>) -> PathBuf {
+    let absolute = |value: OsString| Some(PathBuf::from(value)).filter(|path| path.is_absolute());
+    if let Some(dir) = var("AGENT_DATA_DIR").and_then(absolute) {
+        return dir;
+    }
+    if cfg!(windows) {
+        return var("ProgramData")
+            .and_then(absolute)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join("ctnr");
+    }
+    if let Some(dir) = var("XDG_DATA_HOME").and_then(absolute) {
+        return dir.join("ctnr");
+    }
+    match var("HOME").and_then(absolute) {
+        Some(home) => home.join(".local/share/ctnr"),
+        None => PathBuf::from("/var/lib/ctnr"),
+    }
+}
+
+/// Argumentos de línea de comandos; tienen prioridad sobre el archivo y las variables.
+#[derive(Debug, Default, Parser)]
+#[command(name = "agent", version, about = "Agent host de contenedores Win32")]
+pub struct AgentArgs {
+    /// Archivo de configuración TOML (también `AGENT_CONFIG`)
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Carpeta de contenedores; puede repetirse y reemplaza las del archivo
+    #[arg(long = "containers")]
+    pub container_roots: Vec<PathBuf>,
+    /// Almacén local de runtimes base
+    #[arg(long)]
+    pub runtimes: Option<PathBuf>,
+    /// Filtro de logs (`tracing` env-filter)
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Endpoint gRPC del backend
+    #[arg(long)]
+    pub backend: Option<String>,
+    /// Socket Unix / named pipe del API local
+    #[arg(long)]
+    pub control: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    pub container_roots: Vec<PathBuf>,
+    pub runtimes_dir: PathBuf,
+    /// Paquetes de contenedores descargados por orden del backend.
+    pub packages_dir: PathBuf,
+    /// Planes de hooks publicados para los procesos de los contenedores. Queda
+    /// fuera de las carpetas de contenedores para que la aplicación no pueda
+    /// modificar el plan que recibe.
+    pub plans_dir: PathBuf,
+    pub log_level: String,
+    pub logs: LogConfig,
+    pub mount: MountConfig,
+    pub backend: BackendConfig,
+    pub control: ControlConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MountConfig {
+    /// Permite desactivar los montajes (p. ej. en pruebas).
+    pub enabled: bool,
+    /// Primera letra que se prueba al repartir letras de unidad. Sin ella (ni
+    /// letra en el manifiesto) cada contenedor se monta en `<root>/<id>`.
+    pub preferred_drive: Option<char>,
+    /// Carpeta bajo la que se crean los puntos de montaje por contenedor.
+    pub root: PathBuf,
+    /// Archivo donde se guardan los puntos de montaje asignados.
+    pub assignments: PathBuf,
+    /// Proveedor por defecto; el manifiesto del contenedor puede pedir otro.
+    pub provider: MountProviderKind,
+    /// Si un contenedor cuyo montaje falla se prepara igualmente sin volumen.
+    pub optional: bool,
+    /// Espera máxima a que un volumen recién montado sea accesible.
+    pub ready_timeout_ms: u64,
+    /// Cada cuánto se comprueba que los volúmenes siguen respondiendo.
+    pub health_interval_ms: u64,
+    /// Espera inicial antes de volver a montar un volumen caído; se duplica en
+    /// cada intento fallido (máx. 60 s).
+    pub remount_backoff_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackendConfig {
+    pub endpoint: Option<String>,
+    /// Identificador con el que se registra el agent; por defecto, el hostname.
+    pub agent_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    /// Socket Unix (o named pipe en Windows) donde escucha el API local.
+    pub endpoint: PathBuf,
+    /// Usuarios que pueden conectarse: `owner`, `group` o `everyone`.
+    pub access: ControlAccess,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Espera por proceso entre el cierre ordenado y el `kill` al apagar el agent.
+    pub timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout_ms: DEFAULT_STOP_TIMEOUT.as_millis() as u64,
+        }
+    }
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: control::default_endpoint(),
+            access: ControlAccess::default(),
+        }
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        let data = default_data_dir();
+        Self {
+            container_roots: vec![data.join("containers")],
+            runtimes_dir: data.join("runtimes"),
+            packages_dir: data.join("packages"),
+            plans_dir: data.join("hook-plans"),
+            log_level: DEFAULT_LOG.to_string(),
+            logs: LogConfig::default(),
+            mount: MountConfig::default(),
+            backend: BackendConfig::default(),
+            control: ControlConfig::default(),
+            shutdown: ShutdownConfig::default(),
+        }
+    }
+}
+
+impl Default for MountConfig {
+    fn default() -> Self {
+        let data = default_data_dir();
+        Self {
+            enabled: true,
+            preferred_drive: None,
+            root: data.join("mounts"),
+            assignments: data.join("mounts.json"),
+            provider: MountProviderKind::Auto,
+            optional: false,
+            ready_timeout_ms: 10_000,
+            health_interval_ms: 5_000,
+            remount_backoff_ms: 1_000,
+        }
+    }
+}
+
+impl AgentConfig {
+    /// Carga la configuración combinando, de menor a mayor prioridad: valores por
+    /// defecto, archivo TOML, variables de entorno y argumentos.
+    pub fn load(args: &AgentArgs) -> Result<Self> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| env::var_os("AGENT_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|key| env::var_os(key));
+        config.apply_args(args);
+        Ok(config)
+    }
+
+    /// Lee un archivo TOML. Las rutas relativas se resuelven contra la carpeta del
+    /// archivo para no depender del directorio de trabajo del servicio.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("No se pudo leer la configuración {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Configuración inválida en {}", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for root in &mut config.container_roots {
+            *root = base.join(&*root);
+        }
+        config.runtimes_dir = base.join(&config.runtimes_dir);
+        config.packages_dir = base.join(&config.packages_dir);
+        config.plans_dir = base.join(&config.plans_dir);
+        config.control.endpoint = base.join(&config.control.endpoint);
+        config.mount.root = base.join(&config.mount.root);
+        config.mount.assignments = base.join(&config.mount.assignments);
+        Ok(config)
+    }
+
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<OsString>) {
+        if let Some(roots) = var("AGENT_CONTAINER_ROOTS") {
+            self.container_roots = env::split_paths(&roots).collect();
+        }
+        if let Some(dir) = var("AGENT_RUNTIMES_DIR") {
+            self.runtimes_dir = PathBuf::from(dir);
+        }
+        if let Some(dir) = var("AGENT_PACKAGES_DIR") {
+            self.packages_dir = PathBuf::from(dir);
+        }
+        if let Some(level) = var("AGENT_LOG") {
+            self.log_level = level.to_string_lossy().into_owned();
+        }
+        if let Some(endpoint) = var("AGENT_BACKEND_URL") {
+            self.backend.endpoint = Some(endpoint.to_string_lossy().into_owned());
+        }
+        if let Some(id) = var("AGENT_ID") {
+            self.backend.agent_id = Some(id.to_string_lossy().into_owned());
+        }
+        if let Some(endpoint) = var("AGENT_CONTROL_ENDPOINT") {
+            self.control.endpoint = PathBuf::from(endpoint);
+        }
+    }
+
+    fn apply_args(&mut self, args: &AgentArgs) {
+        if !args.container_roots.is_empty() {
+            self.container_roots = args.container_roots.clone();
+        }
+        if let Some(dir) = &args.runtimes {
+            self.runtimes_dir = dir.clone();
+        }
+        if let Some(level) = &args.log_level {
+            self.log_level = level.clone();
+        }
+        if let Some(endpoint) = &args.backend {
+            self.backend.endpoint = Some(endpoint.clone());
+        }
+        if let Some(endpoint) = &args.control {
+            self.control.endpoint = endpoint.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn file_paths_are_relative_to_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.toml");
+        std::fs::write(
+            &path,
+            r#"
+container_roots = ["containers", "/srv/shared"]
+runtimes_dir = "runtimes"
+log_level = "agent=debug"
+
+[mount]
+enabled = false
+preferred_drive = "X"
+provider = "dokany"
+
+[backend]
+endpoint = "http://backend:50051"
+"#,
+        )
+        .unwrap();
+
+        let config = AgentConfig::from_file(&path).unwrap();
+        assert_eq!(
+            config.container_roots,
+            vec![dir.path().join("containers"), PathBuf::from("/srv/shared")]
+        );
+        assert_eq!(config.runtimes_dir, dir.path().join("runtimes"));
+        assert!(!config.mount.enabled);
+        assert_eq!(config.mount.preferred_drive, Some('X'));
+        assert_eq!(config.mount.provider, MountProviderKind::Dokany);
+        assert!(!config.mount.optional);
+        assert_eq!(
+            config.backend.endpoint.as_deref(),
+            Some("http://backend:50051")
+        );
+    }
+
+    #[test]
+    fn defaults_hang_from_an_absolute_data_dir() {
+        let config = AgentConfig::default();
+        let data = default_data_dir();
+        assert!(data.is_absolute(), "{}", data.display());
+        assert_eq!(config.container_roots, vec![data.join("containers")]);
+        assert_eq!(config.runtimes_dir, data.join("runtimes"));
+        assert_eq!(config.packages_dir, data.join("packages"));
+        assert_eq!(config.plans_dir, data.join("hook-plans"));
+        assert!(config.mount.assignments.starts_with(&data));
+
+        let vars = HashMap::from([
+            ("AGENT_DATA_DIR", OsString::from("relative")),
+            ("HOME", OsString::from("/home/ana")),
+        ]);
+        let dir = data_dir_from(|key| vars.get(key).cloned());
+        if cfg!(windows) {
+            assert_eq!(dir, PathBuf::from(r"C:\ProgramData\ctnr"));
+        } else {
+            assert_eq!(dir, PathBuf::from("/home/ana/.local/share/ctnr"));
+        }
+        assert_eq!(
+            data_dir_from(|_| None),
+            if cfg!(windows) {
+                PathBuf::from(r"C:\ProgramData\ctnr")
+            } else {
+                PathBuf::from("/var/lib/ctnr")
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("agent.toml");
+        std::fs::write(&path, "container_root = \"typo\"\n").unwrap();
+        assert!(AgentConfig::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn env_and_args_override_in_order() {
+        let vars = HashMap::from([
+            (
+                "AGENT_CONTAINER_ROOTS",
+                env::join_paths(["/a", "/b"]).unwrap(),
+            ),
+            ("AGENT_LOG", OsString::from("agent=trace")),
+            ("AGENT_BACKEND_URL", OsString::from("http://env:50051")),
+        ]);
+        let mut config = AgentConfig::default();
+        config.apply_env(|key| vars.get(key).cloned());
+        assert_eq!(
+            config.container_roots,
+            vec![PathBuf::from("/a"), PathBuf::from("/b")]
+        );
+        assert_eq!(config.log_level, "agent=trace");
+
+        config.apply_args(&AgentArgs {
+            container_roots: vec![PathBuf::from("/cli")],
+            backend: Some("http://cli:50051".into()),
+            ..Default::default()
+        });
+        assert_eq!(config.container_roots, vec![PathBuf::from("/cli")]);
+        assert_eq!(config.log_level, "agent=trace");
+        assert_eq!(config.backend.endpoint.as_deref(), Some("http://cli:50051"));
+    }
+}
@@ -0,0 +1,655 @@
+use crate::{
+    host::ShutdownReport, mount::MountStatus, registry::LoadDiagnostic, runtime::HookPlan,
+    supervisor::RunRecord,
+};
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+use tracing::{debug, info, warn};
+
+/// Longitud máxima de una petición; una línea más larga recibe un error y se
+/// cierra la conexión.
+const MAX_REQUEST_LINE: usize = 1024 * 1024;
+/// Pausa tras un error al aceptar conexiones.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// Petición del API local del agent. Se transporta como una línea JSON por petición.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Contenedores cargados y si están preparados para lanzar.
+    ListContainers,
+    /// Plan de hooks calculado para un contenedor preparado.
+    GetPlan { container_id: String },
+    /// Lanza un perfil de `launch` (el por defecto si no se indica) o, en su
+    /// lugar, un ejecutable relativo a la carpeta del contenedor.
+    Launch {
+        container_id: String,
+        #[serde(default)]
+        profile: Option<String>,
+        #[serde(default)]
+        executable: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Detiene una ejecución con cierre ordenado y `kill` pasado el timeout.
+    Stop {
+        run_id: String,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Ejecuciones conocidas, opcionalmente filtradas por run id o contenedor.
+    Status {
+        #[serde(default)]
+        run_id: Option<String>,
+        #[serde(default)]
+        container_id: Option<String>,
+    },
+    /// Diagnósticos de la última carga del registro.
+    Diagnostics,
+    /// Vuelve a leer los manifiestos sin esperar a que el watcher detecte cambios.
+    Refresh,
+    /// Detiene las ejecuciones de un contenedor y desmonta sus volúmenes para
+    /// poder sustituir o borrar su carpeta; queda sin preparar hasta `prepare`.
+    Release {
+        container_id: String,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Vuelve a leer el manifiesto de un contenedor registrado y lo prepara si
+    /// no lo está.
+    Prepare { container_id: String },
+    /// Apaga el agent de forma ordenada y responde con el informe final.
+    Shutdown {
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Containers { containers: Vec<ContainerSummary> },
+    Plan { plan: HookPlan },
+    Launched { run_id: String },
+    Runs { runs: Vec<RunRecord> },
+    Diagnostics { diagnostics: Vec<LoadDiagnostic> },
+    Refreshed { changes: usize },
+    ShutDown { report: ShutdownReport },
+    Error { message: String },
+}
+
+impl ControlResponse {
+    pub fn error(err: impl std::fmt::Display) -> Self {
+        Self::Error {
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerSummary {
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub root: PathBuf,
+    /// Plan calculado y hooks activos; si es `false` no se puede lanzar.
+    pub prepared: bool,
+    pub profiles: Vec<String>,
+    /// Estado del volumen; vacío si el contenedor no se monta.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<MountStatus>,
+    /// Por qué no se pudo preparar; vacío si está preparado o nunca falló.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Quién puede conectarse al API local. Se aplica con permisos del sistema de
+/// archivos sobre el socket (Unix) o con la DACL del named pipe (Windows).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ControlAccess {
+    /// Solo el usuario del agent (`0600`; en Windows, SYSTEM y Administradores).
+    #[default]
+    Owner,
+    /// Además, su grupo (`0660`; en Windows, los usuarios interactivos).
+    Group,
+    /// Cualquier usuario local (`0666`; en Windows, Everyone).
+    Everyone,
+}
+
+impl ControlAccess {
+    #[cfg(unix)]
+    fn mode(self) -> u32 {
+        match self {
+            Self::Owner => 0o600,
+            Self::Group => 0o660,
+            Self::Everyone => 0o666,
+        }
+    }
+
+    /// Permisos de la carpeta del socket: solo el agent escribe en ella y los
+    /// demás usuarios admitidos solo pueden atravesarla.
+    #[cfg(unix)]
+    fn dir_mode(self) -> u32 {
+        match self {
+            Self::Owner => 0o700,
+            Self::Group => 0o710,
+            Self::Everyone => 0o711,
+        }
+    }
+
+    #[cfg(windows)]
+    fn sddl(self) -> &'static str {
+        match self {
+            Self::Owner => "D:P(A;;GA;;;SY)(A;;GA;;;BA)",
+            Self::Group => "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;IU)",
+            Self::Everyone => "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GRGW;;;WD)",
+        }
+    }
+}
+
+/// Petición recibida por el servidor y pendiente de respuesta por el bucle del agent.
+pub struct ControlCommand {
+    pub request: ControlRequest,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+/// Ruta por defecto del socket (Unix) o named pipe (Windows) del agent. En Unix
+/// vive en una carpeta propia del usuario (`$XDG_RUNTIME_DIR/ctnr` o
+/// `<data>/run`), nunca en la carpeta temporal compartida.
+pub fn default_endpoint() -> PathBuf {
+    #[cfg(windows)]
+    {
+        PathBuf::from(r"\\.\pipe\ctnr-agent")
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .map(|dir| dir.join("ctnr"))
+            .unwrap_or_else(|| crate::config::default_data_dir().join("run"))
+            .join("agent.sock")
+    }
+}
+
+/// Servidor del API local. Cada conexión puede enviar varias peticiones; todas se
+/// reenvían al bucle principal del agent por `commands`.
+pub struct ControlServer {
+    endpoint: PathBuf,
+    #[cfg_attr(unix, allow(dead_code))]
+    access: ControlAccess,
+    #[cfg(unix)]
+    listener: tokio::net::UnixListener,
+}
+
+impl ControlServer {
+    pub fn bind(endpoint: impl AsRef<Path>, access: ControlAccess) -> Result<Self> {
+        let endpoint = endpoint.as_ref().to_path_buf();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(parent) = endpoint.parent() {
+                private_dir(parent, access.dir_mode())?;
+            }
+            remove_stale_socket(&endpoint)?;
+            // Entre `bind` y `chmod` el socket tiene los permisos por defecto,
+            // pero la carpeta solo deja pasar a los usuarios admitidos.
+            let listener = tokio::net::UnixListener::bind(&endpoint).with_context(|| {
+                format!(
+                    "No se pudo abrir el socket de control {}",
+                    endpoint.display()
+                )
+            })?;
+            std::fs::set_permissions(&endpoint, std::fs::Permissions::from_mode(access.mode()))
+                .with_context(|| {
+                    format!(
+                        "No se pudieron restringir los permisos de {}",
+                        endpoint.display()
+                    )
+                })?;
+            Ok(Self {
+                endpoint,
+                access,
+                listener,
+            })
+        }
+        #[cfg(windows)]
+        {
+            Ok(Self { endpoint, access })
+        }
+    }
+
+    pub fn endpoint(&self) -> &Path {
+        &self.endpoint
+    }
+
+    pub async fn serve(self, commands: mpsc::Sender<ControlCommand>) -> Result<()> {
+        info!(endpoint = ?self.endpoint, "API local del agent escuchando");
+        // Un error al aceptar (p. ej. sin descriptores libres) afecta a una
+        // conexión, no al API: se registra y se sigue escuchando tras una pausa.
+        #[cfg(unix)]
+        loop {
+            match self.listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, commands.clone()));
+                }
+                Err(err) => {
+                    warn!(?err, "No se pudo aceptar una conexión de control");
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            }
+        }
+        #[cfg(windows)]
+        {
+            let mut security = pipe_security::PipeSecurity::new(self.access.sddl())?;
+            let mut server = security.create(&self.endpoint, true)?;
+            loop {
+                let connected = server.connect().await;
+                // Tras un error la instancia no sirve: también se reemplaza.
+                let instance =
+                    std::mem::replace(&mut server, security.create(&self.endpoint, false)?);
+                match connected {
+                    Ok(()) => {
+                        tokio::spawn(handle_connection(instance, commands.clone()));
+                    }
+                    Err(err) => {
+                        warn!(?err, "No se pudo aceptar una conexión de control");
+                        tokio::time::sleep(ACCEPT_RETRY).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Crea (o comprueba) la carpeta del socket: debe ser del usuario del agent y
+/// nadie más puede escribir en ella, para que otro usuario no pueda crear o
+/// suplantar el socket.
+#[cfg(unix)]
+fn private_dir(dir: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(mode)
+            .create(dir)
+            .with_context(|| format!("No se pudo crear {}", dir.display()))?;
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(mode))?;
+    }
+    let metadata = std::fs::metadata(dir)?;
+    // SAFETY: `geteuid` no tiene precondiciones.
+    let uid = unsafe { libc::geteuid() };
+    if metadata.uid() != uid || metadata.mode() & 0o022 != 0 {
+        anyhow::bail!(
+            "La carpeta del socket de control {} debe ser del usuario del agent y no admitir escritura de otros",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Borra el socket de una ejecución anterior; cualquier otra cosa en su lugar,
+/// o un socket en el que otro agent sigue respondiendo, es un error en vez de
+/// borrarse.
+#[cfg(unix)]
+fn remove_stale_socket(endpoint: &Path) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    let metadata = match std::fs::symlink_metadata(endpoint) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    // SAFETY: `geteuid` no tiene precondiciones.
+    let uid = unsafe { libc::geteuid() };
+    if !metadata.file_type().is_socket() || metadata.uid() != uid {
+        anyhow::bail!(
+            "{} existe y no es un socket del agent; no se reemplaza",
+            endpoint.display()
+        );
+    }
+    if std::os::unix::net::UnixStream::connect(endpoint).is_ok() {
+        anyhow::bail!("Ya hay un agent escuchando en {}", endpoint.display());
+    }
+    std::fs::remove_file(endpoint)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.endpoint);
+    }
+}
+
+#[cfg(windows)]
+mod pipe_security {
+    use anyhow::Result;
+    use std::{ffi::c_void, path::Path};
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+    use windows::{
+        core::PCWSTR,
+        Win32::{
+            Foundation::{LocalFree, HLOCAL},
+            Security::{
+                Authorization::{
+                    ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+                },
+                PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+            },
+        },
+    };
+
+    /// Descriptor de seguridad aplicado a cada instancia del named pipe.
+    pub struct PipeSecurity {
+        descriptor: PSECURITY_DESCRIPTOR,
+    }
+
+    impl PipeSecurity {
+        pub fn new(sddl: &str) -> Result<Self> {
+            let wide: Vec<u16> = sddl.encode_utf16().chain(Some(0)).collect();
+            let mut descriptor = PSECURITY_DESCRIPTOR::default();
+            // SAFETY: `wide` termina en NUL y sobrevive a la llamada.
+            unsafe {
+                ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                    PCWSTR(wide.as_ptr()),
+                    SDDL_REVISION_1,
+                    &mut descriptor,
+                    None,
+                )?;
+            }
+            Ok(Self { descriptor })
+        }
+
+        pub fn create(&mut self, endpoint: &Path, first: bool) -> Result<NamedPipeServer> {
+            let mut attributes = SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: self.descriptor.0,
+                bInheritHandle: false.into(),
+            };
+            // SAFETY: `attributes` y el descriptor viven durante la creación del pipe.
+            let server = unsafe {
+                ServerOptions::new()
+                    .first_pipe_instance(first)
+                    .reject_remote_clients(true)
+                    .create_with_security_attributes_raw(
+                        endpoint,
+                        &mut attributes as *mut SECURITY_ATTRIBUTES as *mut c_void,
+                    )?
+            };
+            Ok(server)
+        }
+    }
+
+    // SAFETY: el descriptor es memoria propia e inmutable tras crearse.
+    unsafe impl Send for PipeSecurity {}
+
+    impl Drop for PipeSecurity {
+        fn drop(&mut self) {
+            // SAFETY: el descriptor lo reservó `ConvertStringSecurityDescriptor…` con LocalAlloc.
+            unsafe {
+                let _ = LocalFree(HLOCAL(self.descriptor.0));
+            }
+        }
+    }
+}
+
+async fn handle_connection<S>(stream: S, commands: mpsc::Sender<ControlCommand>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut lines = FramedRead::new(reader, LinesCodec::new_with_max_length(MAX_REQUEST_LINE));
+    loop {
+        let line = match lines.next().await {
+            Some(Ok(line)) => line,
+            None => return,
+            Some(Err(LinesCodecError::MaxLineLengthExceeded)) => {
+                let message = format!("Petición demasiado larga (máximo {MAX_REQUEST_LINE} bytes)");
+                let _ = write_response(&mut writer, &ControlResponse::error(message)).await;
+                return;
+            }
+            Some(Err(err)) => {
+                warn!(?err, "Conexión de control interrumpida");
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                debug!(?request, "Petición de control recibida");
+                dispatch(request, &commands).await
+            }
+            Err(err) => ControlResponse::error(format!("Petición inválida: {err}")),
+        };
+
+        if write_response(&mut writer, &response).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write_response<W>(writer: &mut W, response: &ControlResponse) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut payload = serde_json::to_string(response).inspect_err(|err| {
+        warn!(?err, "No se pudo serializar la respuesta de control");
+    })?;
+    payload.push('\n');
+    writer.write_all(payload.as_bytes()).await?;
+    Ok(())
+}
+
+async fn dispatch(
+    request: ControlRequest,
+    commands: &mpsc::Sender<ControlCommand>,
+) -> ControlResponse {
+    let (reply, response) = oneshot::channel();
+    if commands
+        .send(ControlCommand { request, reply })
+        .await
+        .is_err()
+    {
+        return ControlResponse::error("El agent se está apagando");
+    }
+    response
+        .await
+        .unwrap_or_else(|_| ControlResponse::error("El agent no respondió a la petición"))
+}
+
+/// Cliente del API local usado por la CLI.
+pub struct ControlClient {
+    endpoint: PathBuf,
+}
+
+impl ControlClient {
+    pub fn new(endpoint: impl Into<PathBuf>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+        }
+    }
+
+    pub async fn request(&self, request: &ControlRequest) -> Result<ControlResponse> {
+        #[cfg(unix)]
+        let stream = tokio::net::UnixStream::connect(&self.endpoint).await;
+        #[cfg(windows)]
+        let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(&self.endpoint);
+        let stream = stream.with_context(|| {
+            format!(
+                "No se pudo conectar con el agent en {}; ¿está en ejecución?",
+                self.endpoint.display()
+            )
+        })?;
+
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut payload = serde_json::to_string(request)?;
+        payload.push('\n');
+        writer.write_all(payload.as_bytes()).await?;
+
+        let line = BufReader::new(reader)
+            .lines()
+            .next_line()
+            .await?
+            .ok_or_else(|| anyhow!("El agent cerró la conexión sin responder"))?;
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn client_and_server_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("agent.sock");
+        let server = ControlServer::bind(&endpoint, ControlAccess::Owner).unwrap();
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&endpoint).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let (tx, mut rx) = mpsc::channel(4);
+        let serve = tokio::spawn(server.serve(tx));
+
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                let response = match command.request {
+                    ControlRequest::Launch { container_id, .. } => ControlResponse::Launched {
+                        run_id: format!("run-{container_id}"),
+                    },
+                    other => ControlResponse::error(format!("{other:?}")),
+                };
+                let _ = command.reply.send(response);
+            }
+        });
+
+        let client = ControlClient::new(&endpoint);
+        let response = client
+            .request(&ControlRequest::Launch {
+                container_id: "demo".into(),
+                profile: None,
+                executable: None,
+                args: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            ControlResponse::Launched {
+                run_id: "run-demo".into()
+            }
+        );
+        serve.abort();
+    }
+
+    #[tokio::test]
+    async fn refuses_shared_dirs_and_foreign_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+
+        let fresh = dir.path().join("run/ctnr");
+        let server = ControlServer::bind(fresh.join("agent.sock"), ControlAccess::Group).unwrap();
+        let mode = std::fs::metadata(&fresh).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o710);
+        drop(server);
+
+        let shared = dir.path().join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let err = ControlServer::bind(shared.join("agent.sock"), ControlAccess::Owner)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no admitir escritura"), "{err}");
+
+        let planted = dir.path().join("run/ctnr/agent.sock");
+        std::fs::write(&planted, b"no soy un socket").unwrap();
+        assert!(ControlServer::bind(&planted, ControlAccess::Owner).is_err());
+        assert_eq!(std::fs::read(&planted).unwrap(), b"no soy un socket");
+    }
+
+    #[tokio::test]
+    async fn replaces_stale_sockets_but_not_live_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("agent.sock");
+        drop(std::os::unix::net::UnixListener::bind(&endpoint).unwrap());
+        assert!(endpoint.exists());
+
+        let server = ControlServer::bind(&endpoint, ControlAccess::Owner).unwrap();
+        let err = ControlServer::bind(&endpoint, ControlAccess::Owner)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Ya hay un agent"), "{err}");
+        assert!(endpoint.exists());
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_get_an_error_and_close_the_connection() {
+        use tokio::io::AsyncReadExt;
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("agent.sock");
+        let server = ControlServer::bind(&endpoint, ControlAccess::Owner).unwrap();
+        let (tx, _rx) = mpsc::channel(4);
+        let serve = tokio::spawn(server.serve(tx));
+
+        let stream = tokio::net::UnixStream::connect(&endpoint).await.unwrap();
+        let (mut reader, mut writer) = stream.into_split();
+        tokio::spawn(async move {
+            let _ = writer.write_all(&vec![b'x'; MAX_REQUEST_LINE + 1]).await;
+        });
+        let mut reply = String::new();
+        reader.read_to_string(&mut reply).await.unwrap();
+        let response: ControlResponse = serde_json::from_str(reply.trim()).unwrap();
+        assert!(
+            matches!(&response, ControlResponse::Error { message } if message.contains("demasiado larga")),
+            "{response:?}"
+        );
+        serve.abort();
+    }
+
+    #[test]
+    fn wire_format_is_stable() {
+        let request: ControlRequest = serde_json::from_str(
+            r#"{"method":"launch","params":{"container_id":"demo","args":["--safe"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            ControlRequest::Launch {
+                container_id: "demo".into(),
+                profile: None,
+                executable: None,
+                args: vec!["--safe".into()],
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&ControlRequest::ListContainers).unwrap(),
+            r#"{"method":"list_containers"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<ControlRequest>(r#"{"method":"stop","params":{"run_id":"r1"}}"#)
+                .unwrap(),
+            ControlRequest::Stop {
+                run_id: "r1".into(),
+                timeout_ms: None
+            }
+        );
+        assert_eq!(
+            serde_json::to_string(&ControlResponse::error("boom")).unwrap(),
+            r#"{"type":"error","message":"boom"}"#
+        );
+    }
+}
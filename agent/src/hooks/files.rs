@@ -0,0 +1,437 @@
+//! Redirección de las APIs de archivos, independiente de la plataforma.
+//!
+//! [`FILE_APIS`] describe cada API hookeada: dónde vive, si es ANSI y qué
+//! argumentos llevan rutas y con qué forma. Todos los detours de `windows.rs`
+//! preguntan a [`PathRedirector::redirect`], así que una ruta se reescribe
+//! igual entre por `CreateFileA`, `FindFirstFileExW` o `NtCreateFile`.
+
+use crate::runtime::PathRedirect;
+
+/// APIs de archivos interceptadas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileApi {
+    CreateFileW,
+    CreateFileA,
+    CreateDirectoryW,
+    CreateDirectoryA,
+    DeleteFileW,
+    DeleteFileA,
+    MoveFileExW,
+    MoveFileExA,
+    FindFirstFileExW,
+    GetFileAttributesW,
+    GetFileAttributesExW,
+    GetFileAttributesExA,
+    NtCreateFile,
+    SHGetKnownFolderPath,
+}
+
+impl FileApi {
+    pub fn spec(self) -> &'static FileApiSpec {
+        FILE_APIS
+            .iter()
+            .find(|spec| spec.api == self)
+            .expect("toda FileApi está en FILE_APIS")
+    }
+
+    /// Nombre exportado por la DLL.
+    pub fn symbol(self) -> &'static str {
+        self.spec().symbol
+    }
+}
+
+/// Forma de una ruta en un argumento.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathShape {
+    /// Ruta Win32 (`C:\...`, `C:/...` o `\\?\C:\...`).
+    Win32,
+    /// Patrón de búsqueda: ruta Win32 cuyo último componente puede llevar comodines.
+    Pattern,
+    /// `ObjectName` de un `OBJECT_ATTRIBUTES` en el espacio NT (`\??\C:\...`).
+    Nt,
+}
+
+/// La API recibe la ruta o la devuelve (`SHGetKnownFolderPath`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathArg {
+    /// Posición del argumento, empezando en 0.
+    pub index: usize,
+    pub shape: PathShape,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileApiSpec {
+    pub api: FileApi,
+    pub module: &'static str,
+    pub symbol: &'static str,
+    /// Las rutas llegan en la página de códigos ANSI; el detour las convierte y
+    /// llama a la variante `W` original.
+    pub ansi: bool,
+    pub paths: &'static [PathArg],
+}
+
+const fn input(index: usize, shape: PathShape) -> PathArg {
+    PathArg {
+        index,
+        shape,
+        direction: Direction::In,
+    }
+}
+
+const FIRST: &[PathArg] = &[input(0, PathShape::Win32)];
+const FIRST_TWO: &[PathArg] = &[input(0, PathShape::Win32), input(1, PathShape::Win32)];
+
+macro_rules! spec {
+    ($api:ident, $module:literal, $ansi:literal, $paths:expr) => {
+        FileApiSpec {
+            api: FileApi::$api,
+            module: $module,
+            symbol: stringify!($api),
+            ansi: $ansi,
+            paths: $paths,
+        }
+    };
+}
+
+/// Tabla de las APIs hookeadas; `windows.rs` instala un detour por entrada.
+pub const FILE_APIS: &[FileApiSpec] = &[
+    spec!(CreateFileW, "kernel32.dll", false, FIRST),
+    spec!(CreateFileA, "kernel32.dll", true, FIRST),
+    spec!(CreateDirectoryW, "kernel32.dll", false, FIRST),
+    spec!(CreateDirectoryA, "kernel32.dll", true, FIRST),
+    spec!(DeleteFileW, "kernel32.dll", false, FIRST),
+    spec!(DeleteFileA, "kernel32.dll", true, FIRST),
+    spec!(MoveFileExW, "kernel32.dll", false, FIRST_TWO),
+    spec!(MoveFileExA, "kernel32.dll", true, FIRST_TWO),
+    spec!(
+        FindFirstFileExW,
+        "kernel32.dll",
+        false,
+        &[input(0, PathShape::Pattern)]
+    ),
+    spec!(GetFileAttributesW, "kernel32.dll", false, FIRST),
+    spec!(GetFileAttributesExW, "kernel32.dll", false, FIRST),
+    spec!(GetFileAttributesExA, "kernel32.dll", true, FIRST),
+    spec!(NtCreateFile, "ntdll.dll", false, &[input(2, PathShape::Nt)]),
+    spec!(
+        SHGetKnownFolderPath,
+        "shell32.dll",
+        false,
+        &[PathArg {
+            index: 3,
+            shape: PathShape::Win32,
+            direction: Direction::Out,
+        }]
+    ),
+];
+
+const EXTENDED_PREFIX: &str = r"\\?\";
+const NT_PREFIX: &str = r"\??\";
+
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    /// Componentes de la ruta original en minúsculas, con la unidad primero.
+    original: Vec<String>,
+    redirected: String,
+    /// Componentes de `redirected` en minúsculas, si es una ruta con unidad.
+    target: Option<Vec<String>>,
+}
+
+/// Decide a dónde va una ruta según los `PathRedirect` del plan.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathRedirector {
+    rules: Vec<Rule>,
+}
+
+impl PathRedirector {
+    /// Las redirecciones más específicas ganan: `%TEMP%` suele estar dentro de
+    /// `%LOCALAPPDATA%` y debe ir a su propia carpeta.
+    pub fn new(redirects: &[PathRedirect]) -> Self {
+        let mut rules: Vec<Rule> = redirects
+            .iter()
+            .filter_map(|redirect| {
+                let original = redirect.original.to_string_lossy();
+                let redirected = redirect
+                    .redirected
+                    .to_string_lossy()
+                    .trim_end_matches(['\\', '/'])
+                    .to_string();
+                Some(Rule {
+                    original: folded_components(&original)?,
+                    target: folded_components(&redirected),
+                    redirected,
+                })
+            })
+            .collect();
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.original.len()));
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Ruta que debe ver `api` en su argumento `index`, o `None` si se deja
+    /// como está (argumento sin ruta, ruta relativa, UNC o fuera del plan).
+    pub fn redirect(&self, api: FileApi, index: usize, path: &str) -> Option<String> {
+        let arg = api.spec().paths.iter().find(|arg| arg.index == index)?;
+        match arg.shape {
+            PathShape::Win32 | PathShape::Pattern => self.rewrite(path),
+            PathShape::Nt => {
+                let rest = path.strip_prefix(NT_PREFIX)?;
+                if rest.starts_with(['\\', '/']) {
+                    return None;
+                }
+                Some(format!("{NT_PREFIX}{}", self.rewrite(rest)?))
+            }
+        }
+    }
+
+    /// Reescribe una ruta Win32 absoluta. Los `.` y `..` se resuelven antes de
+    /// comparar para que `...\Roaming\..\..` no salga de la carpeta redirigida.
+    /// Las rutas que ya están dentro de un destino no se tocan: `CreateFileW`
+    /// llega a `NtCreateFile` con la ruta ya redirigida.
+    pub fn rewrite(&self, path: &str) -> Option<String> {
+        let (prefix, rest) = match path.strip_prefix(EXTENDED_PREFIX) {
+            Some(rest) => (EXTENDED_PREFIX, rest),
+            None => ("", path),
+        };
+        let components = drive_components(rest)?;
+        let folded: Vec<String> = components.iter().map(|name| name.to_lowercase()).collect();
+        if self.rules.iter().any(|rule| {
+            rule.target
+                .as_ref()
+                .is_some_and(|target| folded.starts_with(target))
+        }) {
+            return None;
+        }
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| folded.starts_with(&rule.original))?;
+
+        let mut rewritten = String::new();
+        if drive_components(&rule.redirected).is_some() {
+            rewritten.push_str(prefix);
+        }
+        rewritten.push_str(&rule.redirected);
+        for name in &components[rule.original.len()..] {
+            rewritten.push('\\');
+            rewritten.push_str(name);
+        }
+        if path.ends_with(['\\', '/']) && components.len() > rule.original.len() {
+            rewritten.push('\\');
+        }
+        Some(rewritten)
+    }
+}
+
+/// [`drive_components`] en minúsculas, como se comparan las reglas.
+fn folded_components(path: &str) -> Option<Vec<String>> {
+    Some(
+        drive_components(path)?
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect(),
+    )
+}
+
+/// Componentes normalizados de una ruta `X:\...` (la unidad en mayúsculas
+/// primero); `None` si no es absoluta con letra de unidad.
+fn drive_components(path: &str) -> Option<Vec<String>> {
+    let mut chars = path.chars();
+    let (drive, colon, separator) = (chars.next()?, chars.next()?, chars.next());
+    if !drive.is_ascii_alphabetic() || colon != ':' || !matches!(separator, Some('\\' | '/')) {
+        return None;
+    }
+    let mut components = vec![format!("{}:", drive.to_ascii_uppercase())];
+    for name in path[3..].split(['\\', '/']) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                if components.len() > 1 {
+                    components.pop();
+                }
+            }
+            name => components.push(name.to_string()),
+        }
+    }
+    Some(components)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, path::PathBuf};
+    use FileApi::*;
+
+    /// Caso de la matriz: qué ve `api` en el argumento `arg` para `input`.
+    struct Case {
+        api: FileApi,
+        arg: usize,
+        input: &'static str,
+        expected: Option<&'static str>,
+    }
+
+    const fn case(
+        api: FileApi,
+        arg: usize,
+        input: &'static str,
+        expected: Option<&'static str>,
+    ) -> Case {
+        Case {
+            api,
+            arg,
+            input,
+            expected,
+        }
+    }
+
+    const ROAMING: &str = r"D:\ctnr\demo\user\AppData\Roaming";
+    #[rustfmt::skip]
+    const MATRIX: &[Case] = &[
+        // Forma básica en cada API y argumento.
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Roaming\app.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(CreateFileA, 0, r"C:\Users\ana\AppData\Roaming\app.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(CreateDirectoryW, 0, r"C:\Users\ana\AppData\Local\Vendor", Some(r"D:\ctnr\demo\user\LocalAppData\Vendor")),
+        case(CreateDirectoryA, 0, r"C:\Program Files\Vendor", Some(r"D:\ctnr\demo\rootfs\ProgramFiles\Vendor")),
+        case(DeleteFileW, 0, r"C:\Users\ana\AppData\Local\Temp\x.tmp", Some(r"D:\ctnr\demo\temp\x.tmp")),
+        case(DeleteFileA, 0, r"C:\Windows\win.ini", None),
+        case(DeleteFileA, 0, r"C:\Users\ana\AppData\Roaming\old.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\old.ini")),
+        case(MoveFileExW, 0, r"C:\Users\ana\AppData\Roaming\a", Some(r"D:\ctnr\demo\user\AppData\Roaming\a")),
+        case(MoveFileExW, 1, r"C:\Users\ana\AppData\Roaming\b", Some(r"D:\ctnr\demo\user\AppData\Roaming\b")),
+        case(MoveFileExA, 0, r"C:\Data\a", None),
+        case(MoveFileExA, 0, r"C:\Program Files\Vendor\old", Some(r"D:\ctnr\demo\rootfs\ProgramFiles\Vendor\old")),
+        case(MoveFileExA, 1, r"C:\Users\ana\AppData\Roaming\b", Some(r"D:\ctnr\demo\user\AppData\Roaming\b")),
+        case(MoveFileExW, 2, r"C:\Users\ana\AppData\Roaming\b", None),
+        case(FindFirstFileExW, 0, r"C:\Users\ana\AppData\Roaming\*.log", Some(r"D:\ctnr\demo\user\AppData\Roaming\*.log")),
+        case(FindFirstFileExW, 0, r"C:\Users\ana\AppData\Roaming\*", Some(r"D:\ctnr\demo\user\AppData\Roaming\*")),
+        case(GetFileAttributesW, 0, r"C:\Users\ana\AppData\Roaming", Some(ROAMING)),
+        case(GetFileAttributesExW, 0, r"C:\Users\ana\AppData\Roaming\app.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(GetFileAttributesExA, 0, r"c:/users/ana/appdata/roaming/app.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(NtCreateFile, 2, r"\??\C:\Users\ana\AppData\Roaming\app.ini", Some(r"\??\D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(SHGetKnownFolderPath, 3, r"C:\Users\ana\AppData\Roaming", Some(ROAMING)),
+        // Formas de la ruta.
+        case(CreateFileW, 0, r"\\?\C:\Users\ana\AppData\Roaming\app.ini", Some(r"\\?\D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(CreateFileW, 0, r"C:\USERS\ANA\APPDATA\ROAMING\App.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\App.ini")),
+        case(CreateDirectoryW, 0, r"C:\Users\ana\AppData\Roaming\Vendor\", Some(r"D:\ctnr\demo\user\AppData\Roaming\Vendor\")),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Roaming\.\sub\..\app.ini", Some(r"D:\ctnr\demo\user\AppData\Roaming\app.ini")),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Roaming\..\..\secret.txt", None),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\RoamingX\app.ini", None),
+        case(CreateFileW, 0, r"app.ini", None),
+        case(CreateFileW, 0, r"\Users\ana\AppData\Roaming\app.ini", None),
+        case(CreateFileW, 0, r"C:app.ini", None),
+        case(CreateFileW, 0, r"\\server\share\app.ini", None),
+        case(CreateFileW, 0, r"\\.\pipe\ctnr", None),
+        case(CreateFileW, 0, r"\\?\UNC\server\share\app.ini", None),
+        case(NtCreateFile, 2, r"C:\Users\ana\AppData\Roaming\app.ini", None),
+        case(NtCreateFile, 2, r"\??\UNC\server\share", None),
+        case(NtCreateFile, 2, r"\Device\HarddiskVolume3\Users\ana\AppData\Roaming\app.ini", None),
+        case(SHGetKnownFolderPath, 3, r"C:\Users\ana\Documents", None),
+        // La redirección más específica gana.
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Local\Temp", Some(r"D:\ctnr\demo\temp")),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Local\Tempest\x", Some(r"D:\ctnr\demo\user\LocalAppData\Tempest\x")),
+        // Lo ya redirigido no se vuelve a redirigir, aunque el destino esté
+        // dentro de otra ruta original.
+        case(NtCreateFile, 2, r"\??\D:\ctnr\demo\user\AppData\Roaming\app.ini", None),
+        case(CreateFileW, 0, r"\\?\d:\CTNR\demo\temp\x.tmp", None),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Local\Packages\ctnr\Vendor\app.ini", None),
+        case(NtCreateFile, 2, r"\??\C:\Users\ana\AppData\Local\Packages\ctnr\Vendor\app.ini", None),
+        case(CreateFileW, 0, r"C:\Users\ana\AppData\Local\Packages\other\app.ini", Some(r"D:\ctnr\demo\user\LocalAppData\Packages\other\app.ini")),
+    ];
+
+    fn redirector() -> PathRedirector {
+        let redirect = |variable: &str, original: &str, redirected: &str| PathRedirect {
+            variable: variable.into(),
+            original: PathBuf::from(original),
+            redirected: PathBuf::from(redirected),
+        };
+        PathRedirector::new(&[
+            redirect("APPDATA", r"C:\Users\ana\AppData\Roaming", ROAMING),
+            redirect(
+                "LOCALAPPDATA",
+                r"C:\Users\ana\AppData\Local",
+                r"D:\ctnr\demo\user\LocalAppData",
+            ),
+            redirect(
+                "PROGRAMFILES",
+                r"C:\Program Files",
+                r"D:\ctnr\demo\rootfs\ProgramFiles\",
+            ),
+            redirect(
+                "TEMP",
+                r"C:\Users\ana\AppData\Local\Temp",
+                r"D:\ctnr\demo\temp",
+            ),
+            redirect(
+                "PROGRAMDATA",
+                r"C:\ProgramData\Vendor",
+                r"C:\Users\ana\AppData\Local\Packages\ctnr\Vendor",
+            ),
+            redirect("CONTAINER", "relative", r"D:\ignored"),
+        ])
+    }
+
+    #[test]
+    fn matrix_rewrites_every_api_and_shape() {
+        let redirector = redirector();
+        for case in MATRIX {
+            assert_eq!(
+                redirector
+                    .redirect(case.api, case.arg, case.input)
+                    .as_deref(),
+                case.expected,
+                "{:?} argumento {} con {}",
+                case.api,
+                case.arg,
+                case.input
+            );
+        }
+    }
+
+    #[test]
+    fn matrix_covers_every_hooked_argument() {
+        let covered: HashSet<_> = MATRIX
+            .iter()
+            .filter(|case| case.expected.is_some())
+            .map(|case| (case.api, case.arg))
+            .collect();
+        for spec in FILE_APIS {
+            for arg in spec.paths {
+                assert!(
+                    covered.contains(&(spec.api, arg.index)),
+                    "falta un caso que redirija {} argumento {}",
+                    spec.symbol,
+                    arg.index
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn table_is_consistent() {
+        let mut seen = HashSet::new();
+        for spec in FILE_APIS {
+            assert!(seen.insert(spec.api), "{} repetida", spec.symbol);
+            assert_eq!(spec.symbol.ends_with('A'), spec.ansi, "{}", spec.symbol);
+            assert!(!spec.paths.is_empty(), "{} sin rutas", spec.symbol);
+            assert_eq!(spec.api.symbol(), spec.symbol);
+        }
+    }
+
+    #[test]
+    fn empty_plan_never_rewrites() {
+        let redirector = PathRedirector::new(&[]);
+        assert!(redirector.is_empty());
+        for case in MATRIX {
+            assert_eq!(redirector.redirect(case.api, case.arg, case.input), None);
+        }
+    }
+}
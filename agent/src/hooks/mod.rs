@@ -1,5 +1,9 @@
 use crate::runtime::HookPlan;
-use anyhow::Result;
+use anyhow::{Context, Result};
+
+pub mod files;
+pub mod registry;
+pub mod wire;
 
 #[cfg(all(target_os = "windows", feature = "native-hooks"))]
 mod windows;
@@ -18,11 +22,20 @@ impl PlatformHookManager {
 
     fn apply(&self, plan: &HookPlan) -> Result<()> {
         tracing::debug!(
-            "Hooks nativos deshabilitados; omitiendo plan ({} alias)",
-            plan.redirects.len()
+            "Hooks nativos deshabilitados; omitiendo plan ({} alias, registro {})",
+            plan.redirects.len(),
+            if plan.registry.is_some() {
+                "virtual"
+            } else {
+                "del host"
+            }
         );
         Ok(())
     }
+
+    fn remove(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct NativeHookPipeline {
@@ -39,4 +52,30 @@ impl NativeHookPipeline {
     pub fn apply(&self, plan: &HookPlan) -> Result<()> {
         self.inner.apply(plan)
     }
+
+    /// Desactiva los hooks y olvida las redirecciones; tras esto el agent no
+    /// altera ninguna llamada.
+    pub fn remove(&self) -> Result<()> {
+        self.inner.remove()
+    }
+}
+
+/// Punto de entrada del runtime inyectado en un proceso del contenedor: lee el
+/// plan que indica [`wire::PLAN_ENV`] y activa los hooks con él. Devuelve
+/// `false` si el proceso no se lanzó desde un contenedor.
+pub fn apply_from_env(pipeline: &NativeHookPipeline) -> Result<bool> {
+    let Some(path) = std::env::var_os(wire::PLAN_ENV) else {
+        return Ok(false);
+    };
+    let plan = wire::read_plan(path.as_ref())?;
+    pipeline
+        .apply(&plan)
+        .context("No se pudieron activar los hooks del contenedor")?;
+    Ok(true)
+}
+
+impl Default for NativeHookPipeline {
+    fn default() -> Self {
+        Self::new()
+    }
 }
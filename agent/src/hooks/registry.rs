@@ -0,0 +1,512 @@
+//! Decisiones de la redirección del registro, independientes de la plataforma.
+//!
+//! Los detours de `windows.rs` solo traducen punteros y `HKEY`: qué clave se
+//! virtualiza, qué handle se devuelve y qué código Win32 ve la aplicación se
+//! decide aquí, con `HKEY` representados como `isize`.
+//!
+//! Se virtualizan `HKEY_CURRENT_USER` y `HKEY_LOCAL_MACHINE\SOFTWARE`; el resto
+//! (`HKLM\SYSTEM`, `HKEY_CLASSES_ROOT`, `HKEY_USERS`...) y los handles que no
+//! emitió el redirector pasan al registro del host. Las claves virtuales se
+//! entregan como pseudo-handles propios que solo entienden las APIs hookeadas.
+
+use crate::{
+    runtime::RegistryPlan,
+    winreg::{HiveName, KeyPath, RegValue, VirtualRegistry},
+};
+use anyhow::Result;
+use std::collections::HashMap;
+use tracing::warn;
+
+pub const ERROR_SUCCESS: u32 = 0;
+pub const ERROR_FILE_NOT_FOUND: u32 = 2;
+pub const ERROR_ACCESS_DENIED: u32 = 5;
+pub const ERROR_INVALID_HANDLE: u32 = 6;
+pub const ERROR_INVALID_PARAMETER: u32 = 87;
+pub const ERROR_MORE_DATA: u32 = 234;
+pub const ERROR_NO_MORE_ITEMS: u32 = 259;
+
+/// `REG_CREATED_NEW_KEY` / `REG_OPENED_EXISTING_KEY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    CreatedNewKey = 1,
+    OpenedExistingKey = 2,
+}
+
+/// Resultado de una llamada interceptada: o la atiende el registro virtual
+/// (con un código Win32 si falla) o se deja pasar a la API original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome<T> {
+    Passthrough,
+    Done(Result<T, u32>),
+}
+
+/// Claves predefinidas (`HKEY_*`) que pueden llegar como handle padre.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Predefined {
+    ClassesRoot,
+    CurrentUser,
+    LocalMachine,
+    Users,
+}
+
+impl Predefined {
+    /// Reconoce los valores de `HKEY_*`, extendidos con signo en 64 bits.
+    pub fn from_handle(handle: isize) -> Option<Self> {
+        match handle as i64 {
+            raw if raw == 0x8000_0000u32 as i32 as i64 => Some(Self::ClassesRoot),
+            raw if raw == 0x8000_0001u32 as i32 as i64 => Some(Self::CurrentUser),
+            raw if raw == 0x8000_0002u32 as i32 as i64 => Some(Self::LocalMachine),
+            raw if raw == 0x8000_0003u32 as i32 as i64 => Some(Self::Users),
+            _ => None,
+        }
+    }
+
+    pub fn handle(self) -> isize {
+        let raw = match self {
+            Self::ClassesRoot => 0x8000_0000u32,
+            Self::CurrentUser => 0x8000_0001,
+            Self::LocalMachine => 0x8000_0002,
+            Self::Users => 0x8000_0003,
+        };
+        raw as i32 as isize
+    }
+}
+
+/// Primer pseudo-handle. Los handles del kernel son múltiplos de 4 pequeños,
+/// así que se reserva un rango alto con los dos bits bajos a 1.
+const VIRTUAL_HANDLE_BASE: isize = 0x5245_0003;
+
+/// Registro virtual del contenedor con la tabla de pseudo-handles abiertos.
+#[derive(Debug)]
+pub struct RegistryRedirector {
+    registry: VirtualRegistry,
+    handles: HashMap<isize, (HiveName, KeyPath)>,
+    next_handle: isize,
+}
+
+impl RegistryRedirector {
+    pub fn new(registry: VirtualRegistry) -> Self {
+        Self {
+            registry,
+            handles: HashMap::new(),
+            next_handle: VIRTUAL_HANDLE_BASE,
+        }
+    }
+
+    pub fn from_plan(plan: &RegistryPlan) -> Result<Self> {
+        Ok(Self::new(VirtualRegistry::open(
+            &plan.dir,
+            plan.base.as_deref(),
+        )?))
+    }
+
+    pub fn registry(&self) -> &VirtualRegistry {
+        &self.registry
+    }
+
+    pub fn is_virtual(&self, handle: isize) -> bool {
+        self.handles.contains_key(&handle)
+    }
+
+    /// Clave virtual a la que apunta `subkey` bajo `parent`, o `None` si la
+    /// llamada debe ir al registro del host.
+    pub fn route(&self, parent: isize, subkey: &str) -> Option<Result<(HiveName, KeyPath), u32>> {
+        if let Some((hive, path)) = self.handles.get(&parent) {
+            return Some(
+                KeyPath::parse(subkey)
+                    .map(|relative| (*hive, join(path, &relative)))
+                    .map_err(|_| ERROR_INVALID_PARAMETER),
+            );
+        }
+        let root = match Predefined::from_handle(parent)? {
+            Predefined::CurrentUser => "HKCU",
+            Predefined::LocalMachine => "HKLM",
+            Predefined::ClassesRoot | Predefined::Users => return None,
+        };
+        HiveName::split(&format!("{root}\\{subkey}")).map(Ok)
+    }
+
+    /// `RegOpenKeyExW`: un handle nuevo si la clave existe.
+    pub fn open_key(&mut self, parent: isize, subkey: &str) -> Outcome<isize> {
+        let (hive, path) = match self.route(parent, subkey) {
+            None => return Outcome::Passthrough,
+            Some(Err(code)) => return Outcome::Done(Err(code)),
+            Some(Ok(target)) => target,
+        };
+        if !self.registry.hive(hive).key_exists(&path) {
+            return Outcome::Done(Err(ERROR_FILE_NOT_FOUND));
+        }
+        Outcome::Done(Ok(self.allocate(hive, path)))
+    }
+
+    /// `RegCreateKeyExW`: crea las claves que falten y abre la última.
+    pub fn create_key(&mut self, parent: isize, subkey: &str) -> Outcome<(isize, Disposition)> {
+        let (hive, path) = match self.route(parent, subkey) {
+            None => return Outcome::Passthrough,
+            Some(Err(code)) => return Outcome::Done(Err(code)),
+            Some(Ok(target)) => target,
+        };
+        let overlay = self.registry.hive_mut(hive);
+        let disposition = if overlay.key_exists(&path) {
+            Disposition::OpenedExistingKey
+        } else {
+            overlay.create_key(&path);
+            self.persist();
+            Disposition::CreatedNewKey
+        };
+        Outcome::Done(Ok((self.allocate(hive, path), disposition)))
+    }
+
+    /// `RegQueryValueExW`: tipo y datos del valor (`""` es el predeterminado).
+    pub fn query_value(&self, key: isize, name: &str) -> Outcome<(u32, Vec<u8>)> {
+        let Some((hive, path)) = self.handles.get(&key) else {
+            return Outcome::Passthrough;
+        };
+        let overlay = self.registry.hive(*hive);
+        if !overlay.key_exists(path) {
+            return Outcome::Done(Err(ERROR_INVALID_HANDLE));
+        }
+        Outcome::Done(
+            overlay
+                .query_value(path, name)
+                .map(|value| (value.kind(), value.to_bytes()))
+                .ok_or(ERROR_FILE_NOT_FOUND),
+        )
+    }
+
+    /// `RegSetValueExW`: los datos que no encajan con su tipo se guardan tal cual.
+    pub fn set_value(&mut self, key: isize, name: &str, kind: u32, data: &[u8]) -> Outcome<()> {
+        let Some((hive, path)) = self.handles.get(&key).cloned() else {
+            return Outcome::Passthrough;
+        };
+        let overlay = self.registry.hive_mut(hive);
+        if !overlay.key_exists(&path) {
+            return Outcome::Done(Err(ERROR_INVALID_HANDLE));
+        }
+        overlay.set_value(&path, name, RegValue::from_bytes_lossless(kind, data));
+        self.persist();
+        Outcome::Done(Ok(()))
+    }
+
+    /// `RegDeleteKeyW`: solo claves sin subclaves y nunca la raíz de un hive.
+    pub fn delete_key(&mut self, parent: isize, subkey: &str) -> Outcome<()> {
+        let (hive, path) = match self.route(parent, subkey) {
+            None => return Outcome::Passthrough,
+            Some(Err(code)) => return Outcome::Done(Err(code)),
+            Some(Ok(target)) => target,
+        };
+        if path.is_root() {
+            return Outcome::Done(Err(ERROR_ACCESS_DENIED));
+        }
+        let result = match self.registry.hive_mut(hive).delete_key(&path) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ERROR_FILE_NOT_FOUND),
+            Err(_) => Err(ERROR_ACCESS_DENIED),
+        };
+        if result.is_ok() {
+            self.persist();
+        }
+        Outcome::Done(result)
+    }
+
+    /// `RegEnumKeyExW`: nombre de la subclave `index` en orden alfabético.
+    pub fn enum_key(&self, key: isize, index: u32) -> Outcome<String> {
+        let Some((hive, path)) = self.handles.get(&key) else {
+            return Outcome::Passthrough;
+        };
+        Outcome::Done(match self.registry.hive(*hive).subkeys(path) {
+            None => Err(ERROR_INVALID_HANDLE),
+            Some(names) => names
+                .into_iter()
+                .nth(index as usize)
+                .ok_or(ERROR_NO_MORE_ITEMS),
+        })
+    }
+
+    /// `RegCloseKey`: libera el pseudo-handle.
+    pub fn close_key(&mut self, key: isize) -> Outcome<()> {
+        match self.handles.remove(&key) {
+            Some(_) => Outcome::Done(Ok(())),
+            None => Outcome::Passthrough,
+        }
+    }
+
+    fn allocate(&mut self, hive: HiveName, path: KeyPath) -> isize {
+        let handle = self.next_handle;
+        self.next_handle += 4;
+        self.handles.insert(handle, (hive, path));
+        handle
+    }
+
+    /// Un fallo al guardar no se propaga a la aplicación: el cambio sigue
+    /// visible en memoria y se reintenta con la siguiente escritura.
+    fn persist(&mut self) {
+        if let Err(err) = self.registry.save() {
+            warn!("No se pudo guardar el registro del contenedor: {err:#}");
+        }
+    }
+}
+
+fn join(base: &KeyPath, relative: &KeyPath) -> KeyPath {
+    relative
+        .components()
+        .iter()
+        .fold(base.clone(), |path, name| path.join(name))
+}
+
+/// Copia `data` al buffer de la aplicación con la semántica de
+/// `RegQueryValueExW`: sin buffer solo se informa el tamaño y, si no cabe, se
+/// devuelve `ERROR_MORE_DATA`. El llamador escribe siempre `data.len()` en
+/// `lpcbData`.
+pub fn copy_data(data: &[u8], buffer: Option<&mut [u8]>) -> u32 {
+    match buffer {
+        None => ERROR_SUCCESS,
+        Some(buffer) if buffer.len() < data.len() => ERROR_MORE_DATA,
+        Some(buffer) => {
+            buffer[..data.len()].copy_from_slice(data);
+            ERROR_SUCCESS
+        }
+    }
+}
+
+/// Copia un nombre con su terminador a un buffer de `u16` como
+/// `RegEnumKeyExW`. Devuelve el código y los caracteres escritos (sin el
+/// terminador), que es lo que espera `lpcchName`.
+pub fn copy_name(name: &str, buffer: &mut [u16]) -> (u32, u32) {
+    let wide: Vec<u16> = name.encode_utf16().collect();
+    if buffer.len() <= wide.len() {
+        return (ERROR_MORE_DATA, buffer.len() as u32);
+    }
+    buffer[..wide.len()].copy_from_slice(&wide);
+    buffer[wide.len()] = 0;
+    (ERROR_SUCCESS, wide.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::winreg::{save_hive, RegKey, REG_DWORD, REG_SZ};
+    use std::path::Path;
+
+    const HKCU: isize = 0x8000_0001u32 as i32 as isize;
+    const HKLM: isize = 0x8000_0002u32 as i32 as isize;
+    const HKCR: isize = 0x8000_0000u32 as i32 as isize;
+
+    fn redirector(dir: &Path) -> RegistryRedirector {
+        let base_dir = dir.join("base");
+        let mut base = RegKey::default();
+        base.create(&KeyPath::parse("Vendor\\App").unwrap())
+            .values
+            .insert("Version", RegValue::Sz("1.0".into()));
+        base.create(&KeyPath::parse("Vendor\\Other").unwrap());
+        save_hive(&base_dir.join("HKLM-SW.json"), &base).unwrap();
+        RegistryRedirector::from_plan(&RegistryPlan {
+            dir: dir.join("Registry"),
+            base: Some(base_dir),
+        })
+        .unwrap()
+    }
+
+    fn done<T: std::fmt::Debug>(outcome: Outcome<T>) -> Result<T, u32> {
+        match outcome {
+            Outcome::Done(result) => result,
+            Outcome::Passthrough => panic!("se esperaba el registro virtual"),
+        }
+    }
+
+    #[test]
+    fn recognizes_sign_extended_predefined_keys() {
+        assert_eq!(Predefined::from_handle(HKCU), Some(Predefined::CurrentUser));
+        assert_eq!(Predefined::LocalMachine.handle(), HKLM);
+        assert_eq!(Predefined::from_handle(0x1c4), None);
+    }
+
+    #[test]
+    fn routes_only_virtualized_hives() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut redirector = redirector(dir.path());
+
+        assert_eq!(
+            redirector.route(HKCU, "Software\\App"),
+            Some(Ok((
+                HiveName::CurrentUser,
+                KeyPath::parse("Software\\App").unwrap()
+            )))
+        );
+        assert_eq!(
+            redirector.route(HKLM, "software\\Vendor"),
+            Some(Ok((
+                HiveName::LocalMachineSoftware,
+                KeyPath::parse("Vendor").unwrap()
+            )))
+        );
+        assert_eq!(redirector.route(HKLM, "SYSTEM\\CurrentControlSet"), None);
+        assert_eq!(redirector.route(HKLM, ""), None);
+        assert_eq!(redirector.route(HKCR, ".txt"), None);
+        assert_eq!(redirector.route(0x1c4, "Anything"), None);
+        assert_eq!(redirector.open_key(0x1c4, "Anything"), Outcome::Passthrough);
+
+        let software = done(redirector.open_key(HKLM, "Software")).unwrap();
+        assert_eq!(
+            redirector.route(software, "Vendor\\App"),
+            Some(Ok((
+                HiveName::LocalMachineSoftware,
+                KeyPath::parse("Vendor\\App").unwrap()
+            )))
+        );
+        assert_eq!(
+            redirector.route(software, "Vendor\\\\App"),
+            Some(Err(ERROR_INVALID_PARAMETER))
+        );
+    }
+
+    #[test]
+    fn opens_queries_and_enumerates_base_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut redirector = redirector(dir.path());
+
+        assert_eq!(
+            done(redirector.open_key(HKLM, "Software\\Vendor\\Missing")),
+            Err(ERROR_FILE_NOT_FOUND)
+        );
+        let app = done(redirector.open_key(HKLM, "SOFTWARE\\vendor\\app")).unwrap();
+        assert!(redirector.is_virtual(app));
+        let (kind, data) = done(redirector.query_value(app, "version")).unwrap();
+        assert_eq!(kind, REG_SZ);
+        assert_eq!(
+            RegValue::from_bytes(kind, &data).unwrap(),
+            RegValue::Sz("1.0".into())
+        );
+        assert_eq!(
+            done(redirector.query_value(app, "Missing")),
+            Err(ERROR_FILE_NOT_FOUND)
+        );
+
+        let vendor = done(redirector.open_key(HKLM, "Software\\Vendor")).unwrap();
+        assert_eq!(done(redirector.enum_key(vendor, 0)), Ok("App".to_string()));
+        assert_eq!(
+            done(redirector.enum_key(vendor, 1)),
+            Ok("Other".to_string())
+        );
+        assert_eq!(
+            done(redirector.enum_key(vendor, 2)),
+            Err(ERROR_NO_MORE_ITEMS)
+        );
+
+        assert_eq!(redirector.close_key(app), Outcome::Done(Ok(())));
+        assert!(!redirector.is_virtual(app));
+        assert_eq!(redirector.query_value(app, "Version"), Outcome::Passthrough);
+    }
+
+    #[test]
+    fn writes_go_to_the_container_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut redirector = redirector(dir.path());
+
+        let (key, disposition) =
+            done(redirector.create_key(HKCU, "Software\\App\\Settings")).unwrap();
+        assert_eq!(disposition, Disposition::CreatedNewKey);
+        let (_, disposition) = done(redirector.create_key(HKCU, "software\\app")).unwrap();
+        assert_eq!(disposition, Disposition::OpenedExistingKey);
+
+        assert_eq!(
+            done(redirector.set_value(key, "Count", REG_DWORD, &7u32.to_le_bytes())),
+            Ok(())
+        );
+        // Un REG_DWORD mal formado se conserva en crudo en lugar de fallar.
+        assert_eq!(
+            done(redirector.set_value(key, "Odd", REG_DWORD, &[1, 2])),
+            Ok(())
+        );
+
+        let reopened = RegistryRedirector::from_plan(&RegistryPlan {
+            dir: dir.path().join("Registry"),
+            base: Some(dir.path().join("base")),
+        })
+        .unwrap();
+        let settings = KeyPath::parse("Software\\App\\Settings").unwrap();
+        let hkcu = reopened.registry().hive(HiveName::CurrentUser);
+        assert_eq!(
+            hkcu.query_value(&settings, "Count"),
+            Some(&RegValue::Dword(7))
+        );
+        assert_eq!(
+            hkcu.query_value(&settings, "Odd"),
+            Some(&RegValue::Other {
+                kind: REG_DWORD,
+                data: vec![1, 2]
+            })
+        );
+        assert!(dir.path().join("Registry/HKCU.json").exists());
+    }
+
+    #[test]
+    fn delete_key_follows_reg_delete_key_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut redirector = redirector(dir.path());
+
+        assert_eq!(
+            done(redirector.delete_key(HKLM, "Software\\Vendor")),
+            Err(ERROR_ACCESS_DENIED)
+        );
+        assert_eq!(
+            done(redirector.delete_key(HKLM, "Software")),
+            Err(ERROR_ACCESS_DENIED)
+        );
+        assert_eq!(
+            done(redirector.delete_key(HKLM, "Software\\Vendor\\Nope")),
+            Err(ERROR_FILE_NOT_FOUND)
+        );
+        assert_eq!(
+            done(redirector.delete_key(HKLM, "Software\\Vendor\\App")),
+            Ok(())
+        );
+        assert_eq!(
+            done(redirector.open_key(HKLM, "Software\\Vendor\\App")),
+            Err(ERROR_FILE_NOT_FOUND)
+        );
+        assert_eq!(
+            redirector.delete_key(HKLM, "SYSTEM\\Foo"),
+            Outcome::Passthrough
+        );
+
+        // La base no se toca: el borrado es un marcador en la capa.
+        let base = crate::winreg::load_hive(&dir.path().join("base/HKLM-SW.json")).unwrap();
+        assert!(base.key(&KeyPath::parse("Vendor\\App").unwrap()).is_some());
+    }
+
+    #[test]
+    fn stale_handles_report_invalid_handle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut redirector = redirector(dir.path());
+
+        let (key, _) = done(redirector.create_key(HKCU, "Software\\Temp")).unwrap();
+        assert_eq!(done(redirector.delete_key(HKCU, "Software\\Temp")), Ok(()));
+        assert_eq!(
+            done(redirector.query_value(key, "")),
+            Err(ERROR_INVALID_HANDLE)
+        );
+        assert_eq!(
+            done(redirector.set_value(key, "", REG_SZ, &[])),
+            Err(ERROR_INVALID_HANDLE)
+        );
+        assert_eq!(done(redirector.enum_key(key, 0)), Err(ERROR_INVALID_HANDLE));
+    }
+
+    #[test]
+    fn buffers_follow_win32_size_protocol() {
+        let data = RegValue::Sz("abc".into()).to_bytes();
+        assert_eq!(copy_data(&data, None), ERROR_SUCCESS);
+        let mut small = [0u8; 4];
+        assert_eq!(copy_data(&data, Some(&mut small)), ERROR_MORE_DATA);
+        let mut exact = [0u8; 8];
+        assert_eq!(copy_data(&data, Some(&mut exact)), ERROR_SUCCESS);
+        assert_eq!(exact.to_vec(), data);
+
+        let mut name = [0xffffu16; 4];
+        assert_eq!(copy_name("Vendor", &mut name), (ERROR_MORE_DATA, 4));
+        let mut name = [0xffffu16; 7];
+        assert_eq!(copy_name("Vendor", &mut name), (ERROR_SUCCESS, 6));
+        assert_eq!(name[6], 0);
+    }
+}
@@ -1,38 +1,376 @@
-use crate::runtime::{HookPlan, PathRedirect};
+use super::{
+    files::{FileApi, PathRedirector, FILE_APIS},
+    registry::{copy_data, copy_name, Outcome, RegistryRedirector},
+};
+use crate::runtime::HookPlan;
 use anyhow::{Context, Result};
 use detour::static_detour;
 use once_cell::sync::OnceCell;
 use std::{
-    ffi::OsString,
+    ffi::{c_void, OsString},
     os::windows::ffi::OsStringExt,
-    path::{Path, PathBuf},
-    sync::RwLock,
+    path::PathBuf,
+    sync::{Mutex, RwLock},
 };
 use tracing::{info, warn};
 use widestring::U16CString;
 use windows::{
-    core::PCWSTR,
+    core::{GUID, HRESULT, PCSTR, PCWSTR, PWSTR},
     Win32::{
-        Foundation::HANDLE,
+        Foundation::{
+            BOOL, ERROR_INVALID_PARAMETER, ERROR_SUCCESS, FILETIME, HANDLE, NTSTATUS, WIN32_ERROR,
+        },
         Security::SECURITY_ATTRIBUTES,
-        Storage::FileSystem::{CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_MODE},
-        System::SystemServices::GENERIC_ACCESS_RIGHTS,
+        System::Registry::{
+            HKEY, REG_CREATE_KEY_DISPOSITION, REG_OPEN_CREATE_OPTIONS, REG_SAM_FLAGS,
+            REG_VALUE_TYPE,
+        },
     },
 };
 
+// Declaradas a mano para obtener punteros a función con la firma exacta que
+// reemplazan los detours. Los flags de las APIs de archivos van como `u32`: el
+// detour no los interpreta.
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateFileW(
+        name: PCWSTR,
+        access: u32,
+        share: u32,
+        security_attributes: *const SECURITY_ATTRIBUTES,
+        disposition: u32,
+        flags: u32,
+        template: HANDLE,
+    ) -> HANDLE;
+    fn CreateFileA(
+        name: PCSTR,
+        access: u32,
+        share: u32,
+        security_attributes: *const SECURITY_ATTRIBUTES,
+        disposition: u32,
+        flags: u32,
+        template: HANDLE,
+    ) -> HANDLE;
+    fn CreateDirectoryW(name: PCWSTR, security_attributes: *const SECURITY_ATTRIBUTES) -> BOOL;
+    fn CreateDirectoryA(name: PCSTR, security_attributes: *const SECURITY_ATTRIBUTES) -> BOOL;
+    fn DeleteFileW(name: PCWSTR) -> BOOL;
+    fn DeleteFileA(name: PCSTR) -> BOOL;
+    fn MoveFileExW(existing: PCWSTR, new: PCWSTR, flags: u32) -> BOOL;
+    fn MoveFileExA(existing: PCSTR, new: PCSTR, flags: u32) -> BOOL;
+    fn FindFirstFileExW(
+        pattern: PCWSTR,
+        info_level: i32,
+        data: *mut c_void,
+        search_op: i32,
+        filter: *const c_void,
+        flags: u32,
+    ) -> HANDLE;
+    fn GetFileAttributesW(name: PCWSTR) -> u32;
+    fn GetFileAttributesExW(name: PCWSTR, info_level: i32, info: *mut c_void) -> BOOL;
+    fn GetFileAttributesExA(name: PCSTR, info_level: i32, info: *mut c_void) -> BOOL;
+    fn MultiByteToWideChar(
+        code_page: u32,
+        flags: u32,
+        source: PCSTR,
+        source_len: i32,
+        target: PWSTR,
+        target_len: i32,
+    ) -> i32;
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    fn NtCreateFile(
+        handle: *mut HANDLE,
+        access: u32,
+        object_attributes: *const ObjectAttributes,
+        io_status: *mut c_void,
+        allocation_size: *const i64,
+        attributes: u32,
+        share: u32,
+        disposition: u32,
+        options: u32,
+        ea_buffer: *const c_void,
+        ea_length: u32,
+    ) -> NTSTATUS;
+}
+
+#[link(name = "shell32")]
+extern "system" {
+    fn SHGetKnownFolderPath(
+        folder: *const GUID,
+        flags: u32,
+        token: HANDLE,
+        path: *mut PWSTR,
+    ) -> HRESULT;
+}
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoTaskMemAlloc(size: usize) -> *mut c_void;
+    fn CoTaskMemFree(ptr: *const c_void);
+}
+
+/// `UNICODE_STRING`; `length` y `max_length` en bytes.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UnicodeString {
+    length: u16,
+    max_length: u16,
+    buffer: *const u16,
+}
+
+/// `OBJECT_ATTRIBUTES`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ObjectAttributes {
+    length: u32,
+    root_directory: HANDLE,
+    object_name: *const UnicodeString,
+    attributes: u32,
+    security_descriptor: *const c_void,
+    security_quality_of_service: *const c_void,
+}
+
+const CP_ACP: u32 = 0;
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegOpenKeyExW(
+        hkey: HKEY,
+        subkey: PCWSTR,
+        options: u32,
+        sam: REG_SAM_FLAGS,
+        result: *mut HKEY,
+    ) -> WIN32_ERROR;
+    fn RegCreateKeyExW(
+        hkey: HKEY,
+        subkey: PCWSTR,
+        reserved: u32,
+        class: PCWSTR,
+        options: REG_OPEN_CREATE_OPTIONS,
+        sam: REG_SAM_FLAGS,
+        security_attributes: *const SECURITY_ATTRIBUTES,
+        result: *mut HKEY,
+        disposition: *mut REG_CREATE_KEY_DISPOSITION,
+    ) -> WIN32_ERROR;
+    fn RegQueryValueExW(
+        hkey: HKEY,
+        value_name: PCWSTR,
+        reserved: *const u32,
+        kind: *mut REG_VALUE_TYPE,
+        data: *mut u8,
+        size: *mut u32,
+    ) -> WIN32_ERROR;
+    fn RegSetValueExW(
+        hkey: HKEY,
+        value_name: PCWSTR,
+        reserved: u32,
+        kind: REG_VALUE_TYPE,
+        data: *const u8,
+        size: u32,
+    ) -> WIN32_ERROR;
+    fn RegDeleteKeyW(hkey: HKEY, subkey: PCWSTR) -> WIN32_ERROR;
+    fn RegEnumKeyExW(
+        hkey: HKEY,
+        index: u32,
+        name: PWSTR,
+        name_len: *mut u32,
+        reserved: *const u32,
+        class: PWSTR,
+        class_len: *mut u32,
+        last_write_time: *mut FILETIME,
+    ) -> WIN32_ERROR;
+    fn RegCloseKey(hkey: HKEY) -> WIN32_ERROR;
+}
+
 static_detour! {
-    static CreateFileHook: unsafe extern "system" fn(
+    static CreateFileWHook: unsafe extern "system" fn(
         PCWSTR,
-        GENERIC_ACCESS_RIGHTS,
-        FILE_SHARE_MODE,
+        u32,
+        u32,
         *const SECURITY_ATTRIBUTES,
         u32,
-        FILE_FLAGS_AND_ATTRIBUTES,
+        u32,
         HANDLE
     ) -> HANDLE;
+    static CreateFileAHook: unsafe extern "system" fn(
+        PCSTR,
+        u32,
+        u32,
+        *const SECURITY_ATTRIBUTES,
+        u32,
+        u32,
+        HANDLE
+    ) -> HANDLE;
+    static CreateDirectoryWHook: unsafe extern "system" fn(PCWSTR, *const SECURITY_ATTRIBUTES) -> BOOL;
+    static CreateDirectoryAHook: unsafe extern "system" fn(PCSTR, *const SECURITY_ATTRIBUTES) -> BOOL;
+    static DeleteFileWHook: unsafe extern "system" fn(PCWSTR) -> BOOL;
+    static DeleteFileAHook: unsafe extern "system" fn(PCSTR) -> BOOL;
+    static MoveFileExWHook: unsafe extern "system" fn(PCWSTR, PCWSTR, u32) -> BOOL;
+    static MoveFileExAHook: unsafe extern "system" fn(PCSTR, PCSTR, u32) -> BOOL;
+    static FindFirstFileExWHook: unsafe extern "system" fn(
+        PCWSTR,
+        i32,
+        *mut c_void,
+        i32,
+        *const c_void,
+        u32
+    ) -> HANDLE;
+    static GetFileAttributesWHook: unsafe extern "system" fn(PCWSTR) -> u32;
+    static GetFileAttributesExWHook: unsafe extern "system" fn(PCWSTR, i32, *mut c_void) -> BOOL;
+    static GetFileAttributesExAHook: unsafe extern "system" fn(PCSTR, i32, *mut c_void) -> BOOL;
+    static NtCreateFileHook: unsafe extern "system" fn(
+        *mut HANDLE,
+        u32,
+        *const ObjectAttributes,
+        *mut c_void,
+        *const i64,
+        u32,
+        u32,
+        u32,
+        u32,
+        *const c_void,
+        u32
+    ) -> NTSTATUS;
+    static SHGetKnownFolderPathHook: unsafe extern "system" fn(
+        *const GUID,
+        u32,
+        HANDLE,
+        *mut PWSTR
+    ) -> HRESULT;
+    static RegOpenKeyExHook: unsafe extern "system" fn(
+        HKEY,
+        PCWSTR,
+        u32,
+        REG_SAM_FLAGS,
+        *mut HKEY
+    ) -> WIN32_ERROR;
+    static RegCreateKeyExHook: unsafe extern "system" fn(
+        HKEY,
+        PCWSTR,
+        u32,
+        PCWSTR,
+        REG_OPEN_CREATE_OPTIONS,
+        REG_SAM_FLAGS,
+        *const SECURITY_ATTRIBUTES,
+        *mut HKEY,
+        *mut REG_CREATE_KEY_DISPOSITION
+    ) -> WIN32_ERROR;
+    static RegQueryValueExHook: unsafe extern "system" fn(
+        HKEY,
+        PCWSTR,
+        *const u32,
+        *mut REG_VALUE_TYPE,
+        *mut u8,
+        *mut u32
+    ) -> WIN32_ERROR;
+    static RegSetValueExHook: unsafe extern "system" fn(
+        HKEY,
+        PCWSTR,
+        u32,
+        REG_VALUE_TYPE,
+        *const u8,
+        u32
+    ) -> WIN32_ERROR;
+    static RegDeleteKeyHook: unsafe extern "system" fn(HKEY, PCWSTR) -> WIN32_ERROR;
+    static RegEnumKeyExHook: unsafe extern "system" fn(
+        HKEY,
+        u32,
+        PWSTR,
+        *mut u32,
+        *const u32,
+        PWSTR,
+        *mut u32,
+        *mut FILETIME
+    ) -> WIN32_ERROR;
+    static RegCloseKeyHook: unsafe extern "system" fn(HKEY) -> WIN32_ERROR;
 }
 
 static PLAN: OnceCell<RwLock<PlanContext>> = OnceCell::new();
+static REGISTRY: OnceCell<Mutex<Option<RegistryRedirector>>> = OnceCell::new();
+
+/// Inicializa y habilita un detour si aún no lo está.
+macro_rules! enable_hook {
+    ($hook:ident, $target:expr, $detour:expr, $api:expr) => {
+        if !$hook.is_enabled() {
+            $hook
+                .initialize($target, $detour)
+                .with_context(|| format!("No se pudo inicializar el hook {}", $api))?;
+            $hook
+                .enable()
+                .with_context(|| format!("No se pudo habilitar el hook {}", $api))?;
+        }
+    };
+}
+
+macro_rules! disable_hook {
+    ($hook:ident, $api:expr) => {
+        if $hook.is_enabled() {
+            $hook
+                .disable()
+                .with_context(|| format!("No se pudo deshabilitar el hook {}", $api))?;
+        }
+    };
+}
+
+/// Detour de una entrada de [`FILE_APIS`].
+struct FileHook {
+    api: FileApi,
+    enable: fn() -> Result<()>,
+    disable: fn() -> Result<()>,
+}
+
+macro_rules! file_hook {
+    ($api:ident, $hook:ident, $shim:ident) => {
+        FileHook {
+            api: FileApi::$api,
+            enable: || {
+                unsafe { enable_hook!($hook, $api, $shim, FileApi::$api.symbol()) };
+                Ok(())
+            },
+            disable: || {
+                unsafe { disable_hook!($hook, FileApi::$api.symbol()) };
+                Ok(())
+            },
+        }
+    };
+}
+
+/// Un detour por API de [`FILE_APIS`], en el mismo orden. Las variantes `A`
+/// llaman a la `W` original, así que se instalan después de ella.
+const FILE_HOOKS: &[FileHook] = &[
+    file_hook!(CreateFileW, CreateFileWHook, create_file_w),
+    file_hook!(CreateFileA, CreateFileAHook, create_file_a),
+    file_hook!(CreateDirectoryW, CreateDirectoryWHook, create_directory_w),
+    file_hook!(CreateDirectoryA, CreateDirectoryAHook, create_directory_a),
+    file_hook!(DeleteFileW, DeleteFileWHook, delete_file_w),
+    file_hook!(DeleteFileA, DeleteFileAHook, delete_file_a),
+    file_hook!(MoveFileExW, MoveFileExWHook, move_file_ex_w),
+    file_hook!(MoveFileExA, MoveFileExAHook, move_file_ex_a),
+    file_hook!(FindFirstFileExW, FindFirstFileExWHook, find_first_file_ex_w),
+    file_hook!(
+        GetFileAttributesW,
+        GetFileAttributesWHook,
+        get_file_attributes_w
+    ),
+    file_hook!(
+        GetFileAttributesExW,
+        GetFileAttributesExWHook,
+        get_file_attributes_ex_w
+    ),
+    file_hook!(
+        GetFileAttributesExA,
+        GetFileAttributesExAHook,
+        get_file_attributes_ex_a
+    ),
+    file_hook!(NtCreateFile, NtCreateFileHook, nt_create_file),
+    file_hook!(
+        SHGetKnownFolderPath,
+        SHGetKnownFolderPathHook,
+        sh_get_known_folder_path
+    ),
+];
 
 pub struct DetoursHookManager;
 
@@ -48,103 +386,618 @@ impl DetoursHookManager {
             *guard = PlanContext::from(plan);
         }
 
+        debug_assert!(FILE_HOOKS
+            .iter()
+            .map(|hook| hook.api)
+            .eq(FILE_APIS.iter().map(|spec| spec.api)));
+        for hook in FILE_HOOKS {
+            (hook.enable)()?;
+        }
+        info!(
+            "Hooks de archivos activados mediante Detours ({} APIs)",
+            FILE_HOOKS.len()
+        );
+
+        let Some(registry) = &plan.registry else {
+            return Ok(());
+        };
+        let redirector = RegistryRedirector::from_plan(registry).with_context(|| {
+            format!("No se pudo abrir el registro de {}", registry.dir.display())
+        })?;
+        *REGISTRY
+            .get_or_init(|| Mutex::new(None))
+            .lock()
+            .expect("lock poisoned") = Some(redirector);
+
         unsafe {
-            if !CreateFileHook.is_enabled() {
-                CreateFileHook
-                    .initialize(CreateFileW, create_file_redirect)
-                    .context("No se pudo inicializar el hook CreateFileW")?;
-                CreateFileHook
-                    .enable()
-                    .context("No se pudo habilitar el hook CreateFileW")?;
-                info!("Hook de CreateFileW activado mediante Detours");
-            }
+            enable_hook!(
+                RegOpenKeyExHook,
+                RegOpenKeyExW,
+                reg_open_key_redirect,
+                "RegOpenKeyExW"
+            );
+            enable_hook!(
+                RegCreateKeyExHook,
+                RegCreateKeyExW,
+                reg_create_key_redirect,
+                "RegCreateKeyExW"
+            );
+            enable_hook!(
+                RegQueryValueExHook,
+                RegQueryValueExW,
+                reg_query_value_redirect,
+                "RegQueryValueExW"
+            );
+            enable_hook!(
+                RegSetValueExHook,
+                RegSetValueExW,
+                reg_set_value_redirect,
+                "RegSetValueExW"
+            );
+            enable_hook!(
+                RegDeleteKeyHook,
+                RegDeleteKeyW,
+                reg_delete_key_redirect,
+                "RegDeleteKeyW"
+            );
+            enable_hook!(
+                RegEnumKeyExHook,
+                RegEnumKeyExW,
+                reg_enum_key_redirect,
+                "RegEnumKeyExW"
+            );
+            enable_hook!(
+                RegCloseKeyHook,
+                RegCloseKey,
+                reg_close_key_redirect,
+                "RegCloseKey"
+            );
         }
+        info!(
+            "Hooks del registro activados sobre {}",
+            registry.dir.display()
+        );
+
+        Ok(())
+    }
 
+    pub fn remove(&self) -> Result<()> {
+        for hook in FILE_HOOKS.iter().rev() {
+            (hook.disable)()?;
+        }
+        info!("Hooks de archivos desactivados");
+        unsafe {
+            disable_hook!(RegOpenKeyExHook, "RegOpenKeyExW");
+            disable_hook!(RegCreateKeyExHook, "RegCreateKeyExW");
+            disable_hook!(RegQueryValueExHook, "RegQueryValueExW");
+            disable_hook!(RegSetValueExHook, "RegSetValueExW");
+            disable_hook!(RegDeleteKeyHook, "RegDeleteKeyW");
+            disable_hook!(RegEnumKeyExHook, "RegEnumKeyExW");
+            disable_hook!(RegCloseKeyHook, "RegCloseKey");
+        }
+        if let Some(ctx) = PLAN.get() {
+            *ctx.write().expect("lock poisoned") = PlanContext::default();
+        }
+        if let Some(registry) = REGISTRY.get() {
+            *registry.lock().expect("lock poisoned") = None;
+        }
         Ok(())
     }
 }
 
 #[derive(Default, Clone)]
 struct PlanContext {
-    redirects: Vec<PathRedirect>,
+    paths: PathRedirector,
 }
 
 impl From<&HookPlan> for PlanContext {
     fn from(plan: &HookPlan) -> Self {
         Self {
-            redirects: plan.redirects.clone(),
+            paths: PathRedirector::new(&plan.redirects),
         }
     }
 }
 
-impl PlanContext {
-    fn rewrite(&self, input: &Path) -> Option<PathBuf> {
-        for redirect in &self.redirects {
-            if input.starts_with(&redirect.original) {
-                if let Ok(remainder) = input.strip_prefix(&redirect.original) {
-                    return Some(redirect.redirected.join(remainder));
-                }
-            }
+/// Única decisión de los detours de archivos: la ruta reescrita para el
+/// argumento `index` de `api`, o `None` si la llamada sigue intacta.
+fn redirect(api: FileApi, index: usize, path: &str) -> Option<U16CString> {
+    if path.is_empty() {
+        return None;
+    }
+    let rewritten = PLAN
+        .get()
+        .and_then(|cell| cell.read().ok())
+        .and_then(|ctx| ctx.paths.redirect(api, index, path))?;
+    match U16CString::from_str(&rewritten) {
+        Ok(wide) => Some(wide),
+        Err(_) => {
+            warn!("No se pudo convertir la ruta redirigida {rewritten:?}");
+            None
         }
-        None
     }
 }
 
-unsafe extern "system" fn create_file_redirect(
-    file_name: PCWSTR,
-    desired_access: GENERIC_ACCESS_RIGHTS,
-    share_mode: FILE_SHARE_MODE,
+fn redirect_wide(api: FileApi, index: usize, value: PCWSTR) -> Option<U16CString> {
+    redirect(api, index, &pcwstr_to_string(value))
+}
+
+/// Para las variantes ANSI: la ruta en UTF-16 (redirigida o no) si alguno de
+/// los argumentos se redirige, para llamar a la variante `W` original.
+fn redirect_ansi<const N: usize>(
+    api: FileApi,
+    values: [PCSTR; N],
+) -> Option<[Option<U16CString>; N]> {
+    let paths = values.map(pcstr_to_string);
+    let rewritten: [Option<U16CString>; N] = std::array::from_fn(|index| {
+        paths[index]
+            .as_deref()
+            .and_then(|path| redirect(api, index, path))
+    });
+    if rewritten.iter().all(Option::is_none) {
+        return None;
+    }
+    Some(std::array::from_fn(|index| {
+        rewritten[index].clone().or_else(|| {
+            paths[index]
+                .as_deref()
+                .and_then(|path| U16CString::from_str(path).ok())
+        })
+    }))
+}
+
+fn wide_ptr(value: &Option<U16CString>) -> PCWSTR {
+    value
+        .as_ref()
+        .map_or(PCWSTR::null(), |wide| PCWSTR(wide.as_ptr()))
+}
+
+fn or_original(redirected: &Option<U16CString>, original: PCWSTR) -> PCWSTR {
+    redirected
+        .as_ref()
+        .map_or(original, |wide| PCWSTR(wide.as_ptr()))
+}
+
+unsafe extern "system" fn create_file_w(
+    name: PCWSTR,
+    access: u32,
+    share: u32,
     security_attributes: *const SECURITY_ATTRIBUTES,
-    creation_disposition: u32,
-    flags: FILE_FLAGS_AND_ATTRIBUTES,
-    template_file: HANDLE,
+    disposition: u32,
+    flags: u32,
+    template: HANDLE,
 ) -> HANDLE {
-    let path = pcwstr_to_path(file_name);
-    if path.as_os_str().is_empty() {
-        return CreateFileHook.call(
-            file_name,
-            desired_access,
-            share_mode,
+    let redirected = redirect_wide(FileApi::CreateFileW, 0, name);
+    CreateFileWHook.call(
+        or_original(&redirected, name),
+        access,
+        share,
+        security_attributes,
+        disposition,
+        flags,
+        template,
+    )
+}
+
+unsafe extern "system" fn create_file_a(
+    name: PCSTR,
+    access: u32,
+    share: u32,
+    security_attributes: *const SECURITY_ATTRIBUTES,
+    disposition: u32,
+    flags: u32,
+    template: HANDLE,
+) -> HANDLE {
+    match redirect_ansi(FileApi::CreateFileA, [name]) {
+        Some([wide]) => CreateFileWHook.call(
+            wide_ptr(&wide),
+            access,
+            share,
             security_attributes,
-            creation_disposition,
+            disposition,
             flags,
-            template_file,
-        );
+            template,
+        ),
+        None => CreateFileAHook.call(
+            name,
+            access,
+            share,
+            security_attributes,
+            disposition,
+            flags,
+            template,
+        ),
     }
+}
 
-    let maybe_redirect = PLAN
-        .get()
-        .and_then(|cell| cell.read().ok())
-        .and_then(|ctx| ctx.rewrite(&path));
-
-    if let Some(redirected) = maybe_redirect {
-        if let Ok(wide) = U16CString::from_os_str(redirected.as_os_str()) {
-            let new_ptr = PCWSTR(wide.as_ptr());
-            return CreateFileHook.call(
-                new_ptr,
-                desired_access,
-                share_mode,
-                security_attributes,
-                creation_disposition,
-                flags,
-                template_file,
-            );
-        } else {
-            warn!("No se pudo convertir la ruta redirigida {:?}", redirected);
-        }
+unsafe extern "system" fn create_directory_w(
+    name: PCWSTR,
+    security_attributes: *const SECURITY_ATTRIBUTES,
+) -> BOOL {
+    let redirected = redirect_wide(FileApi::CreateDirectoryW, 0, name);
+    CreateDirectoryWHook.call(or_original(&redirected, name), security_attributes)
+}
+
+unsafe extern "system" fn create_directory_a(
+    name: PCSTR,
+    security_attributes: *const SECURITY_ATTRIBUTES,
+) -> BOOL {
+    match redirect_ansi(FileApi::CreateDirectoryA, [name]) {
+        Some([wide]) => CreateDirectoryWHook.call(wide_ptr(&wide), security_attributes),
+        None => CreateDirectoryAHook.call(name, security_attributes),
     }
+}
 
-    CreateFileHook.call(
-        file_name,
-        desired_access,
-        share_mode,
-        security_attributes,
-        creation_disposition,
+unsafe extern "system" fn delete_file_w(name: PCWSTR) -> BOOL {
+    let redirected = redirect_wide(FileApi::DeleteFileW, 0, name);
+    DeleteFileWHook.call(or_original(&redirected, name))
+}
+
+unsafe extern "system" fn delete_file_a(name: PCSTR) -> BOOL {
+    match redirect_ansi(FileApi::DeleteFileA, [name]) {
+        Some([wide]) => DeleteFileWHook.call(wide_ptr(&wide)),
+        None => DeleteFileAHook.call(name),
+    }
+}
+
+unsafe extern "system" fn move_file_ex_w(existing: PCWSTR, new: PCWSTR, flags: u32) -> BOOL {
+    let redirected_existing = redirect_wide(FileApi::MoveFileExW, 0, existing);
+    let redirected_new = redirect_wide(FileApi::MoveFileExW, 1, new);
+    MoveFileExWHook.call(
+        or_original(&redirected_existing, existing),
+        or_original(&redirected_new, new),
+        flags,
+    )
+}
+
+unsafe extern "system" fn move_file_ex_a(existing: PCSTR, new: PCSTR, flags: u32) -> BOOL {
+    match redirect_ansi(FileApi::MoveFileExA, [existing, new]) {
+        Some([existing, new]) => MoveFileExWHook.call(wide_ptr(&existing), wide_ptr(&new), flags),
+        None => MoveFileExAHook.call(existing, new, flags),
+    }
+}
+
+unsafe extern "system" fn find_first_file_ex_w(
+    pattern: PCWSTR,
+    info_level: i32,
+    data: *mut c_void,
+    search_op: i32,
+    filter: *const c_void,
+    flags: u32,
+) -> HANDLE {
+    let redirected = redirect_wide(FileApi::FindFirstFileExW, 0, pattern);
+    FindFirstFileExWHook.call(
+        or_original(&redirected, pattern),
+        info_level,
+        data,
+        search_op,
+        filter,
         flags,
-        template_file,
     )
 }
 
+unsafe extern "system" fn get_file_attributes_w(name: PCWSTR) -> u32 {
+    let redirected = redirect_wide(FileApi::GetFileAttributesW, 0, name);
+    GetFileAttributesWHook.call(or_original(&redirected, name))
+}
+
+unsafe extern "system" fn get_file_attributes_ex_w(
+    name: PCWSTR,
+    info_level: i32,
+    info: *mut c_void,
+) -> BOOL {
+    let redirected = redirect_wide(FileApi::GetFileAttributesExW, 0, name);
+    GetFileAttributesExWHook.call(or_original(&redirected, name), info_level, info)
+}
+
+unsafe extern "system" fn get_file_attributes_ex_a(
+    name: PCSTR,
+    info_level: i32,
+    info: *mut c_void,
+) -> BOOL {
+    match redirect_ansi(FileApi::GetFileAttributesExA, [name]) {
+        Some([wide]) => GetFileAttributesExWHook.call(wide_ptr(&wide), info_level, info),
+        None => GetFileAttributesExAHook.call(name, info_level, info),
+    }
+}
+
+/// Solo se reescriben nombres absolutos (`RootDirectory` nulo); los relativos
+/// a un handle ya abierto heredan la redirección de ese handle.
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn nt_create_file(
+    handle: *mut HANDLE,
+    access: u32,
+    object_attributes: *const ObjectAttributes,
+    io_status: *mut c_void,
+    allocation_size: *const i64,
+    attributes: u32,
+    share: u32,
+    disposition: u32,
+    options: u32,
+    ea_buffer: *const c_void,
+    ea_length: u32,
+) -> NTSTATUS {
+    let redirected = object_attributes
+        .as_ref()
+        .filter(|attrs| attrs.root_directory.0 == 0)
+        .and_then(|attrs| attrs.object_name.as_ref().map(|name| (attrs, name)))
+        .and_then(|(attrs, name)| {
+            let units = std::slice::from_raw_parts(name.buffer, name.length as usize / 2);
+            let path = String::from_utf16_lossy(units);
+            redirect(FileApi::NtCreateFile, 2, &path).map(|wide| (*attrs, wide))
+        });
+    let Some((mut attrs, wide)) = redirected else {
+        return NtCreateFileHook.call(
+            handle,
+            access,
+            object_attributes,
+            io_status,
+            allocation_size,
+            attributes,
+            share,
+            disposition,
+            options,
+            ea_buffer,
+            ea_length,
+        );
+    };
+    let bytes = (wide.len() * 2) as u16;
+    let name = UnicodeString {
+        length: bytes,
+        max_length: bytes,
+        buffer: wide.as_ptr(),
+    };
+    attrs.object_name = &name;
+    NtCreateFileHook.call(
+        handle,
+        access,
+        &attrs,
+        io_status,
+        allocation_size,
+        attributes,
+        share,
+        disposition,
+        options,
+        ea_buffer,
+        ea_length,
+    )
+}
+
+/// La carpeta conocida sale del host; si cae dentro de una redirección se
+/// sustituye por una copia asignada con `CoTaskMemAlloc`, como espera el
+/// llamador al liberarla.
+unsafe extern "system" fn sh_get_known_folder_path(
+    folder: *const GUID,
+    flags: u32,
+    token: HANDLE,
+    path: *mut PWSTR,
+) -> HRESULT {
+    let result = SHGetKnownFolderPathHook.call(folder, flags, token, path);
+    if result.is_err() || path.is_null() || (*path).is_null() {
+        return result;
+    }
+    let original = PCWSTR((*path).0);
+    let Some(wide) = redirect_wide(FileApi::SHGetKnownFolderPath, 3, original) else {
+        return result;
+    };
+    let units = wide.as_slice_with_nul();
+    let copy = CoTaskMemAlloc(std::mem::size_of_val(units)) as *mut u16;
+    if copy.is_null() {
+        return result;
+    }
+    std::ptr::copy_nonoverlapping(units.as_ptr(), copy, units.len());
+    CoTaskMemFree(original.0 as *const c_void);
+    *path = PWSTR(copy);
+    result
+}
+
+/// Ejecuta una decisión del redirector; sin registro virtual todo pasa al host.
+fn with_registry<T>(decide: impl FnOnce(&mut RegistryRedirector) -> Outcome<T>) -> Outcome<T> {
+    let Some(cell) = REGISTRY.get() else {
+        return Outcome::Passthrough;
+    };
+    match cell.lock() {
+        Ok(mut guard) => guard.as_mut().map_or(Outcome::Passthrough, decide),
+        Err(_) => Outcome::Passthrough,
+    }
+}
+
+fn win32(result: Result<(), u32>) -> WIN32_ERROR {
+    match result {
+        Ok(()) => ERROR_SUCCESS,
+        Err(code) => WIN32_ERROR(code),
+    }
+}
+
+unsafe extern "system" fn reg_open_key_redirect(
+    hkey: HKEY,
+    subkey: PCWSTR,
+    options: u32,
+    sam: REG_SAM_FLAGS,
+    result: *mut HKEY,
+) -> WIN32_ERROR {
+    let name = pcwstr_to_string(subkey);
+    match with_registry(|registry| registry.open_key(hkey.0, &name)) {
+        Outcome::Passthrough => RegOpenKeyExHook.call(hkey, subkey, options, sam, result),
+        Outcome::Done(done) => win32(done.and_then(|handle| {
+            if result.is_null() {
+                return Err(ERROR_INVALID_PARAMETER.0);
+            }
+            *result = HKEY(handle);
+            Ok(())
+        })),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn reg_create_key_redirect(
+    hkey: HKEY,
+    subkey: PCWSTR,
+    reserved: u32,
+    class: PCWSTR,
+    options: REG_OPEN_CREATE_OPTIONS,
+    sam: REG_SAM_FLAGS,
+    security_attributes: *const SECURITY_ATTRIBUTES,
+    result: *mut HKEY,
+    disposition: *mut REG_CREATE_KEY_DISPOSITION,
+) -> WIN32_ERROR {
+    let name = pcwstr_to_string(subkey);
+    match with_registry(|registry| registry.create_key(hkey.0, &name)) {
+        Outcome::Passthrough => RegCreateKeyExHook.call(
+            hkey,
+            subkey,
+            reserved,
+            class,
+            options,
+            sam,
+            security_attributes,
+            result,
+            disposition,
+        ),
+        Outcome::Done(done) => win32(done.and_then(|(handle, created)| {
+            if result.is_null() {
+                return Err(ERROR_INVALID_PARAMETER.0);
+            }
+            *result = HKEY(handle);
+            if !disposition.is_null() {
+                *disposition = REG_CREATE_KEY_DISPOSITION(created as u32);
+            }
+            Ok(())
+        })),
+    }
+}
+
+unsafe extern "system" fn reg_query_value_redirect(
+    hkey: HKEY,
+    value_name: PCWSTR,
+    reserved: *const u32,
+    kind: *mut REG_VALUE_TYPE,
+    data: *mut u8,
+    size: *mut u32,
+) -> WIN32_ERROR {
+    let name = pcwstr_to_string(value_name);
+    match with_registry(|registry| registry.query_value(hkey.0, &name)) {
+        Outcome::Passthrough => {
+            RegQueryValueExHook.call(hkey, value_name, reserved, kind, data, size)
+        }
+        Outcome::Done(Err(code)) => WIN32_ERROR(code),
+        Outcome::Done(Ok((value_kind, bytes))) => {
+            if !kind.is_null() {
+                *kind = REG_VALUE_TYPE(value_kind);
+            }
+            if size.is_null() {
+                return if data.is_null() {
+                    ERROR_SUCCESS
+                } else {
+                    ERROR_INVALID_PARAMETER
+                };
+            }
+            let buffer =
+                (!data.is_null()).then(|| std::slice::from_raw_parts_mut(data, *size as usize));
+            let code = copy_data(&bytes, buffer);
+            *size = bytes.len() as u32;
+            WIN32_ERROR(code)
+        }
+    }
+}
+
+unsafe extern "system" fn reg_set_value_redirect(
+    hkey: HKEY,
+    value_name: PCWSTR,
+    reserved: u32,
+    kind: REG_VALUE_TYPE,
+    data: *const u8,
+    size: u32,
+) -> WIN32_ERROR {
+    let name = pcwstr_to_string(value_name);
+    let bytes = if data.is_null() {
+        &[][..]
+    } else {
+        std::slice::from_raw_parts(data, size as usize)
+    };
+    match with_registry(|registry| registry.set_value(hkey.0, &name, kind.0, bytes)) {
+        Outcome::Passthrough => {
+            RegSetValueExHook.call(hkey, value_name, reserved, kind, data, size)
+        }
+        Outcome::Done(done) => win32(done),
+    }
+}
+
+unsafe extern "system" fn reg_delete_key_redirect(hkey: HKEY, subkey: PCWSTR) -> WIN32_ERROR {
+    let name = pcwstr_to_string(subkey);
+    match with_registry(|registry| registry.delete_key(hkey.0, &name)) {
+        Outcome::Passthrough => RegDeleteKeyHook.call(hkey, subkey),
+        Outcome::Done(done) => win32(done),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "system" fn reg_enum_key_redirect(
+    hkey: HKEY,
+    index: u32,
+    name: PWSTR,
+    name_len: *mut u32,
+    reserved: *const u32,
+    class: PWSTR,
+    class_len: *mut u32,
+    last_write_time: *mut FILETIME,
+) -> WIN32_ERROR {
+    match with_registry(|registry| registry.enum_key(hkey.0, index)) {
+        Outcome::Passthrough => RegEnumKeyExHook.call(
+            hkey,
+            index,
+            name,
+            name_len,
+            reserved,
+            class,
+            class_len,
+            last_write_time,
+        ),
+        Outcome::Done(Err(code)) => WIN32_ERROR(code),
+        Outcome::Done(Ok(subkey)) => {
+            if name.is_null() || name_len.is_null() {
+                return ERROR_INVALID_PARAMETER;
+            }
+            let buffer = std::slice::from_raw_parts_mut(name.0, *name_len as usize);
+            let (code, written) = copy_name(&subkey, buffer);
+            if code == 0 {
+                *name_len = written;
+                if !class_len.is_null() {
+                    *class_len = 0;
+                }
+            }
+            WIN32_ERROR(code)
+        }
+    }
+}
+
+unsafe extern "system" fn reg_close_key_redirect(hkey: HKEY) -> WIN32_ERROR {
+    match with_registry(|registry| registry.close_key(hkey.0)) {
+        Outcome::Passthrough => RegCloseKeyHook.call(hkey),
+        Outcome::Done(done) => win32(done),
+    }
+}
+
+/// Convierte una cadena ANSI con la página de códigos activa; `None` si es nula.
+fn pcstr_to_string(value: PCSTR) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    unsafe {
+        let len = MultiByteToWideChar(CP_ACP, 0, value, -1, PWSTR::null(), 0);
+        if len <= 0 {
+            return None;
+        }
+        let mut units = vec![0u16; len as usize];
+        MultiByteToWideChar(CP_ACP, 0, value, -1, PWSTR(units.as_mut_ptr()), len);
+        units.pop();
+        Some(String::from_utf16_lossy(&units))
+    }
+}
+
+fn pcwstr_to_string(value: PCWSTR) -> String {
+    pcwstr_to_path(value).to_string_lossy().into_owned()
+}
+
 fn pcwstr_to_path(value: PCWSTR) -> PathBuf {
     if value.is_null() {
         return PathBuf::new();
@@ -0,0 +1,263 @@
+//! Formato con el que el agent entrega el [`HookPlan`] al runtime inyectado en
+//! cada proceso del contenedor.
+//!
+//! El agent escribe el plan en `<plans_dir>/<id>.bin`, una carpeta suya fuera
+//! del contenedor, y pasa la ruta en [`PLAN_ENV`]. El launcher inyecta
+//! [`RUNTIME_LIBRARY`] en el proceso y llama a [`RUNTIME_INIT`], que lee el plan
+//! y activa los hooks con él, de modo que cada proceso usa el plan de su
+//! contenedor.
+//!
+//! ```text
+//! 0   magic "CTHP"
+//! 4   versión (u16 LE)
+//! 6   tamaño de la cabecera (u16 LE, >= 16; lo que sobre se ignora)
+//! 8   tamaño del plan (u32 LE, <= MAX_PLAN_SIZE)
+//! 12  FNV-1a de 32 bits del plan (u32 LE)
+//! 16  plan en JSON (UTF-8)
+//! ```
+//!
+//! Compatibilidad: dentro de una versión solo se añaden campos con
+//! `#[serde(default)]` y los desconocidos se ignoran, así que un runtime más
+//! antiguo lee planes nuevos y al revés. Un cambio incompatible sube
+//! [`WIRE_VERSION`]; el runtime rechaza versiones que no conoce.
+
+use crate::runtime::HookPlan;
+use anyhow::{bail, ensure, Context, Result};
+use std::path::Path;
+
+/// Variable de entorno con la ruta del plan en el proceso del contenedor.
+pub const PLAN_ENV: &str = "CTNR_HOOK_PLAN";
+/// Biblioteca del runtime (crate `hook-runtime`), instalada junto al ejecutable
+/// del agent.
+pub const RUNTIME_LIBRARY: &str = "ctnr_hooks.dll";
+/// Función que exporta el runtime: `extern "system" fn(*mut c_void) -> u32`.
+/// Devuelve [`INIT_OK`], [`INIT_NO_PLAN`] o [`INIT_FAILED`].
+pub const RUNTIME_INIT: &str = "ctnr_hook_init";
+pub const INIT_OK: u32 = 0;
+/// El proceso no trae [`PLAN_ENV`].
+pub const INIT_NO_PLAN: u32 = 1;
+/// El plan no se pudo leer o aplicar; el detalle va a stderr del proceso.
+pub const INIT_FAILED: u32 = 2;
+
+pub const WIRE_MAGIC: [u8; 4] = *b"CTHP";
+pub const WIRE_VERSION: u16 = 1;
+pub const HEADER_SIZE: usize = 16;
+/// Límite del plan codificado; el runtime no reserva más memoria que esto.
+pub const MAX_PLAN_SIZE: usize = 1024 * 1024;
+
+pub fn encode(plan: &HookPlan) -> Result<Vec<u8>> {
+    let payload = serde_json::to_vec(plan).context("No se pudo serializar el plan de hooks")?;
+    ensure!(
+        payload.len() <= MAX_PLAN_SIZE,
+        "El plan de hooks ocupa {} bytes (máximo {MAX_PLAN_SIZE})",
+        payload.len()
+    );
+    let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+    data.extend_from_slice(&WIRE_MAGIC);
+    data.extend_from_slice(&WIRE_VERSION.to_le_bytes());
+    data.extend_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(&fnv1a(&payload).to_le_bytes());
+    data.extend_from_slice(&payload);
+    Ok(data)
+}
+
+pub fn decode(data: &[u8]) -> Result<HookPlan> {
+    ensure!(data.len() >= HEADER_SIZE, "Plan de hooks truncado");
+    ensure!(data[..4] == WIRE_MAGIC, "No es un plan de hooks");
+    let version = u16::from_le_bytes([data[4], data[5]]);
+    if version == 0 || version > WIRE_VERSION {
+        bail!("Plan de hooks en versión {version}; este runtime entiende hasta la {WIRE_VERSION}");
+    }
+    let header_size = u16::from_le_bytes([data[6], data[7]]) as usize;
+    ensure!(
+        header_size >= HEADER_SIZE,
+        "Cabecera del plan de hooks inválida ({header_size} bytes)"
+    );
+    let len = u32::from_le_bytes(data[8..12].try_into().expect("4 bytes")) as usize;
+    ensure!(
+        len <= MAX_PLAN_SIZE,
+        "El plan de hooks declara {len} bytes (máximo {MAX_PLAN_SIZE})"
+    );
+    let payload = data
+        .get(header_size..)
+        .and_then(|rest| rest.get(..len))
+        .context("Plan de hooks truncado")?;
+    let checksum = u32::from_le_bytes(data[12..16].try_into().expect("4 bytes"));
+    ensure!(
+        fnv1a(payload) == checksum,
+        "Checksum del plan de hooks incorrecto"
+    );
+    serde_json::from_slice(payload).context("Plan de hooks inválido")
+}
+
+/// Escribe el plan a un archivo temporal y lo renombra, para que un proceso que
+/// arranca a la vez nunca lea uno a medias.
+pub fn write_plan(path: &Path, plan: &HookPlan) -> Result<()> {
+    let data = encode(plan)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("bin.tmp");
+    std::fs::write(&tmp, data).with_context(|| format!("No se pudo guardar {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("No se pudo guardar {}", path.display()))
+}
+
+pub fn read_plan(path: &Path) -> Result<HookPlan> {
+    let size = std::fs::metadata(path)
+        .with_context(|| format!("No se pudo leer {}", path.display()))?
+        .len();
+    ensure!(
+        size <= (HEADER_SIZE + MAX_PLAN_SIZE) as u64,
+        "{} ocupa {size} bytes; no es un plan de hooks",
+        path.display()
+    );
+    let data =
+        std::fs::read(path).with_context(|| format!("No se pudo leer {}", path.display()))?;
+    decode(&data).with_context(|| format!("Plan de hooks inválido en {}", path.display()))
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        overlay::{Layer, LayerKind, LayerStack},
+        runtime::{MountPlan, PathRedirect, RegistryPlan},
+    };
+    use std::{collections::HashMap, path::PathBuf};
+
+    fn plan() -> HookPlan {
+        HookPlan {
+            env: HashMap::from([("TEMP".into(), r"D:\ctnr\demo\temp".into())]),
+            mounts: vec![MountPlan {
+                alias: "%TEMP%".into(),
+                host_path: PathBuf::from(r"D:\ctnr\demo\temp"),
+            }],
+            redirects: vec![PathRedirect {
+                variable: "TEMP".into(),
+                original: PathBuf::from(r"C:\Users\ana\AppData\Local\Temp"),
+                redirected: PathBuf::from(r"D:\ctnr\demo\temp"),
+            }],
+            overlay: LayerStack::new(Layer::new(
+                "demo",
+                LayerKind::Container,
+                r"D:\ctnr\demo\rootfs\ProgramFiles",
+            )),
+            registry: Some(RegistryPlan {
+                dir: PathBuf::from(r"D:\ctnr\demo\user\Registry"),
+                base: None,
+            }),
+        }
+    }
+
+    /// Arma un plan a mano, byte a byte, como lo escribiría un agent con esa
+    /// versión y ese tamaño de cabecera.
+    fn frame(version: u16, header_size: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = b"CTHP".to_vec();
+        data.extend_from_slice(&version.to_le_bytes());
+        data.extend_from_slice(&header_size.to_le_bytes());
+        data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        data.extend_from_slice(&fnv1a(payload).to_le_bytes());
+        data.resize(header_size as usize, 0);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// Plan de la versión 1 anterior al registro virtual.
+    const V1_WITHOUT_REGISTRY: &str = r#"{
+        "env": {"TEMP": "D:\\ctnr\\demo\\temp"},
+        "mounts": [{"alias": "%TEMP%", "host_path": "D:\\ctnr\\demo\\temp"}],
+        "redirects": [{
+            "variable": "TEMP",
+            "original": "C:\\Users\\ana\\AppData\\Local\\Temp",
+            "redirected": "D:\\ctnr\\demo\\temp"
+        }],
+        "overlay": {
+            "upper": {"name": "demo", "kind": "container", "root": "D:\\ctnr\\demo\\rootfs\\ProgramFiles"},
+            "lowers": []
+        }
+    }"#;
+
+    #[test]
+    fn round_trips_a_plan() {
+        let plan = plan();
+        let data = encode(&plan).unwrap();
+        assert_eq!(&data[..4], b"CTHP");
+        assert_eq!(u16::from_le_bytes([data[4], data[5]]), WIRE_VERSION);
+        assert_eq!(decode(&data).unwrap(), plan);
+    }
+
+    #[test]
+    fn reads_version_1_plans_written_before_the_registry() {
+        let decoded = decode(&frame(1, 16, V1_WITHOUT_REGISTRY.as_bytes())).unwrap();
+        assert_eq!(
+            decoded,
+            HookPlan {
+                registry: None,
+                ..plan()
+            }
+        );
+    }
+
+    #[test]
+    fn ignores_unknown_fields_and_longer_headers() {
+        let mut payload: serde_json::Value = serde_json::to_value(plan()).unwrap();
+        payload["future_field"] = serde_json::json!({"enabled": true});
+        payload["registry"]["future_option"] = serde_json::json!(3);
+        let payload = serde_json::to_vec(&payload).unwrap();
+        assert_eq!(decode(&frame(1, 24, &payload)).unwrap(), plan());
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_damaged_data() {
+        let payload = serde_json::to_vec(&plan()).unwrap();
+        let err = decode(&frame(2, 16, &payload)).unwrap_err();
+        assert!(err.to_string().contains("versión 2"), "{err}");
+        assert!(decode(&frame(0, 16, &payload)).is_err());
+        assert!(decode(&frame(1, 8, &payload)).is_err());
+
+        let data = encode(&plan()).unwrap();
+        assert!(decode(&data[..10]).is_err());
+        assert!(decode(&data[..data.len() - 1]).is_err());
+
+        let mut magic = data.clone();
+        magic[0] = b'X';
+        assert!(decode(&magic).is_err());
+
+        let mut flipped = data.clone();
+        let last = flipped.len() - 2;
+        flipped[last] ^= 0x20;
+        let err = decode(&flipped).unwrap_err();
+        assert!(err.to_string().contains("Checksum"), "{err}");
+    }
+
+    #[test]
+    fn enforces_the_size_limit() {
+        let mut big = plan();
+        big.env.insert("HUGE".into(), "x".repeat(MAX_PLAN_SIZE));
+        assert!(encode(&big).is_err());
+
+        let mut data = frame(1, 16, b"{}");
+        data[8..12].copy_from_slice(&(MAX_PLAN_SIZE as u32 + 1).to_le_bytes());
+        let err = decode(&data).unwrap_err();
+        assert!(err.to_string().contains("máximo"), "{err}");
+    }
+
+    #[test]
+    fn writes_and_reads_plan_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hook-plans/demo.bin");
+        write_plan(&path, &plan()).unwrap();
+        assert_eq!(read_plan(&path).unwrap(), plan());
+        assert!(!path.with_extension("bin.tmp").exists());
+
+        std::fs::write(&path, vec![0u8; HEADER_SIZE + MAX_PLAN_SIZE + 1]).unwrap();
+        assert!(read_plan(&path).is_err());
+    }
+}
@@ -0,0 +1,1597 @@
+use crate::{
+    config::AgentConfig,
+    control::{ContainerSummary, ControlCommand, ControlRequest, ControlResponse},
+    launcher::{self, LaunchRequest},
+    logs::ContainerLog,
+    mount::{
+        BindProvider, MountProvider, MountProviders, MountRequest, MountSet, MountState,
+        MountStatus,
+    },
+    mount_points::{DriveProbe, MountPointAllocator},
+    registry::{
+        load_manifest, LoadDiagnostic, RegisteredContainer, RegistryEvent, DEFAULT_PROFILE,
+    },
+    runtime::{HookEngine, HookPlan, MountPlan},
+    runtimes::RuntimeStore,
+    services::ServiceSandbox,
+    supervisor::{RunRecord, RunSpec, Supervisor, DEFAULT_STOP_TIMEOUT},
+};
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+/// Estado que el agent mantiene por cada contenedor preparado.
+struct ActiveContainer {
+    container: RegisteredContainer,
+    plan: HookPlan,
+    log: ContainerLog,
+    /// Raíz del contenedor y entradas de su `MountPlan`, montadas.
+    mount: Option<MountedVolumes>,
+    /// Pendiente de volver a montar: el montaje (opcional) falló o dejó de responder.
+    remount: Option<Remount>,
+}
+
+impl ActiveContainer {
+    fn mount_status(&self) -> Option<MountStatus> {
+        if let Some(mounted) = &self.mount {
+            return Some(MountStatus {
+                state: MountState::Mounted,
+                provider: mounted.provider.map(str::to_string),
+                mount_point: mounted.mount_point.clone(),
+                attempts: 0,
+                error: None,
+            });
+        }
+        self.remount.as_ref().map(|remount| MountStatus {
+            state: MountState::Remounting,
+            provider: None,
+            mount_point: None,
+            attempts: remount.attempts,
+            error: Some(remount.error.clone()),
+        })
+    }
+}
+
+/// Volúmenes montados de un contenedor. El `MountSet` se comparte con la
+/// comprobación en segundo plano; proveedor y punto de montaje de la raíz se
+/// copian para informar sin esperarla.
+struct MountedVolumes {
+    set: Arc<tokio::sync::Mutex<MountSet>>,
+    provider: Option<&'static str>,
+    mount_point: Option<PathBuf>,
+}
+
+impl MountedVolumes {
+    fn new(set: MountSet) -> Self {
+        let root = set.root();
+        Self {
+            provider: root.map(|session| session.provider()),
+            mount_point: root.map(|session| session.mount_point.clone()),
+            set: Arc::new(tokio::sync::Mutex::new(set)),
+        }
+    }
+
+    async fn unmount(&self) -> Result<()> {
+        self.set.lock().await.unmount().await
+    }
+}
+
+struct Remount {
+    /// Intentos fallidos desde el último montaje correcto.
+    attempts: u32,
+    next_attempt: Instant,
+    error: String,
+}
+
+/// Resultado de una pasada de [`AgentHost::check_mounts`].
+pub struct MountCheck {
+    results: Vec<(String, CheckResult)>,
+}
+
+enum CheckResult {
+    /// El volumen dejó de responder y ya se desmontó.
+    Lost {
+        set: Arc<tokio::sync::Mutex<MountSet>>,
+        error: String,
+    },
+    Remounted(Result<MountSet>),
+}
+
+fn log_mounted(container_id: &str, mounts: &MountSet) {
+    for (alias, session) in mounts.sessions() {
+        info!(
+            container_id,
+            alias,
+            provider = session.provider(),
+            mount_point = ?session.mount_point,
+            "Volumen montado"
+        );
+    }
+}
+
+/// Tope de la espera entre intentos de volver a montar.
+const MAX_REMOUNT_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Resultado del apagado ordenado del agent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShutdownReport {
+    /// Estado final de las ejecuciones que seguían activas.
+    pub runs: Vec<RunRecord>,
+    /// Contenedores cuyo volumen se desmontó y verificó.
+    pub unmounted: Vec<String>,
+    /// Pasos que fallaron; el apagado continúa igualmente.
+    pub errors: Vec<String>,
+}
+
+/// Estado del agent: contenedores preparados (plan + hooks + volumen) listos
+/// para lanzar procesos bajo demanda.
+pub struct AgentHost {
+    config: AgentConfig,
+    hook_engine: HookEngine,
+    runtimes: RuntimeStore,
+    service_sandbox: ServiceSandbox,
+    supervisor: Supervisor,
+    mounts: MountProviders,
+    mount_points: MountPointAllocator,
+    /// Todos los contenedores cargados, se hayan podido preparar o no.
+    registered: BTreeMap<String, RegisteredContainer>,
+    active: HashMap<String, ActiveContainer>,
+    /// Por qué no se pudo preparar cada contenedor registrado que no está activo.
+    failures: HashMap<String, String>,
+    diagnostics: Vec<LoadDiagnostic>,
+    /// Inventario publicado para quien lo reporta al backend.
+    inventory: watch::Sender<Vec<ContainerSummary>>,
+    /// Resultados de la comprobación de volúmenes que corre en segundo plano.
+    mount_checks: (
+        mpsc::UnboundedSender<MountCheck>,
+        mpsc::UnboundedReceiver<MountCheck>,
+    ),
+    /// Hay una comprobación en curso cuyo resultado aún no se aplicó.
+    checking_mounts: bool,
+}
+
+impl AgentHost {
+    pub fn new(config: AgentConfig) -> Self {
+        let runtimes = RuntimeStore::new(&config.runtimes_dir);
+        runtimes.reset_usage();
+        let mount_points = MountPointAllocator::new(&config.mount);
+        let hook_engine = HookEngine::new(runtimes.clone(), &config.plans_dir);
+        Self {
+            config,
+            hook_engine,
+            runtimes,
+            service_sandbox: ServiceSandbox::new(),
+            supervisor: Supervisor::new(),
+            mounts: MountProviders::detect(),
+            mount_points,
+            registered: BTreeMap::new(),
+            active: HashMap::new(),
+            failures: HashMap::new(),
+            diagnostics: Vec::new(),
+            inventory: watch::Sender::new(Vec::new()),
+            mount_checks: mpsc::unbounded_channel(),
+            checking_mounts: false,
+        }
+    }
+
+    /// Sustituye los proveedores detectados por uno solo (pruebas).
+    pub fn with_mount_provider(mut self, provider: Arc<dyn MountProvider>) -> Self {
+        self.mounts = MountProviders::single(provider);
+        self
+    }
+
+    /// Sustituye la consulta de letras de unidad ocupadas (pruebas).
+    pub fn with_drive_probe(mut self, probe: impl DriveProbe + 'static) -> Self {
+        self.mount_points = self.mount_points.with_probe(probe);
+        self
+    }
+
+    pub fn config(&self) -> &AgentConfig {
+        &self.config
+    }
+
+    /// Historial y estado de las ejecuciones lanzadas por este agent.
+    pub fn supervisor(&self) -> &Supervisor {
+        &self.supervisor
+    }
+
+    /// Contenedores alojados; el receptor se marca como cambiado cada vez que
+    /// se prepara, recarga o elimina alguno.
+    pub fn inventory(&self) -> watch::Receiver<Vec<ContainerSummary>> {
+        self.inventory.subscribe()
+    }
+
+    fn publish_inventory(&self) {
+        let summaries = self.summaries();
+        self.inventory.send_if_modified(|current| {
+            let changed = *current != summaries;
+            if changed {
+                *current = summaries;
+            }
+            changed
+        });
+    }
+
+    /// Calcula y publica el plan de hooks y monta el volumen del contenedor. No lanza
+    /// ningún proceso: eso ocurre bajo demanda con [`AgentHost::launch`].
+    pub async fn prepare_container(&mut self, container: &RegisteredContainer) -> Result<()> {
+        info!(
+            container_id = container.manifest.id.as_str(),
+            name = container.manifest.name.as_str(),
+            version = container.manifest.version.as_deref().unwrap_or("latest"),
+            "Contenedor registrado"
+        );
+        self.registered
+            .insert(container.manifest.id.clone(), container.clone());
+        self.failures.remove(&container.manifest.id);
+
+        let plan = match self.hook_engine.prepare(container).await {
+            Ok(plan) => plan,
+            Err(err) => {
+                warn!(
+                    container_id = container.manifest.id.as_str(),
+                    error = format!("{err:#}"),
+                    "No se pudo preparar el contenedor; se omite"
+                );
+                self.record_failure(container, &err);
+                return Ok(());
+            }
+        };
+        let (mount, remount) = if !self.config.mount.enabled {
+            (None, None)
+        } else {
+            match self.mount_volumes(container, &plan.mounts).await {
+                Ok(mounts) => (Some(MountedVolumes::new(mounts)), None),
+                Err(err) if self.mount_optional(container) => {
+                    warn!(
+                        container_id = container.manifest.id.as_str(),
+                        error = format!("{err:#}"),
+                        "Montaje opcional fallido; el contenedor sigue sin volumen"
+                    );
+                    (None, Some(self.schedule_remount(0, &err)))
+                }
+                Err(err) => {
+                    warn!(
+                        container_id = container.manifest.id.as_str(),
+                        error = format!("{err:#}"),
+                        "No se pudo montar el contenedor; se omite"
+                    );
+                    self.runtimes.release(&container.manifest.id);
+                    self.record_failure(container, &err);
+                    return Ok(());
+                }
+            }
+        };
+        let activated = self.hook_engine.activate(container, &plan).and_then(|()| {
+            ContainerLog::open(&container.root, self.config.logs).inspect_err(|_| {
+                let _ = self.hook_engine.deactivate(container);
+            })
+        });
+        let log = match activated {
+            Ok(log) => log,
+            Err(err) => {
+                // Sin hooks ni log el volumen no sirve: se desmonta antes de fallar.
+                if let Some(mounts) = mount {
+                    if let Err(unmount) = mounts.unmount().await {
+                        warn!(
+                            container_id = container.manifest.id.as_str(),
+                            error = format!("{unmount:#}"),
+                            "No se pudo desmontar el volumen del contenedor fallido"
+                        );
+                    }
+                }
+                self.runtimes.release(&container.manifest.id);
+                self.record_failure(container, &err);
+                return Err(err);
+            }
+        };
+
+        info!(
+            container_id = container.manifest.id.as_str(),
+            mounts = ?plan.mounts,
+            redirects = ?plan.redirects,
+            "Plan de hooks publicado"
+        );
+        self.active.insert(
+            container.manifest.id.clone(),
+            ActiveContainer {
+                container: container.clone(),
+                plan,
+                log,
+                mount,
+                remount,
+            },
+        );
+        self.publish_inventory();
+        Ok(())
+    }
+
+    /// Guarda el error para el inventario (`ctnr agent containers` y backend).
+    fn record_failure(&mut self, container: &RegisteredContainer, err: &anyhow::Error) {
+        self.failures
+            .insert(container.manifest.id.clone(), format!("{err:#}"));
+        self.publish_inventory();
+    }
+
+    /// Monta la raíz del contenedor con el proveedor que pide el manifiesto (o
+    /// la configuración) y cada entrada del plan en su propia carpeta, con el
+    /// mismo proveedor si admite carpetas o, si no, con una junction/enlace.
+    async fn mount_volumes(
+        &mut self,
+        container: &RegisteredContainer,
+        plan: &[MountPlan],
+    ) -> Result<MountSet> {
+        let requests = self.mount_requests(container, plan)?;
+        let mounts = MountSet::mount_all(requests, self.mount_ready_timeout()).await?;
+        log_mounted(&container.manifest.id, &mounts);
+        Ok(mounts)
+    }
+
+    /// Peticiones de montaje de [`AgentHost::mount_volumes`], con los puntos de
+    /// montaje ya asignados.
+    fn mount_requests(
+        &mut self,
+        container: &RegisteredContainer,
+        plan: &[MountPlan],
+    ) -> Result<Vec<MountRequest>> {
+        let id = container.manifest.id.as_str();
+        let wanted = &container.manifest.mount;
+        let provider = self
+            .mounts
+            .select(wanted.provider.unwrap_or(self.config.mount.provider))?;
+        let entry_provider: Arc<dyn MountProvider> = if provider.capabilities().directories {
+            provider.clone()
+        } else {
+            Arc::new(BindProvider)
+        };
+
+        let mut requests = vec![MountRequest {
+            alias: "root".into(),
+            provider: provider.clone(),
+            source: container.root.clone(),
+            mount_point: self
+                .mount_points
+                .allocate(id, provider.capabilities(), wanted.drive)?,
+        }];
+        for entry in plan {
+            let mount_point = self.mount_points.plan_point(id, &entry.alias);
+            if let Some(clash) = requests.iter().find(|r| r.mount_point == mount_point) {
+                bail!(
+                    "{} y {} se montarían en {}",
+                    clash.alias,
+                    entry.alias,
+                    mount_point.display()
+                );
+            }
+            requests.push(MountRequest {
+                alias: entry.alias.clone(),
+                provider: entry_provider.clone(),
+                source: entry.host_path.clone(),
+                mount_point,
+            });
+        }
+        Ok(requests)
+    }
+
+    fn mount_ready_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.mount.ready_timeout_ms)
+    }
+
+    /// Un montaje opcional que falla deja el contenedor sin volumen (y se
+    /// reintenta); uno obligatorio impide prepararlo.
+    fn mount_optional(&self, container: &RegisteredContainer) -> bool {
+        container
+            .manifest
+            .mount
+            .optional
+            .unwrap_or(self.config.mount.optional)
+    }
+
+    /// Programa el siguiente intento tras `failed` intentos previos fallidos:
+    /// espera exponencial a partir de `mount.remount_backoff_ms`.
+    fn schedule_remount(&self, failed: u32, err: &anyhow::Error) -> Remount {
+        let attempts = failed + 1;
+        let factor = 2u64.saturating_pow(attempts - 1);
+        let delay =
+            Duration::from_millis(self.config.mount.remount_backoff_ms.saturating_mul(factor))
+                .min(MAX_REMOUNT_BACKOFF);
+        Remount {
+            attempts,
+            next_attempt: Instant::now() + delay,
+            error: format!("{err:#}"),
+        }
+    }
+
+    /// Arranca en segundo plano una pasada que comprueba los volúmenes
+    /// montados y vuelve a montar los caídos cuando toca; no hace nada si ya
+    /// hay una en curso. El resultado llega por [`AgentHost::mount_check_done`]
+    /// y se aplica con [`AgentHost::apply_mount_check`].
+    pub fn check_mounts(&mut self) {
+        if self.checking_mounts {
+            return;
+        }
+        let mut ids: Vec<_> = self.active.keys().cloned().collect();
+        ids.sort();
+        let now = Instant::now();
+        let mut checks = Vec::new();
+        let mut remounts = Vec::new();
+        let mut changed = false;
+        for id in ids {
+            let active = &self.active[&id];
+            if let Some(mounted) = &active.mount {
+                checks.push((id, mounted.set.clone()));
+                continue;
+            }
+            let attempts = match &active.remount {
+                Some(remount) if remount.next_attempt <= now => remount.attempts,
+                _ => continue,
+            };
+            let (container, plan) = (active.container.clone(), active.plan.mounts.clone());
+            match self.mount_requests(&container, &plan) {
+                Ok(requests) => remounts.push((id, requests)),
+                Err(err) => {
+                    self.remount_failed(&id, attempts, &err);
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.publish_inventory();
+        }
+        if checks.is_empty() && remounts.is_empty() {
+            return;
+        }
+
+        self.checking_mounts = true;
+        let ready_timeout = self.mount_ready_timeout();
+        let done = self.mount_checks.0.clone();
+        tokio::spawn(async move {
+            let mut results = Vec::new();
+            for (id, set) in checks {
+                let mut mounts = set.lock().await;
+                if let Err(err) = mounts.health().await {
+                    let _ = mounts.unmount().await;
+                    drop(mounts);
+                    results.push((
+                        id,
+                        CheckResult::Lost {
+                            set,
+                            error: format!("{err:#}"),
+                        },
+                    ));
+                }
+            }
+            for (id, requests) in remounts {
+                let mounted = MountSet::mount_all(requests, ready_timeout).await;
+                results.push((id, CheckResult::Remounted(mounted)));
+            }
+            let _ = done.send(MountCheck { results });
+        });
+    }
+
+    /// Espera a que termine la comprobación en curso. Sin ninguna en curso no
+    /// vuelve nunca, así que puede esperarse siempre en un `select!`.
+    pub async fn mount_check_done(&mut self) -> MountCheck {
+        match self.mount_checks.1.recv().await {
+            Some(check) => check,
+            // El host guarda el emisor: el canal no se cierra mientras exista.
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Aplica el resultado de una comprobación y publica los cambios. Si algún
+    /// volumen se cayó, arranca otra pasada para volver a montarlo ya.
+    pub fn apply_mount_check(&mut self, check: MountCheck) {
+        if self.record_mount_check(check) {
+            self.check_mounts();
+        }
+    }
+
+    /// Devuelve si algún volumen dejó de responder. Los resultados de
+    /// contenedores detenidos o reemplazados mientras tanto se descartan (y lo
+    /// que se llegó a montar para ellos se desmonta).
+    fn record_mount_check(&mut self, check: MountCheck) -> bool {
+        self.checking_mounts = false;
+        let mut changed = false;
+        let mut lost = false;
+        for (id, result) in check.results {
+            match result {
+                CheckResult::Lost { set, error } => {
+                    let Some(active) = self.active.get_mut(&id) else {
+                        continue;
+                    };
+                    if !active
+                        .mount
+                        .as_ref()
+                        .is_some_and(|mounted| Arc::ptr_eq(&mounted.set, &set))
+                    {
+                        continue;
+                    }
+                    warn!(
+                        container_id = id.as_str(),
+                        error = error.as_str(),
+                        "El volumen dejó de responder; se volverá a montar"
+                    );
+                    active.mount = None;
+                    active.remount = Some(Remount {
+                        attempts: 0,
+                        next_attempt: Instant::now(),
+                        error,
+                    });
+                    changed = true;
+                    lost = true;
+                }
+                CheckResult::Remounted(mounted) => {
+                    let attempts = match self.active.get(&id) {
+                        Some(ActiveContainer {
+                            mount: None,
+                            remount: Some(remount),
+                            ..
+                        }) => remount.attempts,
+                        _ => {
+                            if let Ok(mut mounts) = mounted {
+                                tokio::spawn(async move {
+                                    let _ = mounts.unmount().await;
+                                });
+                            }
+                            continue;
+                        }
+                    };
+                    match mounted {
+                        Ok(mounts) => {
+                            log_mounted(&id, &mounts);
+                            if let Some(active) = self.active.get_mut(&id) {
+                                active.mount = Some(MountedVolumes::new(mounts));
+                                active.remount = None;
+                            }
+                        }
+                        Err(err) => self.remount_failed(&id, attempts, &err),
+                    }
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            self.publish_inventory();
+        }
+        lost
+    }
+
+    fn remount_failed(&mut self, id: &str, attempts: u32, err: &anyhow::Error) {
+        let next = self.schedule_remount(attempts, err);
+        warn!(
+            container_id = id,
+            attempts = next.attempts,
+            error = next.error.as_str(),
+            "No se pudo volver a montar el volumen"
+        );
+        if let Some(active) = self.active.get_mut(id) {
+            active.remount = Some(next);
+        }
+    }
+
+    /// Detiene las ejecuciones del contenedor (cierre ordenado y `kill` pasado
+    /// `grace`), desmonta y verifica sus volúmenes, retira su plan de hooks y
+    /// vuelca su log. El contenedor deja de estar preparado aunque algún paso
+    /// falle; los fallos se devuelven juntos.
+    pub async fn stop_container(&mut self, id: &str, grace: Duration) -> Result<Vec<RunRecord>> {
+        let runs = self.supervisor.stop_container(id, grace).await;
+        let mut errors: Vec<_> = self
+            .supervisor
+            .list()
+            .iter()
+            .filter(|run| run.container_id == id && !run.state.is_terminal())
+            .map(|run| format!("La ejecución {} sigue activa", run.run_id))
+            .collect();
+        if let Some(mut active) = self.active.remove(id) {
+            if let Some(mounts) = active.mount.take() {
+                if let Err(err) = mounts.unmount().await {
+                    errors.push(format!("{err:#}"));
+                }
+            }
+            if let Err(err) = self.hook_engine.deactivate(&active.container) {
+                errors.push(format!("Plan de hooks no retirado: {err:#}"));
+            }
+            if let Err(err) = active.log.flush().await {
+                errors.push(format!("No se pudo volcar el log: {err}"));
+            }
+            info!(container_id = id, "Contenedor detenido");
+        }
+        self.runtimes.release(id);
+        self.publish_inventory();
+        if errors.is_empty() {
+            Ok(runs)
+        } else {
+            Err(anyhow!("{id}: {}", errors.join("; ")))
+        }
+    }
+
+    /// Apagado ordenado: detiene las ejecuciones (cierre ordenado y `kill` pasado
+    /// `grace`), desmonta y verifica los volúmenes, retira los planes de hooks y vuelca
+    /// los logs. Un paso fallido queda en el informe sin frenar los siguientes.
+    pub async fn shutdown(&mut self, grace: Duration) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        info!(timeout = ?grace, "Deteniendo ejecuciones");
+        report.runs = self.supervisor.stop_all(grace).await;
+        for run in self
+            .supervisor
+            .list()
+            .iter()
+            .filter(|run| !run.state.is_terminal())
+        {
+            report.errors.push(format!(
+                "La ejecución {} ({}) sigue activa",
+                run.run_id, run.container_id
+            ));
+        }
+
+        // Una comprobación a medias podría seguir usando los volúmenes.
+        if self.checking_mounts {
+            let check = self.mount_check_done().await;
+            self.record_mount_check(check);
+        }
+        let mut active: Vec<_> = self.active.drain().collect();
+        active.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (id, container) in &mut active {
+            let Some(mounts) = container.mount.take() else {
+                continue;
+            };
+            match mounts.unmount().await {
+                Ok(()) => report.unmounted.push(id.clone()),
+                Err(err) => report.errors.push(format!("{id}: {err:#}")),
+            }
+        }
+
+        for (id, container) in &active {
+            if let Err(err) = self.hook_engine.deactivate(&container.container) {
+                report.errors.push(format!("{id}: {err:#}"));
+            }
+        }
+
+        for (id, container) in &active {
+            if let Err(err) = container.log.flush().await {
+                report
+                    .errors
+                    .push(format!("{id}: no se pudo volcar el log: {err}"));
+            }
+            self.runtimes.release(id);
+        }
+        self.publish_inventory();
+
+        if report.errors.is_empty() {
+            info!(
+                runs = report.runs.len(),
+                unmounted = report.unmounted.len(),
+                "Apagado completado"
+            );
+        } else {
+            warn!(errors = ?report.errors, "Apagado completado con errores");
+        }
+        report
+    }
+
+    /// Lanza un perfil de `launch` (el indicado o el por defecto) o un ejecutable
+    /// suelto relativo a la carpeta del contenedor. `args` se añaden a los del
+    /// perfil. Devuelve en cuanto el proceso arranca; el [`Supervisor`] lo vigila
+    /// y aplica la política `restart` correspondiente.
+    pub fn launch(
+        &mut self,
+        container_id: &str,
+        profile: Option<&str>,
+        executable: Option<&str>,
+        args: Vec<String>,
+    ) -> Result<String> {
+        let active = self
+            .active
+            .get(container_id)
+            .ok_or_else(|| anyhow!("El contenedor {container_id} no está preparado"))?;
+        let container = &active.container;
+        let root = &container.root;
+
+        let mut hook_plan = active.plan.clone();
+        let (executable, args, working_dir, restart) = match (profile, executable) {
+            (Some(_), Some(_)) => bail!("Indica un perfil o un ejecutable, no ambos"),
+            (None, Some(relative)) => (
+                launcher::resolve_in_container(root, relative)?,
+                args,
+                root.clone(),
+                container.manifest.restart,
+            ),
+            (profile, None) => {
+                let (name, profile) = container.manifest.profile(profile)?;
+                info!(
+                    container_id,
+                    profile = name.as_str(),
+                    "Perfil de lanzamiento"
+                );
+                let working_dir = match &profile.working_dir {
+                    Some(dir) => launcher::resolve_dir_in_container(root, dir)?,
+                    None => root.clone(),
+                };
+                hook_plan.env.extend(profile.env);
+                (
+                    launcher::resolve_in_container(root, &profile.executable)?,
+                    profile.args.into_iter().chain(args).collect(),
+                    working_dir,
+                    profile.restart.unwrap_or(container.manifest.restart),
+                )
+            }
+        };
+
+        let request = LaunchRequest {
+            executable: executable.to_string_lossy().into_owned(),
+            args,
+            working_dir: Some(working_dir.to_string_lossy().into_owned()),
+            hook_plan,
+        };
+
+        self.service_sandbox.register_placeholder(container_id).ok();
+
+        let run_id = self.supervisor.start(RunSpec {
+            container_id: container_id.to_string(),
+            request,
+            restart,
+            log: active.log.clone(),
+        })?;
+        info!(container_id, run_id = run_id.as_str(), "Proceso lanzado");
+        Ok(run_id)
+    }
+
+    /// Atiende una petición del API local. Las que esperan a un proceso (`stop`)
+    /// responden desde su propia tarea para no bloquear el bucle del agent.
+    pub fn handle_control(&mut self, command: ControlCommand) {
+        let ControlCommand { request, reply } = command;
+        if let ControlRequest::Stop { run_id, timeout_ms } = request {
+            let supervisor = self.supervisor.clone();
+            let grace = timeout_ms.map_or(DEFAULT_STOP_TIMEOUT, Duration::from_millis);
+            tokio::spawn(async move {
+                let response = match supervisor.stop(&run_id, grace).await {
+                    Ok(run) => ControlResponse::Runs { runs: vec![run] },
+                    Err(err) => ControlResponse::error(format!("{err:#}")),
+                };
+                let _ = reply.send(response);
+            });
+            return;
+        }
+        let _ = reply.send(self.respond(request));
+    }
+
+    /// `release` y `prepare`: montan o desmontan, así que retienen el host
+    /// hasta terminar para que nada más toque el contenedor mientras tanto.
+    pub async fn handle_lifecycle(&mut self, command: ControlCommand) {
+        let ControlCommand { request, reply } = command;
+        let result = match request {
+            ControlRequest::Release {
+                container_id,
+                timeout_ms,
+            } => {
+                let grace = timeout_ms.map_or(DEFAULT_STOP_TIMEOUT, Duration::from_millis);
+                self.stop_container(&container_id, grace)
+                    .await
+                    .map(|runs| ControlResponse::Runs { runs })
+            }
+            ControlRequest::Prepare { container_id } => self.prepare_again(&container_id).await,
+            request => Err(anyhow!(
+                "La petición {request:?} no se puede atender en este punto"
+            )),
+        };
+        let _ = reply.send(result.unwrap_or_else(|err| ControlResponse::error(format!("{err:#}"))));
+    }
+
+    /// Prepara un contenedor registrado que se detuvo con `release`, con el
+    /// manifiesto que haya ahora en su carpeta.
+    async fn prepare_again(&mut self, id: &str) -> Result<ControlResponse> {
+        let root = self
+            .registered
+            .get(id)
+            .map(|container| container.root.clone())
+            .ok_or_else(|| anyhow!("El contenedor {id} no está registrado"))?;
+        if !self.active.contains_key(id) {
+            let manifest = load_manifest(&root).await?;
+            if manifest.id != id {
+                bail!(
+                    "{} declara el id '{}', no '{id}'",
+                    root.display(),
+                    manifest.id
+                );
+            }
+            self.prepare_container(&RegisteredContainer { manifest, root })
+                .await?;
+        }
+        Ok(ControlResponse::Containers {
+            containers: self
+                .summaries()
+                .into_iter()
+                .filter(|container| container.id == id)
+                .collect(),
+        })
+    }
+
+    /// Respuesta inmediata a las peticiones que no necesitan esperar.
+    fn respond(&mut self, request: ControlRequest) -> ControlResponse {
+        let result = match request {
+            ControlRequest::ListContainers => Ok(ControlResponse::Containers {
+                containers: self.summaries(),
+            }),
+            ControlRequest::GetPlan { container_id } => self
+                .active
+                .get(&container_id)
+                .map(|active| ControlResponse::Plan {
+                    plan: active.plan.clone(),
+                })
+                .ok_or_else(|| anyhow!("El contenedor {container_id} no está preparado")),
+            ControlRequest::Launch {
+                container_id,
+                profile,
+                executable,
+                args,
+            } => self
+                .launch(
+                    &container_id,
+                    profile.as_deref(),
+                    executable.as_deref(),
+                    args,
+                )
+                .map(|run_id| ControlResponse::Launched { run_id }),
+            // Estas peticiones las atienden `handle_control`, `handle_lifecycle`,
+            // el watcher del registro y el bucle del agent; si alguna llega aquí
+            // se rechaza.
+            request @ (ControlRequest::Stop { .. }
+            | ControlRequest::Release { .. }
+            | ControlRequest::Prepare { .. }
+            | ControlRequest::Refresh
+            | ControlRequest::Shutdown { .. }) => Err(anyhow!(
+                "La petición {request:?} no se puede atender en este punto"
+            )),
+            ControlRequest::Status {
+                run_id,
+                container_id,
+            } => {
+                let runs: Vec<_> = self
+                    .supervisor
+                    .list()
+                    .into_iter()
+                    .filter(|run| run_id.as_ref().is_none_or(|id| &run.run_id == id))
+                    .filter(|run| {
+                        container_id
+                            .as_ref()
+                            .is_none_or(|id| &run.container_id == id)
+                    })
+                    .collect();
+                match run_id {
+                    Some(id) if runs.is_empty() => Err(anyhow!("No existe la ejecución {id}")),
+                    _ => Ok(ControlResponse::Runs { runs }),
+                }
+            }
+            ControlRequest::Diagnostics => Ok(ControlResponse::Diagnostics {
+                diagnostics: self.diagnostics.clone(),
+            }),
+        };
+        result.unwrap_or_else(|err| ControlResponse::error(format!("{err:#}")))
+    }
+
+    fn summaries(&self) -> Vec<ContainerSummary> {
+        self.registered
+            .values()
+            .map(|container| {
+                let manifest = &container.manifest;
+                let mut profiles: Vec<_> = manifest.launch.profiles.keys().cloned().collect();
+                if profiles.is_empty() && manifest.entrypoint.is_some() {
+                    profiles.push(DEFAULT_PROFILE.to_string());
+                }
+                ContainerSummary {
+                    id: manifest.id.clone(),
+                    name: manifest.name.clone(),
+                    version: manifest.version.clone(),
+                    root: container.root.clone(),
+                    prepared: self.active.contains_key(&manifest.id),
+                    profiles,
+                    mount: self
+                        .active
+                        .get(&manifest.id)
+                        .and_then(ActiveContainer::mount_status),
+                    error: self.failures.get(&manifest.id).cloned(),
+                }
+            })
+            .collect()
+    }
+
+    /// Aplica un cambio del registro tocando solo el contenedor afectado.
+    pub async fn apply_registry_event(&mut self, event: RegistryEvent) -> Result<()> {
+        match event {
+            RegistryEvent::Added(container) => {
+                info!(
+                    container_id = container.manifest.id.as_str(),
+                    "Nuevo contenedor detectado"
+                );
+                self.prepare_container(&container).await?;
+            }
+            RegistryEvent::Updated(container) => {
+                info!(
+                    container_id = container.manifest.id.as_str(),
+                    "Manifiesto modificado; recargando contenedor"
+                );
+                self.stop_for_event(&container.manifest.id).await;
+                self.prepare_container(&container).await?;
+            }
+            RegistryEvent::Removed(id) => {
+                info!(container_id = id.as_str(), "Contenedor eliminado del disco");
+                self.stop_for_event(&id).await;
+                self.registered.remove(&id);
+                self.failures.remove(&id);
+                if let Err(err) = self.mount_points.release(&id) {
+                    warn!(
+                        container_id = id.as_str(),
+                        error = format!("{err:#}"),
+                        "No se pudo liberar el punto de montaje"
+                    );
+                }
+                self.publish_inventory();
+            }
+            RegistryEvent::Diagnostics(diagnostics) => self.record_diagnostics(diagnostics),
+        }
+        Ok(())
+    }
+
+    /// Los cambios del registro detienen el contenedor aunque algo falle; el
+    /// fallo solo se registra.
+    async fn stop_for_event(&mut self, id: &str) {
+        if let Err(err) = self.stop_container(id, DEFAULT_STOP_TIMEOUT).await {
+            warn!(
+                container_id = id,
+                error = format!("{err:#}"),
+                "El contenedor no se detuvo limpiamente"
+            );
+        }
+    }
+
+    pub fn record_diagnostics(&mut self, diagnostics: Vec<LoadDiagnostic>) {
+        for diagnostic in diagnostics.iter().filter(|d| !self.diagnostics.contains(d)) {
+            warn!(
+                kind = ?diagnostic.kind,
+                path = ?diagnostic.path,
+                container_id = diagnostic.container_id.as_deref().unwrap_or("desconocido"),
+                quarantined = diagnostic.quarantines(),
+                "{}",
+                diagnostic.message
+            );
+        }
+        self.diagnostics = diagnostics;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{
+        hooks::wire, mount::testing::TestProvider, registry::ContainerRegistry,
+        supervisor::RunState,
+    };
+    use std::{
+        collections::BTreeSet,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    fn write_script(path: &Path, body: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    async fn host_with_container(dir: &Path, manifest: &str) -> AgentHost {
+        host_with_mounts(dir, manifest, None).await
+    }
+
+    async fn host_with_mounts(
+        dir: &Path,
+        manifest: &str,
+        mounts: Option<Arc<dyn MountProvider>>,
+    ) -> AgentHost {
+        let containers = dir.join("containers");
+        std::fs::create_dir_all(containers.join("demo")).unwrap();
+        std::fs::write(containers.join("demo/config.yml"), manifest).unwrap();
+
+        let mut config = AgentConfig {
+            container_roots: vec![containers.clone()],
+            runtimes_dir: dir.join("runtimes"),
+            plans_dir: dir.join("hook-plans"),
+            ..Default::default()
+        };
+        config.mount.enabled = mounts.is_some();
+        config.mount.root = dir.join("mnt");
+        config.mount.assignments = dir.join("mounts.json");
+
+        let mut host = AgentHost::new(config);
+        if let Some(mounts) = mounts {
+            host = host.with_mount_provider(mounts);
+        }
+        let registry = ContainerRegistry::load_from(&containers).await.unwrap();
+        for container in registry.list() {
+            host.prepare_container(&container).await.unwrap();
+        }
+        host
+    }
+
+    async fn call(host: &mut AgentHost, request: ControlRequest) -> ControlResponse {
+        let (reply, response) = tokio::sync::oneshot::channel();
+        host.handle_control(ControlCommand { request, reply });
+        response.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn launches_are_on_demand_and_concurrent() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        write_script(&root.join("bin/slow.sh"), "sleep 1");
+        let mut host = host_with_container(dir.path(), "id: demo\nname: Demo\n").await;
+
+        let started = std::time::Instant::now();
+        let first = host
+            .launch("demo", None, Some("bin/slow.sh"), vec![])
+            .unwrap();
+        let second = host
+            .launch("demo", None, Some("bin/slow.sh"), vec![])
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+
+        let record = host.supervisor().wait(&first).await.unwrap();
+        assert_eq!(record.container_id, "demo");
+        assert_eq!(record.exit_code, Some(0));
+        assert_eq!(host.supervisor().list().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn launch_errors_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut host = host_with_container(dir.path(), "id: demo\nname: Demo\n").await;
+
+        let err = host.launch("demo", None, None, vec![]).unwrap_err();
+        assert!(err.to_string().contains("no declara entrypoint"));
+        let err = host.launch("missing", None, None, vec![]).unwrap_err();
+        assert!(err.to_string().contains("no está preparado"));
+        assert!(matches!(
+            call(
+                &mut host,
+                ControlRequest::Launch {
+                    container_id: "demo".into(),
+                    profile: None,
+                    executable: Some("../escape.sh".into()),
+                    args: vec![],
+                }
+            )
+            .await,
+            ControlResponse::Error { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn profiles_set_args_env_and_working_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        write_script(
+            &root.join("bin/app.sh"),
+            "echo \"$PWD|$APP_MODE|$*\" > \"$OUT\"",
+        );
+        std::fs::create_dir_all(root.join("data")).unwrap();
+        let out = dir.path().join("out.txt");
+        let manifest = format!(
+            "id: demo\nname: Demo\nlaunch:\n  default: main\n  profiles:\n    main:\n      executable: bin/app.sh\n      args: [--safe]\n      working_dir: data\n      env:\n        APP_MODE: kiosk\n        OUT: {}\n    tools:\n      executable: bin/missing.sh\n",
+            out.display()
+        );
+        let mut host = host_with_container(dir.path(), &manifest).await;
+
+        let run = host
+            .launch("demo", None, None, vec!["extra".into()])
+            .unwrap();
+        host.supervisor().wait(&run).await.unwrap();
+        let written = std::fs::read_to_string(&out).unwrap();
+        let data = root.join("data").canonicalize().unwrap();
+        assert_eq!(
+            written.trim(),
+            format!("{}|kiosk|--safe extra", data.display())
+        );
+
+        let err = host
+            .launch("demo", Some("tools"), None, vec![])
+            .unwrap_err();
+        assert!(err.to_string().contains("No se encontró bin/missing.sh"));
+        let err = host.launch("demo", Some("nope"), None, vec![]).unwrap_err();
+        assert!(err.to_string().contains("main, tools"));
+    }
+
+    #[tokio::test]
+    async fn launched_processes_receive_their_containers_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        let out = dir.path().join("plan.txt");
+        write_script(
+            &root.join("bin/app.sh"),
+            &format!("echo \"$CTNR_HOOK_PLAN\" > {}", out.display()),
+        );
+        let mut host = host_with_container(dir.path(), "id: demo\nname: Demo\n").await;
+
+        let run = host
+            .launch("demo", None, Some("bin/app.sh"), vec![])
+            .unwrap();
+        host.supervisor().wait(&run).await.unwrap();
+        let published = PathBuf::from(std::fs::read_to_string(&out).unwrap().trim());
+        assert_eq!(published, dir.path().join("hook-plans/demo.bin"));
+        assert!(!published.starts_with(&root));
+        let plan = wire::read_plan(&published).unwrap();
+        assert_eq!(&plan, &host.active["demo"].plan);
+        assert!(plan.registry.is_some());
+
+        host.shutdown(Duration::from_millis(500)).await;
+        assert!(!published.exists());
+    }
+
+    /// Hace de runtime inyectado cuando la prueba siguiente lanza este mismo
+    /// binario como proceso del contenedor; en una ejecución normal no hace nada.
+    #[test]
+    fn hook_runtime_entry() {
+        let Some(plan) = std::env::var_os(wire::PLAN_ENV) else {
+            return;
+        };
+        let applied =
+            crate::hooks::apply_from_env(&crate::hooks::NativeHookPipeline::new()).unwrap();
+        std::fs::write(
+            PathBuf::from(plan).with_extension("applied"),
+            applied.to_string(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn launched_processes_apply_their_plan_from_the_environment() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        // Una copia: un enlace al binario de pruebas apuntaría fuera del contenedor.
+        std::fs::copy(std::env::current_exe().unwrap(), root.join("bin/runtime")).unwrap();
+        let mut host = host_with_container(dir.path(), "id: demo\nname: Demo\n").await;
+
+        let args = [
+            "--exact",
+            "host::tests::hook_runtime_entry",
+            "--test-threads=1",
+        ];
+        let run = host
+            .launch(
+                "demo",
+                None,
+                Some("bin/runtime"),
+                args.map(String::from).to_vec(),
+            )
+            .unwrap();
+        host.supervisor().wait(&run).await.unwrap();
+        let marker = dir.path().join("hook-plans/demo.applied");
+        assert_eq!(std::fs::read_to_string(marker).unwrap(), "true");
+        host.shutdown(Duration::from_millis(500)).await;
+    }
+
+    #[tokio::test]
+    async fn control_api_lists_plans_and_stops_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        write_script(&root.join("bin/loop.sh"), "while true; do sleep 0.1; done");
+        let mut host = host_with_container(
+            dir.path(),
+            "id: demo\nname: Demo\nentrypoint: bin/loop.sh\n",
+        )
+        .await;
+
+        let ControlResponse::Containers { containers } =
+            call(&mut host, ControlRequest::ListContainers).await
+        else {
+            panic!("respuesta inesperada");
+        };
+        assert_eq!(containers.len(), 1);
+        assert!(containers[0].prepared);
+        assert_eq!(containers[0].profiles, vec![DEFAULT_PROFILE.to_string()]);
+
+        let plan = call(
+            &mut host,
+            ControlRequest::GetPlan {
+                container_id: "demo".into(),
+            },
+        )
+        .await;
+        assert!(matches!(plan, ControlResponse::Plan { .. }));
+
+        let run_id = host.launch("demo", None, None, vec![]).unwrap();
+        let ControlResponse::Runs { runs } = call(
+            &mut host,
+            ControlRequest::Status {
+                run_id: None,
+                container_id: Some("demo".into()),
+            },
+        )
+        .await
+        else {
+            panic!("respuesta inesperada");
+        };
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].state, RunState::Running);
+
+        let ControlResponse::Runs { runs } = call(
+            &mut host,
+            ControlRequest::Stop {
+                run_id: run_id.clone(),
+                timeout_ms: Some(2000),
+            },
+        )
+        .await
+        else {
+            panic!("respuesta inesperada");
+        };
+        assert_eq!(runs[0].state, RunState::Stopped);
+
+        assert!(matches!(
+            call(
+                &mut host,
+                ControlRequest::Status {
+                    run_id: Some("nope".into()),
+                    container_id: None,
+                }
+            )
+            .await,
+            ControlResponse::Error { .. }
+        ));
+        assert!(matches!(
+            call(&mut host, ControlRequest::Diagnostics).await,
+            ControlResponse::Diagnostics { .. }
+        ));
+        // `refresh` y `shutdown` son del bucle del agent; aquí no deben tumbarlo.
+        assert!(matches!(
+            host.respond(ControlRequest::Refresh),
+            ControlResponse::Error { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_runs_unmounts_and_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        write_script(&root.join("bin/loop.sh"), "while true; do sleep 0.1; done");
+        // Ignora el cierre ordenado: solo el `kill` del timeout lo detiene.
+        write_script(
+            &root.join("bin/stubborn.sh"),
+            "trap '' TERM\necho listo\nwhile true; do sleep 0.1; done",
+        );
+        let mounts = Arc::new(TestProvider::default());
+        let mut host = host_with_mounts(
+            dir.path(),
+            "id: demo\nname: Demo\nentrypoint: bin/loop.sh\nrestart:\n  policy: always\n",
+            Some(mounts.clone()),
+        )
+        .await;
+        assert!(mounts.mounted.lock().unwrap().contains(&root));
+
+        let polite = host.launch("demo", None, None, vec![]).unwrap();
+        let stubborn = host
+            .launch("demo", None, Some("bin/stubborn.sh"), vec![])
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let started = std::time::Instant::now();
+        let report = host.shutdown(Duration::from_millis(500)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.unmounted, ["demo"]);
+        assert!(mounts.mounted.lock().unwrap().is_empty());
+
+        let mut runs = report.runs.clone();
+        runs.sort_by_key(|run| run.run_id != polite);
+        assert_eq!(runs[0].state, RunState::Stopped);
+        assert_eq!(runs[1].run_id, stubborn);
+        assert_eq!(runs[1].signal, Some(libc::SIGKILL));
+        // Ninguna ejecución se reinicia tras el apagado.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(host
+            .supervisor()
+            .list()
+            .iter()
+            .all(|run| run.state.is_terminal()));
+        assert!(!host.summaries()[0].prepared);
+
+        let logs = std::fs::read_to_string(root.join("logs/events.log")).unwrap();
+        assert!(logs.contains("listo"));
+    }
+
+    #[tokio::test]
+    async fn removed_containers_stop_their_runs_and_unmount() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        write_script(&root.join("bin/loop.sh"), "while true; do sleep 0.1; done");
+        let mounts = Arc::new(TestProvider::default());
+        let mut host = host_with_mounts(
+            dir.path(),
+            "id: demo\nname: Demo\nentrypoint: bin/loop.sh\n",
+            Some(mounts.clone()),
+        )
+        .await;
+        let run_id = host.launch("demo", None, None, vec![]).unwrap();
+
+        host.apply_registry_event(RegistryEvent::Removed("demo".into()))
+            .await
+            .unwrap();
+        assert!(mounts.mounted.lock().unwrap().is_empty());
+        assert_eq!(
+            host.supervisor().get(&run_id).unwrap().state,
+            RunState::Stopped
+        );
+        assert!(host.summaries().is_empty());
+
+        // Un volumen que no se desmonta se informa y el contenedor queda sin preparar.
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Arc::new(TestProvider {
+            fail_unmount: true,
+            ..Default::default()
+        });
+        let mut host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        let err = host
+            .stop_container("demo", Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("volumen ocupado"));
+        assert!(!host.summaries()[0].prepared);
+    }
+
+    #[tokio::test]
+    async fn released_containers_stay_down_until_prepared_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Arc::new(TestProvider::default());
+        let mut host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        let lifecycle = |request| {
+            let (reply, response) = tokio::sync::oneshot::channel();
+            (ControlCommand { request, reply }, response)
+        };
+
+        let (command, response) = lifecycle(ControlRequest::Release {
+            container_id: "demo".into(),
+            timeout_ms: Some(100),
+        });
+        host.handle_lifecycle(command).await;
+        assert!(matches!(
+            response.await.unwrap(),
+            ControlResponse::Runs { .. }
+        ));
+        assert!(mounts.mounted.lock().unwrap().is_empty());
+        assert!(!host.summaries()[0].prepared);
+
+        // Se prepara con el manifiesto que haya ahora en disco.
+        let root = dir.path().join("containers/demo");
+        std::fs::write(root.join("config.yml"), "id: demo\nname: Demo 2\n").unwrap();
+        let (command, response) = lifecycle(ControlRequest::Prepare {
+            container_id: "demo".into(),
+        });
+        host.handle_lifecycle(command).await;
+        let ControlResponse::Containers { containers } = response.await.unwrap() else {
+            panic!("respuesta inesperada");
+        };
+        assert!(containers[0].prepared);
+        assert_eq!(containers[0].name, "Demo 2");
+        assert!(mounts.mounted.lock().unwrap().contains(&root));
+
+        let (command, response) = lifecycle(ControlRequest::Prepare {
+            container_id: "nope".into(),
+        });
+        host.handle_lifecycle(command).await;
+        assert!(matches!(
+            response.await.unwrap(),
+            ControlResponse::Error { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_preparations_unmount_and_report_the_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Arc::new(TestProvider::default());
+        let mut host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        let container = host.registered["demo"].clone();
+
+        // El plan de hooks no se puede publicar si su ruta es una carpeta.
+        host.stop_container("demo", Duration::from_millis(100))
+            .await
+            .unwrap();
+        std::fs::create_dir_all(dir.path().join("hook-plans/demo.bin")).unwrap();
+        let err = host
+            .apply_registry_event(RegistryEvent::Updated(container))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("plan de hooks"));
+        assert!(mounts.mounted.lock().unwrap().is_empty());
+        assert!(!host.summaries()[0].prepared);
+        assert!(host.summaries()[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("plan de hooks"));
+    }
+
+    #[tokio::test]
+    async fn preparation_errors_are_reported_in_the_inventory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut host = host_with_container(
+            dir.path(),
+            "id: demo\nname: Demo\nruntime:\n  build: \"rt@1\"\n",
+        )
+        .await;
+        let summary = host.inventory().borrow()[0].clone();
+        assert!(!summary.prepared);
+        assert!(summary.error.as_deref().unwrap().contains("rt@1"));
+
+        let source = dir.path().join("rt");
+        std::fs::create_dir_all(&source).unwrap();
+        host.runtimes
+            .install(&"rt@1".parse().unwrap(), &source)
+            .unwrap();
+        let container = host.registered["demo"].clone();
+        host.apply_registry_event(RegistryEvent::Updated(container))
+            .await
+            .unwrap();
+        let summary = host.inventory().borrow()[0].clone();
+        assert!(summary.prepared);
+        assert_eq!(summary.error, None);
+    }
+
+    #[tokio::test]
+    async fn failed_unmounts_are_reported_without_aborting_the_teardown() {
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Arc::new(TestProvider {
+            fail_unmount: true,
+            ..Default::default()
+        });
+        let mut host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+
+        let report = host.shutdown(Duration::from_millis(100)).await;
+        assert!(report.unmounted.is_empty());
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("demo:"));
+        assert!(report.errors[0].contains("volumen ocupado"));
+        assert!(!host.summaries()[0].prepared);
+    }
+
+    #[tokio::test]
+    async fn failed_mounts_skip_the_container_unless_optional() {
+        let dir = tempfile::tempdir().unwrap();
+        let failing = || -> Arc<dyn MountProvider> {
+            Arc::new(TestProvider {
+                fail_mount: true,
+                ..Default::default()
+            })
+        };
+        let host = host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(failing())).await;
+        assert!(!host.summaries()[0].prepared);
+        assert!(host.summaries()[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("sin driver"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let host = host_with_mounts(
+            dir.path(),
+            "id: demo\nname: Demo\nmount:\n  optional: true\n",
+            Some(failing()),
+        )
+        .await;
+        assert!(host.summaries()[0].prepared);
+        assert!(host.active["demo"].mount.is_none());
+        let status = host.summaries()[0].mount.clone().unwrap();
+        assert_eq!(status.state, MountState::Remounting);
+        assert_eq!(status.attempts, 1);
+        assert!(status.error.unwrap().contains("sin driver"));
+    }
+
+    #[tokio::test]
+    async fn every_plan_entry_is_mounted_and_failures_unwind() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        let mounts = Arc::new(TestProvider::default());
+        let host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        let mounted = mounts.mounted.lock().unwrap().clone();
+        assert_eq!(
+            mounted,
+            BTreeSet::from([
+                root.clone(),
+                root.join("rootfs/ProgramFiles"),
+                root.join("temp"),
+                root.join("user/AppData/Roaming"),
+                root.join("user/LocalAppData"),
+            ])
+        );
+        let set = host.active["demo"]
+            .mount
+            .as_ref()
+            .unwrap()
+            .set
+            .try_lock()
+            .unwrap();
+        let points: Vec<_> = set
+            .sessions()
+            .map(|(alias, session)| (alias.to_string(), session.mount_point.clone()))
+            .collect();
+        assert_eq!(points[0], ("root".into(), dir.path().join("mnt/demo/root")));
+        assert_eq!(
+            points[1],
+            (
+                "%APPDATA%".into(),
+                dir.path().join("mnt/demo/mounts/appdata")
+            )
+        );
+
+        // `temp` es la última entrada: las anteriores se desmontan al fallar.
+        let dir = tempfile::tempdir().unwrap();
+        let mounts = Arc::new(TestProvider {
+            fail_on: Some("temp"),
+            ..Default::default()
+        });
+        let host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        assert!(!host.summaries()[0].prepared);
+        assert!(mounts.mounted.lock().unwrap().is_empty());
+    }
+
+    /// Ejecuta pasadas de comprobación hasta que no quede ninguna en curso.
+    async fn check_mounts(host: &mut AgentHost) {
+        host.check_mounts();
+        while host.checking_mounts {
+            let check = host.mount_check_done().await;
+            host.apply_mount_check(check);
+        }
+    }
+
+    #[tokio::test]
+    async fn crashed_volumes_are_remounted_and_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("containers/demo");
+        let mounts = Arc::new(TestProvider::default());
+        let mut host =
+            host_with_mounts(dir.path(), "id: demo\nname: Demo\n", Some(mounts.clone())).await;
+        let inventory = host.inventory();
+        assert_eq!(
+            inventory.borrow()[0].mount.as_ref().unwrap().state,
+            MountState::Mounted
+        );
+
+        // Nada cambia mientras el volumen responde.
+        check_mounts(&mut host).await;
+        assert!(!inventory.has_changed().unwrap());
+
+        // El proveedor se cae: la pasada corre aparte y, al aplicarla, arranca
+        // otra que lo vuelve a montar.
+        mounts.mounted.lock().unwrap().clear();
+        host.check_mounts();
+        assert!(host.checking_mounts);
+        let check = host.mount_check_done().await;
+        host.apply_mount_check(check);
+        assert_eq!(
+            host.summaries()[0].mount.as_ref().unwrap().state,
+            MountState::Remounting
+        );
+        check_mounts(&mut host).await;
+        assert!(mounts.mounted.lock().unwrap().contains(&root));
+        let status = host.summaries()[0].mount.clone().unwrap();
+        assert_eq!(status.state, MountState::Mounted);
+        assert_eq!(status.provider.as_deref(), Some("fake"));
+        assert_eq!(status.mount_point, Some(dir.path().join("mnt/demo/root")));
+    }
+}
@@ -1,8 +1,17 @@
 use crate::runtime::HookPlan;
-use anyhow::{Context, Result};
-use std::{path::Path, process::Stdio};
-use tokio::process::Command as TokioCommand;
-use tracing::{info, warn};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    path::{Component, Path, PathBuf},
+    process::Stdio,
+};
+use tokio::process::{Child, Command as TokioCommand};
+use tracing::info;
+
+mod group;
+#[cfg(all(target_os = "windows", feature = "native-hooks"))]
+mod inject;
+
+pub use group::ProcessGroup;
 
 #[derive(Debug, Clone)]
 pub struct LaunchRequest {
@@ -12,18 +21,39 @@ pub struct LaunchRequest {
     pub hook_plan: HookPlan,
 }
 
-pub async fn launch(request: &LaunchRequest) -> Result<()> {
+/// Proceso lanzado por [`spawn`] junto con su grupo, que es lo que hay que
+/// señalar para detenerlo a él y a los procesos que lance.
+#[derive(Debug)]
+pub struct Process {
+    pub child: Child,
+    pub group: ProcessGroup,
+}
+
+/// Inicia el proceso sin esperar a que termine; el llamador decide cómo supervisarlo.
+/// stdout/stderr quedan en tuberías que el llamador debe consumir.
+///
+/// En Unix el proceso encabeza un grupo de procesos nuevo. En Windows arranca
+/// suspendido, se asigna a un job object y, con `native-hooks`, solo se
+/// reanuda tras inyectarle el runtime de hooks con su plan (ver
+/// [`crate::hooks::wire`]); si algo falla, se termina sin ejecutarse.
+pub fn spawn(request: &LaunchRequest) -> Result<Process> {
     validate_binary(&request.executable)?;
 
     let mut command = TokioCommand::new(&request.executable);
     command.args(&request.args);
     command.envs(request.hook_plan.env.clone());
     command.stdin(Stdio::null());
-    command.stdout(Stdio::inherit());
-    command.stderr(Stdio::inherit());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
     if let Some(dir) = &request.working_dir {
         command.current_dir(dir);
     }
+    #[cfg(unix)]
+    command.process_group(0);
+    #[cfg(windows)]
+    command.creation_flags(group::CREATE_SUSPENDED);
+    #[cfg(all(target_os = "windows", feature = "native-hooks"))]
+    let runtime = inject::runtime_library()?;
 
     info!(
         executable = request.executable.as_str(),
@@ -34,21 +64,127 @@ pub async fn launch(request: &LaunchRequest) -> Result<()> {
     let mut child = command
         .spawn()
         .context("No se pudo iniciar el proceso contenedor")?;
-    let status = child.wait().await?;
-    if !status.success() {
-        warn!(
-            code = status.code(),
-            "Proceso contenedor terminó con estado inesperado"
-        );
+    let group = match ProcessGroup::new(&child) {
+        Ok(group) => group,
+        Err(err) => {
+            let _ = child.start_kill();
+            return Err(err);
+        }
+    };
+    #[cfg(all(target_os = "windows", feature = "native-hooks"))]
+    let child = inject::inject(child, &runtime)?;
+    #[cfg(all(target_os = "windows", not(feature = "native-hooks")))]
+    if let Err(err) = child.id().map_or(Ok(()), group::resume_threads) {
+        let _ = child.start_kill();
+        return Err(err);
+    }
+    Ok(Process { child, group })
+}
+
+/// Resuelve un ejecutable indicado relativo a la carpeta del contenedor, sin
+/// permitir rutas absolutas ni `..` que escapen de ella.
+pub fn resolve_in_container(root: &Path, relative: &str) -> Result<PathBuf> {
+    let resolved = contained(root, relative, "El ejecutable")?;
+    if !resolved.is_file() {
+        return Err(anyhow!(
+            "No se encontró {relative} dentro de {}",
+            root.display()
+        ));
     }
+    Ok(resolved)
+}
 
-    Ok(())
+/// Igual que [`resolve_in_container`] pero para la carpeta de trabajo.
+pub fn resolve_dir_in_container(root: &Path, relative: &str) -> Result<PathBuf> {
+    let resolved = contained(root, relative, "La carpeta de trabajo")?;
+    if !resolved.is_dir() {
+        return Err(anyhow!(
+            "No existe la carpeta {relative} dentro de {}",
+            root.display()
+        ));
+    }
+    Ok(resolved)
 }
 
+/// Además de exigir una ruta relativa sin `..`, compara las rutas reales para
+/// que un enlace simbólico o junction del contenedor no lleve fuera de él.
+fn contained(root: &Path, relative: &str, what: &str) -> Result<PathBuf> {
+    let relative_path = Path::new(relative);
+    if relative.is_empty()
+        || !relative_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!(
+            "{what} {relative} debe ser una ruta relativa dentro del contenedor"
+        ));
+    }
+    let resolved = root.join(relative_path);
+    let real_root = root
+        .canonicalize()
+        .with_context(|| format!("No se pudo resolver {}", root.display()))?;
+    let Ok(real) = resolved.canonicalize() else {
+        return Err(anyhow!(
+            "No se encontró {relative} dentro de {}",
+            root.display()
+        ));
+    };
+    if !real.starts_with(&real_root) {
+        return Err(anyhow!(
+            "{what} {relative} apunta fuera del contenedor ({})",
+            real.display()
+        ));
+    }
+    Ok(resolved)
+}
+
+/// Solo se lanzan rutas ya resueltas; no se busca en el `PATH` del host.
 fn validate_binary(path: &str) -> Result<()> {
-    if Path::new(path).exists() || which::which(path).is_ok() {
+    if Path::new(path).is_file() {
         Ok(())
     } else {
-        Err(anyhow::anyhow!("No se encontró el ejecutable {path}"))
+        Err(anyhow!("No se encontró el ejecutable {path}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_only_paths_inside_the_container() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("rootfs/bin")).unwrap();
+        std::fs::write(dir.path().join("rootfs/bin/app.exe"), "").unwrap();
+
+        let resolved = resolve_in_container(dir.path(), "rootfs/bin/app.exe").unwrap();
+        assert_eq!(resolved, dir.path().join("rootfs/bin/app.exe"));
+        assert!(resolve_in_container(dir.path(), "rootfs/bin/missing.exe").is_err());
+        assert!(resolve_in_container(dir.path(), "../outside.exe").is_err());
+        assert!(resolve_in_container(dir.path(), "/bin/sh").is_err());
+        assert!(resolve_dir_in_container(dir.path(), "rootfs/bin").is_ok());
+        assert!(resolve_dir_in_container(dir.path(), "rootfs/bin/app.exe").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_cannot_lead_outside_the_container() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("demo");
+        std::fs::create_dir_all(root.join("bin")).unwrap();
+        std::fs::write(dir.path().join("host.exe"), "").unwrap();
+        std::fs::write(root.join("bin/app.exe"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("host")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("host.exe"), root.join("bin/tool.exe")).unwrap();
+        std::os::unix::fs::symlink("app.exe", root.join("bin/alias.exe")).unwrap();
+
+        let escaped = resolve_in_container(&root, "host/host.exe").unwrap_err();
+        assert!(
+            escaped.to_string().contains("fuera del contenedor"),
+            "{escaped}"
+        );
+        assert!(resolve_in_container(&root, "bin/tool.exe").is_err());
+        assert!(resolve_dir_in_container(&root, "host").is_err());
+        assert!(resolve_in_container(&root, "bin/alias.exe").is_ok());
     }
 }
@@ -0,0 +1,170 @@
+//! Grupo de procesos de cada ejecución.
+//!
+//! En Unix el proceso encabeza su propio grupo de procesos; en Windows se crea
+//! suspendido, se asigna a un job object y solo entonces se reanuda, así que
+//! ningún hijo suyo queda fuera. Detener la ejecución señala al grupo entero y
+//! no deja nietos huérfanos.
+
+use anyhow::{anyhow, Result};
+use tokio::process::Child;
+
+#[cfg(unix)]
+pub use unix::ProcessGroup;
+#[cfg(windows)]
+pub use windows_job::ProcessGroup;
+#[cfg(windows)]
+pub(super) use windows_job::{resume_threads, CREATE_SUSPENDED};
+
+fn pid(child: &Child) -> Result<u32> {
+    child
+        .id()
+        .ok_or_else(|| anyhow!("El proceso terminó antes de crear su grupo"))
+}
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::Result;
+    use tokio::process::Child;
+
+    /// Grupo de procesos que encabeza el proceso lanzado.
+    #[derive(Debug)]
+    pub struct ProcessGroup {
+        pgid: libc::pid_t,
+    }
+
+    impl ProcessGroup {
+        /// `child` debe haberse lanzado con `process_group(0)`.
+        pub(in crate::launcher) fn new(child: &Child) -> Result<Self> {
+            Ok(Self {
+                pgid: super::pid(child)? as libc::pid_t,
+            })
+        }
+
+        /// Pide a todo el grupo que termine (SIGTERM).
+        pub async fn terminate(&self) {
+            self.signal(libc::SIGTERM);
+        }
+
+        /// Termina todo el grupo sin esperar (SIGKILL).
+        pub fn kill(&self) -> std::io::Result<()> {
+            if self.signal(libc::SIGKILL) {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        }
+
+        fn signal(&self, signal: libc::c_int) -> bool {
+            // SAFETY: `kill` solo envía una señal. El líder sigue sin recogerse
+            // mientras el supervisor lo detiene, así que su pgid no se reutiliza.
+            unsafe { libc::kill(-self.pgid, signal) == 0 }
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows_job {
+    use anyhow::{anyhow, ensure, Context, Result};
+    use std::mem::size_of;
+    use tokio::process::{Child, Command};
+    use windows::Win32::{
+        Foundation::{CloseHandle, BOOL, HANDLE},
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD,
+                THREADENTRY32,
+            },
+            JobObjects::{AssignProcessToJobObject, CreateJobObjectW, TerminateJobObject},
+            Threading::{OpenThread, ResumeThread, THREAD_SUSPEND_RESUME},
+        },
+    };
+
+    /// Bandera de creación con la que el launcher lanza el proceso.
+    pub const CREATE_SUSPENDED: u32 = windows::Win32::System::Threading::CREATE_SUSPENDED.0;
+
+    /// Job object al que pertenecen el proceso lanzado y todos sus hijos.
+    #[derive(Debug)]
+    pub struct ProcessGroup {
+        job: HANDLE,
+        pid: u32,
+    }
+
+    impl ProcessGroup {
+        /// `child` debe seguir suspendido (ver [`CREATE_SUSPENDED`]).
+        pub(in crate::launcher) fn new(child: &Child) -> Result<Self> {
+            let pid = super::pid(child)?;
+            let process = HANDLE(
+                child
+                    .raw_handle()
+                    .ok_or_else(|| anyhow!("El proceso terminó antes de crear su grupo"))?
+                    as isize,
+            );
+            // SAFETY: `process` es el handle del hijo que `child` mantiene
+            // abierto; el job se cierra en `drop`.
+            unsafe {
+                let job = CreateJobObjectW(None, None)
+                    .context("No se pudo crear el job object del proceso")?;
+                let group = Self { job, pid };
+                AssignProcessToJobObject(job, process)
+                    .context("No se pudo asignar el proceso a su job object")?;
+                Ok(group)
+            }
+        }
+
+        /// Pide a todo el árbol de procesos que termine. Sin `/F`, taskkill
+        /// envía WM_CLOSE para que cada aplicación cierre ordenadamente.
+        pub async fn terminate(&self) {
+            let _ = Command::new("taskkill")
+                .args(["/T", "/PID", &self.pid.to_string()])
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .status()
+                .await;
+        }
+
+        /// Termina todos los procesos del job sin esperar.
+        pub fn kill(&self) -> std::io::Result<()> {
+            // SAFETY: `job` sigue abierto mientras exista `self`.
+            unsafe { TerminateJobObject(self.job, 1) }.map_err(std::io::Error::from)
+        }
+    }
+
+    impl Drop for ProcessGroup {
+        fn drop(&mut self) {
+            // SAFETY: el handle es propio y no se usa después.
+            let _ = unsafe { CloseHandle(self.job) };
+        }
+    }
+
+    /// Reanuda el hilo principal, que sigue suspendido desde la creación.
+    pub fn resume_threads(pid: u32) -> Result<()> {
+        // SAFETY: solo se abren y reanudan hilos del proceso `pid`; cada handle
+        // se cierra al terminar con él.
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)
+                .context("No se pudieron listar los hilos del proceso")?;
+            let mut entry = THREADENTRY32 {
+                dwSize: size_of::<THREADENTRY32>() as u32,
+                ..Default::default()
+            };
+            let mut resumed = 0;
+            let mut next = Thread32First(snapshot, &mut entry);
+            while next.is_ok() {
+                if entry.th32OwnerProcessID == pid {
+                    if let Ok(thread) =
+                        OpenThread(THREAD_SUSPEND_RESUME, BOOL(0), entry.th32ThreadID)
+                    {
+                        if ResumeThread(thread) != u32::MAX {
+                            resumed += 1;
+                        }
+                        let _ = CloseHandle(thread);
+                    }
+                }
+                next = Thread32Next(snapshot, &mut entry);
+            }
+            let _ = CloseHandle(snapshot);
+            ensure!(resumed > 0, "No se pudo reanudar el proceso");
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Inyección del runtime de hooks en los procesos del contenedor.
+//!
+//! El proceso se crea suspendido. Un hilo remoto carga
+//! [`wire::RUNTIME_LIBRARY`] con `LoadLibraryW` y otro llama a su
+//! [`wire::RUNTIME_INIT`], que lee el plan y activa los hooks. Solo entonces se
+//! reanuda el hilo principal, así que el código de la aplicación nunca corre sin
+//! hooks. Si algo falla, el proceso se termina antes de que arranque.
+
+use super::group::resume_threads;
+use crate::hooks::wire;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use std::{
+    ffi::{c_void, CString, OsString},
+    mem::size_of,
+    os::windows::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+use tokio::process::Child;
+use windows::{
+    core::{s, w, HSTRING, PCSTR},
+    Win32::{
+        Foundation::{CloseHandle, FreeLibrary, BOOL, HANDLE, WAIT_OBJECT_0},
+        System::{
+            Diagnostics::{
+                Debug::WriteProcessMemory,
+                ToolHelp::{
+                    CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W,
+                    TH32CS_SNAPMODULE,
+                },
+            },
+            LibraryLoader::{
+                GetModuleHandleW, GetProcAddress, LoadLibraryExW, DONT_RESOLVE_DLL_REFERENCES,
+            },
+            Memory::{
+                VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_READWRITE,
+            },
+            Threading::{
+                CreateRemoteThread, GetCurrentProcess, GetExitCodeThread, IsWow64Process,
+                WaitForSingleObject,
+            },
+        },
+    },
+};
+
+/// Espera máxima a cada hilo remoto (carga de la DLL y activación del plan).
+const REMOTE_THREAD_TIMEOUT_MS: u32 = 30_000;
+
+type ThreadStart = unsafe extern "system" fn(*mut c_void) -> u32;
+
+/// El runtime, instalado junto al ejecutable del agent.
+pub fn runtime_library() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("No se pudo localizar el ejecutable del agent")?;
+    let library = exe.with_file_name(wire::RUNTIME_LIBRARY);
+    ensure!(
+        library.is_file(),
+        "No se encontró el runtime de hooks {}",
+        library.display()
+    );
+    Ok(library)
+}
+
+/// Carga el runtime en `child`, recién creado con
+/// [`CREATE_SUSPENDED`](super::group::CREATE_SUSPENDED), activa su plan y lo
+/// reanuda. Si no lo consigue, termina el proceso.
+pub fn inject(mut child: Child, library: &Path) -> Result<Child> {
+    match load_runtime(&child, library) {
+        Ok(()) => Ok(child),
+        Err(err) => {
+            let _ = child.start_kill();
+            Err(err.context("No se pudieron inyectar los hooks en el proceso"))
+        }
+    }
+}
+
+fn load_runtime(child: &Child, library: &Path) -> Result<()> {
+    let process = HANDLE(
+        child
+            .raw_handle()
+            .ok_or_else(|| anyhow!("El proceso terminó antes de inyectar los hooks"))?
+            as isize,
+    );
+    let pid = child
+        .id()
+        .ok_or_else(|| anyhow!("El proceso terminó antes de inyectar los hooks"))?;
+    // SAFETY: `process` es el handle del hijo que `child` mantiene abierto; las
+    // direcciones remotas salen del propio proceso (memoria reservada con
+    // `VirtualAllocEx`, `LoadLibraryW` de kernel32, que comparte base entre
+    // procesos de la misma arquitectura, y la base del runtime ya cargado).
+    unsafe {
+        ensure_same_architecture(process)?;
+        load_library(process, library)?;
+        let init = remote_module_base(pid, library)? + export_offset(library, wire::RUNTIME_INIT)?;
+        match run_remote_thread(process, init, None)? {
+            wire::INIT_OK => {}
+            wire::INIT_NO_PLAN => bail!("El proceso no recibió {}", wire::PLAN_ENV),
+            code => bail!(
+                "El runtime de hooks no pudo activar el plan (código {code}); el detalle está en el log del contenedor"
+            ),
+        }
+        resume_threads(pid)
+    }
+}
+
+/// `LoadLibraryW` y el runtime solo sirven a procesos de la misma arquitectura
+/// que el agent.
+unsafe fn ensure_same_architecture(process: HANDLE) -> Result<()> {
+    let (mut child, mut agent) = (BOOL(0), BOOL(0));
+    IsWow64Process(process, &mut child)
+        .context("No se pudo consultar la arquitectura del proceso")?;
+    IsWow64Process(GetCurrentProcess(), &mut agent)
+        .context("No se pudo consultar la arquitectura del agent")?;
+    ensure!(
+        child == agent,
+        "El proceso es de otra arquitectura que el agent; no se le pueden inyectar los hooks"
+    );
+    Ok(())
+}
+
+unsafe fn load_library(process: HANDLE, library: &Path) -> Result<()> {
+    let path: Vec<u16> = library.as_os_str().encode_wide().chain(Some(0)).collect();
+    let size = path.len() * size_of::<u16>();
+    let remote = VirtualAllocEx(
+        process,
+        None,
+        size,
+        MEM_COMMIT | MEM_RESERVE,
+        PAGE_READWRITE,
+    );
+    if remote.is_null() {
+        return Err(windows::core::Error::from_win32())
+            .context("No se pudo reservar memoria en el proceso");
+    }
+    let loaded = WriteProcessMemory(process, remote, path.as_ptr().cast(), size, None)
+        .context("No se pudo copiar la ruta del runtime al proceso")
+        .and_then(|()| {
+            let kernel32 = GetModuleHandleW(w!("kernel32.dll"))?;
+            let load_library = GetProcAddress(kernel32, s!("LoadLibraryW"))
+                .ok_or_else(|| anyhow!("kernel32 no exporta LoadLibraryW"))?;
+            // El código de salida solo conserva 32 bits del módulo cargado; si
+            // la carga falló, `remote_module_base` no lo encontrará.
+            run_remote_thread(process, load_library as usize, Some(remote))
+        });
+    let _ = VirtualFreeEx(process, remote, 0, MEM_RELEASE);
+    loaded.map(drop)
+}
+
+unsafe fn run_remote_thread(
+    process: HANDLE,
+    start: usize,
+    parameter: Option<*const c_void>,
+) -> Result<u32> {
+    let start = std::mem::transmute::<usize, ThreadStart>(start);
+    let thread = CreateRemoteThread(process, None, 0, Some(start), parameter, 0, None)
+        .context("No se pudo crear un hilo en el proceso")?;
+    let waited = WaitForSingleObject(thread, REMOTE_THREAD_TIMEOUT_MS);
+    let mut code = 0;
+    let read = GetExitCodeThread(thread, &mut code);
+    let _ = CloseHandle(thread);
+    ensure!(
+        waited == WAIT_OBJECT_0,
+        "El hilo inyectado no terminó a tiempo"
+    );
+    read.context("No se pudo leer el resultado del hilo inyectado")?;
+    Ok(code)
+}
+
+/// Dirección en la que el proceso cargó `library`.
+unsafe fn remote_module_base(pid: u32, library: &Path) -> Result<usize> {
+    let name = library.file_name().unwrap_or_default();
+    let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPMODULE, pid)
+        .context("No se pudieron listar los módulos del proceso")?;
+    let mut entry = MODULEENTRY32W {
+        dwSize: size_of::<MODULEENTRY32W>() as u32,
+        ..Default::default()
+    };
+    let mut base = None;
+    let mut next = Module32FirstW(snapshot, &mut entry);
+    while next.is_ok() {
+        let len = entry
+            .szModule
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(entry.szModule.len());
+        if OsString::from_wide(&entry.szModule[..len]).eq_ignore_ascii_case(name) {
+            base = Some(entry.modBaseAddr as usize);
+            break;
+        }
+        next = Module32NextW(snapshot, &mut entry);
+    }
+    let _ = CloseHandle(snapshot);
+    base.ok_or_else(|| anyhow!("El proceso no pudo cargar {}", library.display()))
+}
+
+/// Desplazamiento de `export` dentro de `library`, igual en cualquier proceso.
+/// La DLL se mapea en el agent sin ejecutar su código.
+unsafe fn export_offset(library: &Path, export: &str) -> Result<usize> {
+    let module = LoadLibraryExW(
+        &HSTRING::from(library),
+        HANDLE::default(),
+        DONT_RESOLVE_DLL_REFERENCES,
+    )
+    .with_context(|| format!("No se pudo abrir {}", library.display()))?;
+    let name = CString::new(export)?;
+    let address = GetProcAddress(module, PCSTR(name.as_ptr().cast()));
+    let _ = FreeLibrary(module);
+    let address =
+        address.ok_or_else(|| anyhow!("{} no exporta {export}", library.display()))? as usize;
+    Ok(address - module.0 as usize)
+}
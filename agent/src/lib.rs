@@ -0,0 +1,238 @@
+pub mod backend;
+pub mod config;
+pub mod control;
+pub mod hooks;
+pub mod host;
+pub mod launcher;
+pub mod logs;
+pub mod mount;
+pub mod mount_points;
+pub mod overlay;
+pub mod package;
+pub mod reconcile;
+pub mod regfile;
+pub mod registry;
+pub mod runtime;
+pub mod runtimes;
+pub mod services;
+pub mod supervisor;
+pub mod watcher;
+pub mod winreg;
+
+use anyhow::Result;
+use backend::BackendLink;
+use clap::Parser;
+use config::{AgentArgs, AgentConfig};
+use control::{ControlRequest, ControlResponse, ControlServer};
+use host::AgentHost;
+use reconcile::Reconciler;
+use registry::ContainerRegistry;
+use std::time::Duration;
+use tokio::{signal, sync::mpsc};
+use tracing::{error, info, warn};
+use watcher::RegistryWatcher;
+
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+const CONTROL_DRAIN: Duration = Duration::from_secs(1);
+const BACKEND_DRAIN: Duration = Duration::from_secs(5);
+
+pub async fn run() -> Result<()> {
+    let config = AgentConfig::load(&AgentArgs::parse())?;
+    init_tracing(&config.log_level);
+    ensure_permissions()?;
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        roots = ?config.container_roots,
+        backend = config.backend.endpoint.as_deref().unwrap_or("sin configurar"),
+        "Agent runtime inicializado; cargando contenedores..."
+    );
+
+    let registry = ContainerRegistry::load_roots(&config.container_roots).await?;
+    let registered = registry.list();
+    let mut host = AgentHost::new(config);
+    host.record_diagnostics(registry.diagnostics().to_vec());
+
+    if registered.is_empty() {
+        warn!(
+            roots = ?host.config().container_roots,
+            "No se encontraron contenedores registrados"
+        );
+    } else {
+        for container in &registered {
+            if let Err(err) = host.prepare_container(container).await {
+                warn!(
+                    container_id = container.manifest.id.as_str(),
+                    error = format!("{err:#}"),
+                    "No se pudo preparar el contenedor"
+                );
+            }
+        }
+    }
+
+    let (watcher, mut changes) = RegistryWatcher::spawn(
+        host.config().container_roots.clone(),
+        registry,
+        RELOAD_DEBOUNCE,
+    )?;
+
+    // El API local y las órdenes del backend llegan al bucle por el mismo canal.
+    let (command_tx, mut commands) = mpsc::channel(32);
+    // Sin backend no hay estado deseado: el reconciliador solo existe con él.
+    let link = host.config().backend.endpoint.clone().map(|endpoint| {
+        let reconciler = Reconciler::new(host.config()).spawn(host.inventory(), command_tx.clone());
+        BackendLink::new(
+            endpoint,
+            host.config(),
+            host.inventory(),
+            host.supervisor().clone(),
+            command_tx.clone(),
+            reconciler,
+        )
+        .spawn()
+    });
+
+    let control = ControlServer::bind(
+        &host.config().control.endpoint,
+        host.config().control.access,
+    )?;
+    let mut control_task = tokio::spawn(control.serve(command_tx));
+    let mut control_running = true;
+
+    info!("Agent listo; esperando peticiones de lanzamiento");
+    let shutdown = wait_for_shutdown();
+    tokio::pin!(shutdown);
+    let mut mount_health = tokio::time::interval(Duration::from_millis(
+        host.config().mount.health_interval_ms.max(1),
+    ));
+    mount_health.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Si el apagado lo pide el API local, se le responde con el informe final.
+    let (grace, requested_by) = loop {
+        tokio::select! {
+            result = &mut shutdown => {
+                result?;
+                break (host.config().shutdown.timeout_ms, None);
+            }
+            Some(event) = changes.recv() => {
+                // Un contenedor que no se puede preparar no detiene al agent.
+                if let Err(err) = host.apply_registry_event(event).await {
+                    warn!(error = format!("{err:#}"), "No se pudo aplicar el cambio del registro");
+                }
+            }
+            result = &mut control_task, if control_running => {
+                control_running = false;
+                let error = match result {
+                    Ok(result) => result.err().map(|err| format!("{err:#}")),
+                    Err(err) => Some(err.to_string()),
+                };
+                error!(
+                    error = error.as_deref().unwrap_or("sin error"),
+                    "El API local dejó de atender; el agent sigue sin él"
+                );
+            }
+            _ = mount_health.tick() => host.check_mounts(),
+            check = host.mount_check_done() => host.apply_mount_check(check),
+            Some(command) = commands.recv() => match command.request {
+                ControlRequest::Shutdown { timeout_ms } => {
+                    info!("Apagado solicitado por el API local");
+                    break (
+                        timeout_ms.unwrap_or(host.config().shutdown.timeout_ms),
+                        Some(command.reply),
+                    );
+                }
+                ControlRequest::Refresh => {
+                    let pending = watcher.refresh();
+                    tokio::spawn(async move {
+                        let response = match pending.await {
+                            Ok(Ok(changes)) => ControlResponse::Refreshed { changes },
+                            Ok(Err(err)) => ControlResponse::error(format!("{err:#}")),
+                            Err(_) => ControlResponse::error("El watcher del registro no está activo"),
+                        };
+                        let _ = command.reply.send(response);
+                    });
+                }
+                ControlRequest::Release { .. } | ControlRequest::Prepare { .. } => {
+                    host.handle_lifecycle(command).await;
+                }
+                _ => host.handle_control(command),
+            },
+        }
+    };
+
+    info!("Agent apagandose de forma segura.");
+    // Sin recargas ni órdenes del backend mientras se desmonta todo.
+    drop(watcher);
+    if let Some(link) = &link {
+        link.close();
+    }
+    let report = host.shutdown(Duration::from_millis(grace)).await;
+    // El backend recibe las ejecuciones terminadas y los contenedores ya
+    // desmontados antes de cerrar el stream.
+    if let Some(link) = link {
+        link.finish(BACKEND_DRAIN).await;
+    }
+    control_task.abort();
+    if let Some(reply) = requested_by {
+        let _ = reply.send(ControlResponse::ShutDown { report });
+    }
+    // Las conexiones del API local terminan solas; se rechaza lo que llegue
+    // mientras tanto para que ningún cliente quede esperando.
+    let _ = tokio::time::timeout(CONTROL_DRAIN, async {
+        while let Some(command) = commands.recv().await {
+            let _ = command
+                .reply
+                .send(ControlResponse::error("El agent se está apagando"));
+        }
+    })
+    .await;
+    drop(host);
+    Ok(())
+}
+
+pub fn init_tracing(filter: &str) {
+    let _ = tracing_subscriber::fmt().with_env_filter(filter).try_init();
+}
+
+async fn wait_for_shutdown() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => {
+                result?;
+                warn!("Se recibio Ctrl+C, iniciando limpieza.");
+            }
+            _ = terminate.recv() => warn!("Se recibio SIGTERM, iniciando limpieza."),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        signal::ctrl_c().await?;
+        warn!("Se recibio Ctrl+C, iniciando limpieza.");
+    }
+    Ok(())
+}
+
+fn ensure_permissions() -> Result<()> {
+    #[cfg(target_os = "windows")]
+    {
+        use anyhow::Context;
+
+        let output = std::process::Command::new("powershell")
+            .args([
+                "-NoProfile",
+                "-Command",
+                "[Security.Principal.WindowsPrincipal][Security.Principal.WindowsIdentity]::GetCurrent().IsInRole([Security.Principal.WindowsBuiltinRole]::Administrator)",
+            ])
+            .output()
+            .context("No se pudo verificar permisos")?;
+        if !String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .eq_ignore_ascii_case("True")
+        {
+            return Err(anyhow::anyhow!(
+                "El agent requiere permisos de administrador para aplicar hooks y montar volúmenes."
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,610 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    sync::{mpsc, oneshot},
+};
+use tracing::warn;
+
+/// Carpeta (relativa al contenedor) y nombre del log reservados por la especificación.
+pub const LOG_DIR: &str = "logs";
+pub const LOG_FILE: &str = "events.log";
+
+/// Líneas que pueden esperar al hilo escritor. Con la cola llena, la salida de
+/// los procesos espera (y con ella el proceso, al llenarse su tubería) en lugar
+/// de acumularse en memoria.
+const LOG_QUEUE: usize = 1024;
+
+/// Longitud máxima de una entrada; una línea más larga se parte en varias.
+pub const MAX_LINE_BYTES: usize = 64 * 1024;
+
+/// Rotación por tamaño y retención de los logs de cada contenedor (`[logs]`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Tamaño a partir del cual `events.log` pasa a `events.log.1`.
+    pub max_bytes: u64,
+    /// Archivos rotados que se conservan además del actual.
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+    /// Eventos del propio agent sobre la ejecución (inicio, salida, reinicio).
+    Agent,
+}
+
+impl fmt::Display for LogStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+            Self::Agent => "agent",
+        })
+    }
+}
+
+impl FromStr for LogStream {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "stdout" => Ok(Self::Stdout),
+            "stderr" => Ok(Self::Stderr),
+            "agent" => Ok(Self::Agent),
+            other => Err(anyhow::anyhow!("Stream de log desconocido: {other}")),
+        }
+    }
+}
+
+/// Línea de log: `<ms desde epoch> <run id> <stream> <texto>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub timestamp_ms: u64,
+    pub run_id: String,
+    pub stream: LogStream,
+    pub text: String,
+}
+
+impl LogLine {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut parts = line.splitn(4, ' ');
+        let timestamp_ms = parts.next()?.parse().ok()?;
+        let run_id = parts.next()?.to_string();
+        let stream = parts.next()?.parse().ok()?;
+        let text = parts.next().unwrap_or_default().to_string();
+        Some(Self {
+            timestamp_ms,
+            run_id,
+            stream,
+            text,
+        })
+    }
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.timestamp_ms, self.run_id, self.stream, self.text
+        )
+    }
+}
+
+enum LogCommand {
+    Line(String),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
+/// Log compartido por todas las ejecuciones de un contenedor. Las líneas se
+/// encolan (hasta [`LOG_QUEUE`]) y las escribe (y rota) un hilo propio, así que
+/// las escrituras a disco no bloquean el runtime de tokio; al ser por línea
+/// completa, las ejecuciones concurrentes no se entremezclan.
+#[derive(Clone)]
+pub struct ContainerLog {
+    dir: PathBuf,
+    lines: mpsc::Sender<LogCommand>,
+}
+
+impl ContainerLog {
+    pub fn open(container_root: &Path, config: LogConfig) -> Result<Self> {
+        let dir = container_root.join(LOG_DIR);
+        fs::create_dir_all(&dir).with_context(|| format!("No se pudo crear {}", dir.display()))?;
+        let (lines, commands) = mpsc::channel(LOG_QUEUE);
+        let mut writer = LogWriter {
+            dir: dir.clone(),
+            config,
+            file: None,
+            size: 0,
+        };
+        std::thread::Builder::new()
+            .name("ctnr-log".into())
+            .spawn(move || writer.run(commands))
+            .context("No se pudo arrancar el escritor de logs")?;
+        Ok(Self { dir, lines })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Encola una línea sin esperar; con la cola llena la línea se descarta y
+    /// se devuelve el error. Los fallos de disco los informa el hilo escritor.
+    pub fn write(&self, run_id: &str, stream: LogStream, text: &str) -> Result<()> {
+        self.lines
+            .try_send(LogCommand::Line(entry(run_id, stream, text)))
+            .map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => anyhow::anyhow!(
+                    "La cola del log de {} está llena; se descarta la línea",
+                    self.dir.display()
+                ),
+                mpsc::error::TrySendError::Closed(_) => self.closed(),
+            })
+    }
+
+    /// Como [`ContainerLog::write`], pero espera a que haya sitio en la cola.
+    async fn send(&self, run_id: &str, stream: LogStream, text: &str) -> Result<()> {
+        self.lines
+            .send(LogCommand::Line(entry(run_id, stream, text)))
+            .await
+            .map_err(|_| self.closed())
+    }
+
+    /// Espera a que se escriba todo lo encolado y lo lleva a disco.
+    pub async fn flush(&self) -> Result<()> {
+        let (reply, done) = oneshot::channel();
+        self.lines
+            .send(LogCommand::Flush(reply))
+            .await
+            .map_err(|_| self.closed())?;
+        done.await.map_err(|_| self.closed())?
+    }
+
+    fn closed(&self) -> anyhow::Error {
+        anyhow::anyhow!("El escritor de logs de {} terminó", self.dir.display())
+    }
+
+    /// Copia la salida de un proceso al log, una entrada por línea. Las líneas
+    /// de más de [`MAX_LINE_BYTES`] se parten para no acumular en memoria la
+    /// salida de un proceso que nunca escribe un salto de línea.
+    pub async fn pump<R>(self, run_id: String, stream: LogStream, output: R)
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut reader = BufReader::new(output);
+        let mut buffer = Vec::new();
+        loop {
+            let (used, line_end) = match reader.fill_buf().await {
+                Ok([]) => {
+                    if !buffer.is_empty() {
+                        self.pump_line(&run_id, stream, &buffer).await;
+                    }
+                    return;
+                }
+                Ok(chunk) => {
+                    let room = MAX_LINE_BYTES - buffer.len();
+                    let window = &chunk[..chunk.len().min(room)];
+                    match window.iter().position(|byte| *byte == b'\n') {
+                        Some(end) => {
+                            buffer.extend_from_slice(&window[..end]);
+                            (end + 1, true)
+                        }
+                        None => {
+                            buffer.extend_from_slice(window);
+                            (window.len(), buffer.len() == MAX_LINE_BYTES)
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!(
+                        run_id = run_id.as_str(),
+                        ?err,
+                        "Salida del proceso interrumpida"
+                    );
+                    return;
+                }
+            };
+            reader.consume(used);
+            if line_end {
+                self.pump_line(&run_id, stream, &buffer).await;
+                buffer.clear();
+            }
+        }
+    }
+
+    async fn pump_line(&self, run_id: &str, stream: LogStream, line: &[u8]) {
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches('\r');
+        if let Err(err) = self.send(run_id, stream, text).await {
+            warn!(run_id, ?err, "No se pudo escribir el log");
+        }
+    }
+}
+
+/// Entrada de log terminada en salto de línea.
+fn entry(run_id: &str, stream: LogStream, text: &str) -> String {
+    let mut line = LogLine {
+        timestamp_ms: now_ms(),
+        run_id: run_id.to_string(),
+        stream,
+        text: text.to_string(),
+    }
+    .to_string();
+    line.push('\n');
+    line
+}
+
+/// Dueño del archivo abierto; vive en su propio hilo hasta que se sueltan
+/// todas las copias del [`ContainerLog`].
+struct LogWriter {
+    dir: PathBuf,
+    config: LogConfig,
+    file: Option<File>,
+    size: u64,
+}
+
+impl LogWriter {
+    fn run(&mut self, mut commands: mpsc::Receiver<LogCommand>) {
+        while let Some(command) = commands.blocking_recv() {
+            match command {
+                LogCommand::Line(line) => {
+                    if let Err(err) = self.append(&line) {
+                        warn!(dir = ?self.dir, ?err, "No se pudo escribir el log");
+                    }
+                }
+                LogCommand::Flush(reply) => {
+                    let _ = reply.send(self.flush());
+                }
+            }
+        }
+    }
+
+    fn append(&mut self, line: &str) -> Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(LOG_FILE))?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_bytes {
+            self.file = None;
+            rotate(&self.dir, self.config.max_files)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(LOG_FILE))?;
+            self.size = 0;
+            self.file = Some(file);
+        }
+        let file = self.file.as_mut().expect("log abierto");
+        file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// `events.log` → `events.log.1` → … → `events.log.<max_files>`; el más antiguo se borra.
+fn rotate(dir: &Path, max_files: usize) -> Result<()> {
+    let current = dir.join(LOG_FILE);
+    if max_files == 0 {
+        return Ok(fs::remove_file(current)?);
+    }
+    let _ = fs::remove_file(rotated(dir, max_files));
+    for index in (1..max_files).rev() {
+        let from = rotated(dir, index);
+        if from.exists() {
+            fs::rename(from, rotated(dir, index + 1))?;
+        }
+    }
+    fs::rename(current, rotated(dir, 1))?;
+    Ok(())
+}
+
+fn rotated(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{LOG_FILE}.{index}"))
+}
+
+/// Archivos de log de la carpeta, del más antiguo al actual.
+pub fn log_files(dir: &Path) -> Vec<PathBuf> {
+    let mut rotated: Vec<(usize, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name();
+            let index = name
+                .to_str()?
+                .strip_prefix(LOG_FILE)?
+                .strip_prefix('.')?
+                .parse()
+                .ok()?;
+            Some((index, entry.path()))
+        })
+        .collect();
+    rotated.sort_by_key(|(index, _)| std::cmp::Reverse(*index));
+    let mut files: Vec<_> = rotated.into_iter().map(|(_, path)| path).collect();
+    let current = dir.join(LOG_FILE);
+    if current.exists() {
+        files.push(current);
+    }
+    files
+}
+
+/// Lee todas las líneas conservadas, opcionalmente filtradas por run id.
+pub fn read_lines(dir: &Path, run_id: Option<&str>) -> Result<Vec<LogLine>> {
+    read_and_follow(dir, run_id).map(|(lines, _)| lines)
+}
+
+/// Como [`read_lines`], y además devuelve un [`LogFollower`] que continúa justo
+/// donde terminó la lectura: ninguna línea se pierde ni se repite entre ambos.
+/// Una última línea de `events.log` aún sin terminar la devuelve el seguidor.
+pub fn read_and_follow(dir: &Path, run_id: Option<&str>) -> Result<(Vec<LogLine>, LogFollower)> {
+    let current = dir.join(LOG_FILE);
+    let mut lines = Vec::new();
+    let mut follower = LogFollower {
+        dir: dir.to_path_buf(),
+        offset: 0,
+        fingerprint: Vec::new(),
+        pending: Vec::new(),
+    };
+    for path in log_files(dir) {
+        let mut content = fs::read(&path)?;
+        if path == current {
+            let complete = content
+                .iter()
+                .rposition(|byte| *byte == b'\n')
+                .map_or(0, |end| end + 1);
+            follower.offset = complete as u64;
+            follower.fingerprint = content[..content.len().min(FINGERPRINT_LEN)].to_vec();
+            content.truncate(complete);
+        }
+        lines.extend(
+            String::from_utf8_lossy(&content)
+                .lines()
+                .filter_map(LogLine::parse)
+                .filter(|line| run_id.is_none_or(|id| line.run_id == id)),
+        );
+    }
+    Ok((lines, follower))
+}
+
+/// Bytes del principio del archivo con los que el seguidor reconoce que
+/// `events.log` ya no es el que estaba leyendo.
+const FINGERPRINT_LEN: usize = 64;
+
+/// Sigue `events.log` desde su final. Si se rota, termina de leer lo que quedaba
+/// en `events.log.1` antes de pasar al archivo nuevo, para no perder líneas.
+pub struct LogFollower {
+    dir: PathBuf,
+    offset: u64,
+    fingerprint: Vec<u8>,
+    pending: Vec<u8>,
+}
+
+impl LogFollower {
+    pub fn from_end(dir: &Path) -> Self {
+        let path = dir.join(LOG_FILE);
+        let offset = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        Self {
+            dir: dir.to_path_buf(),
+            offset,
+            fingerprint: fingerprint(&path),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Devuelve las líneas completas escritas desde la última llamada.
+    pub fn poll(&mut self) -> Result<Vec<LogLine>> {
+        let current = self.dir.join(LOG_FILE);
+        let Ok(mut file) = File::open(&current) else {
+            return Ok(Vec::new());
+        };
+        let len = file.metadata()?.len();
+        let mut head = fingerprint(&current);
+        if len < self.offset || !head.starts_with(&self.fingerprint) {
+            // Rotado: lo que faltaba del archivo anterior está en `events.log.1`.
+            let previous = rotated(&self.dir, 1);
+            if !self.fingerprint.is_empty() && fingerprint(&previous).starts_with(&self.fingerprint)
+            {
+                if let Ok(mut old) = File::open(&previous) {
+                    old.seek(SeekFrom::Start(self.offset))?;
+                    old.read_to_end(&mut self.pending)?;
+                }
+            }
+            if !self.pending.ends_with(b"\n") {
+                self.pending.clear();
+            }
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let read = file.read_to_end(&mut self.pending)?;
+        self.offset += read as u64;
+        if head.len() < FINGERPRINT_LEN {
+            head = fingerprint(&current);
+        }
+        self.fingerprint = head;
+
+        let Some(end) = self.pending.iter().rposition(|byte| *byte == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let complete: Vec<u8> = self.pending.drain(..=end).collect();
+        Ok(String::from_utf8_lossy(&complete)
+            .lines()
+            .filter_map(LogLine::parse)
+            .collect())
+    }
+}
+
+fn fingerprint(path: &Path) -> Vec<u8> {
+    let mut head = Vec::new();
+    if let Ok(file) = File::open(path) {
+        let _ = file.take(FINGERPRINT_LEN as u64).read_to_end(&mut head);
+    }
+    head
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rotates_by_size_and_keeps_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = ContainerLog::open(
+            dir.path(),
+            LogConfig {
+                max_bytes: 120,
+                max_files: 2,
+            },
+        )
+        .unwrap();
+        for index in 0..20 {
+            log.write("run-a", LogStream::Stdout, &format!("linea {index}"))
+                .unwrap();
+        }
+        log.flush().await.unwrap();
+
+        let files = log_files(log.dir());
+        assert_eq!(files.len(), 3);
+        assert!(files
+            .iter()
+            .all(|file| fs::metadata(file).unwrap().len() <= 120));
+        let lines = read_lines(log.dir(), None).unwrap();
+        assert_eq!(lines.last().unwrap().text, "linea 19");
+        assert!(lines.len() < 20);
+        assert!(lines
+            .windows(2)
+            .all(|pair| pair[0].timestamp_ms <= pair[1].timestamp_ms));
+    }
+
+    #[tokio::test]
+    async fn filters_by_run_and_follows_new_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = ContainerLog::open(dir.path(), LogConfig::default()).unwrap();
+        log.write("run-a", LogStream::Stdout, "hola").unwrap();
+        log.flush().await.unwrap();
+        let mut follower = LogFollower::from_end(log.dir());
+        log.write("run-b", LogStream::Stderr, "fallo con espacios")
+            .unwrap();
+        log.flush().await.unwrap();
+
+        let lines = read_lines(log.dir(), Some("run-b")).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].stream, LogStream::Stderr);
+        assert_eq!(lines[0].text, "fallo con espacios");
+
+        let followed = follower.poll().unwrap();
+        assert_eq!(followed, lines);
+        assert!(follower.poll().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn following_after_reading_neither_repeats_nor_skips_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(LOG_FILE);
+        fs::write(&path, "1 run-a stdout uno\n2 run-a stdout do").unwrap();
+
+        let (lines, mut follower) = read_and_follow(dir.path(), None).unwrap();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, "uno");
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"s\n3 run-a stdout tres\n").unwrap();
+        let followed: Vec<_> = follower
+            .poll()
+            .unwrap()
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(followed, ["dos", "tres"]);
+    }
+
+    #[tokio::test]
+    async fn long_output_lines_are_split() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = ContainerLog::open(dir.path(), LogConfig::default()).unwrap();
+        let mut output = vec![b'a'; MAX_LINE_BYTES * 2 + 10];
+        output.extend_from_slice(b"\r\nfin");
+
+        log.clone()
+            .pump("run-a".into(), LogStream::Stdout, output.as_slice())
+            .await;
+        log.flush().await.unwrap();
+        let lengths: Vec<_> = read_lines(log.dir(), None)
+            .unwrap()
+            .into_iter()
+            .map(|line| line.text.len())
+            .collect();
+        assert_eq!(lengths, [MAX_LINE_BYTES, MAX_LINE_BYTES, 10, 3]);
+    }
+
+    #[tokio::test]
+    async fn follower_drains_the_rotated_file_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = ContainerLog::open(
+            dir.path(),
+            LogConfig {
+                max_bytes: 100,
+                max_files: 2,
+            },
+        )
+        .unwrap();
+        log.write("run-a", LogStream::Stdout, "antes").unwrap();
+        log.flush().await.unwrap();
+        let mut follower = LogFollower::from_end(log.dir());
+
+        // Cada línea ocupa unos 35 bytes: `linea 1` ya no cabe y rota el archivo.
+        for index in 0..3 {
+            log.write("run-a", LogStream::Stdout, &format!("linea {index}"))
+                .unwrap();
+        }
+        log.flush().await.unwrap();
+        assert!(rotated(log.dir(), 1).exists());
+
+        let followed: Vec<_> = follower
+            .poll()
+            .unwrap()
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        assert_eq!(followed, ["linea 0", "linea 1", "linea 2"]);
+        assert!(follower.poll().unwrap().is_empty());
+    }
+}
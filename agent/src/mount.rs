@@ -1,102 +1,1140 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::{
+    fmt,
     path::{Path, PathBuf},
     process::{Child, Command, Stdio},
+    sync::Arc,
     time::Duration,
 };
-use tokio::time::sleep;
+use tokio::time::{sleep, Instant};
 use tracing::{info, warn};
 
-/// Monta el `rootfs` del contenedor usando herramientas WinFSP/Dokany.
-/// La implementación actual invoca binarios externos (`winfsp-launcher.exe` o `dokanctl.exe`),
-/// permitiendo intercambiar proveedores sin modificar el resto del runtime.
+/// Cada cuánto se comprueba si un volumen recién montado ya está accesible.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Proveedor de montaje pedido por el manifiesto o la configuración del agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MountProviderKind {
+    /// El primero disponible en este equipo: WinFSP, Dokany y FUSE, en ese orden.
+    #[default]
+    Auto,
+    WinFsp,
+    Dokany,
+    /// Junction de directorio (Windows) o enlace simbólico: sin sistema de
+    /// archivos virtual, apunta el punto de montaje a la carpeta.
+    Bind,
+    Fuse,
+}
+
+impl MountProviderKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::WinFsp => "winfsp",
+            Self::Dokany => "dokany",
+            Self::Bind => "bind",
+            Self::Fuse => "fuse",
+        }
+    }
+}
+
+impl fmt::Display for MountProviderKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Qué puede montar un proveedor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct MountCapabilities {
+    /// Monta en una letra de unidad (`X:`).
+    pub drive_letters: bool,
+    /// Monta sobre una carpeta.
+    pub directories: bool,
+    /// El volumen depende de un proceso auxiliar que puede caerse.
+    pub helper_process: bool,
+}
+
+/// Monta carpetas de un contenedor como volúmenes. Permite intercambiar el
+/// mecanismo (WinFSP, Dokany, junctions, FUSE o proveedores de prueba) sin
+/// tocar el resto del runtime.
+#[async_trait]
+pub trait MountProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> MountCapabilities;
+
+    /// Si el proveedor está instalado y se puede usar en este equipo.
+    fn available(&self) -> bool;
+
+    async fn mount(&self, root: &Path, mount_point: &Path) -> Result<Box<dyn MountedVolume>>;
+}
+
+/// Volumen montado por un [`MountProvider`].
+#[async_trait]
+pub trait MountedVolume: Send + Sync {
+    async fn unmount(&mut self) -> Result<()>;
+
+    /// Si el volumen sigue accesible; se usa para verificar el desmontaje.
+    fn is_mounted(&self) -> bool;
+
+    /// `Ok(false)` mientras el punto de montaje aún no es accesible; un error
+    /// indica que el montaje falló (p. ej. el proceso del proveedor terminó).
+    async fn ready(&mut self) -> Result<bool> {
+        Ok(self.is_mounted())
+    }
+
+    /// Comprueba que el volumen sigue sirviendo la carpeta del contenedor.
+    async fn health(&mut self) -> Result<()> {
+        if self.ready().await? {
+            Ok(())
+        } else {
+            bail!("El volumen ya no está montado")
+        }
+    }
+}
+
+/// Estado del volumen de un contenedor preparado.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MountState {
+    Mounted,
+    /// El montaje falló o dejó de responder; se reintenta con espera exponencial.
+    Remounting,
+}
+
+impl MountState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Mounted => "mounted",
+            Self::Remounting => "remounting",
+        }
+    }
+}
+
+/// Estado del volumen tal como se reporta junto al del contenedor.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountStatus {
+    pub state: MountState,
+    pub provider: Option<String>,
+    pub mount_point: Option<PathBuf>,
+    /// Reintentos fallidos desde el último montaje correcto.
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// Proveedores conocidos por el agent, en orden de preferencia para `auto`.
+#[derive(Clone)]
+pub struct MountProviders {
+    providers: Vec<(MountProviderKind, Arc<dyn MountProvider>)>,
+}
+
+impl MountProviders {
+    /// Detecta los proveedores instalados en este equipo.
+    pub fn detect() -> Self {
+        Self {
+            providers: vec![
+                (
+                    MountProviderKind::WinFsp,
+                    Arc::new(WinFspProvider::detect()),
+                ),
+                (
+                    MountProviderKind::Dokany,
+                    Arc::new(DokanyProvider::detect()),
+                ),
+                (MountProviderKind::Fuse, Arc::new(FuseProvider::detect())),
+                (MountProviderKind::Bind, Arc::new(BindProvider)),
+            ],
+        }
+    }
+
+    /// Un único proveedor que atiende cualquier petición (pruebas).
+    pub fn single(provider: Arc<dyn MountProvider>) -> Self {
+        Self {
+            providers: vec![(MountProviderKind::Auto, provider)],
+        }
+    }
+
+    /// `auto` elige el primero disponible salvo `bind`, que solo se usa si se
+    /// pide: no aísla nada y no debe ocultar que falta un proveedor real.
+    pub fn select(&self, kind: MountProviderKind) -> Result<Arc<dyn MountProvider>> {
+        let found = self
+            .providers
+            .iter()
+            .filter(|(_, provider)| provider.available())
+            .find(|(candidate, _)| match kind {
+                MountProviderKind::Auto => *candidate != MountProviderKind::Bind,
+                kind => *candidate == kind || *candidate == MountProviderKind::Auto,
+            });
+        match found {
+            Some((_, provider)) => Ok(provider.clone()),
+            None if kind == MountProviderKind::Auto => Err(anyhow!(
+                "No hay ningún proveedor de montaje instalado (WinFSP, Dokany o FUSE)"
+            )),
+            None => Err(anyhow!(
+                "El proveedor de montaje {kind} no está disponible en este equipo"
+            )),
+        }
+    }
+}
+
+/// Volumen de un contenedor. Se desmonta con [`MountSession::unmount`]; si se
+/// suelta sin más, el proveedor hace lo que pueda en su `Drop`.
 pub struct MountSession {
     pub mount_point: PathBuf,
-    child: Option<Child>,
+    provider: &'static str,
+    volume: Box<dyn MountedVolume>,
 }
 
 impl MountSession {
-    pub async fn mount(root: impl AsRef<Path>, preferred_drive: Option<char>) -> Result<Self> {
-        let root = root.as_ref();
-        let mount_point = determine_mount_point(preferred_drive)?;
-
-        if let Some(winfsp) = find_winfsp() {
-            info!(?root, ?mount_point, "Montando rootfs vía WinFSP");
-            let mut child = Command::new(winfsp)
-                .args([
-                    "--foreground",
-                    "--FileSystemName",
-                    "ContainerFS",
-                    "--MountPoint",
-                    mount_point.to_string_lossy().as_ref(),
-                ])
-                .arg(root)
-                .stdin(Stdio::null())
+    /// Monta en `mount_point` y espera hasta `ready_timeout` a que el volumen
+    /// sea accesible; si no lo es, lo desmonta y devuelve el error.
+    pub async fn mount(
+        provider: &dyn MountProvider,
+        root: impl AsRef<Path>,
+        mount_point: PathBuf,
+        ready_timeout: Duration,
+    ) -> Result<Self> {
+        let volume = provider
+            .mount(root.as_ref(), &mount_point)
+            .await
+            .with_context(|| {
+                format!(
+                    "No se pudo montar {} con {}",
+                    mount_point.display(),
+                    provider.name()
+                )
+            })?;
+        let mut session = Self {
+            mount_point,
+            provider: provider.name(),
+            volume,
+        };
+        if let Err(err) = session.wait_ready(ready_timeout).await {
+            let _ = session.volume.unmount().await;
+            return Err(err);
+        }
+        Ok(session)
+    }
+
+    async fn wait_ready(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let ready = self.volume.ready().await.with_context(|| {
+                format!(
+                    "El montaje de {} falló ({})",
+                    self.mount_point.display(),
+                    self.provider
+                )
+            })?;
+            if ready {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                bail!(
+                    "{} no estuvo accesible en {timeout:?} ({})",
+                    self.mount_point.display(),
+                    self.provider
+                );
+            }
+            sleep(READY_POLL_INTERVAL).await;
+        }
+    }
+
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    pub async fn health(&mut self) -> Result<()> {
+        self.volume.health().await
+    }
+
+    /// Desmonta y comprueba que el volumen ya no está accesible.
+    pub async fn unmount(mut self) -> Result<()> {
+        self.volume.unmount().await.with_context(|| {
+            format!(
+                "No se pudo desmontar {} ({})",
+                self.mount_point.display(),
+                self.provider
+            )
+        })?;
+        if self.volume.is_mounted() {
+            bail!(
+                "{} sigue montado tras desmontarlo ({})",
+                self.mount_point.display(),
+                self.provider
+            );
+        }
+        info!(mount_point = ?self.mount_point, provider = self.provider, "Volumen desmontado");
+        Ok(())
+    }
+}
+
+/// Un volumen a montar: la raíz del contenedor o una entrada de su `MountPlan`.
+pub struct MountRequest {
+    /// `root` o el alias de la entrada del plan (`%APPDATA%`...).
+    pub alias: String,
+    pub provider: Arc<dyn MountProvider>,
+    pub source: PathBuf,
+    pub mount_point: PathBuf,
+}
+
+/// Volúmenes de un contenedor. Cada uno es una [`MountSession`] independiente;
+/// se montan en orden y se desmontan en orden inverso.
+#[derive(Default)]
+pub struct MountSet {
+    sessions: Vec<(String, MountSession)>,
+}
+
+impl MountSet {
+    /// Monta todos los volúmenes. Si uno falla, desmonta los ya montados antes
+    /// de devolver el error, para no dejar volúmenes huérfanos.
+    pub async fn mount_all(requests: Vec<MountRequest>, ready_timeout: Duration) -> Result<Self> {
+        let mut set = Self::default();
+        for request in requests {
+            let mounted = MountSession::mount(
+                request.provider.as_ref(),
+                &request.source,
+                request.mount_point,
+                ready_timeout,
+            )
+            .await
+            .with_context(|| format!("Volumen {}", request.alias));
+            match mounted {
+                Ok(session) => set.sessions.push((request.alias, session)),
+                Err(err) => {
+                    if let Err(unwind) = set.unmount().await {
+                        warn!(
+                            error = format!("{unwind:#}"),
+                            "No se pudieron deshacer todos los montajes previos"
+                        );
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn sessions(&self) -> impl Iterator<Item = (&str, &MountSession)> {
+        self.sessions
+            .iter()
+            .map(|(alias, session)| (alias.as_str(), session))
+    }
+
+    /// La sesión de la raíz del contenedor (la primera).
+    pub fn root(&self) -> Option<&MountSession> {
+        self.sessions.first().map(|(_, session)| session)
+    }
+
+    /// Falla si alguno de los volúmenes dejó de responder.
+    pub async fn health(&mut self) -> Result<()> {
+        for (alias, session) in &mut self.sessions {
+            session
+                .health()
+                .await
+                .with_context(|| format!("Volumen {alias}"))?;
+        }
+        Ok(())
+    }
+
+    /// Desmonta todos en orden inverso; sigue aunque alguno falle y devuelve
+    /// los errores juntos.
+    pub async fn unmount(&mut self) -> Result<()> {
+        let mut errors = Vec::new();
+        while let Some((alias, session)) = self.sessions.pop() {
+            if let Err(err) = session.unmount().await {
+                errors.push(format!("{alias}: {err:#}"));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(errors.join("; ")))
+        }
+    }
+}
+
+impl Drop for MountSet {
+    /// Sin desmontaje explícito, cada volumen se desmonta al soltarse, en orden
+    /// inverso y sin verificar.
+    fn drop(&mut self) {
+        if !self.sessions.is_empty() {
+            warn!(
+                volumes = self.sessions.len(),
+                "Volúmenes soltados sin desmontar; se desmontan sin verificar"
+            );
+        }
+        while self.sessions.pop().is_some() {}
+    }
+}
+
+/// WinFSP a través de `winfsp-launcher.exe` (o `WINFSP_LAUNCHER`).
+pub struct WinFspProvider {
+    launcher: Option<PathBuf>,
+}
+
+impl WinFspProvider {
+    pub fn detect() -> Self {
+        Self {
+            launcher: find_tool("WINFSP_LAUNCHER", "winfsp-launcher.exe"),
+        }
+    }
+}
+
+#[async_trait]
+impl MountProvider for WinFspProvider {
+    fn name(&self) -> &'static str {
+        "winfsp"
+    }
+
+    fn capabilities(&self) -> MountCapabilities {
+        MountCapabilities {
+            drive_letters: true,
+            directories: true,
+            helper_process: true,
+        }
+    }
+
+    fn available(&self) -> bool {
+        self.launcher.is_some()
+    }
+
+    async fn mount(&self, root: &Path, mount_point: &Path) -> Result<Box<dyn MountedVolume>> {
+        let launcher = self
+            .launcher
+            .as_ref()
+            .context("winfsp-launcher no está instalado")?;
+        info!(?root, ?mount_point, "Montando rootfs vía WinFSP");
+        let child = Command::new(launcher)
+            .args([
+                "--foreground",
+                "--FileSystemName",
+                "ContainerFS",
+                "--MountPoint",
+                mount_point.to_string_lossy().as_ref(),
+            ])
+            .arg(root)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("No se pudo lanzar winfsp-launcher")?;
+
+        Ok(Box::new(HelperVolume {
+            child: Some(child),
+            mount_point: mount_point.to_path_buf(),
+            release: None,
+            probe: |mount_point| mount_point.exists(),
+        }))
+    }
+}
+
+/// Dokany a través de `dokanctl.exe` (o `DOKANCTL`).
+pub struct DokanyProvider {
+    dokanctl: Option<PathBuf>,
+}
+
+impl DokanyProvider {
+    pub fn detect() -> Self {
+        Self {
+            dokanctl: find_tool("DOKANCTL", "dokanctl.exe"),
+        }
+    }
+}
+
+#[async_trait]
+impl MountProvider for DokanyProvider {
+    fn name(&self) -> &'static str {
+        "dokany"
+    }
+
+    fn capabilities(&self) -> MountCapabilities {
+        MountCapabilities {
+            drive_letters: true,
+            directories: true,
+            helper_process: false,
+        }
+    }
+
+    fn available(&self) -> bool {
+        self.dokanctl.is_some()
+    }
+
+    async fn mount(&self, root: &Path, mount_point: &Path) -> Result<Box<dyn MountedVolume>> {
+        let dokanctl = self
+            .dokanctl
+            .clone()
+            .context("dokanctl no está instalado")?;
+        info!(?root, ?mount_point, "Montando rootfs vía Dokany");
+        let status = tokio::process::Command::new(&dokanctl)
+            .args([
+                "/m",
+                "/r",
+                root.to_string_lossy().as_ref(),
+                "/l",
+                mount_point.to_string_lossy().as_ref(),
+            ])
+            .status()
+            .await
+            .context("No se pudo ejecutar dokanctl")?;
+        if !status.success() {
+            bail!("dokanctl /m terminó con {status}");
+        }
+
+        Ok(Box::new(DokanyVolume {
+            dokanctl,
+            mount_point: mount_point.to_path_buf(),
+            mounted: true,
+        }))
+    }
+}
+
+/// FUSE en Linux mediante `bindfs` (o `CTNR_FUSE_HELPER`) en primer plano; se
+/// desmonta con `fusermount3`/`fusermount`.
+pub struct FuseProvider {
+    helper: Option<PathBuf>,
+    fusermount: Option<PathBuf>,
+}
+
+impl FuseProvider {
+    pub fn detect() -> Self {
+        if !cfg!(target_os = "linux") {
+            return Self {
+                helper: None,
+                fusermount: None,
+            };
+        }
+        Self {
+            helper: find_tool("CTNR_FUSE_HELPER", "bindfs"),
+            fusermount: which::which("fusermount3")
+                .or_else(|_| which::which("fusermount"))
+                .ok(),
+        }
+    }
+}
+
+#[async_trait]
+impl MountProvider for FuseProvider {
+    fn name(&self) -> &'static str {
+        "fuse"
+    }
+
+    fn capabilities(&self) -> MountCapabilities {
+        MountCapabilities {
+            drive_letters: false,
+            directories: true,
+            helper_process: true,
+        }
+    }
+
+    fn available(&self) -> bool {
+        self.helper.is_some() && self.fusermount.is_some()
+    }
+
+    async fn mount(&self, root: &Path, mount_point: &Path) -> Result<Box<dyn MountedVolume>> {
+        let (Some(helper), Some(fusermount)) = (&self.helper, &self.fusermount) else {
+            bail!("FUSE no está disponible (faltan bindfs o fusermount)");
+        };
+        std::fs::create_dir_all(mount_point)?;
+        info!(?root, ?mount_point, "Montando rootfs vía FUSE");
+        let child = Command::new(helper)
+            .arg("-f")
+            .arg(root)
+            .arg(mount_point)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("No se pudo lanzar el helper FUSE")?;
+
+        Ok(Box::new(HelperVolume {
+            child: Some(child),
+            mount_point: mount_point.to_path_buf(),
+            release: Some(fusermount.clone()),
+            probe: fuse_mounted,
+        }))
+    }
+}
+
+/// Junction de directorio en Windows o enlace simbólico en el resto: el punto de
+/// montaje pasa a ser un alias de la carpeta del contenedor.
+pub struct BindProvider;
+
+#[async_trait]
+impl MountProvider for BindProvider {
+    fn name(&self) -> &'static str {
+        "bind"
+    }
+
+    fn capabilities(&self) -> MountCapabilities {
+        MountCapabilities {
+            drive_letters: false,
+            directories: true,
+            helper_process: false,
+        }
+    }
+
+    fn available(&self) -> bool {
+        true
+    }
+
+    async fn mount(&self, root: &Path, mount_point: &Path) -> Result<Box<dyn MountedVolume>> {
+        if mount_point.symlink_metadata().is_ok() {
+            bail!("{} ya existe", mount_point.display());
+        }
+        if let Some(parent) = mount_point.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("No se encontró {}", root.display()))?;
+        link_dir(&root, mount_point)?;
+        info!(?root, ?mount_point, "Carpeta enlazada");
+        Ok(Box::new(BindVolume {
+            root,
+            mount_point: mount_point.to_path_buf(),
+        }))
+    }
+}
+
+/// Volumen servido por un proceso auxiliar (WinFSP, FUSE); existe mientras el
+/// proceso vive.
+struct HelperVolume {
+    child: Option<Child>,
+    mount_point: PathBuf,
+    /// Herramienta que desmonta antes de esperar al proceso (`fusermount`).
+    release: Option<PathBuf>,
+    /// Si el punto de montaje ya sirve el volumen.
+    probe: fn(&Path) -> bool,
+}
+
+#[async_trait]
+impl MountedVolume for HelperVolume {
+    async fn unmount(&mut self) -> Result<()> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+        match &self.release {
+            Some(release) => {
+                let status = tokio::process::Command::new(release)
+                    .arg("-u")
+                    .arg(&self.mount_point)
+                    .status()
+                    .await
+                    .context("No se pudo ejecutar fusermount")?;
+                if !status.success() {
+                    self.child = Some(child);
+                    bail!("fusermount -u terminó con {status}");
+                }
+            }
+            None => {
+                let _ = child.kill();
+            }
+        }
+        tokio::task::spawn_blocking(move || child.wait())
+            .await
+            .context("El proceso del volumen no terminó")??;
+        Ok(())
+    }
+
+    /// Lo decide el punto de montaje, no el proceso: un helper que terminó
+    /// puede dejar el montaje colgado.
+    fn is_mounted(&self) -> bool {
+        (self.probe)(&self.mount_point)
+    }
+
+    async fn ready(&mut self) -> Result<bool> {
+        let Some(child) = self.child.as_mut() else {
+            bail!("El volumen ya no está montado");
+        };
+        if let Some(status) = child.try_wait()? {
+            self.child = None;
+            bail!("El proceso del volumen terminó ({status})");
+        }
+        Ok((self.probe)(&self.mount_point))
+    }
+}
+
+impl Drop for HelperVolume {
+    /// Desmontaje de emergencia: libera el punto de montaje antes de terminar
+    /// el proceso para no dejar un montaje FUSE colgado.
+    fn drop(&mut self) {
+        let Some(mut child) = self.child.take() else {
+            return;
+        };
+        if let Some(release) = &self.release {
+            let _ = Command::new(release)
+                .arg("-u")
+                .arg(&self.mount_point)
                 .stdout(Stdio::null())
                 .stderr(Stdio::null())
-                .spawn()
-                .context("No se pudo lanzar winfsp-launcher")?;
+                .status();
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
 
-            // winfsp tarda unos ms en crear el volumen
-            sleep(Duration::from_millis(500)).await;
+struct DokanyVolume {
+    dokanctl: PathBuf,
+    mount_point: PathBuf,
+    /// Hasta que `dokanctl /u` termina bien; si no, se desmonta al soltarlo.
+    mounted: bool,
+}
 
-            return Ok(Self {
-                mount_point,
-                child: Some(child),
-            });
+#[async_trait]
+impl MountedVolume for DokanyVolume {
+    async fn unmount(&mut self) -> Result<()> {
+        let status = tokio::process::Command::new(&self.dokanctl)
+            .arg("/u")
+            .arg(&self.mount_point)
+            .status()
+            .await
+            .context("No se pudo ejecutar dokanctl")?;
+        if !status.success() {
+            bail!("dokanctl /u terminó con {status}");
         }
+        self.mounted = false;
+        Ok(())
+    }
 
-        if let Some(dokanctl) = find_dokany() {
-            info!(?root, ?mount_point, "Montando rootfs vía Dokany");
-            Command::new(&dokanctl)
-                .args([
-                    "/m",
-                    "/r",
-                    root.to_string_lossy().as_ref(),
-                    "/l",
-                    mount_point.to_string_lossy().as_ref(),
-                ])
-                .status()
-                .context("No se pudo ejecutar dokanctl")?;
-
-            return Ok(Self {
-                mount_point,
-                child: None,
-            });
+    fn is_mounted(&self) -> bool {
+        self.mount_point.exists()
+    }
+}
+
+impl Drop for DokanyVolume {
+    fn drop(&mut self) {
+        if self.mounted {
+            let _ = Command::new(&self.dokanctl)
+                .arg("/u")
+                .arg(&self.mount_point)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
         }
+    }
+}
 
-        warn!("No se encontraron WinFSP ni Dokany; se continuará sin volumen montado.");
-        Ok(Self {
-            mount_point,
-            child: None,
-        })
+struct BindVolume {
+    root: PathBuf,
+    mount_point: PathBuf,
+}
+
+#[async_trait]
+impl MountedVolume for BindVolume {
+    async fn unmount(&mut self) -> Result<()> {
+        if self.mount_point.symlink_metadata().is_ok() {
+            unlink_dir(&self.mount_point)?;
+        }
+        Ok(())
+    }
+
+    fn is_mounted(&self) -> bool {
+        self.mount_point.symlink_metadata().is_ok()
+    }
+
+    async fn ready(&mut self) -> Result<bool> {
+        match self.mount_point.canonicalize() {
+            Ok(target) if target == self.root => Ok(true),
+            Ok(target) => bail!(
+                "{} apunta a {} en lugar de {}",
+                self.mount_point.display(),
+                target.display(),
+                self.root.display()
+            ),
+            Err(_) => Ok(false),
+        }
     }
 }
 
-impl Drop for MountSession {
+impl Drop for BindVolume {
     fn drop(&mut self) {
-        if let Some(child) = self.child.as_mut() {
-            let _ = child.kill();
+        if self.mount_point.symlink_metadata().is_ok() {
+            let _ = unlink_dir(&self.mount_point);
         }
-        // Dokany desmonta automáticamente al terminar el proceso.
     }
 }
 
-fn determine_mount_point(preferred: Option<char>) -> Result<PathBuf> {
-    if let Some(letter) = preferred {
-        return Ok(PathBuf::from(format!("{}:", letter)));
+#[cfg(windows)]
+fn link_dir(target: &Path, link: &Path) -> Result<()> {
+    let status = Command::new("cmd")
+        .args(["/C", "mklink", "/J"])
+        .arg(link)
+        .arg(target)
+        .stdout(Stdio::null())
+        .status()
+        .context("No se pudo ejecutar mklink")?;
+    if !status.success() {
+        bail!("mklink /J terminó con {status}");
     }
-    Ok(PathBuf::from(r"\\?\GLOBALROOT\device\ContainerFS"))
+    Ok(())
 }
 
-fn find_winfsp() -> Option<PathBuf> {
-    std::env::var_os("WINFSP_LAUNCHER")
-        .map(PathBuf::from)
-        .or_else(|| which::which("winfsp-launcher.exe").ok())
+#[cfg(not(windows))]
+fn link_dir(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)
+        .with_context(|| format!("No se pudo enlazar {}", link.display()))
+}
+
+/// Quita el enlace sin tocar la carpeta a la que apunta.
+fn unlink_dir(link: &Path) -> Result<()> {
+    #[cfg(windows)]
+    let removed = std::fs::remove_dir(link);
+    #[cfg(not(windows))]
+    let removed = std::fs::remove_file(link);
+    removed.with_context(|| format!("No se pudo quitar el enlace {}", link.display()))
+}
+
+/// Si `/proc/self/mountinfo` tiene un montaje en `mount_point` (Linux).
+fn fuse_mounted(mount_point: &Path) -> bool {
+    let Ok(mountinfo) = std::fs::read_to_string("/proc/self/mountinfo") else {
+        return false;
+    };
+    mountinfo_contains(&mountinfo, mount_point)
+}
+
+fn mountinfo_contains(mountinfo: &str, mount_point: &Path) -> bool {
+    // Quinto campo: punto de montaje, con espacios y similares en octal (`\040`).
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .any(|field| Path::new(&unescape_mountinfo(field)) == mount_point)
+}
+
+fn unescape_mountinfo(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        out.push_str(&rest[..index]);
+        let code = rest.get(index + 1..index + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
 }
 
-fn find_dokany() -> Option<PathBuf> {
-    std::env::var_os("DOKANCTL")
+fn find_tool(var: &str, binary: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
         .map(PathBuf::from)
-        .or_else(|| which::which("dokanctl.exe").ok())
+        .or_else(|| which::which(binary).ok())
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::*;
+    use std::{collections::BTreeSet, sync::Mutex};
+
+    /// Proveedor en memoria para las pruebas: anota qué raíces están montadas y
+    /// simula fallos al montar, al quedar listo y al desmontar.
+    pub struct TestProvider {
+        pub name: &'static str,
+        pub available: bool,
+        /// Raíces montadas; vaciarlo simula que el proveedor se cayó.
+        pub mounted: Arc<Mutex<BTreeSet<PathBuf>>>,
+        pub fail_mount: bool,
+        /// Falla solo al montar carpetas cuya ruta termina así.
+        pub fail_on: Option<&'static str>,
+        pub fail_unmount: bool,
+        /// Consultas de `ready` que responden `false` antes de quedar listo.
+        pub ready_after: u32,
+        /// Pasadas esas consultas, informa que el proceso del proveedor terminó.
+        pub crash: bool,
+    }
+
+    impl Default for TestProvider {
+        fn default() -> Self {
+            Self {
+                name: "fake",
+                available: true,
+                mounted: Arc::default(),
+                fail_mount: false,
+                fail_on: None,
+                fail_unmount: false,
+                ready_after: 0,
+                crash: false,
+            }
+        }
+    }
+
+    impl TestProvider {
+        pub fn is_mounted(&self, root: impl AsRef<Path>) -> bool {
+            self.mounted.lock().unwrap().contains(root.as_ref())
+        }
+    }
+
+    struct TestVolume {
+        root: PathBuf,
+        mounted: Arc<Mutex<BTreeSet<PathBuf>>>,
+        fail_unmount: bool,
+        polls: u32,
+        crash: bool,
+    }
+
+    #[async_trait]
+    impl MountProvider for TestProvider {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn capabilities(&self) -> MountCapabilities {
+            MountCapabilities {
+                drive_letters: true,
+                directories: true,
+                helper_process: false,
+            }
+        }
+
+        fn available(&self) -> bool {
+            self.available
+        }
+
+        async fn mount(&self, root: &Path, _: &Path) -> Result<Box<dyn MountedVolume>> {
+            if self.fail_mount {
+                bail!("sin driver");
+            }
+            if self.fail_on.is_some_and(|suffix| root.ends_with(suffix)) {
+                bail!("carpeta bloqueada");
+            }
+            self.mounted.lock().unwrap().insert(root.to_path_buf());
+            Ok(Box::new(TestVolume {
+                root: root.to_path_buf(),
+                mounted: self.mounted.clone(),
+                fail_unmount: self.fail_unmount,
+                polls: self.ready_after,
+                crash: self.crash,
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl MountedVolume for TestVolume {
+        async fn unmount(&mut self) -> Result<()> {
+            if self.fail_unmount {
+                bail!("volumen ocupado");
+            }
+            self.mounted.lock().unwrap().remove(&self.root);
+            Ok(())
+        }
+
+        fn is_mounted(&self) -> bool {
+            self.mounted.lock().unwrap().contains(&self.root)
+        }
+
+        async fn ready(&mut self) -> Result<bool> {
+            if self.polls > 0 {
+                self.polls -= 1;
+                return Ok(false);
+            }
+            if self.crash {
+                bail!("el proveedor terminó");
+            }
+            Ok(self.is_mounted())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::TestProvider;
+
+    fn providers(available: [bool; 3]) -> MountProviders {
+        let provider = |name, available| -> Arc<dyn MountProvider> {
+            Arc::new(TestProvider {
+                name,
+                available,
+                ..Default::default()
+            })
+        };
+        MountProviders {
+            providers: vec![
+                (MountProviderKind::WinFsp, provider("winfsp", available[0])),
+                (MountProviderKind::Dokany, provider("dokany", available[1])),
+                (MountProviderKind::Bind, provider("bind", available[2])),
+            ],
+        }
+    }
+
+    #[test]
+    fn selection_prefers_installed_providers_and_fails_explicitly() {
+        let all = providers([true, true, true]);
+        assert_eq!(
+            all.select(MountProviderKind::Auto).unwrap().name(),
+            "winfsp"
+        );
+        assert_eq!(
+            all.select(MountProviderKind::Dokany).unwrap().name(),
+            "dokany"
+        );
+
+        let only_dokany = providers([false, true, true]);
+        assert_eq!(
+            only_dokany.select(MountProviderKind::Auto).unwrap().name(),
+            "dokany"
+        );
+        let err = only_dokany.select(MountProviderKind::WinFsp).err().unwrap();
+        assert!(err.to_string().contains("winfsp no está disponible"));
+
+        // `auto` nunca cae en `bind` por su cuenta.
+        let bind_only = providers([false, false, true]);
+        assert!(bind_only.select(MountProviderKind::Auto).is_err());
+        assert_eq!(
+            bind_only.select(MountProviderKind::Bind).unwrap().name(),
+            "bind"
+        );
+        assert!(bind_only.select(MountProviderKind::Fuse).is_err());
+    }
+
+    #[tokio::test]
+    async fn bind_mounts_link_the_folder_and_unmount_cleanly() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("demo");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("config.yml"), "id: demo\n").unwrap();
+        let mount_point = dir.path().join("mnt/demo");
+
+        let mut volume = BindProvider.mount(&root, &mount_point).await.unwrap();
+        assert!(volume.is_mounted());
+        volume.health().await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(mount_point.join("config.yml")).unwrap(),
+            "id: demo\n"
+        );
+        assert!(BindProvider.mount(&root, &mount_point).await.is_err());
+
+        volume.unmount().await.unwrap();
+        assert!(!volume.is_mounted());
+        assert!(volume.health().await.is_err());
+        assert!(root.join("config.yml").exists());
+    }
+
+    #[tokio::test]
+    async fn mounts_wait_for_readiness_and_unwind_when_it_never_comes() {
+        let timeout = Duration::from_secs(2);
+        let provider = TestProvider {
+            ready_after: 3,
+            ..Default::default()
+        };
+        let session = MountSession::mount(
+            &provider,
+            "demo-1",
+            PathBuf::from("/tmp/ctnr-mounts/demo"),
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(session.provider(), "fake");
+        assert!(provider.is_mounted("demo-1"));
+
+        let provider = TestProvider {
+            ready_after: 1,
+            crash: true,
+            ..Default::default()
+        };
+        let crashed = MountSession::mount(
+            &provider,
+            "demo-2",
+            PathBuf::from("/tmp/ctnr-mounts/demo"),
+            timeout,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(format!("{crashed:#}").contains("el proveedor terminó"));
+        assert!(!provider.is_mounted("demo-2"));
+
+        let provider = TestProvider {
+            ready_after: u32::MAX,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let slow = MountSession::mount(
+            &provider,
+            "demo-3",
+            PathBuf::from("/tmp/ctnr-mounts/demo"),
+            Duration::from_millis(300),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(slow.to_string().contains("no estuvo accesible"));
+        assert!(started.elapsed() < timeout);
+        assert!(!provider.is_mounted("demo-3"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn helper_volumes_notice_a_crashed_provider() {
+        let child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let mut volume = HelperVolume {
+            child: Some(child),
+            mount_point: PathBuf::from("/nonexistent"),
+            release: None,
+            probe: |mount_point| mount_point.exists(),
+        };
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let err = volume.health().await.unwrap_err();
+        assert!(err.to_string().contains("terminó"), "{err}");
+        assert!(!volume.is_mounted());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn helper_volumes_check_the_mount_point_and_release_it_on_drop() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount_point = dir.path().join("mnt");
+        std::fs::create_dir(&mount_point).unwrap();
+        // `release` hace de `fusermount -u`: quita el punto de montaje.
+        let release = dir.path().join("release.sh");
+        std::fs::write(&release, "#!/bin/sh\nrmdir \"$2\"\n").unwrap();
+        std::fs::set_permissions(
+            &release,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+
+        let child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id();
+        let volume = HelperVolume {
+            child: Some(child),
+            mount_point: mount_point.clone(),
+            release: Some(release),
+            probe: |mount_point| mount_point.exists(),
+        };
+        assert!(volume.is_mounted());
+        drop(volume);
+        assert!(!mount_point.exists());
+        // El proceso ya se recogió: no queda ni como zombi.
+        assert!(!Path::new(&format!("/proc/{pid}")).exists());
+    }
+
+    #[test]
+    fn mountinfo_lookups_unescape_mount_points() {
+        let mountinfo = "\
+22 1 0:21 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+57 22 0:48 / /tmp/ctnr-mounts/mi\\040app rw,nosuid - fuse /srv/app rw,user_id=0
+";
+        assert!(mountinfo_contains(
+            mountinfo,
+            Path::new("/tmp/ctnr-mounts/mi app")
+        ));
+        assert!(!mountinfo_contains(
+            mountinfo,
+            Path::new("/tmp/ctnr-mounts")
+        ));
+    }
 }
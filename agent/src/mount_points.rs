@@ -0,0 +1,346 @@
+use crate::{config::MountConfig, mount::MountCapabilities};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, warn};
+
+/// Letras que se reparten automáticamente; A-C quedan para el sistema.
+const AUTO_LETTERS: std::ops::RangeInclusive<char> = 'D'..='Z';
+
+/// Indica qué letras de unidad ya están ocupadas en el equipo.
+pub trait DriveProbe: Send + Sync {
+    fn in_use(&self, letter: char) -> bool;
+}
+
+impl<F> DriveProbe for F
+where
+    F: Fn(char) -> bool + Send + Sync,
+{
+    fn in_use(&self, letter: char) -> bool {
+        self(letter)
+    }
+}
+
+/// Unidades montadas según Windows (`GetLogicalDrives`); fuera de Windows no
+/// hay letras ocupadas.
+pub struct SystemDrives;
+
+impl DriveProbe for SystemDrives {
+    #[cfg(windows)]
+    fn in_use(&self, letter: char) -> bool {
+        let mask = unsafe { windows::Win32::Storage::FileSystem::GetLogicalDrives() };
+        let bit = letter as u32 - 'A' as u32;
+        mask & (1 << bit) != 0
+    }
+
+    #[cfg(not(windows))]
+    fn in_use(&self, _letter: char) -> bool {
+        false
+    }
+}
+
+/// Puntos de montaje asignados, por contenedor (`mount.assignments`).
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Assignments {
+    containers: BTreeMap<String, PathBuf>,
+}
+
+/// Reparte un punto de montaje único por contenedor: una letra de unidad si el
+/// manifiesto la pide o `mount.preferred_drive` está configurado, o si no una
+/// carpeta `<mount.root>/<id>/root`. Las entradas del `MountPlan` van siempre a
+/// `<mount.root>/<id>/mounts/<alias>`. Las asignaciones se guardan para que cada
+/// contenedor recupere la misma tras reiniciar el agent.
+pub struct MountPointAllocator {
+    path: PathBuf,
+    root: PathBuf,
+    preferred_drive: Option<char>,
+    probe: Arc<dyn DriveProbe>,
+    assigned: Assignments,
+}
+
+impl MountPointAllocator {
+    pub fn new(config: &MountConfig) -> Self {
+        let path = config.assignments.clone();
+        let assigned = match std::fs::read(&path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|err| {
+                warn!(
+                    path = ?path,
+                    ?err,
+                    "Asignaciones de puntos de montaje ilegibles; se ignoran"
+                );
+                Assignments::default()
+            }),
+            Err(_) => Assignments::default(),
+        };
+        Self {
+            path,
+            root: config.root.clone(),
+            preferred_drive: config
+                .preferred_drive
+                .map(|letter| letter.to_ascii_uppercase()),
+            probe: Arc::new(SystemDrives),
+            assigned,
+        }
+    }
+
+    pub fn with_probe(mut self, probe: impl DriveProbe + 'static) -> Self {
+        self.probe = Arc::new(probe);
+        self
+    }
+
+    pub fn assigned(&self, container_id: &str) -> Option<&Path> {
+        self.assigned
+            .containers
+            .get(container_id)
+            .map(PathBuf::as_path)
+    }
+
+    /// Punto de montaje para `container_id`. `drive` es la letra que pide el
+    /// manifiesto: si está ocupada es un error, en lugar de elegir otra.
+    pub fn allocate(
+        &mut self,
+        container_id: &str,
+        capabilities: MountCapabilities,
+        drive: Option<char>,
+    ) -> Result<PathBuf> {
+        let drive = drive.map(normalize_letter).transpose()?;
+        let wants_letter = drive.is_some() || self.preferred_drive.is_some();
+        let mount_point = if capabilities.drive_letters && wants_letter {
+            drive_path(self.pick_letter(container_id, drive)?)
+        } else if capabilities.directories {
+            if drive.is_some() {
+                bail!("El proveedor no admite letras de unidad");
+            }
+            self.root.join(container_id).join("root")
+        } else {
+            bail!("El proveedor no admite letras de unidad ni carpetas");
+        };
+
+        if self.assigned(container_id) != Some(mount_point.as_path()) {
+            info!(container_id, ?mount_point, "Punto de montaje asignado");
+            self.assigned
+                .containers
+                .insert(container_id.to_string(), mount_point.clone());
+            self.save()?;
+        }
+        Ok(mount_point)
+    }
+
+    /// Carpeta donde se monta la entrada `alias` del plan (`%APPDATA%` →
+    /// `mounts/appdata`). No se guarda: depende solo del contenedor y el alias.
+    pub fn plan_point(&self, container_id: &str, alias: &str) -> PathBuf {
+        let name: String = alias
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        let name = if name.is_empty() {
+            "mount".into()
+        } else {
+            name
+        };
+        self.root.join(container_id).join("mounts").join(name)
+    }
+
+    /// Libera la asignación de un contenedor que ya no existe.
+    pub fn release(&mut self, container_id: &str) -> Result<()> {
+        if self.assigned.containers.remove(container_id).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn pick_letter(&self, container_id: &str, wanted: Option<char>) -> Result<char> {
+        if let Some(letter) = wanted {
+            if let Some(owner) = self.owner(letter, container_id) {
+                bail!("La unidad {letter}: ya está asignada al contenedor {owner}");
+            }
+            if self.probe.in_use(letter) && !self.holds(container_id, letter) {
+                bail!("La unidad {letter}: ya está en uso en este equipo");
+            }
+            return Ok(letter);
+        }
+
+        let previous = self
+            .assigned(container_id)
+            .and_then(letter_of)
+            .filter(|letter| !self.probe.in_use(*letter));
+        if let Some(letter) = previous {
+            return Ok(letter);
+        }
+        // Desde la preferida hasta la Z y después desde la D.
+        let start = self
+            .preferred_drive
+            .filter(|letter| AUTO_LETTERS.contains(letter))
+            .unwrap_or(*AUTO_LETTERS.start());
+        (start..=*AUTO_LETTERS.end())
+            .chain(*AUTO_LETTERS.start()..start)
+            .find(|letter| {
+                self.owner(*letter, container_id).is_none() && !self.probe.in_use(*letter)
+            })
+            .context("No quedan letras de unidad libres")
+    }
+
+    /// Otro contenedor al que ya se asignó `letter`.
+    fn owner(&self, letter: char, container_id: &str) -> Option<&str> {
+        self.assigned
+            .containers
+            .iter()
+            .find(|(id, path)| id.as_str() != container_id && letter_of(path) == Some(letter))
+            .map(|(id, _)| id.as_str())
+    }
+
+    fn holds(&self, container_id: &str, letter: char) -> bool {
+        self.assigned(container_id).and_then(letter_of) == Some(letter)
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_vec_pretty(&self.assigned)?)
+            .with_context(|| format!("No se pudo guardar {}", self.path.display()))
+    }
+}
+
+fn normalize_letter(letter: char) -> Result<char> {
+    let letter = letter.to_ascii_uppercase();
+    if !letter.is_ascii_uppercase() {
+        bail!("Letra de unidad inválida: {letter}");
+    }
+    Ok(letter)
+}
+
+fn drive_path(letter: char) -> PathBuf {
+    PathBuf::from(format!("{letter}:"))
+}
+
+/// La letra de un punto de montaje `X:`, si lo es.
+fn letter_of(path: &Path) -> Option<char> {
+    let path = path.to_str()?;
+    let mut chars = path.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some(':'), None) if letter.is_ascii_uppercase() => Some(letter),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LETTERS: MountCapabilities = MountCapabilities {
+        drive_letters: true,
+        directories: true,
+        helper_process: true,
+    };
+
+    const FOLDERS: MountCapabilities = MountCapabilities {
+        drive_letters: false,
+        directories: true,
+        helper_process: false,
+    };
+
+    fn config(dir: &Path, preferred_drive: Option<char>) -> MountConfig {
+        MountConfig {
+            root: dir.join("mnt"),
+            assignments: dir.join("state/mounts.json"),
+            preferred_drive,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn letters_are_unique_and_skip_drives_in_use() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut allocator = MountPointAllocator::new(&config(dir.path(), Some('x')))
+            .with_probe(|letter: char| letter == 'Y');
+
+        assert_eq!(
+            allocator.allocate("a", LETTERS, None).unwrap(),
+            PathBuf::from("X:")
+        );
+        // Y está ocupada en el equipo: salta a Z y luego vuelve a empezar en D.
+        assert_eq!(
+            allocator.allocate("b", LETTERS, None).unwrap(),
+            PathBuf::from("Z:")
+        );
+        assert_eq!(
+            allocator.allocate("c", LETTERS, None).unwrap(),
+            PathBuf::from("D:")
+        );
+        // Repetir la petición devuelve la misma asignación.
+        assert_eq!(
+            allocator.allocate("a", LETTERS, None).unwrap(),
+            PathBuf::from("X:")
+        );
+    }
+
+    #[test]
+    fn manifest_letters_are_honoured_or_rejected_on_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut allocator = MountPointAllocator::new(&config(dir.path(), None))
+            .with_probe(|letter: char| letter == 'M');
+
+        assert_eq!(
+            allocator.allocate("a", LETTERS, Some('q')).unwrap(),
+            PathBuf::from("Q:")
+        );
+        let taken = allocator.allocate("b", LETTERS, Some('Q')).unwrap_err();
+        assert!(taken.to_string().contains("contenedor a"), "{taken}");
+        let busy = allocator.allocate("b", LETTERS, Some('M')).unwrap_err();
+        assert!(busy.to_string().contains("en uso"), "{busy}");
+        assert!(allocator.allocate("b", FOLDERS, Some('R')).is_err());
+        assert!(allocator.allocate("b", LETTERS, Some('1')).is_err());
+
+        // Sin letra pedida ni preferida, cada contenedor tiene su carpeta.
+        assert_eq!(
+            allocator.allocate("b", LETTERS, None).unwrap(),
+            dir.path().join("mnt/b/root")
+        );
+        assert_eq!(
+            allocator.allocate("c", FOLDERS, None).unwrap(),
+            dir.path().join("mnt/c/root")
+        );
+        assert_eq!(
+            allocator.plan_point("c", "%LOCALAPPDATA%"),
+            dir.path().join("mnt/c/mounts/localappdata")
+        );
+    }
+
+    #[test]
+    fn assignments_survive_restarts_until_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = config(dir.path(), Some('P'));
+        let mut allocator = MountPointAllocator::new(&config);
+        allocator.allocate("a", LETTERS, None).unwrap();
+        allocator.allocate("b", LETTERS, None).unwrap();
+
+        // Tras reiniciar, "b" conserva su letra aunque "a" ya no la pida antes.
+        let mut restarted = MountPointAllocator::new(&config);
+        assert_eq!(
+            restarted.allocate("b", LETTERS, None).unwrap(),
+            PathBuf::from("Q:")
+        );
+        restarted.release("a").unwrap();
+
+        let mut restarted = MountPointAllocator::new(&config);
+        assert_eq!(restarted.assigned("a"), None);
+        assert_eq!(
+            restarted.allocate("c", LETTERS, None).unwrap(),
+            PathBuf::from("P:")
+        );
+
+        // Si la letra guardada la ocupa otro volumen del equipo, se reasigna.
+        let mut restarted =
+            MountPointAllocator::new(&config).with_probe(|letter: char| letter == 'Q');
+        assert_eq!(
+            restarted.allocate("b", LETTERS, None).unwrap(),
+            PathBuf::from("R:")
+        );
+    }
+}
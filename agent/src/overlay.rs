@@ -0,0 +1,442 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+/// Prefijo de los marcadores de borrado (`.wh.<nombre>`) escritos en la capa superior.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+/// Marcador que oculta todo el contenido de las capas inferiores de un directorio.
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayerKind {
+    Container,
+    BaseRuntime,
+    Host,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Layer {
+    pub name: String,
+    pub kind: LayerKind,
+    pub root: PathBuf,
+}
+
+impl Layer {
+    pub fn new(name: impl Into<String>, kind: LayerKind, root: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            root: root.into(),
+        }
+    }
+}
+
+/// Resultado de resolver una ruta relativa contra la pila de capas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// La ruta existe y la sirve la capa indicada.
+    Found {
+        layer: String,
+        kind: LayerKind,
+        path: PathBuf,
+    },
+    /// Una capa superior marcó la ruta como borrada.
+    Deleted { layer: String },
+    /// Ninguna capa contiene la ruta.
+    Missing,
+}
+
+/// Pila overlay con prioridad `container rootfs > base runtime > host`.
+/// Las capas inferiores son de solo lectura; toda escritura aterriza en `upper`
+/// (copy-on-write) y los borrados se registran con marcadores whiteout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerStack {
+    upper: Layer,
+    lowers: Vec<Layer>,
+}
+
+impl LayerStack {
+    pub fn new(upper: Layer) -> Self {
+        Self {
+            upper,
+            lowers: Vec::new(),
+        }
+    }
+
+    /// Añade una capa inferior con menor prioridad que las ya registradas.
+    pub fn with_lower(mut self, layer: Layer) -> Self {
+        self.lowers.push(layer);
+        self
+    }
+
+    pub fn upper(&self) -> &Layer {
+        &self.upper
+    }
+
+    pub fn lowers(&self) -> &[Layer] {
+        &self.lowers
+    }
+
+    fn layers(&self) -> impl Iterator<Item = &Layer> {
+        std::iter::once(&self.upper).chain(self.lowers.iter())
+    }
+
+    /// Indica qué capa sirve `relative` para una lectura.
+    pub fn resolve(&self, relative: impl AsRef<Path>) -> Result<Resolution> {
+        let relative = normalize(relative.as_ref())?;
+        for layer in self.layers() {
+            if has_whiteout(&layer.root, &relative) {
+                return Ok(Resolution::Deleted {
+                    layer: layer.name.clone(),
+                });
+            }
+            let candidate = layer.root.join(&relative);
+            if candidate.symlink_metadata().is_ok() {
+                return Ok(Resolution::Found {
+                    layer: layer.name.clone(),
+                    kind: layer.kind,
+                    path: candidate,
+                });
+            }
+            if has_opaque_ancestor(&layer.root, &relative) {
+                break;
+            }
+        }
+        Ok(Resolution::Missing)
+    }
+
+    /// Devuelve la ruta de la capa superior donde debe aterrizar una escritura sobre
+    /// `relative`, copiando antes el archivo desde una capa inferior si hace falta.
+    pub fn prepare_write(&self, relative: impl AsRef<Path>) -> Result<PathBuf> {
+        let relative = normalize(relative.as_ref())?;
+        let target = self.upper.root.join(&relative);
+        let resolution = self.resolve(&relative)?;
+
+        self.ensure_upper_parents(&relative)?;
+        clear_whiteout(&self.upper.root, &relative)?;
+
+        if let Resolution::Found { kind, path, .. } = resolution {
+            if kind != LayerKind::Container {
+                if path.is_dir() {
+                    fs::create_dir_all(&target)?;
+                } else {
+                    fs::copy(&path, &target).with_context(|| {
+                        format!("No se pudo copiar {} a la capa superior", path.display())
+                    })?;
+                }
+            }
+        }
+        Ok(target)
+    }
+
+    /// Crea un directorio en la capa superior. Si la ruta había sido borrada se marca
+    /// como opaca para que no reaparezca el contenido de las capas inferiores.
+    pub fn create_dir(&self, relative: impl AsRef<Path>) -> Result<PathBuf> {
+        let relative = normalize(relative.as_ref())?;
+        let was_deleted = matches!(self.resolve(&relative)?, Resolution::Deleted { .. });
+        let target = self.prepare_write(&relative)?;
+        fs::create_dir_all(&target)?;
+        if was_deleted {
+            fs::write(target.join(OPAQUE_MARKER), b"")?;
+        }
+        Ok(target)
+    }
+
+    /// Borra `relative` de la vista combinada: elimina la copia de la capa superior y,
+    /// si una capa inferior sigue sirviendo la ruta, deja un marcador whiteout.
+    pub fn remove(&self, relative: impl AsRef<Path>) -> Result<()> {
+        let relative = normalize(relative.as_ref())?;
+        let upper_path = self.upper.root.join(&relative);
+        if let Ok(meta) = upper_path.symlink_metadata() {
+            if meta.is_dir() {
+                fs::remove_dir_all(&upper_path)?;
+            } else {
+                fs::remove_file(&upper_path)?;
+            }
+        }
+
+        if let Resolution::Found { .. } = self.resolve(&relative)? {
+            self.ensure_upper_parents(&relative)?;
+            fs::write(whiteout_path(&self.upper.root, &relative)?, b"")?;
+        }
+        Ok(())
+    }
+
+    /// Lista el contenido combinado de un directorio respetando whiteouts y marcadores opacos.
+    pub fn read_dir(&self, relative: impl AsRef<Path>) -> Result<Vec<String>> {
+        let relative = normalize(relative.as_ref())?;
+        let mut visible = BTreeSet::new();
+        let mut hidden = BTreeSet::new();
+
+        for layer in self.layers() {
+            if has_whiteout(&layer.root, &relative) {
+                break;
+            }
+            let dir = layer.root.join(&relative);
+            if let Ok(entries) = fs::read_dir(&dir) {
+                for entry in entries {
+                    let name = entry?.file_name().to_string_lossy().into_owned();
+                    if name == OPAQUE_MARKER {
+                        continue;
+                    }
+                    if let Some(deleted) = name.strip_prefix(WHITEOUT_PREFIX) {
+                        hidden.insert(deleted.to_string());
+                    } else if !hidden.contains(&name) {
+                        visible.insert(name);
+                    }
+                }
+            }
+            if dir.join(OPAQUE_MARKER).exists() || has_opaque_ancestor(&layer.root, &relative) {
+                break;
+            }
+        }
+        Ok(visible.into_iter().collect())
+    }
+
+    /// Materializa en la capa superior los directorios padre de `relative`,
+    /// retirando los whiteouts que los ocultaban.
+    fn ensure_upper_parents(&self, relative: &Path) -> Result<()> {
+        let mut current = PathBuf::new();
+        let Some(parent) = relative.parent() else {
+            return Ok(());
+        };
+        for component in parent.components() {
+            current.push(component);
+            let dir = self.upper.root.join(&current);
+            if clear_whiteout(&self.upper.root, &current)? {
+                fs::create_dir_all(&dir)?;
+                fs::write(dir.join(OPAQUE_MARKER), b"")?;
+            }
+        }
+        fs::create_dir_all(self.upper.root.join(parent))?;
+        Ok(())
+    }
+}
+
+fn normalize(relative: &Path) -> Result<PathBuf> {
+    let mut clean = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(anyhow!(
+                    "La ruta {} debe ser relativa al contenedor",
+                    relative.display()
+                ))
+            }
+        }
+    }
+    Ok(clean)
+}
+
+fn whiteout_path(root: &Path, relative: &Path) -> Result<PathBuf> {
+    let name = relative
+        .file_name()
+        .ok_or_else(|| anyhow!("No se puede borrar la raíz de la capa"))?;
+    let marker = format!("{WHITEOUT_PREFIX}{}", name.to_string_lossy());
+    Ok(match relative.parent() {
+        Some(parent) => root.join(parent).join(marker),
+        None => root.join(marker),
+    })
+}
+
+/// Comprueba si la capa contiene un whiteout para `relative` o para alguno de sus ancestros.
+fn has_whiteout(root: &Path, relative: &Path) -> bool {
+    let mut current = PathBuf::new();
+    for component in relative.components() {
+        current.push(component);
+        if whiteout_path(root, &current)
+            .map(|marker| marker.exists())
+            .unwrap_or(false)
+        {
+            return true;
+        }
+    }
+    false
+}
+
+fn has_opaque_ancestor(root: &Path, relative: &Path) -> bool {
+    relative
+        .ancestors()
+        .skip(1)
+        .any(|ancestor| root.join(ancestor).join(OPAQUE_MARKER).exists())
+}
+
+fn clear_whiteout(root: &Path, relative: &Path) -> Result<bool> {
+    if relative.as_os_str().is_empty() {
+        return Ok(false);
+    }
+    let marker = whiteout_path(root, relative)?;
+    if marker.exists() {
+        fs::remove_file(marker)?;
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        stack: LayerStack,
+        upper: PathBuf,
+        runtime: PathBuf,
+        host: PathBuf,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let upper = dir.path().join("upper");
+        let runtime = dir.path().join("runtime");
+        let host = dir.path().join("host");
+        for root in [&upper, &runtime, &host] {
+            fs::create_dir_all(root).unwrap();
+        }
+        let stack = LayerStack::new(Layer::new("demo", LayerKind::Container, &upper))
+            .with_lower(Layer::new(
+                "container-runtime@0.3.0",
+                LayerKind::BaseRuntime,
+                &runtime,
+            ))
+            .with_lower(Layer::new("host", LayerKind::Host, &host));
+        Fixture {
+            _dir: dir,
+            stack,
+            upper,
+            runtime,
+            host,
+        }
+    }
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn served_by(resolution: Resolution) -> String {
+        match resolution {
+            Resolution::Found { layer, .. } => layer,
+            other => panic!("se esperaba una capa, se obtuvo {other:?}"),
+        }
+    }
+
+    #[test]
+    fn resolves_by_layer_priority() {
+        let fx = fixture();
+        write(&fx.host.join("Common/shared.dll"), "host");
+        write(&fx.runtime.join("Common/shared.dll"), "runtime");
+        write(&fx.host.join("Common/host-only.dll"), "host");
+
+        assert_eq!(
+            served_by(fx.stack.resolve("Common/shared.dll").unwrap()),
+            "container-runtime@0.3.0"
+        );
+        assert_eq!(
+            served_by(fx.stack.resolve("Common/host-only.dll").unwrap()),
+            "host"
+        );
+
+        write(&fx.upper.join("Common/shared.dll"), "container");
+        assert_eq!(
+            served_by(fx.stack.resolve("Common/shared.dll").unwrap()),
+            "demo"
+        );
+        assert_eq!(
+            fx.stack.resolve("Common/none.dll").unwrap(),
+            Resolution::Missing
+        );
+    }
+
+    #[test]
+    fn writes_copy_up_into_upper_layer() {
+        let fx = fixture();
+        write(&fx.runtime.join("App/settings.ini"), "base");
+
+        let target = fx.stack.prepare_write("App/settings.ini").unwrap();
+        assert_eq!(target, fx.upper.join("App/settings.ini"));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "base");
+
+        fs::write(&target, "changed").unwrap();
+        assert_eq!(
+            fs::read_to_string(fx.runtime.join("App/settings.ini")).unwrap(),
+            "base"
+        );
+        assert_eq!(
+            served_by(fx.stack.resolve("App/settings.ini").unwrap()),
+            "demo"
+        );
+    }
+
+    #[test]
+    fn removals_leave_whiteouts_and_writes_clear_them() {
+        let fx = fixture();
+        write(&fx.host.join("Tools/tool.exe"), "host");
+        write(&fx.host.join("Tools/keep.txt"), "host");
+
+        fx.stack.remove("Tools/tool.exe").unwrap();
+        assert!(fx.upper.join("Tools/.wh.tool.exe").exists());
+        assert_eq!(
+            fx.stack.resolve("Tools/tool.exe").unwrap(),
+            Resolution::Deleted {
+                layer: "demo".into()
+            }
+        );
+        assert_eq!(fx.stack.read_dir("Tools").unwrap(), vec!["keep.txt"]);
+
+        let target = fx.stack.prepare_write("Tools/tool.exe").unwrap();
+        fs::write(&target, "new").unwrap();
+        assert!(!fx.upper.join("Tools/.wh.tool.exe").exists());
+        assert_eq!(
+            served_by(fx.stack.resolve("Tools/tool.exe").unwrap()),
+            "demo"
+        );
+    }
+
+    #[test]
+    fn recreated_directories_become_opaque() {
+        let fx = fixture();
+        write(&fx.runtime.join("Cache/old.bin"), "old");
+
+        fx.stack.remove("Cache").unwrap();
+        assert!(matches!(
+            fx.stack.resolve("Cache/old.bin").unwrap(),
+            Resolution::Deleted { .. }
+        ));
+
+        fx.stack.create_dir("Cache").unwrap();
+        write(&fx.upper.join("Cache/new.bin"), "new");
+        assert_eq!(fx.stack.read_dir("Cache").unwrap(), vec!["new.bin"]);
+        assert_eq!(
+            fx.stack.resolve("Cache/old.bin").unwrap(),
+            Resolution::Missing
+        );
+    }
+
+    #[test]
+    fn merges_directory_listings() {
+        let fx = fixture();
+        write(&fx.host.join("Fonts/a.ttf"), "");
+        write(&fx.runtime.join("Fonts/b.ttf"), "");
+        write(&fx.upper.join("Fonts/c.ttf"), "");
+
+        assert_eq!(
+            fx.stack.read_dir("Fonts").unwrap(),
+            vec!["a.ttf", "b.ttf", "c.ttf"]
+        );
+    }
+
+    #[test]
+    fn rejects_paths_escaping_the_layer() {
+        let fx = fixture();
+        assert!(fx.stack.resolve("../outside").is_err());
+        assert!(fx.stack.prepare_write("/etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+/// Extensión de los paquetes de contenedor: un tar (ustar) sin comprimir con el
+/// contenido de la carpeta del contenedor y su `config.yml` en la raíz.
+pub const PACKAGE_EXTENSION: &str = "ctnr";
+
+const BLOCK: usize = 512;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PulledPackage {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Descarga el paquete a `<packages_dir>/<container_id>.ctnr`. Se escribe en un
+/// archivo temporal y solo se reemplaza el anterior si coincide con `sha256`
+/// (hexadecimal); sin un SHA-256 válido el paquete ni se descarga.
+pub async fn pull(
+    packages_dir: &Path,
+    container_id: &str,
+    url: &str,
+    sha256: &str,
+) -> Result<PulledPackage> {
+    let id = container_id;
+    if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+        bail!("Identificador de contenedor inválido: {id:?}");
+    }
+    if !is_sha256(sha256) {
+        bail!("El paquete {id} no trae un SHA-256 válido; no se descarga");
+    }
+    tokio::fs::create_dir_all(packages_dir)
+        .await
+        .with_context(|| format!("No se pudo crear {}", packages_dir.display()))?;
+    let path = packages_dir.join(format!("{id}.{PACKAGE_EXTENSION}"));
+    let partial = packages_dir.join(format!("{id}.{PACKAGE_EXTENSION}.part"));
+
+    let mut response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("No se pudo descargar {url}"))?;
+    let mut file = tokio::fs::File::create(&partial).await?;
+    let mut hasher = Sha256::new();
+    let mut bytes = 0;
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        bytes += chunk.len() as u64;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    drop(file);
+
+    let digest = hex::encode(hasher.finalize());
+    if !sha256.eq_ignore_ascii_case(&digest) {
+        let _ = tokio::fs::remove_file(&partial).await;
+        bail!("Checksum del paquete {id} distinto: esperado {sha256}, descargado {digest}");
+    }
+    tokio::fs::rename(&partial, &path).await?;
+    info!(container_id = id, path = ?path, bytes, "Paquete descargado");
+    Ok(PulledPackage {
+        path,
+        bytes,
+        sha256: digest,
+    })
+}
+
+/// SHA-256 en hexadecimal (64 dígitos, sin distinguir mayúsculas).
+pub fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Extrae un paquete en `dest` (que no debe existir). Solo admite archivos y
+/// carpetas con rutas relativas; enlaces, dispositivos o rutas que salgan de
+/// `dest` invalidan el paquete entero. Devuelve cuántos archivos extrajo.
+pub fn unpack(archive: &Path, dest: &Path) -> Result<usize> {
+    let mut reader = io::BufReader::new(
+        fs::File::open(archive)
+            .with_context(|| format!("No se pudo abrir {}", archive.display()))?,
+    );
+    fs::create_dir_all(dest).with_context(|| format!("No se pudo crear {}", dest.display()))?;
+
+    let mut files = 0;
+    let mut header = [0u8; BLOCK];
+    loop {
+        reader
+            .read_exact(&mut header)
+            .context("Paquete truncado: falta el bloque final")?;
+        if header.iter().all(|byte| *byte == 0) {
+            return Ok(files);
+        }
+        let entry = Header::parse(&header)?;
+        let target = dest.join(&entry.path);
+        match entry.kind {
+            b'0' | 0 => {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut file = fs::File::create(&target)
+                    .with_context(|| format!("No se pudo crear {}", target.display()))?;
+                let copied = io::copy(&mut (&mut reader).take(entry.size), &mut file)?;
+                if copied != entry.size {
+                    bail!("Paquete truncado en {}", entry.path.display());
+                }
+                files += 1;
+            }
+            b'5' => fs::create_dir_all(&target)?,
+            kind => bail!(
+                "Entrada no soportada ({}) en el paquete: {}",
+                kind as char,
+                entry.path.display()
+            ),
+        }
+        let padding = (BLOCK - (entry.size as usize % BLOCK)) % BLOCK;
+        io::copy(&mut (&mut reader).take(padding as u64), &mut io::sink())?;
+    }
+}
+
+struct Header {
+    path: PathBuf,
+    size: u64,
+    kind: u8,
+}
+
+impl Header {
+    fn parse(block: &[u8; BLOCK]) -> Result<Self> {
+        let stored = octal(&block[148..156]).context("Checksum de cabecera inválido")?;
+        let computed: u64 = block
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| if (148..156).contains(&i) { b' ' } else { *byte } as u64)
+            .sum();
+        if stored != computed {
+            bail!("Cabecera corrupta en el paquete");
+        }
+
+        let name = text(&block[0..100])?;
+        let prefix = if &block[257..262] == b"ustar" {
+            text(&block[345..500])?
+        } else {
+            ""
+        };
+        let raw = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}/{name}")
+        };
+        let path = Path::new(&raw);
+        if path.as_os_str().is_empty()
+            || !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            bail!("Ruta no permitida en el paquete: {raw}");
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: octal(&block[124..136]).context("Tamaño inválido en el paquete")?,
+            kind: block[156],
+        })
+    }
+}
+
+fn text(field: &[u8]) -> Result<&str> {
+    let end = field
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(field.len());
+    std::str::from_utf8(&field[..end]).context("Nombre no UTF-8 en el paquete")
+}
+
+fn octal(field: &[u8]) -> Result<u64> {
+    let digits = text(field)?.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    Ok(u64::from_str_radix(digits, 8)?)
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Construye un paquete ustar con los archivos indicados.
+    pub fn tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (path, body) in files {
+            let mut header = [0u8; 512];
+            header[..path.len()].copy_from_slice(path.as_bytes());
+            header[100..107].copy_from_slice(b"0000644");
+            header[124..135].copy_from_slice(format!("{:011o}", body.len()).as_bytes());
+            header[136..147].copy_from_slice(b"00000000000");
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[263..265].copy_from_slice(b"00");
+            header[148..156].copy_from_slice(b"        ");
+            let sum: u32 = header.iter().map(|byte| *byte as u32).sum();
+            header[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+            out.extend_from_slice(&header);
+            out.extend_from_slice(body);
+            out.resize(out.len().div_ceil(512) * 512, 0);
+        }
+        out.resize(out.len() + 1024, 0);
+        out
+    }
+
+    /// Servidor HTTP mínimo que responde siempre con `body`; devuelve su URL.
+    pub async fn serve(body: Vec<u8>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(&body).await;
+            }
+        });
+        format!("http://{addr}/package.ctnr")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{testing::*, *};
+
+    #[tokio::test]
+    async fn pulls_check_the_package_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = serve(b"paquete".to_vec()).await;
+        let expected = hex::encode(Sha256::digest(b"paquete"));
+
+        let rejected = pull(dir.path(), "demo", &url, &"0".repeat(64))
+            .await
+            .unwrap_err();
+        assert!(rejected.to_string().contains("Checksum"));
+        assert!(!dir.path().join("demo.ctnr").exists());
+        for invalid in ["", "00", &format!("{}zz", &expected[2..])] {
+            let rejected = pull(dir.path(), "demo", &url, invalid).await.unwrap_err();
+            assert!(
+                rejected.to_string().contains("SHA-256 válido"),
+                "{rejected}"
+            );
+        }
+        assert!(!dir.path().join("demo.ctnr.part").exists());
+
+        let pulled = pull(dir.path(), "demo", &url, &expected.to_uppercase())
+            .await
+            .unwrap();
+        assert_eq!(pulled.path, dir.path().join("demo.ctnr"));
+        assert_eq!(pulled.bytes, 7);
+        assert_eq!(fs::read(&pulled.path).unwrap(), b"paquete");
+
+        assert!(
+            pull(dir.path(), "../fuera", "http://127.0.0.1:1/", &expected)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn unpacks_files_and_rejects_escaping_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("demo.ctnr");
+        fs::write(
+            &archive,
+            tar(&[("config.yml", b"id: demo\n"), ("bin/app.exe", b"MZ")]),
+        )
+        .unwrap();
+        let dest = dir.path().join("demo");
+        assert_eq!(unpack(&archive, &dest).unwrap(), 2);
+        assert_eq!(fs::read(dest.join("config.yml")).unwrap(), b"id: demo\n");
+        assert_eq!(fs::read(dest.join("bin/app.exe")).unwrap(), b"MZ");
+
+        fs::write(&archive, tar(&[("../fuera.txt", b"x")])).unwrap();
+        let err = unpack(&archive, &dir.path().join("evil")).unwrap_err();
+        assert!(err.to_string().contains("Ruta no permitida"));
+        assert!(!dir.path().join("fuera.txt").exists());
+
+        let mut corrupt = tar(&[("config.yml", b"id: demo\n")]);
+        corrupt[0] = b'X';
+        fs::write(&archive, corrupt).unwrap();
+        assert!(unpack(&archive, &dir.path().join("corrupt")).is_err());
+    }
+}
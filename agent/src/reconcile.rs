@@ -0,0 +1,906 @@
+use crate::{
+    config::AgentConfig,
+    control::{ContainerSummary, ControlCommand, ControlRequest, ControlResponse},
+    package,
+    registry::load_manifest,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{info, warn};
+
+const MANAGED_FILE: &str = "managed.json";
+
+/// Espera antes de repetir una instalación o desinstalación fallida; se
+/// duplica en cada fallo hasta el máximo.
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(600);
+
+/// Contenedor que el backend quiere instalado en este agent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesiredContainer {
+    pub container_id: String,
+    /// Versión exigida; `None` si vale cualquiera.
+    pub version: Option<String>,
+    pub package_url: String,
+    /// SHA-256 del paquete en hexadecimal; un paquete sin él no se instala.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncStatus {
+    Installing,
+    InSync,
+    /// Hay otra versión instalada que el agent no administra y no va a tocar.
+    Drifted,
+    Failed,
+}
+
+impl SyncStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Installing => "installing",
+            Self::InSync => "in_sync",
+            Self::Drifted => "drifted",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Estado de un contenedor asignado, tal como se informa al backend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContainerSync {
+    pub container_id: String,
+    pub status: SyncStatus,
+    pub version: Option<String>,
+    pub message: Option<String>,
+}
+
+impl ContainerSync {
+    fn new(container_id: &str, status: SyncStatus, version: Option<String>) -> Self {
+        Self {
+            container_id: container_id.to_string(),
+            status,
+            version,
+            message: None,
+        }
+    }
+}
+
+/// Contenedores instalados por el reconciliador. Solo esos se reemplazan o
+/// eliminan; los que se copiaron a mano nunca se tocan.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Managed {
+    containers: BTreeMap<String, ManagedContainer>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManagedContainer {
+    root: PathBuf,
+    version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Keep(ContainerSync),
+    Install(DesiredContainer),
+    Remove(String),
+}
+
+/// Lleva las carpetas de contenedores al estado deseado por el backend:
+/// descarga e instala los paquetes asignados que faltan o cuya versión no
+/// coincide y elimina los que instaló y ya no están asignados.
+pub struct Reconciler {
+    install_root: PathBuf,
+    packages_dir: PathBuf,
+    managed: Managed,
+    /// Acciones fallidas por contenedor; no se repiten antes de su turno salvo
+    /// que cambie lo que pide el backend.
+    retries: BTreeMap<String, Retry>,
+}
+
+struct Retry {
+    action: Action,
+    attempts: u32,
+    next_attempt: Instant,
+    message: String,
+}
+
+impl Reconciler {
+    /// Los paquetes se instalan en la primera carpeta de `container_roots`.
+    pub fn new(config: &AgentConfig) -> Self {
+        let packages_dir = config.packages_dir.clone();
+        let managed_path = packages_dir.join(MANAGED_FILE);
+        let managed = match std::fs::read(&managed_path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|err| {
+                warn!(
+                    path = ?managed_path,
+                    ?err,
+                    "Registro de contenedores administrados ilegible; se ignora"
+                );
+                Managed::default()
+            }),
+            Err(_) => Managed::default(),
+        };
+        Self {
+            install_root: config
+                .container_roots
+                .first()
+                .cloned()
+                .unwrap_or_else(|| PathBuf::from("containers")),
+            packages_dir,
+            managed,
+            retries: BTreeMap::new(),
+        }
+    }
+
+    /// Arranca el bucle de reconciliación. Vuelve a reconciliar cada vez que
+    /// cambia el estado deseado o el inventario y cuando toca reintentar algo
+    /// que falló; antes del primer estado deseado no hace nada, para no
+    /// desinstalar por no haber hablado aún con el backend.
+    pub fn spawn(
+        self,
+        inventory: watch::Receiver<Vec<ContainerSummary>>,
+        control: mpsc::Sender<ControlCommand>,
+    ) -> ReconcilerHandle {
+        let (desired, desired_rx) = watch::channel(None);
+        let (reports_tx, reports) = watch::channel(Vec::new());
+        let task = tokio::spawn(self.run(desired_rx, inventory, control, reports_tx));
+        ReconcilerHandle {
+            desired,
+            reports,
+            task,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut desired: watch::Receiver<Option<Vec<DesiredContainer>>>,
+        mut inventory: watch::Receiver<Vec<ContainerSummary>>,
+        control: mpsc::Sender<ControlCommand>,
+        reports: watch::Sender<Vec<ContainerSync>>,
+    ) {
+        loop {
+            let retry = self.next_retry();
+            tokio::select! {
+                result = desired.changed() => if result.is_err() { return },
+                result = inventory.changed() => if result.is_err() { return },
+                _ = async {
+                    match retry {
+                        Some(at) => tokio::time::sleep_until(at).await,
+                        None => std::future::pending().await,
+                    }
+                } => info!("Reintentando la reconciliación"),
+            }
+            let Some(wanted) = desired.borrow_and_update().clone() else {
+                continue;
+            };
+            let installed = inventory.borrow_and_update().clone();
+
+            let actions = self.hold_back(self.plan(&wanted, &installed));
+            if actions
+                .iter()
+                .any(|action| matches!(action, Action::Install(_)))
+            {
+                reports.send_replace(
+                    actions
+                        .iter()
+                        .filter_map(|action| match action {
+                            Action::Keep(sync) => Some(sync.clone()),
+                            Action::Install(wanted) => Some(ContainerSync::new(
+                                &wanted.container_id,
+                                SyncStatus::Installing,
+                                None,
+                            )),
+                            Action::Remove(_) => None,
+                        })
+                        .collect(),
+                );
+            }
+
+            let (report, changed) = self.apply(actions, &control).await;
+            reports.send_replace(report);
+            if changed {
+                refresh(&control).await;
+            }
+        }
+    }
+
+    fn plan(&self, desired: &[DesiredContainer], installed: &[ContainerSummary]) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for wanted in desired {
+            let id = wanted.container_id.as_str();
+            let managed = self.managed.containers.get(id);
+            // Recién instalado, el inventario puede no reflejarlo todavía.
+            let current = installed
+                .iter()
+                .find(|container| container.id == id)
+                .map(|container| container.version.clone())
+                .or_else(|| {
+                    managed
+                        .filter(|managed| managed.root.is_dir())
+                        .map(|managed| managed.version.clone())
+                });
+            let matches =
+                |version: &Option<String>| wanted.version.is_none() || wanted.version == *version;
+            match current {
+                Some(version) if matches(&version) => {
+                    actions.push(Action::Keep(ContainerSync::new(
+                        id,
+                        SyncStatus::InSync,
+                        version,
+                    )));
+                }
+                Some(version) if managed.is_none() => {
+                    let mut sync = ContainerSync::new(id, SyncStatus::Drifted, version.clone());
+                    sync.message = Some(format!(
+                        "Copia local no administrada (versión {}); se requiere {}",
+                        version.as_deref().unwrap_or("sin versión"),
+                        wanted.version.as_deref().unwrap_or("cualquiera"),
+                    ));
+                    actions.push(Action::Keep(sync));
+                }
+                _ => actions.push(Action::Install(wanted.clone())),
+            }
+        }
+        for id in self.managed.containers.keys() {
+            if !desired.iter().any(|wanted| &wanted.container_id == id) {
+                actions.push(Action::Remove(id.clone()));
+            }
+        }
+        actions
+    }
+
+    /// Aparta las acciones que fallaron hace poco: una instalación pendiente se
+    /// informa con su último error y una desinstalación espera a su turno.
+    fn hold_back(&mut self, actions: Vec<Action>) -> Vec<Action> {
+        self.retries
+            .retain(|_, retry| actions.contains(&retry.action));
+        let now = Instant::now();
+        actions
+            .into_iter()
+            .filter_map(|action| {
+                let id = match &action {
+                    Action::Install(wanted) => wanted.container_id.as_str(),
+                    Action::Remove(id) => id.as_str(),
+                    Action::Keep(_) => return Some(action),
+                };
+                match self.retries.get(id) {
+                    Some(retry) if retry.next_attempt > now => match action {
+                        Action::Install(_) => {
+                            let mut sync = ContainerSync::new(id, SyncStatus::Failed, None);
+                            sync.message = Some(retry.message.clone());
+                            Some(Action::Keep(sync))
+                        }
+                        _ => None,
+                    },
+                    _ => Some(action),
+                }
+            })
+            .collect()
+    }
+
+    fn next_retry(&self) -> Option<Instant> {
+        self.retries.values().map(|retry| retry.next_attempt).min()
+    }
+
+    /// Anota el fallo y programa el siguiente intento con espera exponencial.
+    fn failed(&mut self, id: &str, action: Action, err: &anyhow::Error) {
+        let attempts = self
+            .retries
+            .get(id)
+            .filter(|retry| retry.action == action)
+            .map_or(1, |retry| retry.attempts + 1);
+        let delay = RETRY_BACKOFF
+            .saturating_mul(2u32.saturating_pow(attempts - 1))
+            .min(MAX_RETRY_BACKOFF);
+        self.retries.insert(
+            id.to_string(),
+            Retry {
+                action,
+                attempts,
+                next_attempt: Instant::now() + delay,
+                message: format!("{err:#}"),
+            },
+        );
+    }
+
+    /// Devuelve el informe de los contenedores asignados y si cambió el disco.
+    async fn apply(
+        &mut self,
+        actions: Vec<Action>,
+        control: &mpsc::Sender<ControlCommand>,
+    ) -> (Vec<ContainerSync>, bool) {
+        let mut report = Vec::new();
+        let mut changed = false;
+        for action in actions {
+            match action {
+                Action::Keep(sync) => report.push(sync),
+                Action::Install(wanted) => {
+                    let id = wanted.container_id.clone();
+                    match self.install(&wanted, control).await {
+                        Ok(version) => {
+                            info!(container_id = id.as_str(), ?version, "Contenedor instalado");
+                            self.retries.remove(&id);
+                            changed = true;
+                            report.push(ContainerSync::new(&id, SyncStatus::InSync, version));
+                        }
+                        Err(err) => {
+                            warn!(
+                                container_id = id.as_str(),
+                                error = format!("{err:#}"),
+                                "No se pudo instalar el contenedor"
+                            );
+                            let mut sync = ContainerSync::new(&id, SyncStatus::Failed, None);
+                            sync.message = Some(format!("{err:#}"));
+                            report.push(sync);
+                            self.failed(&id, Action::Install(wanted), &err);
+                        }
+                    }
+                }
+                Action::Remove(id) => match self.remove(&id, control).await {
+                    Ok(()) => {
+                        info!(container_id = id.as_str(), "Contenedor desinstalado");
+                        self.retries.remove(&id);
+                        changed = true;
+                    }
+                    Err(err) => {
+                        warn!(
+                            container_id = id.as_str(),
+                            error = format!("{err:#}"),
+                            "No se pudo desinstalar el contenedor"
+                        );
+                        self.failed(&id, Action::Remove(id.clone()), &err);
+                    }
+                },
+            }
+        }
+        (report, changed)
+    }
+
+    /// Descarga, extrae en una carpeta oculta, valida el manifiesto y solo
+    /// entonces sustituye la carpeta del contenedor. Si ya estaba instalado, el
+    /// agent lo detiene y desmonta antes y lo vuelve a preparar después.
+    async fn install(
+        &mut self,
+        wanted: &DesiredContainer,
+        control: &mpsc::Sender<ControlCommand>,
+    ) -> Result<Option<String>> {
+        let id = wanted.container_id.as_str();
+        let pulled =
+            package::pull(&self.packages_dir, id, &wanted.package_url, &wanted.sha256).await?;
+
+        tokio::fs::create_dir_all(&self.install_root).await?;
+        let staging = self.install_root.join(format!(".staging-{id}"));
+        remove_if_exists(&staging).await?;
+        let (archive, dest) = (pulled.path.clone(), staging.clone());
+        tokio::task::spawn_blocking(move || package::unpack(&archive, &dest))
+            .await
+            .map_err(|err| anyhow!(err))??;
+
+        let result = async {
+            let manifest = load_manifest(&staging).await?;
+            if manifest.id != id {
+                bail!("El paquete declara el id '{}', no '{id}'", manifest.id);
+            }
+            if wanted.version.is_some() && manifest.version != wanted.version {
+                bail!(
+                    "El paquete trae la versión {}, se esperaba {}",
+                    manifest.version.as_deref().unwrap_or("sin versión"),
+                    wanted.version.as_deref().unwrap_or_default()
+                );
+            }
+
+            let target = match self.managed.containers.get(id) {
+                Some(managed) => managed.root.clone(),
+                None => self.install_root.join(id),
+            };
+            let previous = target.exists();
+            if previous && !self.managed.containers.contains_key(id) {
+                bail!("{} ya existe y no la administra el agent", target.display());
+            }
+            let replaced = async {
+                if previous {
+                    release(control, id).await?;
+                }
+                replace_dir(
+                    &staging,
+                    &target,
+                    &self.install_root.join(format!(".old-{id}")),
+                )
+                .await
+            }
+            .await;
+            if previous {
+                if let Err(err) = prepare(control, id).await {
+                    warn!(
+                        container_id = id,
+                        error = format!("{err:#}"),
+                        "No se pudo volver a preparar el contenedor"
+                    );
+                }
+            }
+            replaced?;
+            Ok((target, manifest.version))
+        }
+        .await;
+        let (root, version) = match result {
+            Ok(installed) => installed,
+            Err(err) => {
+                let _ = remove_if_exists(&staging).await;
+                return Err(err);
+            }
+        };
+
+        let _ = tokio::fs::remove_file(&pulled.path).await;
+        self.managed.containers.insert(
+            id.to_string(),
+            ManagedContainer {
+                root,
+                version: version.clone(),
+            },
+        );
+        self.save().await?;
+        Ok(version)
+    }
+
+    /// Detiene y desmonta el contenedor antes de borrar su carpeta; si el agent
+    /// no puede, la carpeta se queda y se reintenta más tarde.
+    async fn remove(&mut self, id: &str, control: &mpsc::Sender<ControlCommand>) -> Result<()> {
+        let Some(managed) = self.managed.containers.get(id) else {
+            return Ok(());
+        };
+        if managed.root.exists() {
+            release(control, id).await?;
+        }
+        remove_if_exists(&managed.root).await?;
+        self.managed.containers.remove(id);
+        self.save().await
+    }
+
+    async fn save(&self) -> Result<()> {
+        tokio::fs::create_dir_all(&self.packages_dir).await?;
+        let path = self.packages_dir.join(MANAGED_FILE);
+        tokio::fs::write(&path, serde_json::to_vec_pretty(&self.managed)?)
+            .await
+            .with_context(|| format!("No se pudo guardar {}", path.display()))
+    }
+}
+
+/// Canal hacia el bucle de reconciliación; al soltarlo se detiene.
+pub struct ReconcilerHandle {
+    desired: watch::Sender<Option<Vec<DesiredContainer>>>,
+    reports: watch::Receiver<Vec<ContainerSync>>,
+    task: JoinHandle<()>,
+}
+
+impl ReconcilerHandle {
+    pub fn set_desired(&self, containers: Vec<DesiredContainer>) {
+        self.desired.send_replace(Some(containers));
+    }
+
+    /// Último informe de sincronización; cambia tras cada reconciliación.
+    pub fn reports(&self) -> watch::Receiver<Vec<ContainerSync>> {
+        self.reports.clone()
+    }
+}
+
+impl Drop for ReconcilerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Pide al agent que relea el registro para preparar lo instalado sin esperar
+/// al watcher.
+async fn refresh(control: &mpsc::Sender<ControlCommand>) {
+    let _ = call(control, ControlRequest::Refresh).await;
+}
+
+/// Pide al agent que detenga las ejecuciones del contenedor y desmonte sus
+/// volúmenes antes de tocar su carpeta.
+async fn release(control: &mpsc::Sender<ControlCommand>, id: &str) -> Result<()> {
+    let request = ControlRequest::Release {
+        container_id: id.to_string(),
+        timeout_ms: None,
+    };
+    match call(control, request).await? {
+        ControlResponse::Error { message } => {
+            Err(anyhow!(message).context(format!("No se pudo detener {id}")))
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn prepare(control: &mpsc::Sender<ControlCommand>, id: &str) -> Result<()> {
+    let request = ControlRequest::Prepare {
+        container_id: id.to_string(),
+    };
+    match call(control, request).await? {
+        ControlResponse::Error { message } => Err(anyhow!(message)),
+        _ => Ok(()),
+    }
+}
+
+async fn call(
+    control: &mpsc::Sender<ControlCommand>,
+    request: ControlRequest,
+) -> Result<ControlResponse> {
+    let (reply, response) = oneshot::channel();
+    control
+        .send(ControlCommand { request, reply })
+        .await
+        .map_err(|_| anyhow!("El agent se está apagando"))?;
+    response.await.map_err(|_| anyhow!("El agent no respondió"))
+}
+
+async fn replace_dir(staging: &Path, target: &Path, backup: &Path) -> Result<()> {
+    remove_if_exists(backup).await?;
+    let had_previous = target.exists();
+    if had_previous {
+        tokio::fs::rename(target, backup)
+            .await
+            .with_context(|| format!("No se pudo apartar {}", target.display()))?;
+    }
+    if let Err(err) = tokio::fs::rename(staging, target).await {
+        if had_previous {
+            let _ = tokio::fs::rename(backup, target).await;
+        }
+        return Err(
+            anyhow::Error::new(err).context(format!("No se pudo instalar en {}", target.display()))
+        );
+    }
+    remove_if_exists(backup).await
+}
+
+async fn remove_if_exists(path: &Path) -> Result<()> {
+    match tokio::fs::remove_dir_all(path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(anyhow::Error::new(err).context(format!("No se pudo eliminar {}", path.display())))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::testing::{serve, tar};
+    use sha2::{Digest, Sha256};
+    use std::sync::{Arc, Mutex};
+
+    /// URL y SHA-256 de un paquete.
+    type Package = (String, String);
+
+    /// Sirve un paquete con esos archivos.
+    async fn publish(files: &[(&str, &[u8])]) -> Package {
+        let body = tar(files);
+        let sha256 = hex::encode(Sha256::digest(&body));
+        (serve(body).await, sha256)
+    }
+
+    /// Paquete que no se llega a descargar.
+    fn unreachable(url: &str) -> Package {
+        (url.into(), "0".repeat(64))
+    }
+
+    fn desired(id: &str, version: Option<&str>, (url, sha256): &Package) -> DesiredContainer {
+        DesiredContainer {
+            container_id: id.into(),
+            version: version.map(String::from),
+            package_url: url.clone(),
+            sha256: sha256.clone(),
+        }
+    }
+
+    fn installed(id: &str, version: &str) -> ContainerSummary {
+        ContainerSummary {
+            id: id.into(),
+            name: id.into(),
+            version: Some(version.into()),
+            root: PathBuf::from(id),
+            prepared: true,
+            profiles: vec![],
+            mount: None,
+            error: None,
+        }
+    }
+
+    /// Canal de control que anota las peticiones; `release` falla si se pide.
+    fn control(fail_release: bool) -> (mpsc::Sender<ControlCommand>, Arc<Mutex<Vec<String>>>) {
+        let (control, mut requests) = mpsc::channel::<ControlCommand>(4);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            while let Some(ControlCommand { request, reply }) = requests.recv().await {
+                let response = match &request {
+                    ControlRequest::Release { container_id, .. } => {
+                        log.lock().unwrap().push(format!("release {container_id}"));
+                        if fail_release {
+                            ControlResponse::error("volumen ocupado")
+                        } else {
+                            ControlResponse::Runs { runs: vec![] }
+                        }
+                    }
+                    ControlRequest::Prepare { container_id } => {
+                        log.lock().unwrap().push(format!("prepare {container_id}"));
+                        ControlResponse::Containers { containers: vec![] }
+                    }
+                    _ => ControlResponse::Refreshed { changes: 0 },
+                };
+                let _ = reply.send(response);
+            }
+        });
+        (control, seen)
+    }
+
+    fn reconciler(dir: &Path) -> Reconciler {
+        let config = AgentConfig {
+            container_roots: vec![dir.join("containers")],
+            packages_dir: dir.join("packages"),
+            ..AgentConfig::default()
+        };
+        Reconciler::new(&config)
+    }
+
+    #[test]
+    fn plans_only_touch_managed_containers() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reconciler = reconciler(dir.path());
+        reconciler.managed.containers.insert(
+            "viejo".into(),
+            ManagedContainer {
+                root: dir.path().join("viejo"),
+                version: None,
+            },
+        );
+        reconciler.managed.containers.insert(
+            "gestionado".into(),
+            ManagedContainer {
+                root: dir.path().join("gestionado"),
+                version: Some("1.0".into()),
+            },
+        );
+
+        let u = unreachable("u");
+        let actions = reconciler.plan(
+            &[
+                desired("igual", Some("1.0"), &u),
+                desired("manual", Some("2.0"), &u),
+                desired("gestionado", Some("2.0"), &u),
+                desired("nuevo", None, &u),
+            ],
+            &[
+                installed("igual", "1.0"),
+                installed("manual", "1.0"),
+                installed("gestionado", "1.0"),
+            ],
+        );
+
+        assert_eq!(
+            actions[0],
+            Action::Keep(ContainerSync::new(
+                "igual",
+                SyncStatus::InSync,
+                Some("1.0".into())
+            ))
+        );
+        assert!(matches!(
+            &actions[1],
+            Action::Keep(sync) if sync.status == SyncStatus::Drifted && sync.message.is_some()
+        ));
+        assert_eq!(
+            actions[2],
+            Action::Install(desired("gestionado", Some("2.0"), &u))
+        );
+        assert_eq!(actions[3], Action::Install(desired("nuevo", None, &u)));
+        assert_eq!(actions[4], Action::Remove("viejo".into()));
+        assert_eq!(actions.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn installs_validated_packages_and_removes_unassigned_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let good = publish(&[
+            ("config.yml", b"id: demo\nname: Demo\nversion: \"2.0\"\n"),
+            ("bin/app.exe", b"MZ"),
+        ])
+        .await;
+        let wrong = publish(&[("config.yml", b"id: otro\nname: Otro\n")]).await;
+
+        let (control, seen) = control(false);
+        let mut reconciler = reconciler(dir.path());
+        let wanted = [desired("demo", Some("2.0"), &good)];
+        let (report, changed) = reconciler
+            .apply(reconciler.plan(&wanted, &[]), &control)
+            .await;
+        assert!(changed);
+        assert_eq!(
+            report,
+            [ContainerSync::new(
+                "demo",
+                SyncStatus::InSync,
+                Some("2.0".into())
+            )]
+        );
+        let root = dir.path().join("containers/demo");
+        assert_eq!(std::fs::read(root.join("bin/app.exe")).unwrap(), b"MZ");
+        assert!(!dir.path().join("packages/demo.ctnr").exists());
+
+        // El registro sobrevive a un reinicio y un paquete incorrecto no toca
+        // la instalación existente.
+        let mut reconciler = self::reconciler(dir.path());
+        assert!(reconciler
+            .plan(&wanted, &[])
+            .iter()
+            .all(|action| matches!(action, Action::Keep(_))));
+        let (report, _) = reconciler
+            .apply(
+                vec![Action::Install(desired("demo", None, &wrong))],
+                &control,
+            )
+            .await;
+        assert_eq!(report[0].status, SyncStatus::Failed);
+        assert!(report[0].message.as_deref().unwrap().contains("otro"));
+        assert!(root.join("bin/app.exe").exists());
+        assert!(!dir.path().join("containers/.staging-demo").exists());
+
+        // Un paquete inválido se descarta sin detener lo instalado.
+        assert!(seen.lock().unwrap().is_empty());
+
+        let (_, changed) = reconciler.apply(reconciler.plan(&[], &[]), &control).await;
+        assert!(changed);
+        assert!(!root.exists());
+        assert!(reconciler.managed.containers.is_empty());
+        assert_eq!(*seen.lock().unwrap(), ["release demo"]);
+    }
+
+    #[tokio::test]
+    async fn never_overwrites_folders_it_did_not_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let manual = dir.path().join("containers/demo");
+        std::fs::create_dir_all(&manual).unwrap();
+        std::fs::write(manual.join("config.yml"), "id: demo\nname: Demo\n").unwrap();
+        let package = publish(&[("config.yml", b"id: demo\nname: Demo\n")]).await;
+
+        let (control, _) = control(false);
+        let mut reconciler = reconciler(dir.path());
+        let (report, changed) = reconciler
+            .apply(
+                vec![Action::Install(desired("demo", None, &package))],
+                &control,
+            )
+            .await;
+        assert!(!changed);
+        assert_eq!(report[0].status, SyncStatus::Failed);
+        assert!(report[0]
+            .message
+            .as_deref()
+            .unwrap()
+            .contains("no la administra"));
+        assert_eq!(
+            std::fs::read_to_string(manual.join("config.yml")).unwrap(),
+            "id: demo\nname: Demo\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn packages_without_a_matching_checksum_are_not_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (url, sha256) = publish(&[("config.yml", b"id: demo\nname: Demo\n")]).await;
+        let (control, seen) = control(false);
+        let mut reconciler = reconciler(dir.path());
+
+        for (sha256, error) in [
+            ("0".repeat(64), "Checksum"),
+            (String::new(), "SHA-256 válido"),
+        ] {
+            let (report, changed) = reconciler
+                .apply(
+                    vec![Action::Install(desired(
+                        "demo",
+                        None,
+                        &(url.clone(), sha256),
+                    ))],
+                    &control,
+                )
+                .await;
+            assert!(!changed);
+            assert_eq!(report[0].status, SyncStatus::Failed);
+            assert!(report[0].message.as_deref().unwrap().contains(error));
+            assert!(!dir.path().join("containers/demo").exists());
+            assert!(!dir.path().join("packages/demo.ctnr").exists());
+        }
+        assert!(seen.lock().unwrap().is_empty());
+
+        let (report, changed) = reconciler
+            .apply(
+                vec![Action::Install(desired("demo", None, &(url, sha256)))],
+                &control,
+            )
+            .await;
+        assert!(changed);
+        assert_eq!(report[0].status, SyncStatus::InSync);
+    }
+
+    #[tokio::test]
+    async fn live_containers_are_released_before_replacing_or_removing_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let v1 = publish(&[("config.yml", b"id: demo\nname: Demo\nversion: \"1.0\"\n")]).await;
+        let v2 = publish(&[("config.yml", b"id: demo\nname: Demo\nversion: \"2.0\"\n")]).await;
+        let root = dir.path().join("containers/demo");
+
+        let (control, seen) = control(false);
+        let mut reconciler = reconciler(dir.path());
+        reconciler
+            .apply(vec![Action::Install(desired("demo", None, &v1))], &control)
+            .await;
+        assert!(seen.lock().unwrap().is_empty());
+        let (report, _) = reconciler
+            .apply(vec![Action::Install(desired("demo", None, &v2))], &control)
+            .await;
+        assert_eq!(report[0].version.as_deref(), Some("2.0"));
+        assert_eq!(*seen.lock().unwrap(), ["release demo", "prepare demo"]);
+
+        // Si el agent no puede detenerlo, la carpeta se queda y se reintenta.
+        let (busy, seen) = self::control(true);
+        let (_, changed) = reconciler
+            .apply(vec![Action::Remove("demo".into())], &busy)
+            .await;
+        assert!(!changed);
+        assert!(root.join("config.yml").exists());
+        assert!(reconciler.managed.containers.contains_key("demo"));
+        assert_eq!(*seen.lock().unwrap(), ["release demo"]);
+        assert!(reconciler.next_retry().is_some());
+    }
+
+    #[tokio::test]
+    async fn failed_installs_wait_for_their_retry() {
+        let dir = tempfile::tempdir().unwrap();
+        let (control, _) = control(false);
+        let mut reconciler = reconciler(dir.path());
+        let broken = desired("demo", None, &unreachable("http://127.0.0.1:9/demo.ctnr"));
+        let (report, _) = reconciler
+            .apply(vec![Action::Install(broken.clone())], &control)
+            .await;
+        assert_eq!(report[0].status, SyncStatus::Failed);
+        let first = reconciler.next_retry().unwrap();
+        assert!(first > Instant::now() + RETRY_BACKOFF / 2);
+
+        // Antes de su turno se informa el último error sin volver a descargar.
+        let wanted = [broken.clone()];
+        let actions = reconciler.hold_back(reconciler.plan(&wanted, &[]));
+        assert!(matches!(
+            &actions[..],
+            [Action::Keep(sync)] if sync.status == SyncStatus::Failed && sync.message == report[0].message
+        ));
+
+        // Cumplido el plazo se reintenta y la espera se duplica.
+        reconciler.retries.get_mut("demo").unwrap().next_attempt = Instant::now();
+        let actions = reconciler.hold_back(reconciler.plan(&wanted, &[]));
+        assert_eq!(actions, [Action::Install(broken)]);
+        reconciler.apply(actions, &control).await;
+        assert_eq!(reconciler.retries["demo"].attempts, 2);
+
+        // Otra asignación se intenta enseguida y lo que deja de pedirse se olvida.
+        let fixed = desired(
+            "demo",
+            Some("2.0"),
+            &unreachable("http://127.0.0.1:9/demo.ctnr"),
+        );
+        let wanted = [fixed];
+        let actions = reconciler.hold_back(reconciler.plan(&wanted, &[]));
+        assert_eq!(actions, [Action::Install(wanted[0].clone())]);
+        assert!(reconciler.retries.is_empty());
+    }
+}
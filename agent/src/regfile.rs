@@ -0,0 +1,834 @@
+use crate::winreg::{
+    HiveName, KeyPath, RegKey, RegValue, VirtualRegistry, REG_BINARY, REG_EXPAND_SZ, REG_MULTI_SZ,
+    REG_SZ,
+};
+use anyhow::{bail, ensure, Context, Result};
+
+const HEADER_V4: &str = "REGEDIT4";
+const HEADER_V5: &str = "Windows Registry Editor Version 5.00";
+/// Ancho a partir del cual `regedit` parte las líneas `hex:` con `\`.
+const HEX_LINE_WIDTH: usize = 77;
+
+/// Versión de un archivo `.reg`. `REGEDIT4` es ANSI y guarda las cadenas de
+/// `hex(1)`/`hex(2)`/`hex(7)` en ANSI; la 5.00 es UTF-16LE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegFileVersion {
+    Regedit4,
+    V5,
+}
+
+/// Bloque `[CLAVE]` (o `[-CLAVE]` si `delete`) con sus valores; un valor
+/// `None` es un borrado (`"nombre"=-`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegSection {
+    pub key: String,
+    pub delete: bool,
+    pub values: Vec<(String, Option<RegValue>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegFile {
+    pub version: RegFileVersion,
+    pub sections: Vec<RegSection>,
+}
+
+/// Qué se exporta del registro de un contenedor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportMode {
+    /// La vista combinada, como `reg export`.
+    Full,
+    /// Solo la capa del contenedor, con sus borrados como `[-CLAVE]` y `=-`.
+    Changes,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub keys: usize,
+    pub deleted_keys: usize,
+    pub values: usize,
+    pub deleted_values: usize,
+}
+
+impl RegFile {
+    pub fn new(version: RegFileVersion) -> Self {
+        Self {
+            version,
+            sections: Vec::new(),
+        }
+    }
+
+    /// Interpreta un `.reg` en UTF-16LE (con BOM), UTF-8 con BOM o ANSI.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = decode(bytes);
+        let mut lines = logical_lines(&text)
+            .into_iter()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'));
+        let version = match lines.next() {
+            Some((_, line)) if line == HEADER_V5 => RegFileVersion::V5,
+            Some((_, line)) if line == HEADER_V4 => RegFileVersion::Regedit4,
+            _ => bail!("Falta la cabecera `{HEADER_V5}` o `{HEADER_V4}`"),
+        };
+
+        let mut file = Self::new(version);
+        for (number, line) in lines {
+            file.parse_line(&line)
+                .with_context(|| format!("Línea {number}"))?;
+        }
+        Ok(file)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        if let Some(key) = line.strip_prefix('[') {
+            let key = key.strip_suffix(']').context("Falta el `]` de la clave")?;
+            let (key, delete) = match key.strip_prefix('-') {
+                Some(key) => (key, true),
+                None => (key, false),
+            };
+            ensure!(!key.is_empty(), "Clave vacía");
+            self.sections.push(RegSection {
+                key: key.to_string(),
+                delete,
+                values: Vec::new(),
+            });
+            return Ok(());
+        }
+        let value = parse_value_line(self.version, line)?;
+        match self.sections.last_mut() {
+            Some(section) if !section.delete => section.values.push(value),
+            Some(section) => bail!("[-{}] no puede tener valores", section.key),
+            None => bail!("Valor fuera de una clave"),
+        }
+        Ok(())
+    }
+
+    /// Texto del archivo con saltos de línea CRLF, como lo escribe `regedit`.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            match self.version {
+                RegFileVersion::V5 => HEADER_V5,
+                RegFileVersion::Regedit4 => HEADER_V4,
+            }
+            .to_string(),
+            String::new(),
+        ];
+        for section in &self.sections {
+            let dash = if section.delete { "-" } else { "" };
+            lines.push(format!("[{dash}{}]", section.key));
+            for (name, value) in &section.values {
+                format_value(self.version, name, value.as_ref(), &mut lines);
+            }
+            lines.push(String::new());
+        }
+        let mut text = lines.join("\r\n");
+        text.push_str("\r\n");
+        text
+    }
+
+    /// Bytes del archivo: UTF-16LE con BOM para la 5.00 y ANSI para REGEDIT4
+    /// (los caracteres sin equivalente quedan como `?`).
+    pub fn encode(&self) -> Vec<u8> {
+        let text = self.to_text();
+        match self.version {
+            RegFileVersion::V5 => [0xff, 0xfe]
+                .into_iter()
+                .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+                .collect(),
+            RegFileVersion::Regedit4 => encode_ansi(&text),
+        }
+    }
+
+    /// Aplica el archivo sobre la capa del contenedor. Antes de tocar nada se
+    /// comprueba que todas las claves estén en hives virtualizados.
+    pub fn apply(&self, registry: &mut VirtualRegistry) -> Result<ImportSummary> {
+        let targets = self
+            .sections
+            .iter()
+            .map(|section| {
+                HiveName::split(&section.key).with_context(|| {
+                    format!(
+                        "{} no está virtualizado (solo HKEY_CURRENT_USER y HKEY_LOCAL_MACHINE\\SOFTWARE)",
+                        section.key
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut summary = ImportSummary::default();
+        for (section, (hive, path)) in self.sections.iter().zip(targets) {
+            let overlay = registry.hive_mut(hive);
+            if section.delete {
+                if overlay.delete_tree(&path)? {
+                    summary.deleted_keys += 1;
+                }
+                continue;
+            }
+            overlay.create_key(&path);
+            summary.keys += 1;
+            for (name, value) in &section.values {
+                match value {
+                    Some(value) => {
+                        overlay.set_value(&path, name, value.clone());
+                        summary.values += 1;
+                    }
+                    None => {
+                        if overlay.delete_value(&path, name) {
+                            summary.deleted_values += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Exporta el registro de un contenedor, entero o a partir de `key`
+    /// (`HKEY_CURRENT_USER\Software\Vendor`).
+    pub fn export(
+        registry: &VirtualRegistry,
+        key: Option<&str>,
+        mode: ExportMode,
+        version: RegFileVersion,
+    ) -> Result<Self> {
+        let starts = match key {
+            Some(key) => {
+                vec![HiveName::split(key).with_context(|| format!("{key} no está virtualizado"))?]
+            }
+            None => HiveName::ALL
+                .into_iter()
+                .map(|hive| (hive, KeyPath::root()))
+                .collect(),
+        };
+        let mut file = Self::new(version);
+        for (hive, path) in starts {
+            let overlay = registry.hive(hive);
+            match mode {
+                ExportMode::Full => {
+                    ensure!(
+                        overlay.key_exists(&path) || key.is_none(),
+                        "La clave {} no existe",
+                        full_name(hive, &path)
+                    );
+                    export_full(&mut file, registry, hive, &path);
+                }
+                ExportMode::Changes => {
+                    if let Some(layer) = overlay.layer().key(&path) {
+                        export_changes(&mut file, hive, &path, layer);
+                    }
+                }
+            }
+        }
+        Ok(file)
+    }
+}
+
+fn full_name(hive: HiveName, path: &KeyPath) -> String {
+    if path.is_root() {
+        hive.to_string()
+    } else {
+        format!("{hive}\\{path}")
+    }
+}
+
+fn export_full(file: &mut RegFile, registry: &VirtualRegistry, hive: HiveName, path: &KeyPath) {
+    let overlay = registry.hive(hive);
+    let Some(values) = overlay.values(path) else {
+        return;
+    };
+    let values = values
+        .into_iter()
+        .map(|(name, value)| (name, Some(value)))
+        .collect::<Vec<_>>();
+    let subkeys = overlay.subkeys(path).unwrap_or_default();
+    // Las raíces de hive vacías no aportan nada y ensucian el archivo.
+    if !(path.is_root() && values.is_empty()) {
+        file.sections.push(RegSection {
+            key: full_name(hive, path),
+            delete: false,
+            values,
+        });
+    }
+    for name in subkeys {
+        export_full(file, registry, hive, &path.join(&name));
+    }
+}
+
+fn export_changes(file: &mut RegFile, hive: HiveName, path: &KeyPath, layer: &RegKey) {
+    let key = full_name(hive, path);
+    if layer.opaque {
+        file.sections.push(RegSection {
+            key: key.clone(),
+            delete: true,
+            values: Vec::new(),
+        });
+    }
+    let mut values: Vec<(String, Option<RegValue>)> = layer
+        .deleted_values
+        .iter()
+        .map(|name| (name.to_string(), None))
+        .collect();
+    values.extend(
+        layer
+            .values
+            .iter()
+            .map(|(name, value)| (name.to_string(), Some(value.clone()))),
+    );
+    if !values.is_empty() || layer.opaque || (layer.subkeys.is_empty() && !path.is_root()) {
+        file.sections.push(RegSection {
+            key,
+            delete: false,
+            values,
+        });
+    }
+    for name in layer.deleted_subkeys.iter() {
+        file.sections.push(RegSection {
+            key: full_name(hive, &path.join(name)),
+            delete: true,
+            values: Vec::new(),
+        });
+    }
+    for (name, subkey) in layer.subkeys.iter() {
+        export_changes(file, hive, &path.join(name), subkey);
+    }
+}
+
+fn parse_value_line(version: RegFileVersion, line: &str) -> Result<(String, Option<RegValue>)> {
+    let (name, rest) = if let Some(rest) = line.strip_prefix('@') {
+        (String::new(), rest)
+    } else if let Some(rest) = line.strip_prefix('"') {
+        parse_quoted(rest)?
+    } else {
+        bail!("Se esperaba `\"nombre\"=` o `@=`");
+    };
+    let data = rest
+        .trim_start()
+        .strip_prefix('=')
+        .context("Falta el `=` del valor")?
+        .trim_start();
+    Ok((name, parse_data(version, data)?))
+}
+
+fn parse_data(version: RegFileVersion, data: &str) -> Result<Option<RegValue>> {
+    if data == "-" {
+        return Ok(None);
+    }
+    if let Some(rest) = data.strip_prefix('"') {
+        let (text, rest) = parse_quoted(rest)?;
+        ensure!(rest.trim().is_empty(), "Texto sobrante tras la cadena");
+        return Ok(Some(RegValue::Sz(text)));
+    }
+    let lower = data.to_ascii_lowercase();
+    if let Some(number) = lower.strip_prefix("dword:") {
+        let number = u32::from_str_radix(number.trim(), 16)
+            .with_context(|| format!("dword inválido: {number}"))?;
+        return Ok(Some(RegValue::Dword(number)));
+    }
+    let (kind, bytes) = if let Some(bytes) = lower.strip_prefix("hex:") {
+        (REG_BINARY, bytes)
+    } else if let Some(rest) = lower.strip_prefix("hex(") {
+        let (kind, bytes) = rest.split_once("):").context("Se esperaba `hex(tipo):`")?;
+        let kind =
+            u32::from_str_radix(kind, 16).with_context(|| format!("Tipo hex inválido: {kind}"))?;
+        (kind, bytes)
+    } else {
+        bail!("Dato no reconocido: {data}");
+    };
+    let bytes = parse_hex(bytes)?;
+    let value = match (version, kind) {
+        // REGEDIT4 guarda estas cadenas en ANSI con terminador de un byte.
+        (RegFileVersion::Regedit4, REG_SZ | REG_EXPAND_SZ | REG_MULTI_SZ) => {
+            let strings: Vec<String> = bytes
+                .split(|byte| *byte == 0)
+                .take_while(|item| !item.is_empty())
+                .map(decode_ansi)
+                .collect();
+            let value = match kind {
+                REG_MULTI_SZ => RegValue::MultiSz(strings),
+                REG_EXPAND_SZ => RegValue::ExpandSz(strings.into_iter().next().unwrap_or_default()),
+                _ => RegValue::Sz(strings.into_iter().next().unwrap_or_default()),
+            };
+            // Como en `from_bytes_lossless`: lo que no se escribiría igual se
+            // conserva en bruto.
+            if ansi_bytes(&value).as_deref() == Some(&bytes[..]) {
+                value
+            } else {
+                RegValue::Other { kind, data: bytes }
+            }
+        }
+        _ => RegValue::from_bytes_lossless(kind, &bytes),
+    };
+    Ok(Some(value))
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|byte| !byte.is_empty())
+        .map(|byte| u8::from_str_radix(byte, 16).with_context(|| format!("Byte inválido: {byte}")))
+        .collect()
+}
+
+/// Cadena entre comillas con escapes `\\` y `\"`; devuelve también el resto
+/// de la línea tras la comilla de cierre.
+fn parse_quoted(text: &str) -> Result<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => {
+                let (_, escaped) = chars.next().context("Escape incompleto")?;
+                value.push(escaped);
+            }
+            '"' => return Ok((value, &text[index + 1..])),
+            c => value.push(c),
+        }
+    }
+    bail!("Falta la comilla de cierre")
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn format_value(
+    version: RegFileVersion,
+    name: &str,
+    value: Option<&RegValue>,
+    lines: &mut Vec<String>,
+) {
+    let name = if name.is_empty() {
+        "@".to_string()
+    } else {
+        quote(name)
+    };
+    let (prefix, bytes) = match value {
+        None => return lines.push(format!("{name}=-")),
+        // Los saltos de línea y los NUL no caben entre comillas: van como `hex(1):`.
+        Some(RegValue::Sz(text)) if !text.contains(['\r', '\n', '\0']) => {
+            return lines.push(format!("{name}={}", quote(text)))
+        }
+        Some(RegValue::Dword(number)) => return lines.push(format!("{name}=dword:{number:08x}")),
+        Some(RegValue::Binary(data)) => ("hex:".to_string(), data.clone()),
+        Some(value) => {
+            let ansi = match version {
+                RegFileVersion::Regedit4 => ansi_bytes(value),
+                RegFileVersion::V5 => None,
+            };
+            (
+                format!("hex({:x}):", value.kind()),
+                ansi.unwrap_or_else(|| value.to_bytes()),
+            )
+        }
+    };
+
+    let mut line = format!("{name}={prefix}");
+    for (index, byte) in bytes.iter().enumerate() {
+        let last = index + 1 == bytes.len();
+        let piece = if last {
+            format!("{byte:02x}")
+        } else {
+            format!("{byte:02x},")
+        };
+        if line.len() + piece.len() > HEX_LINE_WIDTH {
+            line.push('\\');
+            lines.push(std::mem::replace(&mut line, "  ".to_string()));
+        }
+        line.push_str(&piece);
+    }
+    lines.push(line);
+}
+
+/// Datos de una cadena como los guarda REGEDIT4: ANSI con terminador de un
+/// byte (y uno más al final de `REG_MULTI_SZ`); `None` si no es una cadena.
+fn ansi_bytes(value: &RegValue) -> Option<Vec<u8>> {
+    let (strings, multi) = match value {
+        RegValue::Sz(text) | RegValue::ExpandSz(text) => (std::slice::from_ref(text), false),
+        RegValue::MultiSz(items) => (items.as_slice(), true),
+        _ => return None,
+    };
+    let mut bytes: Vec<u8> = strings
+        .iter()
+        .flat_map(|item| encode_ansi(item).into_iter().chain([0]))
+        .collect();
+    if multi {
+        bytes.push(0);
+    }
+    Some(bytes)
+}
+
+/// Líneas con su número (desde 1), uniendo las que terminan en `\` y quitando
+/// los espacios de los extremos.
+fn logical_lines(text: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (index, raw) in text.lines().enumerate() {
+        let raw = raw.trim();
+        let (number, mut line) = pending.take().unwrap_or((index + 1, String::new()));
+        let continues = !raw.starts_with('[') && raw.ends_with('\\');
+        line.push_str(raw.strip_suffix('\\').filter(|_| continues).unwrap_or(raw));
+        if continues {
+            pending = Some((number, line));
+        } else {
+            lines.push((number, line));
+        }
+    }
+    lines.extend(pending);
+    lines
+}
+
+/// Caracteres de Windows-1252 en 0x80-0x9F; el resto coincide con Latin-1.
+const CP1252_HIGH: [u16; 32] = [
+    0x20ac, 0x0081, 0x201a, 0x0192, 0x201e, 0x2026, 0x2020, 0x2021, 0x02c6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008d, 0x017d, 0x008f, 0x0090, 0x2018, 0x2019, 0x201c, 0x201d, 0x2022, 0x2013, 0x2014,
+    0x02dc, 0x2122, 0x0161, 0x203a, 0x0153, 0x009d, 0x017e, 0x0178,
+];
+
+fn decode(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xff, 0xfe]) {
+        let units: Vec<u16> = utf16
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if let Some(utf8) = bytes.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        String::from_utf8_lossy(utf8).into_owned()
+    } else {
+        decode_ansi(bytes)
+    }
+}
+
+fn decode_ansi(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| match byte {
+            0x80..=0x9f => {
+                char::from_u32(CP1252_HIGH[(byte - 0x80) as usize] as u32).expect("tabla válida")
+            }
+            byte => char::from(*byte),
+        })
+        .collect()
+}
+
+fn encode_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c as u32 {
+            code @ (0..=0x7f | 0xa0..=0xff) => code as u8,
+            code => CP1252_HIGH
+                .iter()
+                .position(|high| *high as u32 == code)
+                .map_or(b'?', |index| 0x80 + index as u8),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::winreg::save_hive;
+
+    fn utf16(text: &str) -> Vec<u8> {
+        [0xff, 0xfe]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect()
+    }
+
+    fn path(path: &str) -> KeyPath {
+        KeyPath::parse(path).unwrap()
+    }
+
+    /// Registro con una base que tiene `Software\Vendor\App` y `...\Legacy`.
+    fn registry(dir: &std::path::Path) -> VirtualRegistry {
+        let mut base = RegKey::default();
+        let app = base.create(&path(r"Software\Vendor\App"));
+        app.values.insert("Telemetry", RegValue::Dword(1));
+        app.values.insert("Name", RegValue::Sz("App".into()));
+        base.create(&path(r"Software\Vendor\Legacy\Old"));
+        save_hive(&dir.join("base/HKCU.json"), &base).unwrap();
+        VirtualRegistry::open(&dir.join("user"), Some(&dir.join("base"))).unwrap()
+    }
+
+    #[test]
+    fn parses_unicode_files_with_every_value_type() {
+        let text = "Windows Registry Editor Version 5.00\r\n\
+            \r\n\
+            ; comentario\r\n\
+            [HKEY_CURRENT_USER\\Software\\Vendor\\App]\r\n\
+            @=\"C:\\\\Apps \\\"quoted\\\" ✓\"\r\n\
+            \"Count\"=dword:0000002A\r\n\
+            \"Blob\"=hex:de,ad,\\\r\n\
+            \x20 be,ef\r\n\
+            \"Path\"=hex(2):25,00,54,00,45,00,4d,00,50,00,25,00,00,00\r\n\
+            \"List\"=hex(7):61,00,00,00,62,00,00,00,00,00\r\n\
+            \"Big\"=hex(b):01,00,00,00,00,00,00,00\r\n\
+            \"None\"=hex(0):\r\n\
+            \"Gone\"=-\r\n\
+            \r\n\
+            [-HKEY_CURRENT_USER\\Software\\Vendor\\Legacy]\r\n";
+        let file = RegFile::parse(&utf16(text)).unwrap();
+        assert_eq!(file.version, RegFileVersion::V5);
+        assert_eq!(
+            file.sections[0].values,
+            [
+                (
+                    String::new(),
+                    Some(RegValue::Sz("C:\\Apps \"quoted\" ✓".into()))
+                ),
+                ("Count".into(), Some(RegValue::Dword(42))),
+                (
+                    "Blob".into(),
+                    Some(RegValue::Binary(vec![0xde, 0xad, 0xbe, 0xef]))
+                ),
+                ("Path".into(), Some(RegValue::ExpandSz("%TEMP%".into()))),
+                (
+                    "List".into(),
+                    Some(RegValue::MultiSz(vec!["a".into(), "b".into()]))
+                ),
+                ("Big".into(), Some(RegValue::Qword(1))),
+                (
+                    "None".into(),
+                    Some(RegValue::Other {
+                        kind: 0,
+                        data: Vec::new()
+                    })
+                ),
+                ("Gone".into(), None),
+            ]
+        );
+        assert_eq!(
+            file.sections[1],
+            RegSection {
+                key: r"HKEY_CURRENT_USER\Software\Vendor\Legacy".into(),
+                delete: true,
+                values: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn regedit4_files_are_ansi_throughout() {
+        // "é" es 0xE9 y "€" 0x80 en Windows-1252.
+        let mut text =
+            b"REGEDIT4\r\n\r\n[HKEY_CURRENT_USER\\Software\\Vendor]\r\n\"Caf\xe9\"=\"\x80 5\"\r\n"
+                .to_vec();
+        text.extend_from_slice(
+            b"\"Path\"=hex(2):25,41,25,00\r\n\"List\"=hex(7):78,00,e9,00,00\r\n",
+        );
+        let file = RegFile::parse(&text).unwrap();
+        assert_eq!(file.version, RegFileVersion::Regedit4);
+        assert_eq!(
+            file.sections[0].values,
+            [
+                ("Café".into(), Some(RegValue::Sz("€ 5".into()))),
+                ("Path".into(), Some(RegValue::ExpandSz("%A%".into()))),
+                (
+                    "List".into(),
+                    Some(RegValue::MultiSz(vec!["x".into(), "é".into()]))
+                ),
+            ]
+        );
+        assert_eq!(RegFile::parse(&file.encode()).unwrap(), file);
+    }
+
+    #[test]
+    fn imports_apply_values_and_deletions_to_the_layer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = registry(dir.path());
+        let text = "Windows Registry Editor Version 5.00\r\n\
+            [HKEY_CURRENT_USER\\Software\\Vendor\\App]\r\n\
+            \"Telemetry\"=-\r\n\
+            \"Missing\"=-\r\n\
+            \"Version\"=dword:00000002\r\n\
+            [-HKEY_CURRENT_USER\\Software\\Vendor\\Legacy]\r\n\
+            [HKEY_LOCAL_MACHINE\\SOFTWARE\\Vendor]\r\n\
+            @=\"machine\"\r\n";
+        let summary = RegFile::parse(&utf16(text))
+            .unwrap()
+            .apply(&mut registry)
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                keys: 2,
+                deleted_keys: 1,
+                values: 2,
+                deleted_values: 1,
+            }
+        );
+        let hkcu = registry.hive(HiveName::CurrentUser);
+        let app = path(r"Software\Vendor\App");
+        assert_eq!(hkcu.query_value(&app, "Telemetry"), None);
+        assert_eq!(hkcu.query_value(&app, "Version"), Some(&RegValue::Dword(2)));
+        assert!(!hkcu.key_exists(&path(r"Software\Vendor\Legacy")));
+        assert_eq!(
+            registry
+                .hive(HiveName::LocalMachineSoftware)
+                .query_value(&path("Vendor"), ""),
+            Some(&RegValue::Sz("machine".into()))
+        );
+
+        // Nada se aplica si alguna clave no está virtualizada.
+        let outside = "REGEDIT4\r\n[HKEY_CURRENT_USER\\Software\\X]\r\n\"a\"=\"b\"\r\n\
+            [HKEY_LOCAL_MACHINE\\SYSTEM\\CurrentControlSet]\r\n";
+        let err = RegFile::parse(outside.as_bytes())
+            .unwrap()
+            .apply(&mut registry)
+            .unwrap_err();
+        assert!(err.to_string().contains("SYSTEM"), "{err}");
+        assert!(!registry
+            .hive(HiveName::CurrentUser)
+            .key_exists(&path(r"Software\X")));
+    }
+
+    #[test]
+    fn exports_round_trip_in_both_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut registry = registry(dir.path());
+        let hkcu = registry.hive_mut(HiveName::CurrentUser);
+        let app = path(r"Software\Vendor\App");
+        hkcu.set_value(&app, "", RegValue::Sz("C:\\App \"x\"".into()));
+        hkcu.set_value(&app, "Path", RegValue::ExpandSz("%APPDATA%\\App".into()));
+        hkcu.set_value(
+            &app,
+            "List",
+            RegValue::MultiSz(vec!["uno".into(), "dos".into()]),
+        );
+        hkcu.set_value(&app, "Big", RegValue::Qword(u64::MAX));
+        hkcu.set_value(&app, "Blob", RegValue::Binary((0..=80).collect()));
+        hkcu.delete_value(&app, "Telemetry");
+        hkcu.delete_tree(&path(r"Software\Vendor\Legacy")).unwrap();
+        hkcu.set_value(&path(r"Software\Vendor\Legacy"), "New", RegValue::Dword(1));
+
+        for version in [RegFileVersion::V5, RegFileVersion::Regedit4] {
+            // La vista completa se reconstruye en un registro sin base.
+            let full = RegFile::export(&registry, None, ExportMode::Full, version).unwrap();
+            let parsed = RegFile::parse(&full.encode()).unwrap();
+            assert_eq!(parsed, full);
+            let empty = tempfile::tempdir().unwrap();
+            let mut copy = VirtualRegistry::open(empty.path(), None).unwrap();
+            parsed.apply(&mut copy).unwrap();
+            assert_eq!(
+                RegFile::export(&copy, None, ExportMode::Full, version).unwrap(),
+                full
+            );
+
+            // Los cambios, sobre la misma base, dan la misma vista.
+            let changes = RegFile::export(&registry, None, ExportMode::Changes, version).unwrap();
+            let other = tempfile::tempdir().unwrap();
+            let mut replay = self::registry(other.path());
+            RegFile::parse(&changes.encode())
+                .unwrap()
+                .apply(&mut replay)
+                .unwrap();
+            assert_eq!(
+                RegFile::export(&replay, None, ExportMode::Full, version).unwrap(),
+                full
+            );
+        }
+
+        let changes = RegFile::export(
+            &registry,
+            Some(r"HKCU\Software\Vendor"),
+            ExportMode::Changes,
+            RegFileVersion::V5,
+        )
+        .unwrap()
+        .to_text();
+        assert!(changes.contains("\"Telemetry\"=-\r\n"), "{changes}");
+        assert!(
+            changes.contains("[-HKEY_CURRENT_USER\\Software\\Vendor\\Legacy]\r\n\r\n[HKEY_CURRENT_USER\\Software\\Vendor\\Legacy]\r\n\"New\"=dword:00000001"),
+            "{changes}"
+        );
+        assert!(
+            changes.lines().all(|line| line.len() <= HEX_LINE_WIDTH + 1),
+            "{changes}"
+        );
+        assert!(RegFile::export(
+            &registry,
+            Some(r"HKCU\Software\Missing"),
+            ExportMode::Full,
+            RegFileVersion::V5
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn strings_that_do_not_fit_quotes_round_trip_as_hex() {
+        for version in [RegFileVersion::V5, RegFileVersion::Regedit4] {
+            let mut file = RegFile::new(version);
+            file.sections.push(RegSection {
+                key: r"HKEY_CURRENT_USER\Software\Vendor".into(),
+                delete: false,
+                values: vec![
+                    ("Lines".into(), Some(RegValue::Sz("uno\r\ndos\n".into()))),
+                    ("Plain".into(), Some(RegValue::Sz("uno".into()))),
+                ],
+            });
+            let text = file.to_text();
+            assert!(text.contains("\"Lines\"=hex(1):"), "{text}");
+            assert!(text.contains("\"Plain\"=\"uno\""), "{text}");
+            assert_eq!(RegFile::parse(&file.encode()).unwrap(), file);
+        }
+
+        // Un NUL corta la cadena al leerla: vuelve con los mismos bytes.
+        let mut file = RegFile::new(RegFileVersion::V5);
+        let nul = RegValue::Sz("a\0b".into());
+        file.sections.push(RegSection {
+            key: r"HKEY_CURRENT_USER\Software\Vendor".into(),
+            delete: false,
+            values: vec![("Nul".into(), Some(nul.clone()))],
+        });
+        let parsed = RegFile::parse(&file.encode()).unwrap();
+        let value = parsed.sections[0].values[0].1.as_ref().unwrap();
+        assert_eq!(value.kind(), REG_SZ);
+        assert_eq!(value.to_bytes(), nul.to_bytes());
+    }
+
+    #[test]
+    fn unterminated_strings_round_trip_unchanged() {
+        let cases = [
+            (
+                "Windows Registry Editor Version 5.00",
+                "\"Path\"=hex(2):25,00,41,00,25,00",
+                vec![0x25, 0, 0x41, 0, 0x25, 0],
+            ),
+            (
+                "REGEDIT4",
+                "\"Path\"=hex(2):25,41,25",
+                vec![0x25, 0x41, 0x25],
+            ),
+        ];
+        for (header, line, data) in cases {
+            let text =
+                format!("{header}\r\n\r\n[HKEY_CURRENT_USER\\Software\\Vendor]\r\n{line}\r\n\r\n");
+            let bytes = match header {
+                HEADER_V5 => utf16(&text),
+                _ => text.clone().into_bytes(),
+            };
+            let file = RegFile::parse(&bytes).unwrap();
+            assert_eq!(
+                file.sections[0].values[0].1,
+                Some(RegValue::Other {
+                    kind: REG_EXPAND_SZ,
+                    data
+                })
+            );
+            assert_eq!(file.to_text(), text);
+        }
+    }
+
+    #[test]
+    fn malformed_files_report_the_line() {
+        assert!(RegFile::parse(b"[HKEY_CURRENT_USER\\X]\r\n").is_err());
+        let cases = [
+            "REGEDIT4\r\n\"a\"=\"b\"\r\n",
+            "REGEDIT4\r\n[HKEY_CURRENT_USER\\X]\r\n\"a\"=dword:zz\r\n",
+            "REGEDIT4\r\n[HKEY_CURRENT_USER\\X]\r\n\"a\"=\"sin cierre\r\n",
+            "REGEDIT4\r\n[-HKEY_CURRENT_USER\\X]\r\n\"a\"=\"b\"\r\n",
+            "REGEDIT4\r\n[HKEY_CURRENT_USER\\X\r\n",
+        ];
+        for case in cases {
+            let err = RegFile::parse(case.as_bytes()).unwrap_err();
+            assert!(err.to_string().starts_with("Línea "), "{case}: {err}");
+        }
+    }
+}
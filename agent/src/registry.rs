@@ -1,17 +1,18 @@
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use crate::{mount::MountProviderKind, supervisor::RestartPolicy};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    path::{Path, PathBuf},
+    collections::{BTreeMap, HashMap},
+    path::{Component, Path, PathBuf},
 };
 use tokio::fs;
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
 pub struct RuntimeConfig {
     pub build: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
 pub struct PathConfig {
     pub program_files: Option<PathBuf>,
     pub appdata: Option<PathBuf>,
@@ -19,7 +20,7 @@ pub struct PathConfig {
     pub temp: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct ContainerManifest {
     pub id: String,
     pub name: String,
@@ -30,9 +31,51 @@ pub struct ContainerManifest {
     pub runtime: RuntimeConfig,
     #[serde(default)]
     pub paths: PathConfig,
+    /// Política por defecto para los perfiles que no declaran la suya.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+    #[serde(default)]
+    pub launch: LaunchConfig,
+    #[serde(default)]
+    pub mount: MountManifest,
+}
+
+/// Sección `mount`: preferencias de montaje que prevalecen sobre las del agent.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountManifest {
+    pub provider: Option<MountProviderKind>,
+    /// Letra de unidad fija; si ya está ocupada el contenedor no se monta.
+    pub drive: Option<char>,
+    /// Si el contenedor puede arrancar sin volumen cuando el montaje falla.
+    pub optional: Option<bool>,
 }
 
-#[derive(Debug, Clone)]
+/// Sección `launch`: perfiles de arranque con nombre.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LaunchConfig {
+    /// Perfil usado cuando no se indica ninguno; opcional si solo hay uno.
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, LaunchProfile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LaunchProfile {
+    /// Ejecutable relativo a la carpeta del contenedor.
+    pub executable: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Carpeta de trabajo relativa al contenedor (por defecto, su raíz).
+    pub working_dir: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub restart: Option<RestartPolicy>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RegisteredContainer {
     pub manifest: ContainerManifest,
     pub root: PathBuf,
@@ -44,45 +87,216 @@ impl RegisteredContainer {
     }
 }
 
-#[derive(Default, Clone)]
+impl ContainerManifest {
+    /// Comprobaciones que `serde` no cubre: el `id` se usa como nombre de carpeta,
+    /// clave de registro y alias de montaje.
+    pub fn validate(&self) -> Result<()> {
+        if self.id.trim().is_empty() {
+            bail!("El campo id está vacío");
+        }
+        if self
+            .id
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '/' | '\\' | ':'))
+        {
+            bail!(
+                "El id '{}' contiene espacios o separadores de ruta",
+                self.id
+            );
+        }
+        if self.name.trim().is_empty() {
+            bail!("El campo name está vacío");
+        }
+        if let Some(entrypoint) = &self.entrypoint {
+            ensure_relative("entrypoint", entrypoint)?;
+        }
+        for (name, profile) in &self.launch.profiles {
+            if name.trim().is_empty() || name.chars().any(char::is_whitespace) {
+                bail!("El perfil '{name}' tiene un nombre vacío o con espacios");
+            }
+            ensure_relative(
+                &format!("launch.profiles.{name}.executable"),
+                &profile.executable,
+            )?;
+            if let Some(dir) = &profile.working_dir {
+                ensure_relative(&format!("launch.profiles.{name}.working_dir"), dir)?;
+            }
+        }
+        if let Some(default) = &self.launch.default {
+            if !self.launch.profiles.contains_key(default) {
+                bail!("launch.default apunta al perfil inexistente '{default}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// Devuelve el perfil pedido o el por defecto. Sin sección `launch`, el
+    /// `entrypoint` heredado actúa como perfil implícito `default`.
+    pub fn profile(&self, name: Option<&str>) -> Result<(String, LaunchProfile)> {
+        let profiles = &self.launch.profiles;
+        if profiles.is_empty() {
+            let executable = self.entrypoint.clone().ok_or_else(|| {
+                anyhow!(
+                    "El contenedor {} no declara entrypoint ni perfiles de lanzamiento",
+                    self.id
+                )
+            })?;
+            if name.is_some_and(|name| name != DEFAULT_PROFILE) {
+                bail!(
+                    "El contenedor {} no declara perfiles de lanzamiento",
+                    self.id
+                );
+            }
+            let profile = LaunchProfile {
+                executable,
+                args: Vec::new(),
+                working_dir: None,
+                env: BTreeMap::new(),
+                restart: None,
+            };
+            return Ok((DEFAULT_PROFILE.to_string(), profile));
+        }
+
+        let name = match (name, self.launch.default.as_deref()) {
+            (Some(name), _) | (None, Some(name)) => name.to_string(),
+            (None, None) if profiles.len() == 1 => {
+                profiles.keys().next().cloned().unwrap_or_default()
+            }
+            (None, None) => bail!(
+                "El contenedor {} tiene varios perfiles y ninguno por defecto; elige uno de: {}",
+                self.id,
+                profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        };
+        let profile = profiles.get(&name).cloned().ok_or_else(|| {
+            anyhow!(
+                "El contenedor {} no tiene el perfil '{name}' (disponibles: {})",
+                self.id,
+                profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        Ok((name, profile))
+    }
+}
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+fn ensure_relative(field: &str, path: &str) -> Result<()> {
+    let contained = !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !contained {
+        bail!("{field} debe ser una ruta relativa dentro del contenedor: {path}");
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiagnosticKind {
+    /// La carpeta de contenedores configurada no existe.
+    MissingRoot,
+    /// No se pudo leer la carpeta o el manifiesto.
+    Unreadable,
+    /// El `config.yml` no se pudo interpretar o no pasó la validación.
+    InvalidManifest,
+    /// Dos carpetas declaran el mismo `id`.
+    DuplicateId,
+}
+
+/// Problema encontrado al cargar el registro. Los contenedores afectados quedan
+/// en cuarentena: no se preparan ni se lanzan, pero el resto sigue cargando.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadDiagnostic {
+    pub kind: DiagnosticKind,
+    pub path: PathBuf,
+    pub container_id: Option<String>,
+    pub message: String,
+    /// El manifiesto se rompió estando cargado: el contenedor sigue con su
+    /// última versión válida en lugar de darse de baja.
+    #[serde(default)]
+    pub retained: bool,
+}
+
+impl LoadDiagnostic {
+    pub fn quarantines(&self) -> bool {
+        self.kind != DiagnosticKind::MissingRoot && !self.retained
+    }
+}
+
+/// Cambio detectado entre dos lecturas del registro.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegistryEvent {
+    Added(RegisteredContainer),
+    Updated(RegisteredContainer),
+    Removed(String),
+    /// El conjunto de diagnósticos cambió (se envía completo).
+    Diagnostics(Vec<LoadDiagnostic>),
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct ContainerRegistry {
     containers: HashMap<String, RegisteredContainer>,
+    diagnostics: Vec<LoadDiagnostic>,
+    /// Contenedores en cuarentena por `id` repetido, para que
+    /// [`ContainerRegistry::retain_broken`] pueda conservar el que ya estaba cargado.
+    duplicates: Vec<RegisteredContainer>,
 }
 
 impl ContainerRegistry {
     pub async fn load_from(root: impl AsRef<Path>) -> Result<Self> {
-        let root = root.as_ref().to_path_buf();
-        if !fs::try_exists(&root).await? {
-            return Ok(Self::default());
+        Self::load_roots(&[root.as_ref().to_path_buf()]).await
+    }
+
+    /// Carga y combina varias carpetas de contenedores. Los manifiestos inválidos y
+    /// los `id` repetidos (en la misma carpeta o en carpetas distintas) no abortan la
+    /// carga: se registran como diagnósticos y esos contenedores quedan en cuarentena.
+    pub async fn load_roots(roots: &[PathBuf]) -> Result<Self> {
+        let mut candidates: Vec<RegisteredContainer> = Vec::new();
+        let mut diagnostics = Vec::new();
+        for root in roots {
+            scan_root(root, &mut candidates, &mut diagnostics).await;
         }
 
-        let mut entries = fs::read_dir(&root).await?;
-        let mut containers = HashMap::new();
+        let mut by_id: HashMap<String, Vec<RegisteredContainer>> = HashMap::new();
+        for candidate in candidates {
+            by_id
+                .entry(candidate.manifest.id.clone())
+                .or_default()
+                .push(candidate);
+        }
 
-        while let Some(entry) = entries.next_entry().await? {
-            if !entry.file_type().await?.is_dir() {
+        let mut containers = HashMap::new();
+        let mut duplicates = Vec::new();
+        for (id, mut found) in by_id {
+            if found.len() == 1 {
+                containers.insert(id, found.remove(0));
                 continue;
             }
-
-            let manifest_path = entry.path().join("config.yml");
-            if !fs::try_exists(&manifest_path).await? {
-                continue;
+            let paths = found
+                .iter()
+                .map(|c| c.root.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            for duplicate in found {
+                diagnostics.push(LoadDiagnostic {
+                    kind: DiagnosticKind::DuplicateId,
+                    path: duplicate.root.clone(),
+                    container_id: Some(id.clone()),
+                    message: format!("El id '{id}' está declarado en varias carpetas: {paths}"),
+                    retained: false,
+                });
+                duplicates.push(duplicate);
             }
-
-            let manifest_content = fs::read_to_string(&manifest_path).await?;
-            let manifest: ContainerManifest = serde_yaml::from_str(&manifest_content)
-                .with_context(|| format!("Manifiesto inválido en {}", manifest_path.display()))?;
-
-            containers.insert(
-                manifest.id.clone(),
-                RegisteredContainer {
-                    manifest,
-                    root: entry.path(),
-                },
-            );
         }
 
-        Ok(Self { containers })
+        diagnostics.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Self {
+            containers,
+            diagnostics,
+            duplicates,
+        })
     }
 
     pub fn list(&self) -> Vec<RegisteredContainer> {
@@ -92,4 +306,363 @@ impl ContainerRegistry {
     pub fn get(&self, id: &str) -> Option<&RegisteredContainer> {
         self.containers.get(id)
     }
+
+    pub fn diagnostics(&self) -> &[LoadDiagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn quarantined(&self) -> impl Iterator<Item = &LoadDiagnostic> {
+        self.diagnostics.iter().filter(|d| d.quarantines())
+    }
+
+    /// Conserva los contenedores de `previous` cuyo manifiesto dejó de ser válido
+    /// o legible en esta lectura: una edición rota de un contenedor cargado no
+    /// debe darlo de baja. Su diagnóstico queda marcado como `retained`.
+    ///
+    /// Del mismo modo, si otra carpeta empieza a declarar el `id` de un
+    /// contenedor cargado, este sigue cargado (con su manifiesto actual) y solo
+    /// la carpeta nueva queda en cuarentena.
+    pub fn retain_broken(&mut self, previous: &ContainerRegistry) {
+        for candidate in &self.duplicates {
+            let id = &candidate.manifest.id;
+            let loaded = previous
+                .containers
+                .get(id)
+                .is_some_and(|loaded| loaded.root == candidate.root);
+            if loaded && !self.containers.contains_key(id) {
+                self.containers.insert(id.clone(), candidate.clone());
+                self.diagnostics.retain(|diagnostic| {
+                    diagnostic.kind != DiagnosticKind::DuplicateId
+                        || diagnostic.path != candidate.root
+                });
+            }
+        }
+
+        for diagnostic in &mut self.diagnostics {
+            if !matches!(
+                diagnostic.kind,
+                DiagnosticKind::InvalidManifest | DiagnosticKind::Unreadable
+            ) {
+                continue;
+            }
+            let Some(last_good) = previous
+                .containers
+                .values()
+                .find(|container| container.root == diagnostic.path)
+            else {
+                continue;
+            };
+            let id = &last_good.manifest.id;
+            if self.containers.contains_key(id) {
+                continue;
+            }
+            self.containers.insert(id.clone(), last_good.clone());
+            diagnostic.container_id = Some(id.clone());
+            diagnostic.retained = true;
+        }
+    }
+
+    /// Calcula los eventos necesarios para pasar de `self` a `next`.
+    pub fn diff(&self, next: &ContainerRegistry) -> Vec<RegistryEvent> {
+        let mut events = Vec::new();
+        for (id, container) in &next.containers {
+            match self.containers.get(id) {
+                None => events.push(RegistryEvent::Added(container.clone())),
+                Some(current) if current != container => {
+                    events.push(RegistryEvent::Updated(container.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for id in self.containers.keys() {
+            if !next.containers.contains_key(id) {
+                events.push(RegistryEvent::Removed(id.clone()));
+            }
+        }
+        events.sort_by_key(|event| match event {
+            RegistryEvent::Added(c) | RegistryEvent::Updated(c) => c.manifest.id.clone(),
+            RegistryEvent::Removed(id) => id.clone(),
+            RegistryEvent::Diagnostics(_) => String::new(),
+        });
+        if self.diagnostics != next.diagnostics {
+            events.push(RegistryEvent::Diagnostics(next.diagnostics.clone()));
+        }
+        events
+    }
+
+    pub fn apply(&mut self, event: &RegistryEvent) {
+        match event {
+            RegistryEvent::Added(container) | RegistryEvent::Updated(container) => {
+                self.containers
+                    .insert(container.manifest.id.clone(), container.clone());
+            }
+            RegistryEvent::Removed(id) => {
+                self.containers.remove(id);
+            }
+            RegistryEvent::Diagnostics(diagnostics) => {
+                self.diagnostics = diagnostics.clone();
+            }
+        }
+    }
+}
+
+async fn scan_root(
+    root: &Path,
+    candidates: &mut Vec<RegisteredContainer>,
+    diagnostics: &mut Vec<LoadDiagnostic>,
+) {
+    let mut entries = match fs::read_dir(root).await {
+        Ok(entries) => entries,
+        Err(err) => {
+            let kind = if err.kind() == std::io::ErrorKind::NotFound {
+                DiagnosticKind::MissingRoot
+            } else {
+                DiagnosticKind::Unreadable
+            };
+            diagnostics.push(LoadDiagnostic {
+                kind,
+                path: root.to_path_buf(),
+                container_id: None,
+                message: format!("No se pudo leer la carpeta de contenedores: {err}"),
+                retained: false,
+            });
+            return;
+        }
+    };
+
+    loop {
+        let entry = match entries.next_entry().await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(err) => {
+                diagnostics.push(LoadDiagnostic {
+                    kind: DiagnosticKind::Unreadable,
+                    path: root.to_path_buf(),
+                    container_id: None,
+                    message: format!("Lectura interrumpida: {err}"),
+                    retained: false,
+                });
+                break;
+            }
+        };
+        if !entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+            continue;
+        }
+        // Las carpetas ocultas son instalaciones en curso del reconciliador.
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let manifest_path = entry.path().join("config.yml");
+        if !fs::try_exists(&manifest_path).await.unwrap_or(false) {
+            continue;
+        }
+
+        match read_manifest(&manifest_path).await {
+            Ok(manifest) => candidates.push(RegisteredContainer {
+                manifest,
+                root: entry.path(),
+            }),
+            Err((kind, container_id, err)) => diagnostics.push(LoadDiagnostic {
+                kind,
+                path: entry.path(),
+                container_id,
+                message: format!("{err:#}"),
+                retained: false,
+            }),
+        }
+    }
+}
+
+type ManifestError = (DiagnosticKind, Option<String>, anyhow::Error);
+
+/// Lee y valida el `config.yml` de una carpeta de contenedor.
+pub async fn load_manifest(container_root: &Path) -> Result<ContainerManifest> {
+    read_manifest(&container_root.join("config.yml"))
+        .await
+        .map_err(|(_, _, err)| err)
+}
+
+async fn read_manifest(path: &Path) -> std::result::Result<ContainerManifest, ManifestError> {
+    let content = fs::read_to_string(path).await.map_err(|err| {
+        (
+            DiagnosticKind::Unreadable,
+            None,
+            anyhow::Error::new(err).context(format!("No se pudo leer {}", path.display())),
+        )
+    })?;
+
+    let parsed = serde_yaml::from_str::<ContainerManifest>(&content)
+        .with_context(|| format!("Manifiesto inválido en {}", path.display()))
+        .and_then(|manifest| {
+            manifest
+                .validate()
+                .with_context(|| format!("Manifiesto inválido en {}", path.display()))?;
+            Ok(manifest)
+        });
+
+    parsed.map_err(|err| {
+        // Se intenta rescatar el id para poder identificar el contenedor en cuarentena.
+        let id = serde_yaml::from_str::<serde_yaml::Value>(&content)
+            .ok()
+            .and_then(|value| value.get("id")?.as_str().map(str::to_string));
+        (DiagnosticKind::InvalidManifest, id, err)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_manifest(root: &Path, dir: &str, body: &str) {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+        std::fs::write(root.join(dir).join("config.yml"), body).unwrap();
+    }
+
+    #[tokio::test]
+    async fn diff_reports_added_updated_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), "a", "id: a\nname: A\nversion: \"1\"\n");
+        write_manifest(dir.path(), "b", "id: b\nname: B\n");
+        let before = ContainerRegistry::load_from(dir.path()).await.unwrap();
+
+        write_manifest(dir.path(), "a", "id: a\nname: A\nversion: \"2\"\n");
+        std::fs::remove_dir_all(dir.path().join("b")).unwrap();
+        write_manifest(dir.path(), "c", "id: c\nname: C\n");
+        let after = ContainerRegistry::load_from(dir.path()).await.unwrap();
+
+        let events = before.diff(&after);
+        assert_eq!(events.len(), 3);
+        assert!(
+            matches!(&events[0], RegistryEvent::Updated(c) if c.manifest.version.as_deref() == Some("2"))
+        );
+        assert_eq!(events[1], RegistryEvent::Removed("b".into()));
+        assert!(matches!(&events[2], RegistryEvent::Added(c) if c.manifest.id == "c"));
+
+        let mut applied = before.clone();
+        for event in &events {
+            applied.apply(event);
+        }
+        assert!(applied.diff(&after).is_empty());
+    }
+
+    #[tokio::test]
+    async fn broken_edits_keep_the_last_good_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), "app", "id: app\nname: App\n");
+        let before = ContainerRegistry::load_from(dir.path()).await.unwrap();
+
+        write_manifest(dir.path(), "app", "id: app\nname: [typo\n");
+        let mut after = ContainerRegistry::load_from(dir.path()).await.unwrap();
+        assert!(after.get("app").is_none());
+        after.retain_broken(&before);
+
+        assert_eq!(after.get("app"), before.get("app"));
+        let events = before.diff(&after);
+        assert_eq!(events.len(), 1);
+        let RegistryEvent::Diagnostics(diagnostics) = &events[0] else {
+            panic!("se esperaba solo un diagnóstico: {events:?}");
+        };
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::InvalidManifest);
+        assert_eq!(diagnostics[0].container_id.as_deref(), Some("app"));
+        assert!(diagnostics[0].retained && !diagnostics[0].quarantines());
+
+        std::fs::remove_dir_all(dir.path().join("app")).unwrap();
+        let mut gone = ContainerRegistry::load_from(dir.path()).await.unwrap();
+        gone.retain_broken(&after);
+        assert_eq!(after.diff(&gone)[0], RegistryEvent::Removed("app".into()));
+    }
+
+    #[tokio::test]
+    async fn duplicate_ids_across_roots_are_quarantined() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        write_manifest(first.path(), "app", "id: app\nname: App\n");
+        write_manifest(second.path(), "other", "id: other\nname: Other\n");
+
+        let roots = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let merged = ContainerRegistry::load_roots(&roots).await.unwrap();
+        assert_eq!(merged.list().len(), 2);
+        assert!(merged.diagnostics().is_empty());
+
+        write_manifest(second.path(), "app-copy", "id: app\nname: Copy\n");
+        let merged = ContainerRegistry::load_roots(&roots).await.unwrap();
+        assert!(merged.get("app").is_none());
+        assert!(merged.get("other").is_some());
+        let quarantined: Vec<_> = merged.quarantined().collect();
+        assert_eq!(quarantined.len(), 2);
+        assert!(quarantined
+            .iter()
+            .all(|d| d.kind == DiagnosticKind::DuplicateId
+                && d.container_id.as_deref() == Some("app")));
+    }
+
+    #[tokio::test]
+    async fn duplicates_of_a_loaded_container_only_quarantine_the_newcomer() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        write_manifest(first.path(), "app", "id: app\nname: App\n");
+        let roots = vec![first.path().to_path_buf(), second.path().to_path_buf()];
+        let loaded = ContainerRegistry::load_roots(&roots).await.unwrap();
+
+        write_manifest(second.path(), "app-copy", "id: app\nname: Copy\n");
+        write_manifest(first.path(), "app", "id: app\nname: App 2\n");
+        let mut next = ContainerRegistry::load_roots(&roots).await.unwrap();
+        next.retain_broken(&loaded);
+        let kept = next.get("app").unwrap();
+        assert_eq!(kept.root, first.path().join("app"));
+        assert_eq!(kept.manifest.name, "App 2");
+        let quarantined: Vec<_> = next.quarantined().collect();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].kind, DiagnosticKind::DuplicateId);
+        assert_eq!(quarantined[0].path, second.path().join("app-copy"));
+        assert!(!loaded
+            .diff(&next)
+            .iter()
+            .any(|event| matches!(event, RegistryEvent::Removed(_))));
+    }
+
+    #[tokio::test]
+    async fn invalid_manifests_do_not_stop_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        write_manifest(dir.path(), "good", "id: good\nname: Good\n");
+        write_manifest(dir.path(), "broken", "id: broken\nname: [unterminated\n");
+        write_manifest(
+            dir.path(),
+            "typed",
+            "id: typed\nname: Typed\nversion: [1, 2]\n",
+        );
+        write_manifest(dir.path(), "no-name", "id: no-name\n");
+        write_manifest(dir.path(), "bad-id", "id: \"bad id\"\nname: Bad\n");
+        write_manifest(
+            dir.path(),
+            "bad-launch",
+            "id: bad-launch\nname: Bad\nlaunch:\n  default: main\n  profiles:\n    tools:\n      executable: /usr/bin/tool\n",
+        );
+        let missing = dir.path().join("missing");
+
+        let registry = ContainerRegistry::load_roots(&[dir.path().to_path_buf(), missing.clone()])
+            .await
+            .unwrap();
+        assert_eq!(registry.list().len(), 1);
+        assert!(registry.get("good").is_some());
+
+        let invalid: Vec<_> = registry
+            .quarantined()
+            .map(|d| (d.kind, d.container_id.clone()))
+            .collect();
+        assert_eq!(invalid.len(), 5);
+        assert!(invalid.contains(&(DiagnosticKind::InvalidManifest, None)));
+        assert!(invalid.contains(&(DiagnosticKind::InvalidManifest, Some("typed".into()))));
+        assert!(invalid.contains(&(DiagnosticKind::InvalidManifest, Some("no-name".into()))));
+        assert!(invalid.contains(&(DiagnosticKind::InvalidManifest, Some("bad-launch".into()))));
+
+        let missing_root = registry
+            .diagnostics()
+            .iter()
+            .find(|d| d.kind == DiagnosticKind::MissingRoot)
+            .unwrap();
+        assert_eq!(missing_root.path, missing);
+        assert!(!missing_root.quarantines());
+    }
 }
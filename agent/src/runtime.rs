@@ -1,27 +1,46 @@
 use crate::{
-    hooks::NativeHookPipeline,
+    hooks::wire,
+    overlay::{Layer, LayerKind, LayerStack},
     registry::{ContainerManifest, RegisteredContainer},
+    runtimes::{InstalledRuntime, RuntimeRef, RuntimeStore},
+    winreg::REGISTRY_DIR,
+};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
 };
-use anyhow::Result;
-use serde::Serialize;
-use std::{collections::HashMap, path::PathBuf};
 use tokio::fs;
 use tracing::info;
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MountPlan {
     pub alias: String,
     pub host_path: PathBuf,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct HookPlan {
     pub env: HashMap<String, String>,
     pub mounts: Vec<MountPlan>,
     pub redirects: Vec<PathRedirect>,
+    pub overlay: LayerStack,
+    /// Registro virtual del contenedor; `None` deja pasar las llamadas al
+    /// registro del host.
+    #[serde(default)]
+    pub registry: Option<RegistryPlan>,
+}
+
+/// Dónde viven los hives del contenedor y el snapshot base de su runtime (ver
+/// [`crate::winreg::VirtualRegistry::open`]).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryPlan {
+    pub dir: PathBuf,
+    pub base: Option<PathBuf>,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PathRedirect {
     pub variable: String,
     pub original: PathBuf,
@@ -29,17 +48,25 @@ pub struct PathRedirect {
 }
 
 pub struct HookEngine {
-    hooks: NativeHookPipeline,
+    runtimes: RuntimeStore,
+    plans_dir: PathBuf,
 }
 
 impl HookEngine {
-    pub fn new() -> Self {
+    pub fn new(runtimes: RuntimeStore, plans_dir: impl Into<PathBuf>) -> Self {
         Self {
-            hooks: NativeHookPipeline::new(),
+            runtimes,
+            plans_dir: plans_dir.into(),
         }
     }
 
+    /// Dónde se publica el plan del contenedor (ver [`crate::hooks::wire`]).
+    pub fn plan_path(&self, container_id: &str) -> PathBuf {
+        self.plans_dir.join(format!("{container_id}.bin"))
+    }
+
     pub async fn prepare(&self, container: &RegisteredContainer) -> Result<HookPlan> {
+        let runtime = self.resolve_runtime(&container.manifest)?;
         let layout = PathLayout::from_manifest(&container.manifest, &container.root);
         layout.ensure_directories().await?;
 
@@ -62,6 +89,12 @@ impl HookEngine {
             ),
             ("TEMP".into(), layout.temp.to_string_lossy().into_owned()),
             ("TMP".into(), layout.temp.to_string_lossy().into_owned()),
+            (
+                wire::PLAN_ENV.into(),
+                self.plan_path(&container.manifest.id)
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
         ]);
 
         let mounts = vec![
@@ -84,6 +117,15 @@ impl HookEngine {
         ];
 
         let redirects = build_redirects(&layout);
+        let overlay = build_overlay(&container.manifest, &layout, runtime.as_ref());
+        let registry = RegistryPlan {
+            dir: container.path(REGISTRY_DIR),
+            base: runtime.as_ref().map(InstalledRuntime::registry),
+        };
+        if let Some(runtime) = &runtime {
+            self.runtimes
+                .acquire(&container.manifest.id, &runtime.reference);
+        }
 
         info!(
             container_id = container.manifest.id.as_str(),
@@ -96,11 +138,48 @@ impl HookEngine {
             env,
             mounts,
             redirects,
+            overlay,
+            registry: Some(registry),
+        })
+    }
+
+    /// Deja el plan donde lo buscará el runtime que el launcher inyecta en los
+    /// procesos del contenedor. El agent no activa hooks en su propio proceso.
+    pub fn activate(&self, container: &RegisteredContainer, plan: &HookPlan) -> Result<()> {
+        let path = self.plan_path(&container.manifest.id);
+        wire::write_plan(&path, plan).with_context(|| {
+            format!(
+                "No se pudo publicar el plan de hooks de {}",
+                container.manifest.id
+            )
         })
     }
 
-    pub fn activate(&self, plan: &HookPlan) -> Result<()> {
-        self.hooks.apply(plan)
+    /// Retira el plan publicado; los procesos que ya lo leyeron no cambian.
+    pub fn deactivate(&self, container: &RegisteredContainer) -> Result<()> {
+        match std::fs::remove_file(self.plan_path(&container.manifest.id)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err).with_context(|| {
+                format!(
+                    "No se pudo retirar el plan de hooks de {}",
+                    container.manifest.id
+                )
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn resolve_runtime(&self, manifest: &ContainerManifest) -> Result<Option<InstalledRuntime>> {
+        let Some(build) = manifest.runtime.build.as_deref() else {
+            return Ok(None);
+        };
+        let reference: RuntimeRef = build
+            .parse()
+            .with_context(|| format!("runtime.build inválido en el contenedor {}", manifest.id))?;
+        let runtime = self
+            .runtimes
+            .resolve(&reference)
+            .with_context(|| format!("El contenedor {} requiere {reference}", manifest.id))?;
+        Ok(Some(runtime))
     }
 }
 
@@ -112,7 +191,7 @@ struct PathLayout {
 }
 
 impl PathLayout {
-    fn from_manifest(manifest: &ContainerManifest, root: &PathBuf) -> Self {
+    fn from_manifest(manifest: &ContainerManifest, root: &Path) -> Self {
         Self {
             program_files: resolve_path(
                 root,
@@ -146,7 +225,7 @@ impl PathLayout {
     }
 }
 
-fn resolve_path(root: &PathBuf, value: Option<&PathBuf>, default: &str) -> PathBuf {
+fn resolve_path(root: &Path, value: Option<&PathBuf>, default: &str) -> PathBuf {
     match value {
         Some(val) => root.join(val),
         None => root.join(default),
@@ -174,3 +253,29 @@ fn build_redirects(layout: &PathLayout) -> Vec<PathRedirect> {
     }
     redirects
 }
+
+/// Construye la pila overlay de `ProgramFiles`: el rootfs del contenedor es la capa
+/// escribible; el runtime base y el `%PROGRAMFILES%` del host quedan como capas
+/// inferiores de solo lectura.
+fn build_overlay(
+    manifest: &ContainerManifest,
+    layout: &PathLayout,
+    runtime: Option<&InstalledRuntime>,
+) -> LayerStack {
+    let mut stack = LayerStack::new(Layer::new(
+        manifest.id.clone(),
+        LayerKind::Container,
+        layout.program_files.clone(),
+    ));
+    if let Some(runtime) = runtime {
+        stack = stack.with_lower(Layer::new(
+            runtime.reference.to_string(),
+            LayerKind::BaseRuntime,
+            runtime.program_files(),
+        ));
+    }
+    match std::env::var_os("PROGRAMFILES") {
+        Some(host) => stack.with_lower(Layer::new("host", LayerKind::Host, host)),
+        None => stack,
+    }
+}
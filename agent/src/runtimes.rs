@@ -0,0 +1,390 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tracing::{info, warn};
+
+/// Archivo del almacén con los contenedores que montan cada runtime, para que
+/// `ctnr runtime gc` los respete aunque corra en otro proceso que el agent.
+const USAGE_FILE: &str = ".in-use.json";
+
+/// Referencia `nombre@versión` a un runtime base, tal como aparece en `runtime.build`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct RuntimeRef {
+    pub name: String,
+    pub version: String,
+}
+
+impl FromStr for RuntimeRef {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (name, version) = value.trim().split_once('@').ok_or_else(|| {
+            anyhow!("Referencia de runtime inválida '{value}'; se espera nombre@versión")
+        })?;
+        let valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        };
+        if !valid(name) || !valid(version) {
+            bail!("Referencia de runtime inválida '{value}'; se espera nombre@versión");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            version: version.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RuntimeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.version)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstalledRuntime {
+    pub reference: RuntimeRef,
+    pub root: PathBuf,
+}
+
+impl InstalledRuntime {
+    /// Directorio que se monta como capa inferior de `ProgramFiles`.
+    pub fn program_files(&self) -> PathBuf {
+        self.root.join("ProgramFiles")
+    }
+
+    /// Snapshot base del registro, bajo la capa de cada contenedor.
+    pub fn registry(&self) -> PathBuf {
+        self.root.join("Registry")
+    }
+}
+
+type Usage = BTreeMap<RuntimeRef, BTreeSet<String>>;
+
+/// Almacén local de runtimes compartidos (`runtimes/<nombre>@<versión>/`).
+/// Lleva la cuenta de qué contenedores usan cada runtime, y la guarda en
+/// [`USAGE_FILE`], para que `gc` no borre capas montadas.
+#[derive(Debug, Clone)]
+pub struct RuntimeStore {
+    root: PathBuf,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl RuntimeStore {
+    /// Abre el almacén con el uso que dejó guardado el agent.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let usage = read_usage(&root.join(USAGE_FILE));
+        Self {
+            root,
+            usage: Arc::new(Mutex::new(usage)),
+        }
+    }
+
+    /// Olvida el uso guardado. El agent lo hace al arrancar, cuando aún no
+    /// montó nada aunque un cierre brusco haya dejado el archivo.
+    pub fn reset_usage(&self) {
+        let mut usage = self.usage.lock().expect("lock poisoned");
+        usage.clear();
+        self.save_usage(&usage);
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn list(&self) -> Result<Vec<InstalledRuntime>> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut runtimes = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Ok(reference) = name.parse::<RuntimeRef>() {
+                runtimes.push(InstalledRuntime {
+                    reference,
+                    root: entry.path(),
+                });
+            }
+        }
+        runtimes.sort_by(|a, b| a.reference.cmp(&b.reference));
+        Ok(runtimes)
+    }
+
+    pub fn resolve(&self, reference: &RuntimeRef) -> Result<InstalledRuntime> {
+        let root = self.root.join(reference.to_string());
+        if !root.is_dir() {
+            bail!(
+                "El runtime {reference} no está instalado en {}; instálelo con `ctnr runtime install {reference} <carpeta>`",
+                self.root.display()
+            );
+        }
+        Ok(InstalledRuntime {
+            reference: reference.clone(),
+            root,
+        })
+    }
+
+    /// Copia una carpeta de runtime ya desempaquetada dentro del almacén.
+    pub fn install(
+        &self,
+        reference: &RuntimeRef,
+        source: impl AsRef<Path>,
+    ) -> Result<InstalledRuntime> {
+        let source = source.as_ref();
+        if !source.is_dir() {
+            bail!("La carpeta de origen {} no existe", source.display());
+        }
+        let target = self.root.join(reference.to_string());
+        if target.exists() {
+            bail!(
+                "El runtime {reference} ya está instalado en {}",
+                target.display()
+            );
+        }
+
+        fs::create_dir_all(&self.root)?;
+        let staging = self.root.join(format!(".{reference}.partial"));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        copy_dir(source, &staging)
+            .with_context(|| format!("No se pudo copiar el runtime desde {}", source.display()))?;
+        fs::rename(&staging, &target)?;
+
+        info!(runtime = %reference, root = ?target, "Runtime instalado");
+        Ok(InstalledRuntime {
+            reference: reference.clone(),
+            root: target,
+        })
+    }
+
+    /// Registra que `container_id` monta `reference` como capa inferior.
+    pub fn acquire(&self, container_id: &str, reference: &RuntimeRef) {
+        let mut usage = self.usage.lock().expect("lock poisoned");
+        for users in usage.values_mut() {
+            users.remove(container_id);
+        }
+        usage
+            .entry(reference.clone())
+            .or_default()
+            .insert(container_id.to_string());
+        usage.retain(|_, users| !users.is_empty());
+        self.save_usage(&usage);
+    }
+
+    pub fn release(&self, container_id: &str) {
+        let mut usage = self.usage.lock().expect("lock poisoned");
+        if !usage.values().any(|users| users.contains(container_id)) {
+            return;
+        }
+        for users in usage.values_mut() {
+            users.remove(container_id);
+        }
+        usage.retain(|_, users| !users.is_empty());
+        self.save_usage(&usage);
+    }
+
+    pub fn users(&self, reference: &RuntimeRef) -> Vec<String> {
+        self.usage
+            .lock()
+            .expect("lock poisoned")
+            .get(reference)
+            .map(|users| users.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Elimina los runtimes que ningún contenedor monta ni referencia en su
+    /// manifiesto. El uso guardado se vuelve a leer por si el agent montó algo
+    /// desde que se abrió el almacén.
+    pub fn gc(
+        &self,
+        referenced: &HashSet<RuntimeRef>,
+        dry_run: bool,
+    ) -> Result<Vec<InstalledRuntime>> {
+        let saved = read_usage(&self.root.join(USAGE_FILE));
+        let mut removed = Vec::new();
+        for runtime in self.list()? {
+            if referenced.contains(&runtime.reference)
+                || saved.contains_key(&runtime.reference)
+                || !self.users(&runtime.reference).is_empty()
+            {
+                continue;
+            }
+            if !dry_run {
+                fs::remove_dir_all(&runtime.root)?;
+                info!(runtime = %runtime.reference, "Runtime sin referencias eliminado");
+            }
+            removed.push(runtime);
+        }
+        Ok(removed)
+    }
+}
+
+impl RuntimeStore {
+    /// Escribe el uso en un archivo temporal y lo renombra, para que `gc` nunca
+    /// lea uno a medias. Si falla solo se avisa: el agent sigue funcionando y
+    /// únicamente `gc` desde otro proceso pierde la información.
+    fn save_usage(&self, usage: &Usage) {
+        let saved: BTreeMap<String, &BTreeSet<String>> = usage
+            .iter()
+            .map(|(reference, users)| (reference.to_string(), users))
+            .collect();
+        let path = self.root.join(USAGE_FILE);
+        let staging = path.with_extension("json.tmp");
+        let written = fs::create_dir_all(&self.root)
+            .and_then(|()| fs::write(&staging, serde_json::to_vec_pretty(&saved)?))
+            .and_then(|()| fs::rename(&staging, &path));
+        if let Err(err) = written {
+            warn!(path = ?path, %err, "No se pudo guardar el uso de los runtimes");
+        }
+    }
+}
+
+/// Uso guardado por [`RuntimeStore::save_usage`]; vacío si no hay archivo.
+fn read_usage(path: &Path) -> Usage {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Usage::new(),
+        Err(err) => {
+            warn!(path = ?path, %err, "No se pudo leer el uso de los runtimes");
+            return Usage::new();
+        }
+    };
+    match serde_json::from_slice::<BTreeMap<String, BTreeSet<String>>>(&content) {
+        Ok(saved) => saved
+            .into_iter()
+            .filter_map(|(reference, users)| Some((reference.parse().ok()?, users)))
+            .collect(),
+        Err(err) => {
+            warn!(path = ?path, %err, "Uso de los runtimes ilegible; se ignora");
+            Usage::new()
+        }
+    }
+}
+
+fn copy_dir(source: &Path, target: &Path) -> Result<()> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &destination)?;
+        } else {
+            fs::copy(entry.path(), destination)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(value: &str) -> RuntimeRef {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_runtime_references() {
+        let parsed = reference("container-runtime@0.3.0");
+        assert_eq!(parsed.name, "container-runtime");
+        assert_eq!(parsed.version, "0.3.0");
+        assert_eq!(parsed.to_string(), "container-runtime@0.3.0");
+
+        assert!("container-runtime".parse::<RuntimeRef>().is_err());
+        assert!("@0.3.0".parse::<RuntimeRef>().is_err());
+        assert!("../evil@1".parse::<RuntimeRef>().is_err());
+    }
+
+    #[test]
+    fn install_resolve_and_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("ProgramFiles/Common")).unwrap();
+        fs::write(source.join("ProgramFiles/Common/vcruntime.dll"), "dll").unwrap();
+
+        let store = RuntimeStore::new(dir.path().join("runtimes"));
+        let runtime_ref = reference("container-runtime@0.3.0");
+        let err = store.resolve(&runtime_ref).unwrap_err();
+        assert!(err.to_string().contains("no está instalado"));
+
+        store.install(&runtime_ref, &source).unwrap();
+        assert!(store.install(&runtime_ref, &source).is_err());
+
+        let resolved = store.resolve(&runtime_ref).unwrap();
+        assert!(resolved
+            .program_files()
+            .join("Common/vcruntime.dll")
+            .exists());
+        let listed = store.list().unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].reference, runtime_ref);
+    }
+
+    #[test]
+    fn gc_keeps_referenced_and_mounted_runtimes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+        let store = RuntimeStore::new(dir.path().join("runtimes"));
+        for value in ["rt@1", "rt@2", "rt@3"] {
+            store.install(&reference(value), &source).unwrap();
+        }
+
+        store.acquire("demo", &reference("rt@2"));
+        let referenced = HashSet::from([reference("rt@1")]);
+
+        let candidates = store.gc(&referenced, true).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(store.list().unwrap().len(), 3);
+
+        let removed = store.gc(&referenced, false).unwrap();
+        assert_eq!(removed[0].reference, reference("rt@3"));
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        store.release("demo");
+        assert!(store.users(&reference("rt@2")).is_empty());
+        let removed = store.gc(&referenced, false).unwrap();
+        assert_eq!(removed[0].reference, reference("rt@2"));
+    }
+
+    #[test]
+    fn other_processes_see_the_saved_usage() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::create_dir_all(&source).unwrap();
+        let agent = RuntimeStore::new(dir.path().join("runtimes"));
+        agent.install(&reference("rt@1"), &source).unwrap();
+        let cli = RuntimeStore::new(dir.path().join("runtimes"));
+
+        // El agent monta el runtime después de que la CLI abrió el almacén.
+        agent.acquire("demo", &reference("rt@1"));
+        assert!(cli.gc(&HashSet::new(), false).unwrap().is_empty());
+        let reopened = RuntimeStore::new(dir.path().join("runtimes"));
+        assert_eq!(reopened.users(&reference("rt@1")), ["demo"]);
+        assert_eq!(reopened.list().unwrap().len(), 1);
+
+        // Al arrancar, el agent descarta lo que dejó un cierre brusco.
+        RuntimeStore::new(dir.path().join("runtimes")).reset_usage();
+        let removed = RuntimeStore::new(dir.path().join("runtimes"))
+            .gc(&HashSet::new(), false)
+            .unwrap();
+        assert_eq!(removed.len(), 1);
+    }
+}
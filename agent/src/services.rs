@@ -3,6 +3,7 @@ use tracing::info;
 
 /// Administra servicios instalados dentro del contenedor.
 /// En el futuro interceptaremos el SCM para registrar servicios aislados.
+#[derive(Default)]
 pub struct ServiceSandbox;
 
 impl ServiceSandbox {
@@ -0,0 +1,819 @@
+use crate::{
+    launcher::{self, LaunchRequest, Process, ProcessGroup},
+    logs::{ContainerLog, LogStream},
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    process::Child,
+    sync::{broadcast, watch},
+    task::JoinHandle,
+    time::{sleep, timeout, Instant},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Tiempo de gracia por defecto entre la señal de cierre y el `kill`.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Ejecuciones terminadas que se conservan; las más antiguas se descartan.
+const MAX_FINISHED_RUNS: usize = 200;
+/// Un proceso que aguanta esto en marcha vuelve a empezar la cuenta de
+/// reinicios (y con ella la espera y el límite de reintentos).
+const STABLE_UPTIME: Duration = Duration::from_secs(300);
+const OUTPUT_DRAIN: Duration = Duration::from_millis(500);
+
+/// Política de reinicio declarada en el manifiesto (`restart:`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "policy", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure {
+        #[serde(default = "default_max_retries")]
+        max_retries: u32,
+        #[serde(default = "default_backoff_ms")]
+        backoff_ms: u64,
+    },
+    Always {
+        #[serde(default = "default_backoff_ms")]
+        backoff_ms: u64,
+    },
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
+impl RestartPolicy {
+    fn should_restart(&self, success: bool, restarts: u32) -> bool {
+        match self {
+            Self::Never => false,
+            Self::OnFailure { max_retries, .. } => !success && restarts < *max_retries,
+            Self::Always { .. } => true,
+        }
+    }
+
+    /// Espera exponencial a partir de `backoff_ms`, acotada a un minuto.
+    fn backoff(&self, restarts: u32) -> Duration {
+        let base = match self {
+            Self::Never => return Duration::ZERO,
+            Self::OnFailure { backoff_ms, .. } | Self::Always { backoff_ms } => *backoff_ms,
+        };
+        let factor = 2u64.saturating_pow(restarts.saturating_sub(1));
+        Duration::from_millis(base.saturating_mul(factor)).min(MAX_BACKOFF)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunState {
+    Running,
+    Restarting,
+    Stopping,
+    /// Terminó por sí mismo con código 0.
+    Exited,
+    /// Terminó con error y no quedan reintentos.
+    Failed,
+    /// Detenido a petición.
+    Stopped,
+}
+
+impl RunState {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Exited | Self::Failed | Self::Stopped)
+    }
+}
+
+/// Registro de una ejecución. Los tiempos son milisegundos desde epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub container_id: String,
+    pub executable: String,
+    pub args: Vec<String>,
+    pub state: RunState,
+    pub pid: Option<u32>,
+    pub started_at_ms: u64,
+    pub ended_at_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub restarts: u32,
+    pub error: Option<String>,
+}
+
+pub struct RunSpec {
+    pub container_id: String,
+    pub request: LaunchRequest,
+    pub restart: RestartPolicy,
+    /// Destino de stdout/stderr y de los eventos de la ejecución.
+    pub log: ContainerLog,
+}
+
+struct RunEntry {
+    record: RunRecord,
+    /// Orden de lanzamiento; desempata ejecuciones del mismo milisegundo.
+    sequence: u64,
+    stop: watch::Sender<Option<Duration>>,
+}
+
+struct Inner {
+    runs: Mutex<HashMap<String, RunEntry>>,
+    next_sequence: AtomicU64,
+    events: broadcast::Sender<RunRecord>,
+    max_finished: usize,
+    stable_uptime: Duration,
+}
+
+impl Inner {
+    fn update(&self, run_id: &str, change: impl FnOnce(&mut RunRecord)) {
+        let record = {
+            let mut runs = self.runs.lock().expect("lock poisoned");
+            let Some(entry) = runs.get_mut(run_id) else {
+                return;
+            };
+            change(&mut entry.record);
+            let record = entry.record.clone();
+            if record.state.is_terminal() {
+                self.prune(&mut runs);
+            }
+            record
+        };
+        info!(
+            run_id,
+            container_id = record.container_id.as_str(),
+            state = ?record.state,
+            pid = record.pid,
+            exit_code = record.exit_code,
+            "Estado de ejecución actualizado"
+        );
+        let _ = self.events.send(record);
+    }
+
+    /// Descarta las ejecuciones terminadas más antiguas por encima del límite.
+    fn prune(&self, runs: &mut HashMap<String, RunEntry>) {
+        let mut finished: Vec<(u64, u64, String)> = runs
+            .values()
+            .filter(|entry| entry.record.state.is_terminal())
+            .map(|entry| {
+                let record = &entry.record;
+                (
+                    record.ended_at_ms.unwrap_or(record.started_at_ms),
+                    entry.sequence,
+                    record.run_id.clone(),
+                )
+            })
+            .collect();
+        if finished.len() <= self.max_finished {
+            return;
+        }
+        finished.sort();
+        let excess = finished.len() - self.max_finished;
+        for (_, _, run_id) in finished.into_iter().take(excess) {
+            runs.remove(&run_id);
+        }
+    }
+}
+
+/// Supervisa los procesos lanzados: guarda su historial, aplica la política de
+/// reinicio y permite detenerlos con cierre ordenado y `kill` tras el timeout.
+#[derive(Clone)]
+pub struct Supervisor {
+    inner: Arc<Inner>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_FINISHED_RUNS, STABLE_UPTIME)
+    }
+
+    /// `max_finished`: ejecuciones terminadas que se recuerdan; `stable_uptime`:
+    /// tiempo en marcha tras el que se olvidan los reinicios anteriores.
+    pub fn with_limits(max_finished: usize, stable_uptime: Duration) -> Self {
+        let (events, _) = broadcast::channel(256);
+        Self {
+            inner: Arc::new(Inner {
+                runs: Mutex::new(HashMap::new()),
+                next_sequence: AtomicU64::new(0),
+                events,
+                max_finished,
+                stable_uptime,
+            }),
+        }
+    }
+
+    /// Cambios de estado de todas las ejecuciones (para logs y reportes al backend).
+    pub fn subscribe(&self) -> broadcast::Receiver<RunRecord> {
+        self.inner.events.subscribe()
+    }
+
+    /// Lanza el proceso y devuelve su run id. Los errores del primer arranque se
+    /// devuelven al llamador; los de reinicios posteriores quedan en el registro.
+    pub fn start(&self, spec: RunSpec) -> Result<String> {
+        let process = launcher::spawn(&spec.request)?;
+        let run_id = Uuid::new_v4().to_string();
+        let (stop, stop_rx) = watch::channel(None);
+        let record = RunRecord {
+            run_id: run_id.clone(),
+            container_id: spec.container_id.clone(),
+            executable: spec.request.executable.clone(),
+            args: spec.request.args.clone(),
+            state: RunState::Running,
+            pid: process.child.id(),
+            started_at_ms: now_ms(),
+            ended_at_ms: None,
+            exit_code: None,
+            signal: None,
+            restarts: 0,
+            error: None,
+        };
+        self.inner.runs.lock().expect("lock poisoned").insert(
+            run_id.clone(),
+            RunEntry {
+                record: record.clone(),
+                sequence: self.inner.next_sequence.fetch_add(1, Ordering::Relaxed),
+                stop,
+            },
+        );
+        let _ = self.inner.events.send(record);
+
+        tokio::spawn(supervise(
+            self.inner.clone(),
+            run_id.clone(),
+            spec,
+            process,
+            stop_rx,
+        ));
+        Ok(run_id)
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<RunRecord> {
+        self.inner
+            .runs
+            .lock()
+            .expect("lock poisoned")
+            .get(run_id)
+            .map(|entry| entry.record.clone())
+    }
+
+    pub fn list(&self) -> Vec<RunRecord> {
+        let mut runs: Vec<_> = self
+            .inner
+            .runs
+            .lock()
+            .expect("lock poisoned")
+            .values()
+            .map(|entry| {
+                (
+                    entry.record.started_at_ms,
+                    entry.sequence,
+                    entry.record.clone(),
+                )
+            })
+            .collect();
+        runs.sort_by_key(|(started_at_ms, sequence, _)| (*started_at_ms, *sequence));
+        runs.into_iter().map(|(_, _, record)| record).collect()
+    }
+
+    /// Pide el cierre ordenado del proceso y espera a que termine; pasado
+    /// `grace` se fuerza con `kill`. Devuelve el registro final.
+    pub async fn stop(&self, run_id: &str, grace: Duration) -> Result<RunRecord> {
+        let mut events = self.subscribe();
+        {
+            let runs = self.inner.runs.lock().expect("lock poisoned");
+            let entry = runs
+                .get(run_id)
+                .ok_or_else(|| anyhow!("No existe la ejecución {run_id}"))?;
+            if entry.record.state.is_terminal() {
+                return Ok(entry.record.clone());
+            }
+            entry.stop.send_replace(Some(grace));
+        }
+        self.wait_terminal(run_id, &mut events).await
+    }
+
+    /// Detiene a la vez todas las ejecuciones activas (sin reinicios) y devuelve
+    /// su registro final. Cada una recibe `grace` antes del `kill`.
+    pub async fn stop_all(&self, grace: Duration) -> Vec<RunRecord> {
+        self.stop_where(grace, |_| true).await
+    }
+
+    /// Como [`Supervisor::stop_all`], solo con las ejecuciones de un contenedor.
+    pub async fn stop_container(&self, container_id: &str, grace: Duration) -> Vec<RunRecord> {
+        self.stop_where(grace, |run| run.container_id == container_id)
+            .await
+    }
+
+    async fn stop_where(
+        &self,
+        grace: Duration,
+        filter: impl Fn(&RunRecord) -> bool,
+    ) -> Vec<RunRecord> {
+        let active: Vec<_> = self
+            .list()
+            .into_iter()
+            .filter(|run| !run.state.is_terminal() && filter(run))
+            .map(|run| run.run_id)
+            .collect();
+        let stops: Vec<_> = active
+            .into_iter()
+            .map(|run_id| {
+                let supervisor = self.clone();
+                tokio::spawn(async move { supervisor.stop(&run_id, grace).await })
+            })
+            .collect();
+        let mut stopped = Vec::new();
+        for stop in stops {
+            match stop.await {
+                Ok(Ok(run)) => stopped.push(run),
+                Ok(Err(err)) => warn!(?err, "No se pudo detener la ejecución"),
+                Err(err) => warn!(?err, "La detención de la ejecución no terminó"),
+            }
+        }
+        stopped
+    }
+
+    /// Espera a que la ejecución llegue a un estado final.
+    pub async fn wait(&self, run_id: &str) -> Result<RunRecord> {
+        let mut events = self.subscribe();
+        self.wait_terminal(run_id, &mut events).await
+    }
+
+    async fn wait_terminal(
+        &self,
+        run_id: &str,
+        events: &mut broadcast::Receiver<RunRecord>,
+    ) -> Result<RunRecord> {
+        loop {
+            let current = self
+                .get(run_id)
+                .ok_or_else(|| anyhow!("No existe la ejecución {run_id}"))?;
+            if current.state.is_terminal() {
+                return Ok(current);
+            }
+            match events.recv().await {
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Ok(current),
+            }
+        }
+    }
+}
+
+async fn supervise(
+    inner: Arc<Inner>,
+    run_id: String,
+    spec: RunSpec,
+    first: Process,
+    mut stop_rx: watch::Receiver<Option<Duration>>,
+) {
+    let Process {
+        mut child,
+        mut group,
+    } = first;
+    // `restarts` cuenta desde el último periodo estable y decide la espera y
+    // el límite; `total` es lo que se informa.
+    let mut restarts = 0;
+    let mut total = 0;
+    loop {
+        let started = Instant::now();
+        let pumps = capture_output(&spec.log, &run_id, &mut child);
+        let (status, stopped) = tokio::select! {
+            status = child.wait() => (status, false),
+            _ = stop_rx.changed() => {
+                let grace = stop_rx.borrow().unwrap_or(DEFAULT_STOP_TIMEOUT);
+                inner.update(&run_id, |run| run.state = RunState::Stopping);
+                (terminate(&mut child, &group, grace).await, true)
+            }
+        };
+
+        // Vacía la salida pendiente antes de anotar el final; un nieto que herede
+        // las tuberías no debe bloquear al supervisor.
+        for pump in pumps {
+            let _ = timeout(OUTPUT_DRAIN, pump).await;
+        }
+        let success = matches!(&status, Ok(status) if status.success());
+        if started.elapsed() >= inner.stable_uptime {
+            restarts = 0;
+        }
+        log_event(&spec.log, &run_id, &describe_exit(&status));
+        inner.update(&run_id, |run| {
+            run.ended_at_ms = Some(now_ms());
+            match &status {
+                Ok(status) => {
+                    run.exit_code = status.code();
+                    run.signal = exit_signal(status);
+                }
+                Err(err) => run.error = Some(err.to_string()),
+            }
+            run.state = if stopped {
+                RunState::Stopped
+            } else if spec.restart.should_restart(success, restarts) {
+                RunState::Restarting
+            } else if success {
+                RunState::Exited
+            } else {
+                RunState::Failed
+            };
+        });
+        if stopped || !spec.restart.should_restart(success, restarts) {
+            return;
+        }
+
+        restarts += 1;
+        total += 1;
+        let delay = spec.restart.backoff(restarts);
+        tokio::select! {
+            _ = sleep(delay) => {}
+            _ = stop_rx.changed() => {
+                inner.update(&run_id, |run| run.state = RunState::Stopped);
+                return;
+            }
+        }
+
+        match launcher::spawn(&spec.request) {
+            Ok(next) => {
+                (child, group) = (next.child, next.group);
+                inner.update(&run_id, |run| {
+                    run.state = RunState::Running;
+                    run.pid = child.id();
+                    run.started_at_ms = now_ms();
+                    run.ended_at_ms = None;
+                    run.exit_code = None;
+                    run.signal = None;
+                    run.restarts = total;
+                });
+            }
+            Err(err) => {
+                warn!(
+                    run_id = run_id.as_str(),
+                    ?err,
+                    "No se pudo reiniciar el proceso"
+                );
+                inner.update(&run_id, |run| {
+                    run.state = RunState::Failed;
+                    run.restarts = total;
+                    run.error = Some(format!("{err:#}"));
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// Conecta stdout/stderr del proceso al log del contenedor.
+fn capture_output(log: &ContainerLog, run_id: &str, child: &mut Child) -> Vec<JoinHandle<()>> {
+    log_event(
+        log,
+        run_id,
+        &format!("proceso iniciado (pid {})", child.id().unwrap_or_default()),
+    );
+    let mut pumps = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        pumps.push(tokio::spawn(log.clone().pump(
+            run_id.to_string(),
+            LogStream::Stdout,
+            stdout,
+        )));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        pumps.push(tokio::spawn(log.clone().pump(
+            run_id.to_string(),
+            LogStream::Stderr,
+            stderr,
+        )));
+    }
+    pumps
+}
+
+fn log_event(log: &ContainerLog, run_id: &str, text: &str) {
+    if let Err(err) = log.write(run_id, LogStream::Agent, text) {
+        warn!(run_id, ?err, "No se pudo escribir el log");
+    }
+}
+
+fn describe_exit(status: &std::io::Result<ExitStatus>) -> String {
+    match status {
+        Ok(status) => match (status.code(), exit_signal(status)) {
+            (Some(code), _) => format!("proceso terminado con código {code}"),
+            (None, Some(signal)) => format!("proceso terminado por la señal {signal}"),
+            (None, None) => "proceso terminado".to_string(),
+        },
+        Err(err) => format!("no se pudo esperar el proceso: {err}"),
+    }
+}
+
+/// Pide al grupo del proceso que termine y, pasado `grace`, lo mata entero.
+async fn terminate(
+    child: &mut Child,
+    group: &ProcessGroup,
+    grace: Duration,
+) -> std::io::Result<ExitStatus> {
+    group.terminate().await;
+    match timeout(grace, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            warn!(
+                pid = child.id(),
+                "El proceso no terminó a tiempo; forzando kill"
+            );
+            group.kill()?;
+            child.wait().await
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::logs::{self, LogConfig};
+    use crate::overlay::{Layer, LayerKind, LayerStack};
+    use crate::runtime::HookPlan;
+    use std::{collections::HashMap as Map, path::Path};
+
+    fn spec(dir: &Path, script: &str, restart: RestartPolicy) -> RunSpec {
+        RunSpec {
+            container_id: "demo".into(),
+            request: LaunchRequest {
+                executable: "/bin/sh".into(),
+                args: vec!["-c".into(), script.into()],
+                working_dir: None,
+                hook_plan: HookPlan {
+                    env: Map::new(),
+                    mounts: vec![],
+                    redirects: vec![],
+                    overlay: LayerStack::new(Layer::new("demo", LayerKind::Container, "/tmp")),
+                    registry: None,
+                },
+            },
+            restart,
+            log: ContainerLog::open(dir, LogConfig::default()).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn records_exit_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new();
+        let ok = supervisor
+            .start(spec(dir.path(), "exit 0", RestartPolicy::Never))
+            .unwrap();
+        let failed = supervisor
+            .start(spec(dir.path(), "exit 7", RestartPolicy::Never))
+            .unwrap();
+
+        let ok = supervisor.wait(&ok).await.unwrap();
+        assert_eq!(ok.state, RunState::Exited);
+        assert_eq!(ok.exit_code, Some(0));
+        assert!(ok.pid.is_some());
+        assert!(ok.ended_at_ms.unwrap() >= ok.started_at_ms);
+
+        let failed = supervisor.wait(&failed).await.unwrap();
+        assert_eq!(failed.state, RunState::Failed);
+        assert_eq!(failed.exit_code, Some(7));
+        assert_eq!(supervisor.list().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn on_failure_retries_with_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("starts");
+        let script = format!("echo x >> {}; exit 3", counter.display());
+        let supervisor = Supervisor::new();
+        let run = supervisor
+            .start(spec(
+                dir.path(),
+                &script,
+                RestartPolicy::OnFailure {
+                    max_retries: 2,
+                    backoff_ms: 10,
+                },
+            ))
+            .unwrap();
+
+        let record = supervisor.wait(&run).await.unwrap();
+        assert_eq!(record.state, RunState::Failed);
+        assert_eq!(record.restarts, 2);
+        assert_eq!(std::fs::read_to_string(counter).unwrap().lines().count(), 3);
+    }
+
+    #[tokio::test]
+    async fn stable_runs_reset_the_restart_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::with_limits(MAX_FINISHED_RUNS, Duration::from_millis(100));
+        let run = supervisor
+            .start(spec(
+                dir.path(),
+                "sleep 0.3; exit 1",
+                RestartPolicy::OnFailure {
+                    max_retries: 1,
+                    backoff_ms: 10,
+                },
+            ))
+            .unwrap();
+
+        let mut events = supervisor.subscribe();
+        timeout(Duration::from_secs(10), async {
+            while supervisor.get(&run).unwrap().restarts < 3 {
+                let _ = events.recv().await;
+            }
+        })
+        .await
+        .expect("cada arranque estable debe permitir otro reintento");
+        let record = supervisor.stop(&run, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(record.state, RunState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn forgets_the_oldest_finished_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::with_limits(2, STABLE_UPTIME);
+        let live = supervisor
+            .start(spec(dir.path(), "sleep 10", RestartPolicy::Never))
+            .unwrap();
+        let mut finished = Vec::new();
+        for code in 0..4 {
+            let run = supervisor
+                .start(spec(
+                    dir.path(),
+                    &format!("exit {code}"),
+                    RestartPolicy::Never,
+                ))
+                .unwrap();
+            supervisor.wait(&run).await.unwrap();
+            finished.push(run);
+        }
+
+        let kept: Vec<_> = supervisor
+            .list()
+            .into_iter()
+            .map(|run| run.run_id)
+            .collect();
+        assert_eq!(
+            kept,
+            vec![live.clone(), finished[2].clone(), finished[3].clone()]
+        );
+        supervisor
+            .stop(&live, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn stop_prefers_graceful_shutdown() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new();
+        let run = supervisor
+            .start(spec(
+                dir.path(),
+                "trap 'exit 0' TERM; while true; do sleep 0.1; done",
+                RestartPolicy::Always { backoff_ms: 10 },
+            ))
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+
+        let record = supervisor.stop(&run, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(record.state, RunState::Stopped);
+        assert_eq!(record.exit_code, Some(0));
+        assert_eq!(record.restarts, 0);
+    }
+
+    #[tokio::test]
+    async fn stop_kills_after_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new();
+        let run = supervisor
+            .start(spec(
+                dir.path(),
+                "trap '' TERM; while true; do sleep 0.1; done",
+                RestartPolicy::Never,
+            ))
+            .unwrap();
+        sleep(Duration::from_millis(200)).await;
+
+        let record = supervisor
+            .stop(&run, Duration::from_millis(300))
+            .await
+            .unwrap();
+        assert_eq!(record.state, RunState::Stopped);
+        assert_eq!(record.signal, Some(libc::SIGKILL));
+    }
+
+    #[tokio::test]
+    async fn stop_reaches_the_processes_it_launched() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("grandchild");
+        let script = format!("sleep 30 & echo $! > {}; wait", pid_file.display());
+        let supervisor = Supervisor::new();
+        let run = supervisor
+            .start(spec(dir.path(), &script, RestartPolicy::Never))
+            .unwrap();
+        while std::fs::read_to_string(&pid_file).map_or(true, |pid| !pid.ends_with('\n')) {
+            sleep(Duration::from_millis(20)).await;
+        }
+        let grandchild = std::fs::read_to_string(&pid_file).unwrap();
+
+        let record = supervisor.stop(&run, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(record.state, RunState::Stopped);
+        // Al morir queda huérfano y, hasta que alguien lo recoge, como zombi.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let ps = std::process::Command::new("ps")
+                .args(["-o", "stat=", "-p", grandchild.trim()])
+                .output()
+                .unwrap();
+            let stat = String::from_utf8_lossy(&ps.stdout);
+            if stat.trim().is_empty() || stat.trim_start().starts_with('Z') {
+                break;
+            }
+            assert!(Instant::now() < deadline, "el nieto sigue vivo: {stat}");
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn captures_output_tagged_by_run_and_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let supervisor = Supervisor::new();
+        let spec = spec(
+            dir.path(),
+            "echo hola; echo fallo >&2",
+            RestartPolicy::Never,
+        );
+        let log = spec.log.clone();
+        let run = supervisor.start(spec).unwrap();
+        supervisor.wait(&run).await.unwrap();
+        log.flush().await.unwrap();
+
+        let lines = logs::read_lines(&dir.path().join(logs::LOG_DIR), Some(&run)).unwrap();
+        let output: Vec<_> = lines
+            .iter()
+            .filter(|line| line.stream != LogStream::Agent)
+            .map(|line| (line.stream, line.text.as_str()))
+            .collect();
+        assert!(output.contains(&(LogStream::Stdout, "hola")));
+        assert!(output.contains(&(LogStream::Stderr, "fallo")));
+        assert!(lines
+            .iter()
+            .any(|line| line.text == "proceso terminado con código 0"));
+    }
+
+    #[test]
+    fn parses_policies_and_backoff() {
+        let policy: RestartPolicy =
+            serde_yaml::from_str("policy: on-failure\nmax_retries: 5\n").unwrap();
+        assert_eq!(
+            policy,
+            RestartPolicy::OnFailure {
+                max_retries: 5,
+                backoff_ms: 1000
+            }
+        );
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(30), MAX_BACKOFF);
+        assert!(policy.should_restart(false, 4));
+        assert!(!policy.should_restart(false, 5));
+        assert!(!policy.should_restart(true, 0));
+        assert_eq!(
+            serde_yaml::from_str::<RestartPolicy>("policy: never").unwrap(),
+            RestartPolicy::Never
+        );
+    }
+}
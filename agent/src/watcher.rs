@@ -0,0 +1,306 @@
+use crate::registry::{ContainerRegistry, RegistryEvent};
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{debug, info, warn};
+
+/// Observa la carpeta de contenedores y emite eventos de alta, cambio y baja
+/// cuando se modifican los `config.yml`, sin reiniciar el agent. Ninguna
+/// observación es recursiva: cada raíz avisa de las carpetas que aparecen o
+/// desaparecen y cada `<raíz>/<id>/` de los cambios en su `config.yml`, así
+/// que lo que escriben las aplicaciones dentro del contenedor no cuesta nada.
+pub struct RegistryWatcher {
+    task: JoinHandle<()>,
+    refresh: mpsc::UnboundedSender<oneshot::Sender<Result<usize>>>,
+}
+
+impl RegistryWatcher {
+    /// Arranca el watcher a partir de `current`, el registro ya aplicado por el agent.
+    /// Los cambios llegan agrupados tras `debounce` por el canal devuelto.
+    pub fn spawn(
+        roots: Vec<PathBuf>,
+        current: ContainerRegistry,
+        debounce: Duration,
+    ) -> Result<(Self, mpsc::UnboundedReceiver<RegistryEvent>)> {
+        let mut watched = Vec::new();
+        for root in &roots {
+            std::fs::create_dir_all(root)
+                .with_context(|| format!("No se pudo crear {}", root.display()))?;
+            watched.push(root.clone());
+            watched.push(root.canonicalize()?);
+        }
+
+        let (raw_tx, mut raw_rx) = mpsc::unbounded_channel();
+        let mut debouncer = new_debouncer(debounce, move |result: DebounceEventResult| {
+            let _ = raw_tx.send(result);
+        })
+        .context("No se pudo crear el watcher de contenedores")?;
+        for root in &roots {
+            debouncer
+                .watcher()
+                .watch(root, RecursiveMode::NonRecursive)
+                .with_context(|| format!("No se pudo observar {}", root.display()))?;
+        }
+        let mut containers = BTreeSet::new();
+        watch_containers(debouncer.watcher(), &roots, &mut containers);
+
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (refresh, mut refresh_rx) = mpsc::unbounded_channel::<oneshot::Sender<Result<usize>>>();
+        let task = tokio::spawn(async move {
+            // El debouncer deja de emitir eventos al destruirse.
+            let mut debouncer = debouncer;
+            let mut current = current;
+            info!(?roots, "Observando cambios en contenedores");
+
+            loop {
+                let reply = tokio::select! {
+                    Some(result) = raw_rx.recv() => {
+                        let paths: Vec<PathBuf> = match result {
+                            Ok(events) => events.into_iter().map(|event| event.path).collect(),
+                            Err(err) => {
+                                warn!(?err, "Error del watcher de contenedores");
+                                continue;
+                            }
+                        };
+                        if !paths
+                            .iter()
+                            .any(|path| watched.iter().any(|root| is_relevant(root, path)))
+                        {
+                            continue;
+                        }
+                        None
+                    }
+                    Some(reply) = refresh_rx.recv() => Some(reply),
+                    else => return,
+                };
+
+                // Antes de recargar, para no perder un `config.yml` escrito
+                // entre la recarga y el alta de la carpeta.
+                watch_containers(debouncer.watcher(), &roots, &mut containers);
+                let mut next = match ContainerRegistry::load_roots(&roots).await {
+                    Ok(next) => next,
+                    Err(err) => {
+                        warn!(
+                            error = format!("{err:#}"),
+                            "Recarga de contenedores descartada; se mantiene el registro actual"
+                        );
+                        if let Some(reply) = reply {
+                            let _ = reply.send(Err(err));
+                        }
+                        continue;
+                    }
+                };
+
+                next.retain_broken(&current);
+                let events = current.diff(&next);
+                let changes = events.len();
+                for event in events {
+                    debug!(?event, "Cambio detectado en el registro");
+                    current.apply(&event);
+                    if events_tx.send(event).is_err() {
+                        return;
+                    }
+                }
+                if let Some(reply) = reply {
+                    let _ = reply.send(Ok(changes));
+                }
+            }
+        });
+
+        Ok((Self { task, refresh }, events_rx))
+    }
+
+    /// Fuerza una recarga sin esperar a que cambie ningún archivo. El receptor
+    /// devuelve cuántos eventos generó, ya enviados por el canal de cambios.
+    pub fn refresh(&self) -> oneshot::Receiver<Result<usize>> {
+        let (reply, rx) = oneshot::channel();
+        let _ = self.refresh.send(reply);
+        rx
+    }
+}
+
+impl Drop for RegistryWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Observa, sin recursión, las carpetas de contenedor que hay ahora en `roots` y
+/// deja de observar las que ya no están. Las que no se pueden observar se
+/// reintentan en la siguiente recarga.
+fn watch_containers(watcher: &mut dyn Watcher, roots: &[PathBuf], watched: &mut BTreeSet<PathBuf>) {
+    let mut found = BTreeSet::new();
+    for root in roots {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                found.insert(entry.path());
+            }
+        }
+    }
+    for gone in watched.difference(&found) {
+        let _ = watcher.unwatch(gone);
+    }
+    watched.retain(|dir| found.contains(dir));
+    for dir in found {
+        if watched.contains(&dir) {
+            continue;
+        }
+        match watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            Ok(()) => {
+                watched.insert(dir);
+            }
+            Err(err) => {
+                warn!(?err, dir = %dir.display(), "No se pudo observar la carpeta del contenedor")
+            }
+        }
+    }
+}
+
+/// Solo interesan `<root>/<id>/config.yml` y las carpetas de primer nivel que
+/// aparecen o desaparecen (altas/bajas de contenedores).
+fn is_relevant(root: &Path, path: &Path) -> bool {
+    match path
+        .strip_prefix(root)
+        .map(|rest| rest.components().count())
+    {
+        Ok(1) => !path.is_file(),
+        Ok(2) => path.file_name().is_some_and(|name| name == "config.yml"),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    async fn next_event(rx: &mut mpsc::UnboundedReceiver<RegistryEvent>) -> RegistryEvent {
+        timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("no llegó ningún evento")
+            .expect("canal cerrado")
+    }
+
+    fn write_manifest(root: &Path, dir: &str, body: &str) {
+        std::fs::create_dir_all(root.join(dir)).unwrap();
+        std::fs::write(root.join(dir).join("config.yml"), body).unwrap();
+    }
+
+    #[tokio::test]
+    async fn emits_incremental_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        write_manifest(&root, "stable", "id: stable\nname: Stable\n");
+        let registry = ContainerRegistry::load_from(&root).await.unwrap();
+
+        let (_watcher, mut rx) =
+            RegistryWatcher::spawn(vec![root.clone()], registry, Duration::from_millis(100))
+                .unwrap();
+
+        write_manifest(&root, "fresh", "id: fresh\nname: Fresh\n");
+        assert!(matches!(
+            next_event(&mut rx).await,
+            RegistryEvent::Added(c) if c.manifest.id == "fresh"
+        ));
+
+        write_manifest(&root, "fresh", "id: fresh\nname: Fresh 2\n");
+        assert!(matches!(
+            next_event(&mut rx).await,
+            RegistryEvent::Updated(c) if c.manifest.name == "Fresh 2"
+        ));
+
+        write_manifest(&root, "fresh", "id: fresh\nname: [typo\n");
+        assert!(matches!(
+            next_event(&mut rx).await,
+            RegistryEvent::Diagnostics(d) if d.len() == 1 && d[0].retained
+        ));
+
+        std::fs::remove_dir_all(root.join("fresh")).unwrap();
+        assert_eq!(
+            next_event(&mut rx).await,
+            RegistryEvent::Removed("fresh".into())
+        );
+        assert_eq!(
+            next_event(&mut rx).await,
+            RegistryEvent::Diagnostics(vec![])
+        );
+        assert!(rx.try_recv().is_err(), "stable no debe generar eventos");
+    }
+
+    #[tokio::test]
+    async fn refresh_reloads_on_demand() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let registry = ContainerRegistry::load_from(&root).await.unwrap();
+        let (watcher, mut rx) =
+            RegistryWatcher::spawn(vec![root.clone()], registry, Duration::from_secs(3600))
+                .unwrap();
+
+        write_manifest(&root, "late", "id: late\nname: Late\n");
+        let changes = timeout(Duration::from_secs(10), watcher.refresh())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(changes, 1);
+        assert!(matches!(
+            rx.try_recv().unwrap(),
+            RegistryEvent::Added(c) if c.manifest.id == "late"
+        ));
+        assert_eq!(watcher.refresh().await.unwrap().unwrap(), 0);
+    }
+
+    #[test]
+    fn filters_irrelevant_paths() {
+        let root = Path::new("/containers");
+        assert!(is_relevant(root, Path::new("/containers/app/config.yml")));
+        assert!(is_relevant(root, Path::new("/containers/app")));
+        assert!(!is_relevant(
+            root,
+            Path::new("/containers/app/temp/file.tmp")
+        ));
+        assert!(!is_relevant(
+            root,
+            Path::new("/containers/app/data/config.yml")
+        ));
+        assert!(!is_relevant(root, Path::new("/containers/app/notes.txt")));
+        assert!(!is_relevant(root, Path::new("/otros/app/config.yml")));
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("readme.txt"), "").unwrap();
+        assert!(!is_relevant(dir.path(), &dir.path().join("readme.txt")));
+    }
+
+    #[tokio::test]
+    async fn ignores_writes_inside_containers() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        write_manifest(&root, "app", "id: app\nname: App\n");
+        std::fs::create_dir_all(root.join("app/data")).unwrap();
+        let registry = ContainerRegistry::load_from(&root).await.unwrap();
+        let (_watcher, mut rx) =
+            RegistryWatcher::spawn(vec![root.clone()], registry, Duration::from_millis(100))
+                .unwrap();
+
+        std::fs::write(root.join("app/data/cache.bin"), "x").unwrap();
+        std::fs::write(root.join("app/data/config.yml"), "id: otro\n").unwrap();
+        write_manifest(&root, "app", "id: app\nname: App 2\n");
+        assert!(matches!(
+            next_event(&mut rx).await,
+            RegistryEvent::Updated(c) if c.manifest.name == "App 2"
+        ));
+        assert!(rx.try_recv().is_err());
+    }
+}
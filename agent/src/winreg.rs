@@ -0,0 +1,1214 @@
+use crate::{
+    registry::RegisteredContainer,
+    runtimes::{RuntimeRef, RuntimeStore},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{de, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+pub const REG_SZ: u32 = 1;
+pub const REG_EXPAND_SZ: u32 = 2;
+pub const REG_BINARY: u32 = 3;
+pub const REG_DWORD: u32 = 4;
+pub const REG_MULTI_SZ: u32 = 7;
+pub const REG_QWORD: u32 = 11;
+
+/// Carpeta de los hives dentro del contenedor.
+pub const REGISTRY_DIR: &str = "user/Registry";
+
+/// Identificador del formato de los archivos de registro del contenedor.
+pub const REGISTRY_FORMAT: &str = "ctnr-registry/1";
+
+/// Valor de registro tipado.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum RegValue {
+    Sz(String),
+    ExpandSz(String),
+    Binary(#[serde(with = "hex_bytes")] Vec<u8>),
+    Dword(u32),
+    Qword(u64),
+    MultiSz(Vec<String>),
+    /// Cualquier otro tipo (`REG_NONE`, `REG_RESOURCE_LIST`...) o datos que no
+    /// encajan con su tipo; se conservan tal cual.
+    Other {
+        kind: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+}
+
+impl RegValue {
+    /// Código `REG_*` del tipo.
+    pub fn kind(&self) -> u32 {
+        match self {
+            Self::Sz(_) => REG_SZ,
+            Self::ExpandSz(_) => REG_EXPAND_SZ,
+            Self::Binary(_) => REG_BINARY,
+            Self::Dword(_) => REG_DWORD,
+            Self::Qword(_) => REG_QWORD,
+            Self::MultiSz(_) => REG_MULTI_SZ,
+            Self::Other { kind, .. } => *kind,
+        }
+    }
+
+    /// Nombre del tipo como lo muestra `reg.exe`.
+    pub fn type_name(&self) -> String {
+        match self.kind() {
+            0 => "REG_NONE".into(),
+            REG_SZ => "REG_SZ".into(),
+            REG_EXPAND_SZ => "REG_EXPAND_SZ".into(),
+            REG_BINARY => "REG_BINARY".into(),
+            REG_DWORD => "REG_DWORD".into(),
+            5 => "REG_DWORD_BIG_ENDIAN".into(),
+            6 => "REG_LINK".into(),
+            REG_MULTI_SZ => "REG_MULTI_SZ".into(),
+            8 => "REG_RESOURCE_LIST".into(),
+            REG_QWORD => "REG_QWORD".into(),
+            other => format!("0x{other:x}"),
+        }
+    }
+
+    /// Datos tal como los devuelve `RegQueryValueExW`: cadenas UTF-16LE con su
+    /// terminador y enteros little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Sz(text) | Self::ExpandSz(text) => utf16_bytes([text.as_str()]),
+            Self::MultiSz(items) => {
+                let mut bytes = utf16_bytes(items.iter().map(String::as_str));
+                bytes.extend([0, 0]);
+                bytes
+            }
+            Self::Binary(data) => data.clone(),
+            Self::Dword(value) => value.to_le_bytes().to_vec(),
+            Self::Qword(value) => value.to_le_bytes().to_vec(),
+            Self::Other { data, .. } => data.clone(),
+        }
+    }
+
+    /// Inversa de [`RegValue::to_bytes`]. Tolera cadenas sin terminador, como
+    /// hace Windows con los datos escritos por aplicaciones descuidadas.
+    pub fn from_bytes(kind: u32, data: &[u8]) -> Result<Self> {
+        Ok(match kind {
+            REG_SZ => Self::Sz(utf16_strings(data).into_iter().next().unwrap_or_default()),
+            REG_EXPAND_SZ => {
+                Self::ExpandSz(utf16_strings(data).into_iter().next().unwrap_or_default())
+            }
+            REG_MULTI_SZ => Self::MultiSz(utf16_strings(data)),
+            REG_BINARY => Self::Binary(data.to_vec()),
+            REG_DWORD => Self::Dword(u32::from_le_bytes(
+                data.try_into()
+                    .map_err(|_| anyhow!("REG_DWORD de {} bytes", data.len()))?,
+            )),
+            REG_QWORD => Self::Qword(u64::from_le_bytes(
+                data.try_into()
+                    .map_err(|_| anyhow!("REG_QWORD de {} bytes", data.len()))?,
+            )),
+            kind => Self::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        })
+    }
+
+    /// Como [`RegValue::from_bytes`], pero solo devuelve un valor tipado si
+    /// [`RegValue::to_bytes`] reproduce los mismos bytes. Lo demás (un
+    /// `REG_DWORD` de 2 bytes, una cadena sin terminador o con datos tras el
+    /// NUL, UTF-16 inválido) se conserva como [`RegValue::Other`].
+    pub fn from_bytes_lossless(kind: u32, data: &[u8]) -> Self {
+        match Self::from_bytes(kind, data) {
+            Ok(value) if value.to_bytes() == data => value,
+            _ => Self::Other {
+                kind,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for RegValue {
+    /// Datos con el formato de `reg query`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sz(text) | Self::ExpandSz(text) => f.write_str(text),
+            Self::MultiSz(items) => f.write_str(&items.join("\\0")),
+            Self::Dword(value) => write!(f, "0x{value:x}"),
+            Self::Qword(value) => write!(f, "0x{value:x}"),
+            Self::Binary(data) | Self::Other { data, .. } => f.write_str(&hex::encode_upper(data)),
+        }
+    }
+}
+
+fn utf16_bytes<'a>(items: impl IntoIterator<Item = &'a str>) -> Vec<u8> {
+    items
+        .into_iter()
+        .flat_map(|item| item.encode_utf16().chain([0]))
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+/// Cadenas separadas por NUL; se descarta todo lo que sigue al doble NUL.
+fn utf16_strings(data: &[u8]) -> Vec<String> {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    let mut items = Vec::new();
+    for item in units.split(|unit| *unit == 0) {
+        if item.is_empty() {
+            break;
+        }
+        items.push(String::from_utf16_lossy(item));
+    }
+    items
+}
+
+mod hex_bytes {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        hex::decode(text).map_err(de::Error::custom)
+    }
+}
+
+/// Ruta de una clave relativa a la raíz de su hive (`Software\Vendor\App`).
+/// Los componentes conservan sus mayúsculas; las comparaciones las ignoran.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyPath(Vec<String>);
+
+impl KeyPath {
+    pub fn root() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(path: &str) -> Result<Self> {
+        let path = path.trim_matches('\\');
+        if path.is_empty() {
+            return Ok(Self::root());
+        }
+        let components: Vec<_> = path.split('\\').map(str::to_string).collect();
+        if components.iter().any(String::is_empty) {
+            bail!("Ruta de registro inválida: {path}");
+        }
+        Ok(Self(components))
+    }
+
+    pub fn components(&self) -> &[String] {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn join(&self, name: &str) -> Self {
+        let mut components = self.0.clone();
+        components.push(name.to_string());
+        Self(components)
+    }
+
+    /// Ruta del padre y nombre de la clave; `None` para la raíz.
+    pub fn split_last(&self) -> Option<(KeyPath, &str)> {
+        let (last, parent) = self.0.split_last()?;
+        Some((KeyPath(parent.to_vec()), last.as_str()))
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join("\\"))
+    }
+}
+
+/// Nombre normalizado para comparar claves y valores sin distinguir mayúsculas.
+fn fold(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Mapa indexado sin distinguir mayúsculas que conserva el nombre original.
+/// Se serializa como un objeto JSON con los nombres originales.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseMap<V>(BTreeMap<String, (String, V)>);
+
+impl<V> Default for CaseMap<V> {
+    fn default() -> Self {
+        Self(BTreeMap::new())
+    }
+}
+
+impl<V> CaseMap<V> {
+    pub fn get(&self, name: &str) -> Option<&V> {
+        self.0.get(&fold(name)).map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut V> {
+        self.0.get_mut(&fold(name)).map(|(_, value)| value)
+    }
+
+    /// Inserta o reemplaza; el nombre nuevo sustituye al anterior.
+    pub fn insert(&mut self, name: &str, value: V) -> Option<V> {
+        self.0
+            .insert(fold(name), (name.to_string(), value))
+            .map(|(_, old)| old)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<V> {
+        self.0.remove(&fold(name)).map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(&fold(name))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Pares `(nombre original, valor)` en orden alfabético sin mayúsculas.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &V)> {
+        self.0.values().map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Obtiene o crea la entrada, conservando el nombre existente.
+    pub fn entry_or_default(&mut self, name: &str) -> &mut V
+    where
+        V: Default,
+    {
+        &mut self
+            .0
+            .entry(fold(name))
+            .or_insert_with(|| (name.to_string(), V::default()))
+            .1
+    }
+}
+
+impl<V: Serialize> Serialize for CaseMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in self.iter() {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for CaseMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = BTreeMap::<String, V>::deserialize(deserializer)?;
+        let mut map = CaseMap::default();
+        for (name, value) in raw {
+            if map.insert(&name, value).is_some() {
+                return Err(de::Error::custom(format!("nombre repetido: {name}")));
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// Conjunto de nombres sin distinguir mayúsculas (marcadores de borrado).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CaseSet(CaseMap<()>);
+
+impl CaseSet {
+    pub fn insert(&mut self, name: &str) {
+        self.0.insert(name, ());
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.0.remove(name).is_some()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(name, _)| name)
+    }
+}
+
+impl Serialize for CaseSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de> Deserialize<'de> for CaseSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut set = CaseSet::default();
+        for name in BTreeSet::<String>::deserialize(deserializer)? {
+            set.insert(&name);
+        }
+        Ok(set)
+    }
+}
+
+/// Clave de registro con sus valores y subclaves. En una capa overlay puede
+/// llevar marcadores de borrado de valores y subclaves de las capas inferiores.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegKey {
+    /// Valores por nombre; `""` es el valor por defecto de la clave.
+    #[serde(skip_serializing_if = "CaseMap::is_empty")]
+    pub values: CaseMap<RegValue>,
+    #[serde(skip_serializing_if = "CaseMap::is_empty")]
+    pub subkeys: CaseMap<RegKey>,
+    /// Valores de la base ocultos por esta capa.
+    #[serde(skip_serializing_if = "CaseSet::is_empty")]
+    pub deleted_values: CaseSet,
+    /// Subclaves de la base ocultas por esta capa.
+    #[serde(skip_serializing_if = "CaseSet::is_empty")]
+    pub deleted_subkeys: CaseSet,
+    /// La clave se borró y se volvió a crear: oculta todo lo de la base.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub opaque: bool,
+}
+
+impl RegKey {
+    pub fn key(&self, path: &KeyPath) -> Option<&RegKey> {
+        path.components()
+            .iter()
+            .try_fold(self, |key, name| key.subkeys.get(name))
+    }
+
+    /// Crea las claves que falten hasta `path`.
+    pub fn create(&mut self, path: &KeyPath) -> &mut RegKey {
+        path.components()
+            .iter()
+            .fold(self, |key, name| key.subkeys.entry_or_default(name))
+    }
+
+    /// Copia `other` encima de esta clave: sus valores reemplazan a los
+    /// existentes y sus subclaves se combinan recursivamente.
+    pub fn merge(&mut self, other: &RegKey) {
+        for (name, value) in other.values.iter() {
+            self.values.insert(name, value.clone());
+        }
+        for (name, subkey) in other.subkeys.iter() {
+            self.subkeys.entry_or_default(name).merge(subkey);
+        }
+    }
+
+    /// Árbol de un hive `regf`; los valores de tipos desconocidos se conservan
+    /// como [`RegValue::Other`].
+    pub fn from_hive(key: &regf::Key) -> Self {
+        let mut converted = RegKey::default();
+        for value in &key.values {
+            converted.values.insert(
+                &value.name,
+                RegValue::from_bytes_lossless(value.kind, &value.data),
+            );
+        }
+        for subkey in &key.subkeys {
+            converted
+                .subkeys
+                .insert(&subkey.name, RegKey::from_hive(subkey));
+        }
+        converted
+    }
+
+    /// Clave `regf` con este contenido. Los marcadores de borrado no tienen
+    /// equivalente en un hive y se descartan.
+    pub fn to_hive(&self, name: &str) -> regf::Key {
+        let mut key = regf::Key::new(name);
+        for (name, value) in self.values.iter() {
+            key.values.push(regf::Value {
+                name: name.to_string(),
+                kind: value.kind(),
+                data: value.to_bytes(),
+            });
+        }
+        for (name, subkey) in self.subkeys.iter() {
+            key.subkeys.push(subkey.to_hive(name));
+        }
+        key
+    }
+}
+
+/// Archivo de un hive en disco (ver `docs/spec.md`, sección 4.1).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegistryFile {
+    format: String,
+    root: RegKey,
+}
+
+/// Lee un hive guardado con [`save_hive`]; si no existe, devuelve uno vacío.
+pub fn load_hive(path: &Path) -> Result<RegKey> {
+    let raw = match std::fs::read(path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(RegKey::default()),
+        Err(err) => return Err(err).with_context(|| format!("No se pudo leer {}", path.display())),
+    };
+    let file: RegistryFile = serde_json::from_slice(&raw)
+        .with_context(|| format!("Registro inválido en {}", path.display()))?;
+    if file.format != REGISTRY_FORMAT {
+        bail!(
+            "{}: formato {} no soportado (se esperaba {REGISTRY_FORMAT})",
+            path.display(),
+            file.format
+        );
+    }
+    Ok(file.root)
+}
+
+/// Guarda un hive escribiendo a un archivo temporal y renombrándolo, para no
+/// dejar un archivo a medias si el agent se detiene. El temporal lleva el pid
+/// y un contador para que dos escritores no se pisen.
+pub fn save_hive(path: &Path, root: &RegKey) -> Result<()> {
+    static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = RegistryFile {
+        format: REGISTRY_FORMAT.to_string(),
+        root: root.clone(),
+    };
+    let tmp = path.with_extension(format!(
+        "json.{}-{}.tmp",
+        std::process::id(),
+        NEXT_TMP.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&tmp, serde_json::to_vec(&file)?)
+        .with_context(|| format!("No se pudo guardar {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("No se pudo guardar {}", path.display()))
+}
+
+/// Bloqueo exclusivo del hive en `path` (sobre `<hive>.json.lock`, porque el
+/// propio archivo se reemplaza al guardar); se suelta al soltar el `File`.
+fn lock_hive(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let lock = path.with_extension("json.lock");
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock)
+        .with_context(|| format!("No se pudo abrir {}", lock.display()))?;
+    file.lock()
+        .with_context(|| format!("No se pudo bloquear {}", lock.display()))?;
+    Ok(file)
+}
+
+/// Vista de una clave en el overlay: lo que aporta cada capa.
+#[derive(Clone, Copy)]
+struct Merged<'a> {
+    layer: Option<&'a RegKey>,
+    /// `None` si la base no tiene la clave o la capa la oculta.
+    base: Option<&'a RegKey>,
+}
+
+/// Capa escribible del contenedor sobre un snapshot base de solo lectura. Las
+/// lecturas combinan ambas (la capa gana); las escrituras y los borrados solo
+/// tocan la capa, con marcadores para ocultar lo que viene de la base.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RegistryOverlay {
+    base: RegKey,
+    layer: RegKey,
+    /// Cambios aún sin guardar, para volver a aplicarlos sobre lo que otro
+    /// proceso del contenedor haya guardado mientras tanto.
+    edits: Vec<LayerEdit>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LayerEdit {
+    CreateKey(KeyPath),
+    SetValue(KeyPath, String, RegValue),
+    DeleteValue(KeyPath, String),
+    DeleteKey(KeyPath),
+    DeleteTree(KeyPath),
+}
+
+impl RegistryOverlay {
+    pub fn new(base: RegKey, layer: RegKey) -> Self {
+        Self {
+            base,
+            layer,
+            edits: Vec::new(),
+        }
+    }
+
+    /// Hay cambios sin guardar.
+    pub fn is_dirty(&self) -> bool {
+        !self.edits.is_empty()
+    }
+
+    /// Sustituye la capa por `layer` (la guardada en disco) y le vuelve a
+    /// aplicar los cambios pendientes. Un borrado de clave que ya no procede
+    /// (otro proceso le añadió subclaves) se descarta.
+    fn rebase(&mut self, layer: RegKey) {
+        let mut merged = Self::new(std::mem::take(&mut self.base), layer);
+        for edit in &self.edits {
+            match edit {
+                LayerEdit::CreateKey(path) => merged.create_key(path),
+                LayerEdit::SetValue(path, name, value) => {
+                    merged.set_value(path, name, value.clone())
+                }
+                LayerEdit::DeleteValue(path, name) => {
+                    merged.delete_value(path, name);
+                }
+                LayerEdit::DeleteKey(path) => {
+                    let _ = merged.delete_key(path);
+                }
+                LayerEdit::DeleteTree(path) => {
+                    let _ = merged.delete_tree(path);
+                }
+            }
+        }
+        self.base = merged.base;
+        self.layer = merged.layer;
+    }
+
+    pub fn layer(&self) -> &RegKey {
+        &self.layer
+    }
+
+    fn resolve(&self, path: &KeyPath) -> Option<Merged<'_>> {
+        let mut current = Merged {
+            layer: Some(&self.layer),
+            base: Some(&self.base),
+        };
+        for name in path.components() {
+            let hidden = current
+                .layer
+                .is_some_and(|layer| layer.deleted_subkeys.contains(name));
+            let layer = current.layer.and_then(|layer| layer.subkeys.get(name));
+            let base = if hidden || layer.is_some_and(|layer| layer.opaque) {
+                None
+            } else {
+                current.base.and_then(|base| base.subkeys.get(name))
+            };
+            if layer.is_none() && base.is_none() {
+                return None;
+            }
+            current = Merged { layer, base };
+        }
+        Some(current)
+    }
+
+    pub fn key_exists(&self, path: &KeyPath) -> bool {
+        self.resolve(path).is_some()
+    }
+
+    pub fn query_value(&self, path: &KeyPath, name: &str) -> Option<&RegValue> {
+        let merged = self.resolve(path)?;
+        if let Some(value) = merged.layer.and_then(|layer| layer.values.get(name)) {
+            return Some(value);
+        }
+        if merged
+            .layer
+            .is_some_and(|layer| layer.deleted_values.contains(name))
+        {
+            return None;
+        }
+        merged.base.and_then(|base| base.values.get(name))
+    }
+
+    /// Subclaves visibles en orden alfabético, o `None` si la clave no existe.
+    pub fn subkeys(&self, path: &KeyPath) -> Option<Vec<String>> {
+        let merged = self.resolve(path)?;
+        let mut names = CaseMap::default();
+        if let Some(base) = merged.base {
+            for (name, _) in base.subkeys.iter() {
+                let hidden = merged
+                    .layer
+                    .is_some_and(|layer| layer.deleted_subkeys.contains(name));
+                if !hidden {
+                    names.insert(name, ());
+                }
+            }
+        }
+        if let Some(layer) = merged.layer {
+            for (name, _) in layer.subkeys.iter() {
+                names.insert(name, ());
+            }
+        }
+        Some(names.iter().map(|(name, _)| name.to_string()).collect())
+    }
+
+    /// Valores visibles en orden alfabético, o `None` si la clave no existe.
+    pub fn values(&self, path: &KeyPath) -> Option<Vec<(String, RegValue)>> {
+        let merged = self.resolve(path)?;
+        let mut values = CaseMap::default();
+        if let Some(base) = merged.base {
+            for (name, value) in base.values.iter() {
+                let hidden = merged
+                    .layer
+                    .is_some_and(|layer| layer.deleted_values.contains(name));
+                if !hidden {
+                    values.insert(name, value.clone());
+                }
+            }
+        }
+        if let Some(layer) = merged.layer {
+            for (name, value) in layer.values.iter() {
+                values.insert(name, value.clone());
+            }
+        }
+        Some(
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    /// Clave de la capa para `path`, creándola si falta. Si un antecesor estaba
+    /// borrado, se recrea opaco para que no reaparezca el contenido de la base.
+    fn layer_mut(&mut self, path: &KeyPath) -> &mut RegKey {
+        let mut key = &mut self.layer;
+        for name in path.components() {
+            let recreated = key.deleted_subkeys.remove(name);
+            key = key.subkeys.entry_or_default(name);
+            key.opaque |= recreated;
+        }
+        key
+    }
+
+    pub fn create_key(&mut self, path: &KeyPath) {
+        self.layer_mut(path);
+        self.edits.push(LayerEdit::CreateKey(path.clone()));
+    }
+
+    pub fn set_value(&mut self, path: &KeyPath, name: &str, value: RegValue) {
+        let key = self.layer_mut(path);
+        key.deleted_values.remove(name);
+        key.values.insert(name, value.clone());
+        self.edits
+            .push(LayerEdit::SetValue(path.clone(), name.to_string(), value));
+    }
+
+    /// Borra un valor visible; devuelve `false` si no existía.
+    pub fn delete_value(&mut self, path: &KeyPath, name: &str) -> bool {
+        let Some(merged) = self.resolve(path) else {
+            return false;
+        };
+        let in_base = merged.base.is_some_and(|base| base.values.contains(name));
+        if self.query_value(path, name).is_none() {
+            return false;
+        }
+        if in_base {
+            let key = self.layer_mut(path);
+            key.values.remove(name);
+            key.deleted_values.insert(name);
+        } else if let Some(key) = self.layer_key_mut(path) {
+            key.values.remove(name);
+        }
+        self.edits
+            .push(LayerEdit::DeleteValue(path.clone(), name.to_string()));
+        true
+    }
+
+    /// Borra una clave sin subclaves, como `RegDeleteKeyW`.
+    pub fn delete_key(&mut self, path: &KeyPath) -> Result<bool> {
+        match self.subkeys(path) {
+            None => Ok(false),
+            Some(subkeys) if !subkeys.is_empty() => {
+                bail!("{path} tiene subclaves; bórrala con su contenido")
+            }
+            Some(_) => {
+                let deleted = self.remove_tree(path)?;
+                self.edits.push(LayerEdit::DeleteKey(path.clone()));
+                Ok(deleted)
+            }
+        }
+    }
+
+    /// Borra una clave con todo su contenido, como `RegDeleteTreeW`.
+    pub fn delete_tree(&mut self, path: &KeyPath) -> Result<bool> {
+        let deleted = self.remove_tree(path)?;
+        if deleted {
+            self.edits.push(LayerEdit::DeleteTree(path.clone()));
+        }
+        Ok(deleted)
+    }
+
+    fn remove_tree(&mut self, path: &KeyPath) -> Result<bool> {
+        let Some((parent, name)) = path.split_last() else {
+            bail!("No se puede borrar la raíz del hive");
+        };
+        let Some(merged) = self.resolve(path) else {
+            return Ok(false);
+        };
+        let in_base = merged.base.is_some();
+        if in_base {
+            let parent = self.layer_mut(&parent);
+            parent.subkeys.remove(name);
+            parent.deleted_subkeys.insert(name);
+        } else if let Some(parent) = self.layer_key_mut(&parent) {
+            parent.subkeys.remove(name);
+        }
+        Ok(true)
+    }
+
+    /// Clave de la capa sin crearla.
+    fn layer_key_mut(&mut self, path: &KeyPath) -> Option<&mut RegKey> {
+        path.components()
+            .iter()
+            .try_fold(&mut self.layer, |key, name| key.subkeys.get_mut(name))
+    }
+}
+
+/// Hives virtualizados por contenedor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HiveName {
+    /// `HKEY_CURRENT_USER`.
+    CurrentUser,
+    /// `HKEY_LOCAL_MACHINE\Software`.
+    LocalMachineSoftware,
+}
+
+impl HiveName {
+    pub const ALL: [HiveName; 2] = [HiveName::CurrentUser, HiveName::LocalMachineSoftware];
+
+    /// Nombre del archivo en `user/Registry/` (sin extensión).
+    pub fn file_stem(self) -> &'static str {
+        match self {
+            Self::CurrentUser => "HKCU",
+            Self::LocalMachineSoftware => "HKLM-SW",
+        }
+    }
+
+    /// Separa una ruta absoluta (`HKCU\...`, `HKEY_LOCAL_MACHINE\SOFTWARE\...`)
+    /// en hive y ruta relativa. `None` si la ruta no está virtualizada.
+    pub fn split(path: &str) -> Option<(HiveName, KeyPath)> {
+        let path = KeyPath::parse(path).ok()?;
+        let (root, rest) = path.components().split_first()?;
+        let root = fold(root);
+        match root.as_str() {
+            "hkcu" | "hkey_current_user" => Some((HiveName::CurrentUser, KeyPath(rest.to_vec()))),
+            "hklm" | "hkey_local_machine" => {
+                let (software, rest) = rest.split_first()?;
+                (fold(software) == "software")
+                    .then(|| (HiveName::LocalMachineSoftware, KeyPath(rest.to_vec())))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for HiveName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::CurrentUser => "HKEY_CURRENT_USER",
+            Self::LocalMachineSoftware => "HKEY_LOCAL_MACHINE\\SOFTWARE",
+        })
+    }
+}
+
+/// Lee un snapshot: `<dir>/<stem>.hiv` (formato `regf`) si existe, si no
+/// `<dir>/<stem>.json`; vacío si no hay ninguno.
+pub fn load_snapshot(dir: &Path, stem: &str) -> Result<RegKey> {
+    let hiv = dir.join(format!("{stem}.hiv"));
+    if hiv.exists() {
+        return Ok(RegKey::from_hive(&regf::Hive::read(&hiv)?.root));
+    }
+    load_hive(&dir.join(format!("{stem}.json")))
+}
+
+/// Registro virtual de un contenedor: un overlay por hive. La base es el
+/// snapshot del runtime (`<base_dir>/<hive>.hiv|json`) con el hive importado
+/// del contenedor (`<dir>/<hive>.hiv`) encima; la capa escribible es
+/// `<dir>/<hive>.json`.
+#[derive(Debug, Clone)]
+pub struct VirtualRegistry {
+    dir: PathBuf,
+    hives: BTreeMap<HiveName, RegistryOverlay>,
+}
+
+impl VirtualRegistry {
+    /// Registro de un contenedor con el snapshot de su runtime como base.
+    pub fn for_container(container: &RegisteredContainer, runtimes: &RuntimeStore) -> Result<Self> {
+        let base = match container.manifest.runtime.build.as_deref() {
+            Some(build) => {
+                let reference: RuntimeRef = build.parse().with_context(|| {
+                    format!(
+                        "runtime.build inválido en el contenedor {}",
+                        container.manifest.id
+                    )
+                })?;
+                Some(runtimes.resolve(&reference)?.registry())
+            }
+            None => None,
+        };
+        Self::open(&container.path(REGISTRY_DIR), base.as_deref())
+    }
+
+    pub fn open(dir: &Path, base_dir: Option<&Path>) -> Result<Self> {
+        let mut hives = BTreeMap::new();
+        for hive in HiveName::ALL {
+            let stem = hive.file_stem();
+            let mut base = match base_dir {
+                Some(base_dir) => load_snapshot(base_dir, stem)?,
+                None => RegKey::default(),
+            };
+            let imported = dir.join(format!("{stem}.hiv"));
+            if imported.exists() {
+                base.merge(&RegKey::from_hive(&regf::Hive::read(&imported)?.root));
+            }
+            let layer = load_hive(&dir.join(format!("{stem}.json")))?;
+            hives.insert(hive, RegistryOverlay::new(base, layer));
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            hives,
+        })
+    }
+
+    pub fn hive(&self, hive: HiveName) -> &RegistryOverlay {
+        &self.hives[&hive]
+    }
+
+    pub fn hive_mut(&mut self, hive: HiveName) -> &mut RegistryOverlay {
+        self.hives.get_mut(&hive).expect("hive conocido")
+    }
+
+    /// Guarda la capa de los hives con cambios; la base nunca se modifica.
+    /// Varios procesos del contenedor comparten los archivos: con el hive
+    /// bloqueado se vuelve a leer su capa, se le aplican los cambios de este
+    /// proceso y se escribe el resultado, que pasa a ser la capa en memoria.
+    pub fn save(&mut self) -> Result<()> {
+        for (hive, overlay) in &mut self.hives {
+            if !overlay.is_dirty() {
+                continue;
+            }
+            let path = self.dir.join(format!("{}.json", hive.file_stem()));
+            let _lock = lock_hive(&path)?;
+            overlay.rebase(load_hive(&path)?);
+            save_hive(&path, overlay.layer())?;
+            overlay.edits.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> KeyPath {
+        KeyPath::parse(path).unwrap()
+    }
+
+    fn base() -> RegKey {
+        let mut base = RegKey::default();
+        let app = base.create(&path(r"Software\Vendor\App"));
+        app.values.insert("", RegValue::Sz("App".into()));
+        app.values.insert("Version", RegValue::Dword(1));
+        app.values.insert("Telemetry", RegValue::Dword(1));
+        base.create(&path(r"Software\Vendor\App\Plugins\Spell"));
+        base.create(&path(r"Software\Vendor\Legacy"));
+        base
+    }
+
+    #[test]
+    fn values_round_trip_through_their_wire_bytes() {
+        let values = [
+            RegValue::Sz("C:\\Program Files\\App".into()),
+            RegValue::ExpandSz("%APPDATA%\\App".into()),
+            RegValue::Binary(vec![0, 1, 0xfe]),
+            RegValue::Dword(0xdead_beef),
+            RegValue::Qword(u64::MAX - 1),
+            RegValue::MultiSz(vec!["uno".into(), "dos".into()]),
+        ];
+        for value in values {
+            let bytes = value.to_bytes();
+            assert_eq!(RegValue::from_bytes(value.kind(), &bytes).unwrap(), value);
+        }
+        assert_eq!(
+            RegValue::Sz("ab".into()).to_bytes(),
+            [b'a', 0, b'b', 0, 0, 0]
+        );
+        assert_eq!(
+            RegValue::MultiSz(vec!["a".into()]).to_bytes(),
+            [b'a', 0, 0, 0, 0, 0]
+        );
+        // Sin terminador, como lo dejan algunos instaladores.
+        assert_eq!(
+            RegValue::from_bytes(REG_SZ, &[b'o', 0, b'k', 0]).unwrap(),
+            RegValue::Sz("ok".into())
+        );
+        assert!(RegValue::from_bytes(REG_DWORD, &[1, 2]).is_err());
+        assert_eq!(
+            RegValue::from_bytes_lossless(REG_DWORD, &[1, 2]),
+            RegValue::Other {
+                kind: REG_DWORD,
+                data: vec![1, 2]
+            }
+        );
+        // Lo que no sobrevive a la vuelta no se tipa.
+        for (kind, data) in [
+            (REG_SZ, &[b'o', 0, b'k', 0][..]),
+            (REG_SZ, &[b'a', 0, 0, 0, b'b', 0, 0, 0]),
+            (REG_EXPAND_SZ, &[0x00, 0xd8, 0, 0]),
+            (REG_MULTI_SZ, &[b'a', 0, 0, 0]),
+            (REG_SZ, &[b'a', 0, 0]),
+        ] {
+            assert_eq!(
+                RegValue::from_bytes_lossless(kind, data),
+                RegValue::Other {
+                    kind,
+                    data: data.to_vec()
+                }
+            );
+        }
+        assert_eq!(
+            RegValue::from_bytes_lossless(REG_SZ, &[b'o', 0, b'k', 0, 0, 0]),
+            RegValue::Sz("ok".into())
+        );
+        let none = RegValue::from_bytes(0, &[7]).unwrap();
+        assert_eq!(none.to_bytes(), [7]);
+        assert_eq!(none.type_name(), "REG_NONE");
+    }
+
+    #[test]
+    fn lookups_ignore_case_but_keep_original_names() {
+        let overlay = RegistryOverlay::new(base(), RegKey::default());
+        assert!(overlay.key_exists(&path(r"SOFTWARE\vendor\APP")));
+        assert_eq!(
+            overlay.query_value(&path(r"software\vendor\app"), "VERSION"),
+            Some(&RegValue::Dword(1))
+        );
+        assert_eq!(
+            overlay.subkeys(&path(r"software\VENDOR")).unwrap(),
+            ["App", "Legacy"]
+        );
+        assert!(KeyPath::parse(r"Software\\App").is_err());
+        assert_eq!(path(r"\Software\App\").to_string(), r"Software\App");
+    }
+
+    #[test]
+    fn the_layer_overrides_and_hides_the_base() {
+        let mut overlay = RegistryOverlay::new(base(), RegKey::default());
+        let app = path(r"Software\Vendor\App");
+
+        overlay.set_value(&app, "version", RegValue::Dword(2));
+        assert!(overlay.delete_value(&app, "Telemetry"));
+        assert!(!overlay.delete_value(&app, "Telemetry"));
+        overlay.set_value(&app, "Channel", RegValue::Sz("beta".into()));
+        let names: Vec<_> = overlay
+            .values(&app)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["", "Channel", "version"]);
+        assert_eq!(
+            overlay.query_value(&app, "Version"),
+            Some(&RegValue::Dword(2))
+        );
+        assert_eq!(overlay.query_value(&app, "Telemetry"), None);
+
+        // Borrar y volver a crear no hace reaparecer el contenido de la base.
+        assert!(overlay.delete_key(&app).is_err());
+        assert!(overlay.delete_tree(&app).unwrap());
+        assert!(!overlay.key_exists(&app));
+        assert!(!overlay.delete_tree(&app).unwrap());
+        assert_eq!(
+            overlay.subkeys(&path(r"Software\Vendor")).unwrap(),
+            ["Legacy"]
+        );
+        overlay.set_value(&app.join("Plugins"), "Enabled", RegValue::Dword(0));
+        assert_eq!(overlay.query_value(&app, ""), None);
+        assert_eq!(overlay.subkeys(&app).unwrap(), ["Plugins"]);
+        assert_eq!(
+            overlay.subkeys(&app.join("plugins")).unwrap(),
+            Vec::<String>::new()
+        );
+
+        // Claves solo de la capa se borran sin dejar marcadores.
+        overlay.create_key(&path(r"Software\Mine"));
+        assert!(overlay.delete_key(&path(r"Software\Mine")).unwrap());
+        assert!(!overlay
+            .layer()
+            .key(&path("Software"))
+            .unwrap()
+            .deleted_subkeys
+            .contains("Mine"));
+        assert!(overlay
+            .delete_key(&path(r"Software\Vendor\Legacy"))
+            .unwrap());
+        assert!(overlay.delete_tree(&KeyPath::root()).is_err());
+    }
+
+    #[test]
+    fn layers_persist_in_the_documented_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().join("base");
+        save_hive(&base_dir.join("HKCU.json"), &base()).unwrap();
+
+        let registry_dir = dir.path().join("user/Registry");
+        let mut registry = VirtualRegistry::open(&registry_dir, Some(&base_dir)).unwrap();
+        let (hive, app) = HiveName::split(r"HKEY_CURRENT_USER\Software\Vendor\App").unwrap();
+        assert_eq!(hive, HiveName::CurrentUser);
+        let overlay = registry.hive_mut(hive);
+        overlay.set_value(&app, "Data", RegValue::Binary(vec![0xab, 0xcd]));
+        overlay.delete_value(&app, "Telemetry");
+        registry.save().unwrap();
+
+        let raw: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(registry_dir.join("HKCU.json")).unwrap())
+                .unwrap();
+        assert_eq!(
+            raw,
+            serde_json::json!({
+                "format": "ctnr-registry/1",
+                "root": { "subkeys": { "Software": { "subkeys": { "Vendor": { "subkeys": { "App": {
+                    "values": { "Data": { "type": "binary", "data": "abcd" } },
+                    "deleted_values": ["Telemetry"]
+                } } } } } } }
+            })
+        );
+        // Solo se escribe el hive que cambió.
+        assert!(!registry_dir.join("HKLM-SW.json").exists());
+
+        let reopened = VirtualRegistry::open(&registry_dir, Some(&base_dir)).unwrap();
+        let overlay = reopened.hive(HiveName::CurrentUser);
+        assert_eq!(
+            overlay.query_value(&app, "data"),
+            Some(&RegValue::Binary(vec![0xab, 0xcd]))
+        );
+        assert_eq!(overlay.query_value(&app, "Telemetry"), None);
+        assert_eq!(
+            overlay.query_value(&app, "Version"),
+            Some(&RegValue::Dword(1))
+        );
+
+        std::fs::write(
+            registry_dir.join("HKCU.json"),
+            r#"{"format":"otro","root":{}}"#,
+        )
+        .unwrap();
+        assert!(VirtualRegistry::open(&registry_dir, None).is_err());
+    }
+
+    #[test]
+    fn saves_merge_with_what_other_processes_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().join("base");
+        save_hive(&base_dir.join("HKCU.json"), &base()).unwrap();
+        let registry_dir = dir.path().join("user/Registry");
+        let open = || VirtualRegistry::open(&registry_dir, Some(&base_dir)).unwrap();
+        let app = path(r"Software\Vendor\App");
+
+        // Dos procesos abren el mismo registro y cada uno cambia algo distinto.
+        let mut first = open();
+        let mut second = open();
+        let hkcu = first.hive_mut(HiveName::CurrentUser);
+        hkcu.set_value(&app, "First", RegValue::Dword(1));
+        hkcu.delete_value(&app, "Telemetry");
+        first.save().unwrap();
+        let hkcu = second.hive_mut(HiveName::CurrentUser);
+        hkcu.set_value(&app, "Second", RegValue::Dword(2));
+        hkcu.create_key(&path(r"Software\Second"));
+        assert!(hkcu.is_dirty());
+        second.save().unwrap();
+        assert!(!second.hive(HiveName::CurrentUser).is_dirty());
+
+        // El último en guardar conserva lo del otro, también en memoria.
+        for registry in [open(), second] {
+            let hkcu = registry.hive(HiveName::CurrentUser);
+            assert_eq!(hkcu.query_value(&app, "First"), Some(&RegValue::Dword(1)));
+            assert_eq!(hkcu.query_value(&app, "Second"), Some(&RegValue::Dword(2)));
+            assert_eq!(hkcu.query_value(&app, "Telemetry"), None);
+            assert!(hkcu.key_exists(&path(r"Software\Second")));
+        }
+
+        // Sin cambios no se reescribe nada y no quedan temporales.
+        let before = std::fs::read(registry_dir.join("HKCU.json")).unwrap();
+        first.save().unwrap();
+        assert_eq!(
+            std::fs::read(registry_dir.join("HKCU.json")).unwrap(),
+            before
+        );
+        let mut names: Vec<_> = std::fs::read_dir(&registry_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["HKCU.json", "HKCU.json.lock"]);
+    }
+
+    #[test]
+    fn regf_hives_feed_the_base_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = dir.path().join("runtime/Registry");
+        let registry_dir = dir.path().join("user/Registry");
+        std::fs::create_dir_all(&runtime).unwrap();
+        std::fs::create_dir_all(&registry_dir).unwrap();
+
+        let mut runtime_hive = regf::Hive::new();
+        runtime_hive.root = base().to_hive("ROOT");
+        runtime_hive
+            .root
+            .create_key(r"Software\Vendor\App")
+            .values
+            .push(regf::Value {
+                name: "Odd".into(),
+                kind: REG_DWORD,
+                data: vec![1, 2],
+            });
+        runtime_hive.write(&runtime.join("HKCU.hiv")).unwrap();
+
+        let mut imported = regf::Hive::new();
+        let app = imported.root.create_key(r"Software\Vendor\App");
+        app.set_value("Version", REG_DWORD, 7u32.to_le_bytes().to_vec());
+        imported.write(&registry_dir.join("HKCU.hiv")).unwrap();
+
+        let registry = VirtualRegistry::open(&registry_dir, Some(&runtime)).unwrap();
+        let overlay = registry.hive(HiveName::CurrentUser);
+        let app = path(r"Software\Vendor\App");
+        assert_eq!(
+            overlay.query_value(&app, "Version"),
+            Some(&RegValue::Dword(7))
+        );
+        assert_eq!(
+            overlay.query_value(&app, ""),
+            Some(&RegValue::Sz("App".into()))
+        );
+        assert_eq!(
+            overlay.query_value(&app, "Odd"),
+            Some(&RegValue::Other {
+                kind: REG_DWORD,
+                data: vec![1, 2]
+            })
+        );
+        assert!(overlay.key_exists(&path(r"Software\Vendor\App\Plugins\Spell")));
+        assert_eq!(RegKey::from_hive(&base().to_hive("ROOT")), base());
+    }
+
+    #[test]
+    fn only_virtualized_hives_are_resolved() {
+        assert_eq!(
+            HiveName::split(r"HKLM\SOFTWARE\Vendor").unwrap(),
+            (HiveName::LocalMachineSoftware, path("Vendor"))
+        );
+        assert_eq!(
+            HiveName::split("hkcu").unwrap(),
+            (HiveName::CurrentUser, KeyPath::root())
+        );
+        assert!(HiveName::split(r"HKLM\SYSTEM\CurrentControlSet").is_none());
+        assert!(HiveName::split(r"HKEY_CLASSES_ROOT\.txt").is_none());
+    }
+}
@@ -1,8 +1,21 @@
 fn main() {
+    // protoc viene precompilado en `protoc-bin-vendored`, así que no hace falta
+    // instalarlo; `PROTOC` permite usar otro.
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect(
+            "protoc-bin-vendored no trae protoc para esta plataforma; indique uno con PROTOC",
+        );
+        std::env::set_var("PROTOC", protoc);
+    }
     println!("cargo:rerun-if-changed=../proto/containers.proto");
+    println!("cargo:rerun-if-changed=../proto/agents.proto");
     tonic_build::configure()
         .build_client(true)
         .build_server(true)
-        .compile(&["../proto/containers.proto"], &["../proto"])
+        .compile(
+            &["../proto/containers.proto", "../proto/agents.proto"],
+            &["../proto"],
+        )
         .expect("failed to compile proto definitions");
 }
@@ -0,0 +1,14 @@
+-- Agents registrados en el plano de control
+CREATE TABLE IF NOT EXISTS agents (
+    id TEXT PRIMARY KEY,
+    hostname TEXT NOT NULL,
+    version TEXT NOT NULL,
+    capabilities TEXT NOT NULL DEFAULT '[]',
+    containers TEXT NOT NULL DEFAULT '[]',
+    runs TEXT NOT NULL DEFAULT '[]',
+    status TEXT NOT NULL DEFAULT 'offline',
+    last_seen_ms BIGINT NOT NULL,
+    registered_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_agents_last_seen ON agents (last_seen_ms);
@@ -0,0 +1,13 @@
+-- Órdenes enviadas desde el backend a un agent y su último estado conocido
+CREATE TABLE IF NOT EXISTS agent_commands (
+    id TEXT PRIMARY KEY,
+    agent_id TEXT NOT NULL,
+    command TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    message TEXT,
+    output TEXT,
+    created_ms BIGINT NOT NULL,
+    updated_ms BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_agent_commands_agent ON agent_commands (agent_id, status);
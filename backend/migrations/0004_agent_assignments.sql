@@ -0,0 +1,13 @@
+-- Estado deseado: qué contenedores (y versiones) debe tener instalados cada agent
+CREATE TABLE IF NOT EXISTS agent_assignments (
+    agent_id TEXT NOT NULL,
+    container_id TEXT NOT NULL,
+    version TEXT,
+    package_url TEXT NOT NULL,
+    sha256 TEXT NOT NULL,
+    sync_status TEXT NOT NULL DEFAULT 'pending',
+    sync_message TEXT,
+    installed_version TEXT,
+    updated_ms BIGINT NOT NULL,
+    PRIMARY KEY (agent_id, container_id)
+);
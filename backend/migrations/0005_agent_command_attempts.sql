@@ -0,0 +1,2 @@
+-- Veces que se envió cada orden; las que no informan resultado se reenvían hasta un máximo
+ALTER TABLE agent_commands ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,616 @@
+use crate::proto::{
+    agent_message, agent_service_server::AgentService, backend_message, command_request,
+    AgentHeartbeat, AgentMessage, BackendMessage, CollectDiagnostics, CommandAck, CommandRequest,
+    CommandResult, ContainerSync, DesiredContainer, DesiredState, HeartbeatAck, HostedContainer,
+    LaunchProfile, PullPackage, RefreshRegistry, RegisterAgentRequest, RegisterAgentResponse,
+    RunStatus, StopRun,
+};
+use crate::shutdown::Shutdown;
+use crate::store::{
+    command_status, AgentCommand, AgentContainer, AgentMount, AgentRegistration, AgentRun,
+    AssignmentRecord, CommandRecord, ContainerSyncStatus, Store,
+};
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, warn};
+
+/// Tiempo sin heartbeat tras el cual un agent pasa a offline.
+pub const DEFAULT_AGENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Tiempo que una orden enviada puede pasar sin resultado antes de reenviarse.
+pub const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Envíos de una orden antes de darla por fallida si el agent nunca responde.
+pub const MAX_COMMAND_ATTEMPTS: u32 = 3;
+
+type Outbound = mpsc::Sender<Result<BackendMessage, Status>>;
+
+/// Resultado de intentar entregar una orden `pending`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Se envió por el stream del agent.
+    Sent,
+    /// El agent no está conectado; sigue en cola hasta que se conecte.
+    Queued,
+    /// Otro envío concurrente la reclamó antes.
+    Claimed,
+}
+
+/// Streams de heartbeat abiertos, por agent. Lo comparten el servicio gRPC y el
+/// API REST para empujar órdenes al agent que corresponda.
+#[derive(Clone, Default)]
+pub struct AgentHub {
+    streams: Arc<Mutex<HashMap<String, Outbound>>>,
+}
+
+impl AgentHub {
+    /// Un stream nuevo del mismo agent reemplaza al anterior.
+    fn attach(&self, agent_id: &str, outbound: Outbound) {
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(agent_id.to_string(), outbound);
+    }
+
+    /// Quita el stream si sigue siendo el actual del agent. Devuelve `false` si
+    /// ya lo reemplazó una reconexión, que es la que manda sobre su estado.
+    fn detach(&self, agent_id: &str, outbound: &Outbound) -> bool {
+        let mut streams = self.streams.lock().unwrap();
+        let current = streams
+            .get(agent_id)
+            .is_some_and(|current| current.same_channel(outbound));
+        if current {
+            streams.remove(agent_id);
+        }
+        current
+    }
+
+    pub fn is_connected(&self, agent_id: &str) -> bool {
+        self.streams.lock().unwrap().contains_key(agent_id)
+    }
+
+    /// Envía una orden `pending` si el agent tiene el stream abierto.
+    pub async fn dispatch(
+        &self,
+        store: &Store,
+        record: &CommandRecord,
+    ) -> anyhow::Result<Dispatch> {
+        let Some(outbound) = self.streams.lock().unwrap().get(&record.agent_id).cloned() else {
+            return Ok(Dispatch::Queued);
+        };
+        // Se reclama antes de enviar para que un ack rápido no quede pisado y
+        // para no entregar dos veces la misma orden.
+        if !store
+            .claim_command(&record.agent_id, &record.id, now_ms())
+            .await?
+        {
+            return Ok(Dispatch::Claimed);
+        }
+        let message = BackendMessage {
+            payload: Some(backend_message::Payload::Command(CommandRequest::from(
+                record,
+            ))),
+        };
+        if outbound.send(Ok(message)).await.is_err() {
+            store
+                .update_command(
+                    &record.agent_id,
+                    &record.id,
+                    command_status::PENDING,
+                    None,
+                    None,
+                    now_ms(),
+                )
+                .await?;
+            return Ok(Dispatch::Queued);
+        }
+        Ok(Dispatch::Sent)
+    }
+
+    /// Envía al agent, si está conectado, la lista completa de contenedores que
+    /// debe tener instalados. Se llama al conectar y tras cada cambio de asignación.
+    pub async fn push_desired(&self, store: &Store, agent_id: &str) -> anyhow::Result<bool> {
+        let Some(outbound) = self.streams.lock().unwrap().get(agent_id).cloned() else {
+            return Ok(false);
+        };
+        let containers = store
+            .list_assignments(agent_id)
+            .await?
+            .iter()
+            .map(DesiredContainer::from)
+            .collect();
+        let message = BackendMessage {
+            payload: Some(backend_message::Payload::Desired(DesiredState {
+                containers,
+            })),
+        };
+        Ok(outbound.send(Ok(message)).await.is_ok())
+    }
+
+    /// Entrega las órdenes que esperaban a que el agent se conectara. Las que
+    /// reclamó otro envío ya están en camino; se sigue con las demás.
+    async fn flush(&self, store: &Store, agent_id: &str) -> anyhow::Result<()> {
+        for record in store
+            .list_commands(agent_id, Some(command_status::PENDING))
+            .await?
+        {
+            match self.dispatch(store, &record).await? {
+                Dispatch::Sent | Dispatch::Claimed => continue,
+                Dispatch::Queued => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Servicio gRPC con el que los agents se registran, mantienen su heartbeat y
+/// reciben órdenes.
+#[derive(Clone)]
+pub struct AgentGrpc {
+    store: Store,
+    hub: AgentHub,
+    timeout: Duration,
+    command_timeout: Duration,
+    shutdown: Shutdown,
+}
+
+impl AgentGrpc {
+    pub fn new(store: Store, hub: AgentHub, timeout: Duration) -> Self {
+        Self {
+            store,
+            hub,
+            timeout,
+            command_timeout: DEFAULT_COMMAND_TIMEOUT,
+            shutdown: Shutdown::default(),
+        }
+    }
+
+    /// Las órdenes sin resultado pasado este tiempo se reenvían; el agent
+    /// reconoce su `command_id` y no las ejecuta dos veces.
+    pub fn with_command_timeout(mut self, timeout: Duration) -> Self {
+        self.command_timeout = timeout;
+        self
+    }
+
+    /// Al apagar, los streams de heartbeat se cierran con `UNAVAILABLE` y los
+    /// agents reconectan por su cuenta.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Intervalo sugerido al agent: tres heartbeats por ventana de timeout.
+    fn heartbeat_interval(&self) -> Duration {
+        (self.timeout / 3).max(Duration::from_secs(1))
+    }
+}
+
+type HeartbeatStream = Pin<Box<dyn Stream<Item = Result<BackendMessage, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl AgentService for AgentGrpc {
+    async fn register(
+        &self,
+        request: Request<RegisterAgentRequest>,
+    ) -> Result<Response<RegisterAgentResponse>, Status> {
+        let payload = request.into_inner();
+        if payload.agent_id.trim().is_empty() {
+            return Err(Status::invalid_argument("agent_id is required"));
+        }
+        if payload.hostname.trim().is_empty() {
+            return Err(Status::invalid_argument("hostname is required"));
+        }
+
+        let registration = AgentRegistration {
+            id: payload.agent_id.clone(),
+            hostname: payload.hostname,
+            version: payload.version,
+            capabilities: payload.capabilities,
+            containers: payload
+                .containers
+                .into_iter()
+                .map(AgentContainer::from)
+                .collect(),
+        };
+        self.store
+            .register_agent(&registration, now_ms())
+            .await
+            .map_err(map_internal)?;
+        info!(
+            agent_id = registration.id.as_str(),
+            hostname = registration.hostname.as_str(),
+            containers = registration.containers.len(),
+            "Agent registrado"
+        );
+
+        Ok(Response::new(RegisterAgentResponse {
+            agent_id: payload.agent_id,
+            heartbeat_interval_secs: self.heartbeat_interval().as_secs() as u32,
+        }))
+    }
+
+    type HeartbeatStream = HeartbeatStream;
+
+    async fn heartbeat(
+        &self,
+        request: Request<Streaming<AgentMessage>>,
+    ) -> Result<Response<Self::HeartbeatStream>, Status> {
+        let inbound = request.into_inner();
+        let (outbound, responses) = mpsc::channel(16);
+        tokio::spawn(pump_heartbeats(self.clone(), inbound, outbound));
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(responses)) as Self::HeartbeatStream
+        ))
+    }
+}
+
+/// Atiende un stream de heartbeat hasta que el agent lo cierra o deja de enviar
+/// durante más del timeout; en ambos casos, salvo que el agent ya haya abierto
+/// otro stream, queda offline y las órdenes enviadas sin confirmar vuelven a la cola.
+async fn pump_heartbeats(
+    service: AgentGrpc,
+    mut inbound: Streaming<AgentMessage>,
+    outbound: Outbound,
+) {
+    let mut agent_id: Option<String> = None;
+    let shutdown = service.shutdown.wait();
+    tokio::pin!(shutdown);
+    loop {
+        let received = tokio::select! {
+            received = tokio::time::timeout(service.timeout, inbound.message()) => received,
+            _ = &mut shutdown => {
+                let _ = outbound
+                    .send(Err(Status::unavailable("backend shutting down")))
+                    .await;
+                break;
+            }
+        };
+        let message = match received {
+            Ok(Ok(Some(message))) => message,
+            Ok(Ok(None)) => break,
+            Ok(Err(status)) => {
+                warn!(?agent_id, %status, "Stream de heartbeat interrumpido");
+                break;
+            }
+            Err(_) => {
+                warn!(?agent_id, "Agent sin heartbeat dentro del timeout");
+                let _ = outbound
+                    .send(Err(Status::deadline_exceeded("heartbeat timeout")))
+                    .await;
+                break;
+            }
+        };
+
+        let heartbeat = match message.payload {
+            Some(agent_message::Payload::Heartbeat(heartbeat)) => heartbeat,
+            Some(agent_message::Payload::Ack(ack)) => {
+                if let Some(id) = &agent_id {
+                    let command_id = ack.command_id.clone();
+                    command_update(&service.store, id, &command_id, ack.into()).await;
+                }
+                continue;
+            }
+            Some(agent_message::Payload::Result(result)) => {
+                if let Some(id) = &agent_id {
+                    let command_id = result.command_id.clone();
+                    command_update(&service.store, id, &command_id, result.into()).await;
+                }
+                continue;
+            }
+            Some(agent_message::Payload::Sync(report)) => {
+                if let Some(id) = &agent_id {
+                    let containers: Vec<_> = report
+                        .containers
+                        .into_iter()
+                        .map(ContainerSyncStatus::from)
+                        .collect();
+                    if let Err(err) = service.store.record_sync(id, &containers, now_ms()).await {
+                        warn!(
+                            agent_id = id.as_str(),
+                            ?err,
+                            "No se pudo guardar la sincronización"
+                        );
+                    }
+                }
+                continue;
+            }
+            None => continue,
+        };
+        if agent_id
+            .as_deref()
+            .is_some_and(|id| id != heartbeat.agent_id)
+        {
+            let _ = outbound
+                .send(Err(Status::invalid_argument(
+                    "agent_id changed within the heartbeat stream",
+                )))
+                .await;
+            break;
+        }
+
+        match record(&service.store, &heartbeat).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = outbound
+                    .send(Err(Status::failed_precondition(
+                        "agent must register before sending heartbeats",
+                    )))
+                    .await;
+                break;
+            }
+            Err(status) => {
+                let _ = outbound.send(Err(status)).await;
+                break;
+            }
+        }
+
+        let ack = BackendMessage {
+            payload: Some(backend_message::Payload::Ack(HeartbeatAck {
+                server_time_ms: now_ms() as u64,
+            })),
+        };
+        if outbound.send(Ok(ack)).await.is_err() {
+            break;
+        }
+
+        if agent_id.is_none() {
+            service.hub.attach(&heartbeat.agent_id, outbound.clone());
+            let delivered = async {
+                service
+                    .hub
+                    .flush(&service.store, &heartbeat.agent_id)
+                    .await?;
+                service
+                    .hub
+                    .push_desired(&service.store, &heartbeat.agent_id)
+                    .await
+            }
+            .await;
+            if let Err(err) = delivered {
+                warn!(
+                    agent_id = heartbeat.agent_id.as_str(),
+                    ?err,
+                    "No se pudieron entregar las órdenes pendientes ni el estado deseado"
+                );
+            }
+            agent_id = Some(heartbeat.agent_id);
+        } else if let Err(err) = resend_expired(&service, &heartbeat.agent_id).await {
+            warn!(
+                agent_id = heartbeat.agent_id.as_str(),
+                ?err,
+                "No se pudieron reenviar las órdenes sin resultado"
+            );
+        }
+    }
+
+    if let Some(id) = agent_id {
+        if !service.hub.detach(&id, &outbound) {
+            info!(
+                agent_id = id.as_str(),
+                "Stream de heartbeat anterior cerrado; el agent sigue conectado"
+            );
+            return;
+        }
+        info!(agent_id = id.as_str(), "Agent desconectado");
+        let result = async {
+            service.store.mark_agent_offline(&id).await?;
+            service.store.requeue_unacknowledged(&id, now_ms()).await
+        }
+        .await;
+        if let Err(err) = result {
+            warn!(
+                agent_id = id.as_str(),
+                ?err,
+                "No se pudo marcar el agent offline"
+            );
+        }
+    }
+}
+
+/// Vuelve a encolar y reenvía las órdenes que llevan más del timeout sin
+/// resultado, o las da por fallidas si ya agotaron los envíos.
+async fn resend_expired(service: &AgentGrpc, agent_id: &str) -> anyhow::Result<()> {
+    let now = now_ms();
+    let cutoff = now - service.command_timeout.as_millis() as i64;
+    let requeued = service
+        .store
+        .expire_commands(agent_id, cutoff, MAX_COMMAND_ATTEMPTS, now)
+        .await?;
+    if requeued > 0 {
+        info!(agent_id, requeued, "Órdenes sin resultado reenviadas");
+        service.hub.flush(&service.store, agent_id).await?;
+    }
+    Ok(())
+}
+
+/// Nuevo estado de una orden según lo que informa el agent.
+struct CommandUpdate {
+    status: &'static str,
+    message: Option<String>,
+    output: Option<serde_json::Value>,
+}
+
+impl From<CommandAck> for CommandUpdate {
+    fn from(_: CommandAck) -> Self {
+        CommandUpdate {
+            status: command_status::ACKNOWLEDGED,
+            message: None,
+            output: None,
+        }
+    }
+}
+
+impl From<CommandResult> for CommandUpdate {
+    fn from(result: CommandResult) -> Self {
+        CommandUpdate {
+            status: if result.success {
+                command_status::SUCCEEDED
+            } else {
+                command_status::FAILED
+            },
+            message: Some(result.message).filter(|message| !message.is_empty()),
+            // Una salida que no es JSON se conserva como cadena.
+            output: Some(result.output)
+                .filter(|output| !output.is_empty())
+                .map(|output| {
+                    serde_json::from_str(&output).unwrap_or(serde_json::Value::String(output))
+                }),
+        }
+    }
+}
+
+async fn command_update(store: &Store, agent_id: &str, command_id: &str, update: CommandUpdate) {
+    match store
+        .update_command(
+            agent_id,
+            command_id,
+            update.status,
+            update.message.as_deref(),
+            update.output.as_ref(),
+            now_ms(),
+        )
+        .await
+    {
+        Ok(true) => info!(
+            agent_id,
+            command_id,
+            status = update.status,
+            "Orden actualizada"
+        ),
+        Ok(false) => warn!(
+            agent_id,
+            command_id, "El agent informó de una orden desconocida"
+        ),
+        Err(err) => warn!(
+            agent_id,
+            command_id,
+            ?err,
+            "No se pudo guardar el estado de la orden"
+        ),
+    }
+}
+
+async fn record(store: &Store, heartbeat: &AgentHeartbeat) -> Result<bool, Status> {
+    let containers: Vec<_> = heartbeat
+        .containers
+        .iter()
+        .cloned()
+        .map(AgentContainer::from)
+        .collect();
+    let runs: Vec<_> = heartbeat.runs.iter().cloned().map(AgentRun::from).collect();
+    store
+        .record_heartbeat(&heartbeat.agent_id, &containers, &runs, now_ms())
+        .await
+        .map_err(map_internal)
+}
+
+impl From<HostedContainer> for AgentContainer {
+    fn from(value: HostedContainer) -> Self {
+        AgentContainer {
+            id: value.id,
+            name: value.name,
+            version: Some(value.version).filter(|v| !v.is_empty()),
+            prepared: value.prepared,
+            mount: value.mount.map(|mount| AgentMount {
+                state: mount.state,
+                provider: Some(mount.provider).filter(|v| !v.is_empty()),
+                mount_point: Some(mount.mount_point).filter(|v| !v.is_empty()),
+                attempts: mount.attempts,
+                error: Some(mount.error).filter(|v| !v.is_empty()),
+            }),
+            error: Some(value.error).filter(|v| !v.is_empty()),
+        }
+    }
+}
+
+impl From<RunStatus> for AgentRun {
+    fn from(value: RunStatus) -> Self {
+        AgentRun {
+            run_id: value.run_id,
+            container_id: value.container_id,
+            state: value.state,
+            pid: value.pid,
+            started_at_ms: value.started_at_ms,
+            ended_at_ms: value.ended_at_ms,
+            exit_code: value.exit_code,
+            signal: value.signal,
+            restarts: value.restarts,
+        }
+    }
+}
+
+impl From<&CommandRecord> for CommandRequest {
+    fn from(record: &CommandRecord) -> Self {
+        let action = match record.command.clone() {
+            AgentCommand::Launch {
+                container_id,
+                profile,
+                args,
+            } => command_request::Action::Launch(LaunchProfile {
+                container_id,
+                profile,
+                args,
+            }),
+            AgentCommand::Stop { run_id, timeout_ms } => {
+                command_request::Action::Stop(StopRun { run_id, timeout_ms })
+            }
+            AgentCommand::RefreshRegistry => command_request::Action::Refresh(RefreshRegistry {}),
+            AgentCommand::PullPackage {
+                container_id,
+                url,
+                sha256,
+            } => command_request::Action::Pull(PullPackage {
+                container_id,
+                url,
+                sha256,
+            }),
+            AgentCommand::CollectDiagnostics => {
+                command_request::Action::Diagnostics(CollectDiagnostics {})
+            }
+        };
+        CommandRequest {
+            command_id: record.id.clone(),
+            action: Some(action),
+        }
+    }
+}
+
+impl From<&AssignmentRecord> for DesiredContainer {
+    fn from(record: &AssignmentRecord) -> Self {
+        DesiredContainer {
+            container_id: record.container_id.clone(),
+            version: record.version.clone().unwrap_or_default(),
+            package_url: record.package_url.clone(),
+            sha256: record.sha256.clone(),
+        }
+    }
+}
+
+impl From<ContainerSync> for ContainerSyncStatus {
+    fn from(value: ContainerSync) -> Self {
+        ContainerSyncStatus {
+            container_id: value.container_id,
+            status: value.status,
+            version: Some(value.version).filter(|v| !v.is_empty()),
+            message: Some(value.message).filter(|m| !m.is_empty()),
+        }
+    }
+}
+
+pub fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+fn map_internal(err: anyhow::Error) -> Status {
+    Status::internal(err.to_string())
+}
@@ -1,14 +1,19 @@
 use crate::{
+    agents::{self, AgentHub, DEFAULT_AGENT_TIMEOUT},
     queue::TaskQueue,
     security::{self, AuthConfig},
-    store::{ContainerRecord, ListFilter, Store},
+    shutdown::Shutdown,
+    store::{
+        AgentCommand, AgentRecord, Assignment, AssignmentRecord, CommandRecord, ContainerRecord,
+        ListFilter, Store,
+    },
 };
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
     middleware,
     response::sse::{Event, KeepAlive, Sse},
-    routing::{get, post},
+    routing::{get, post, put},
     Json, Router,
 };
 use futures_util::StreamExt;
@@ -23,6 +28,11 @@ pub struct AppState {
     pub version: String,
     pub store: Store,
     pub queue: Option<TaskQueue>,
+    pub agent_timeout: Duration,
+    /// Streams de los agents conectados a este backend.
+    pub agents: AgentHub,
+    /// Cierra los streams SSE abiertos cuando el backend se apaga.
+    pub shutdown: Shutdown,
 }
 
 impl AppState {
@@ -31,8 +41,21 @@ impl AppState {
             version,
             store,
             queue,
+            agent_timeout: DEFAULT_AGENT_TIMEOUT,
+            agents: AgentHub::default(),
+            shutdown: Shutdown::default(),
         }
     }
+
+    pub fn with_agent_timeout(mut self, timeout: Duration) -> Self {
+        self.agent_timeout = timeout;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -68,6 +91,21 @@ pub fn build_router(state: AppState) -> Router {
             get(get_container).delete(delete_container),
         )
         .route("/api/events/containers", get(stream_containers))
+        .route("/api/agents", get(list_agents))
+        .route("/api/agents/:id", get(get_agent))
+        .route(
+            "/api/agents/:id/commands",
+            post(create_agent_command).get(list_agent_commands),
+        )
+        .route(
+            "/api/agents/:id/commands/:command_id",
+            get(get_agent_command),
+        )
+        .route("/api/agents/:id/assignments", get(list_assignments))
+        .route(
+            "/api/agents/:id/assignments/:container_id",
+            put(assign_container).delete(unassign_container),
+        )
         .layer(middleware::from_fn_with_state(rate, security::rate_limit))
         .layer(middleware::from_fn_with_state(
             auth,
@@ -142,6 +180,251 @@ async fn get_container(
     }
 }
 
+async fn list_agents(State(state): State<AppState>) -> Result<Json<Vec<AgentRecord>>, StatusCode> {
+    state
+        .store
+        .list_agents(agents::now_ms(), state.agent_timeout)
+        .await
+        .map(Json)
+        .map_err(|err| {
+            error!(?err, "Error listando agents");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+async fn get_agent(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AgentRecord>, StatusCode> {
+    match state
+        .store
+        .get_agent(&id, agents::now_ms(), state.agent_timeout)
+        .await
+    {
+        Ok(Some(agent)) => Ok(Json(agent)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!(agent_id = id, ?err, "Error obteniendo agent");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Guarda la orden y la empuja al agent si está conectado; si no, queda
+/// `pending` hasta su próximo heartbeat.
+async fn create_agent_command(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Json(command): Json<AgentCommand>,
+) -> Result<(StatusCode, Json<CommandRecord>), StatusCode> {
+    if !valid_command(&command) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let internal = |err: anyhow::Error| {
+        error!(agent_id = id, ?err, "Error creando orden para el agent");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let agent = state
+        .store
+        .get_agent(&id, agents::now_ms(), state.agent_timeout)
+        .await
+        .map_err(internal)?;
+    if agent.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let record = state
+        .store
+        .create_command(&id, &command, agents::now_ms())
+        .await
+        .map_err(internal)?;
+    state
+        .agents
+        .dispatch(&state.store, &record)
+        .await
+        .map_err(internal)?;
+    let record = state
+        .store
+        .get_command(&id, &record.id)
+        .await
+        .map_err(internal)?
+        .unwrap_or(record);
+    Ok((StatusCode::ACCEPTED, Json(record)))
+}
+
+fn valid_command(command: &AgentCommand) -> bool {
+    match command {
+        AgentCommand::Launch { container_id, .. } => !container_id.trim().is_empty(),
+        AgentCommand::Stop { run_id, .. } => !run_id.trim().is_empty(),
+        AgentCommand::PullPackage {
+            container_id,
+            url,
+            sha256,
+        } => !container_id.trim().is_empty() && !url.trim().is_empty() && is_sha256(sha256),
+        AgentCommand::RefreshRegistry | AgentCommand::CollectDiagnostics => true,
+    }
+}
+
+/// SHA-256 en hexadecimal (64 dígitos), como lo exige el agent.
+fn is_sha256(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct CommandQuery {
+    status: Option<String>,
+}
+
+async fn list_agent_commands(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+    Query(query): Query<CommandQuery>,
+) -> Result<Json<Vec<CommandRecord>>, StatusCode> {
+    state
+        .store
+        .list_commands(&id, query.status.as_deref())
+        .await
+        .map(Json)
+        .map_err(|err| {
+            error!(agent_id = id, ?err, "Error listando órdenes del agent");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+async fn get_agent_command(
+    Path((id, command_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Result<Json<CommandRecord>, StatusCode> {
+    match state.store.get_command(&id, &command_id).await {
+        Ok(Some(record)) => Ok(Json(record)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            error!(agent_id = id, command_id, ?err, "Error obteniendo orden");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Estado deseado de un agent y su deriva: las asignaciones con su último
+/// estado de sincronización y los contenedores que tiene sin estar asignados.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AgentSync {
+    pub assignments: Vec<AssignmentRecord>,
+    pub unmanaged: Vec<String>,
+}
+
+async fn list_assignments(
+    Path(id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<AgentSync>, StatusCode> {
+    let internal = |err: anyhow::Error| {
+        error!(agent_id = id, ?err, "Error listando asignaciones del agent");
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let Some(agent) = state
+        .store
+        .get_agent(&id, agents::now_ms(), state.agent_timeout)
+        .await
+        .map_err(internal)?
+    else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let assignments = state.store.list_assignments(&id).await.map_err(internal)?;
+    let unmanaged = agent
+        .containers
+        .into_iter()
+        .map(|container| container.id)
+        .filter(|container_id| {
+            !assignments
+                .iter()
+                .any(|assignment| &assignment.container_id == container_id)
+        })
+        .collect();
+    Ok(Json(AgentSync {
+        assignments,
+        unmanaged,
+    }))
+}
+
+/// Asigna un contenedor del backend a un agent y le envía el nuevo estado deseado.
+async fn assign_container(
+    Path((id, container_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Json(mut assignment): Json<Assignment>,
+) -> Result<Json<AssignmentRecord>, StatusCode> {
+    if assignment.package_url.trim().is_empty() || !is_sha256(&assignment.sha256) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let internal = |err: anyhow::Error| {
+        error!(
+            agent_id = id,
+            container_id,
+            ?err,
+            "Error asignando contenedor"
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+    let agent = state
+        .store
+        .get_agent(&id, agents::now_ms(), state.agent_timeout)
+        .await
+        .map_err(internal)?;
+    let Some(container) = state.store.get(&container_id).await.map_err(internal)? else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    if agent.is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    if assignment.version.is_none() {
+        assignment.version = container.version;
+    }
+
+    state
+        .store
+        .assign_container(&id, &container_id, &assignment, agents::now_ms())
+        .await
+        .map_err(internal)?;
+    push_desired(&state, &id).await;
+    state
+        .store
+        .list_assignments(&id)
+        .await
+        .map_err(internal)?
+        .into_iter()
+        .find(|record| record.container_id == container_id)
+        .map(Json)
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+async fn unassign_container(
+    Path((id, container_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    match state.store.unassign_container(&id, &container_id).await {
+        Ok(true) => {
+            push_desired(&state, &id).await;
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            error!(
+                agent_id = id,
+                container_id,
+                ?err,
+                "Error quitando asignación"
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Si el agent no está conectado recibirá el estado deseado al reconectar.
+async fn push_desired(state: &AppState, agent_id: &str) {
+    if let Err(err) = state.agents.push_desired(&state.store, agent_id).await {
+        warn!(agent_id, ?err, "No se pudo enviar el estado deseado");
+    }
+}
+
 async fn stream_containers(
     State(state): State<AppState>,
 ) -> Sse<impl futures_core::Stream<Item = Result<Event, Infallible>>> {
@@ -160,6 +443,12 @@ async fn stream_containers(
                 Ok::<_, Infallible>(Event::default().data(payload))
             }
         });
+    // Al apagar se avisa al cliente con un evento `shutdown` y se cierra el
+    // stream, para que el servidor no espere al timeout de drenaje.
+    let closing = futures_util::stream::once(async {
+        Ok::<_, Infallible>(Event::default().event("shutdown").data("{}"))
+    });
+    let stream = stream.take_until(state.shutdown.wait()).chain(closing);
 
     Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
 }
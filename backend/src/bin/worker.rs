@@ -1,4 +1,10 @@
-use backend::{config::Settings, queue::TaskQueue, store::Store, workers::InstallWorker};
+use backend::{
+    config::Settings,
+    queue::TaskQueue,
+    shutdown::{self, Shutdown},
+    store::Store,
+    workers::InstallWorker,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -12,6 +18,18 @@ async fn main() -> anyhow::Result<()> {
         anyhow::bail!("Redis no está configurado; defina REDIS_URL para ejecutar el worker");
     };
 
-    InstallWorker::new(queue, store).run().await?;
-    Ok(())
+    let shutdown = Shutdown::default();
+    let signalled = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        signalled.trigger();
+    });
+
+    let worker = tokio::spawn(InstallWorker::new(queue, store).run(shutdown.clone()));
+    shutdown::supervise(
+        vec![("worker", worker)],
+        &shutdown,
+        settings.shutdown_timeout,
+    )
+    .await
 }
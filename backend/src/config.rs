@@ -1,4 +1,8 @@
-use std::env;
+use crate::{
+    agents::{DEFAULT_AGENT_TIMEOUT, DEFAULT_COMMAND_TIMEOUT},
+    shutdown::DEFAULT_DRAIN_TIMEOUT,
+};
+use std::{env, time::Duration};
 
 #[derive(Debug, Clone)]
 pub struct Settings {
@@ -6,6 +10,14 @@ pub struct Settings {
     pub redis_url: Option<String>,
     pub http_addr: String,
     pub grpc_addr: String,
+    /// Sin heartbeat durante este tiempo, un agent se considera offline.
+    pub agent_timeout: Duration,
+    /// Una orden enviada sin resultado durante este tiempo se reenvía al agent.
+    pub command_timeout: Duration,
+    /// Tiempo de drenaje al apagar antes de cortar lo que siga en curso.
+    pub shutdown_timeout: Duration,
+    /// Ejecuta el worker de instalación dentro del proceso del backend.
+    pub embedded_worker: bool,
 }
 
 impl Settings {
@@ -21,6 +33,20 @@ impl Settings {
                 .ok(),
             http_addr: env::var("CONTAINERS_HTTP_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".into()),
             grpc_addr: env::var("CONTAINERS_GRPC_ADDR").unwrap_or_else(|_| "0.0.0.0:50051".into()),
+            agent_timeout: secs("CONTAINERS_AGENT_TIMEOUT_SECS").unwrap_or(DEFAULT_AGENT_TIMEOUT),
+            command_timeout: secs("CONTAINERS_COMMAND_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+            shutdown_timeout: secs("CONTAINERS_SHUTDOWN_TIMEOUT_SECS")
+                .unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+            embedded_worker: env::var("CONTAINERS_EMBEDDED_WORKER")
+                .is_ok_and(|value| matches!(value.as_str(), "1" | "true" | "yes")),
         }
     }
 }
+
+fn secs(var: &str) -> Option<Duration> {
+    env::var(var)
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .map(Duration::from_secs)
+}
@@ -1,9 +1,12 @@
+use crate::agents::AgentGrpc;
+use crate::proto::agent_service_server::AgentServiceServer;
 use crate::proto::container_service_server::{ContainerService, ContainerServiceServer};
 use crate::proto::{
     Container, CreateContainerRequest, CreateContainerResponse, DeleteContainerRequest,
     DeleteContainerResponse, GetContainerRequest, GetContainerResponse, ListContainersRequest,
     ListContainersResponse,
 };
+use crate::shutdown::Shutdown;
 use crate::store::{ContainerRecord, ListFilter, Store};
 use anyhow::Result;
 use std::net::SocketAddr;
@@ -18,14 +21,21 @@ impl ContainerGrpc {
     pub fn new(store: Store) -> Self {
         Self { store }
     }
+}
 
-    pub async fn serve(self, addr: SocketAddr) -> Result<()> {
-        tonic::transport::Server::builder()
-            .add_service(ContainerServiceServer::new(self))
-            .serve(addr)
-            .await?;
-        Ok(())
-    }
+/// Sirve todos los servicios gRPC del backend en la misma dirección.
+pub async fn serve(
+    addr: SocketAddr,
+    containers: ContainerGrpc,
+    agents: AgentGrpc,
+    shutdown: Shutdown,
+) -> Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(ContainerServiceServer::new(containers))
+        .add_service(AgentServiceServer::new(agents))
+        .serve_with_shutdown(addr, shutdown.wait())
+        .await?;
+    Ok(())
 }
 
 #[tonic::async_trait]
@@ -1,8 +1,10 @@
+pub mod agents;
 pub mod app;
 pub mod config;
 pub mod grpc;
 pub mod queue;
 pub mod security;
+pub mod shutdown;
 pub mod store;
 pub mod workers;
 
@@ -10,14 +12,17 @@ pub mod proto {
     tonic::include_proto!("containers.v1");
 }
 
+use agents::AgentGrpc;
 use app::{build_router, AppState};
 use config::Settings;
 use grpc::ContainerGrpc;
 use queue::TaskQueue;
+use shutdown::Shutdown;
 use std::net::SocketAddr;
 use store::Store;
 use tokio::net::TcpListener;
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
+use workers::InstallWorker;
 
 pub async fn run() -> anyhow::Result<()> {
     init_tracing();
@@ -26,33 +31,72 @@ pub async fn run() -> anyhow::Result<()> {
     let store = Store::open(&settings.database_url).await?;
     let queue = TaskQueue::connect(settings.redis_url.as_deref()).await?;
 
+    let shutdown = Shutdown::default();
     let state = AppState::new(
         env!("CARGO_PKG_VERSION").to_string(),
         store.clone(),
         queue.clone(),
-    );
+    )
+    .with_agent_timeout(settings.agent_timeout)
+    .with_shutdown(shutdown.clone());
+    let agents = AgentGrpc::new(store.clone(), state.agents.clone(), settings.agent_timeout)
+        .with_command_timeout(settings.command_timeout)
+        .with_shutdown(shutdown.clone());
 
     let http_addr: SocketAddr = settings.http_addr.parse()?;
     let grpc_addr: SocketAddr = settings.grpc_addr.parse()?;
 
-    let http_task = tokio::spawn(async move {
-        let listener = TcpListener::bind(http_addr).await?;
-        info!("Backend HTTP en http://{http_addr}");
-        let app = build_router(state);
-        axum::serve(listener, app).await?;
-        Ok::<_, anyhow::Error>(())
-    });
+    let mut tasks = Vec::new();
+    let http_shutdown = shutdown.clone();
+    tasks.push((
+        "http",
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(http_addr).await?;
+            info!("Backend HTTP en http://{http_addr}");
+            let app = build_router(state);
+            axum::serve(listener, app)
+                .with_graceful_shutdown(http_shutdown.wait())
+                .await?;
+            Ok::<_, anyhow::Error>(())
+        }),
+    ));
 
-    let grpc_task = tokio::spawn(async move {
-        info!("gRPC escuchando en {grpc_addr}");
-        ContainerGrpc::new(store).serve(grpc_addr).await?;
-        Ok::<_, anyhow::Error>(())
-    });
+    let grpc_store = store.clone();
+    let grpc_shutdown = shutdown.clone();
+    tasks.push((
+        "grpc",
+        tokio::spawn(async move {
+            info!("gRPC escuchando en {grpc_addr}");
+            grpc::serve(
+                grpc_addr,
+                ContainerGrpc::new(grpc_store),
+                agents,
+                grpc_shutdown,
+            )
+            .await?;
+            Ok::<_, anyhow::Error>(())
+        }),
+    ));
 
-    let (http_res, grpc_res) = tokio::join!(http_task, grpc_task);
-    http_res??;
-    grpc_res??;
+    if settings.embedded_worker {
+        match queue {
+            Some(queue) => tasks.push((
+                "worker",
+                tokio::spawn(InstallWorker::new(queue, store).run(shutdown.clone())),
+            )),
+            None => warn!("CONTAINERS_EMBEDDED_WORKER requiere Redis; el worker no se inicia"),
+        }
+    }
+
+    let signalled = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        signalled.trigger();
+    });
 
+    // Un fallo en cualquiera de las tareas apaga el resto y sale con error.
+    shutdown::supervise(tasks, &shutdown, settings.shutdown_timeout).await?;
+    info!("Backend detenido");
     Ok(())
 }
 
@@ -0,0 +1,143 @@
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
+use tracing::{info, warn};
+
+/// Tiempo que se espera a que terminen las peticiones en curso antes de cortar.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Señal de apagado compartida por los servidores, los streams largos (SSE y
+/// heartbeats) y los workers. Una vez disparada no se puede rearmar.
+#[derive(Clone)]
+pub struct Shutdown {
+    tx: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            tx: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.tx.borrow()
+    }
+
+    /// Termina cuando se dispara el apagado (o enseguida si ya se disparó).
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.tx.subscribe();
+        async move {
+            let _ = rx.wait_for(|triggered| *triggered).await;
+        }
+    }
+}
+
+/// Espera Ctrl+C o, en Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!(?err, "No se pudo escuchar Ctrl+C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                warn!(?err, "No se pudo escuchar SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Se recibió Ctrl+C; apagando"),
+        _ = terminate => info!("Se recibió SIGTERM; apagando"),
+    }
+}
+
+/// Espera a que terminen todas las tareas. La primera que falle dispara el
+/// apagado de las demás; pasado `drain` desde el apagado, las que sigan vivas se
+/// abortan. Devuelve el primer error, si hubo alguno.
+pub async fn supervise(
+    tasks: Vec<(&'static str, JoinHandle<anyhow::Result<()>>)>,
+    shutdown: &Shutdown,
+    drain: Duration,
+) -> anyhow::Result<()> {
+    let mut pending: Vec<_> = tasks
+        .into_iter()
+        .map(|(name, handle)| {
+            let abort = handle.abort_handle();
+            let joined = async move {
+                match handle.await {
+                    Ok(result) => result,
+                    Err(err) if err.is_cancelled() => Ok(()),
+                    Err(err) => Err(anyhow::Error::new(err)),
+                }
+            };
+            (name, abort, Box::pin(joined))
+        })
+        .collect();
+
+    let mut failure: Option<anyhow::Error> = None;
+    let mut deadline = None;
+    while !pending.is_empty() {
+        let next = futures_util::future::select_all(
+            pending.iter_mut().map(|(_, _, joined)| joined.as_mut()),
+        );
+        let drained = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => shutdown.wait().await,
+            }
+        };
+        let finished = tokio::select! {
+            (result, index, _) = next => Some((index, result)),
+            _ = drained => None,
+        };
+
+        match finished {
+            Some((index, result)) => {
+                let (name, ..) = pending.remove(index);
+                match result {
+                    Ok(()) => info!(task = name, "Tarea detenida"),
+                    Err(err) => {
+                        warn!(
+                            task = name,
+                            error = format!("{err:#}"),
+                            "La tarea falló; apagando el resto"
+                        );
+                        failure.get_or_insert(err.context(format!("{name} falló")));
+                        shutdown.trigger();
+                    }
+                }
+            }
+            None if deadline.is_some() => {
+                for (name, abort, _) in pending.drain(..) {
+                    warn!(task = name, timeout = ?drain, "No terminó a tiempo; se aborta");
+                    abort.abort();
+                }
+            }
+            None => {}
+        }
+        if deadline.is_none() && shutdown.is_triggered() {
+            deadline = Some(tokio::time::Instant::now() + drain);
+        }
+    }
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
@@ -4,7 +4,7 @@ use sqlx::{
     any::{install_default_drivers, AnyPoolOptions, AnyRow},
     AnyPool, QueryBuilder, Row,
 };
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 use tokio::fs;
 use uuid::Uuid;
 
@@ -21,6 +21,165 @@ pub struct ContainerRecord {
     pub status: String,
 }
 
+/// Contenedor alojado por un agent (columna JSON `agents.containers`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentContainer {
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub prepared: bool,
+    /// Estado del volumen; ausente si el contenedor no se monta.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mount: Option<AgentMount>,
+    /// Por qué el agent no pudo prepararlo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Volumen de un contenedor alojado, tal como lo reporta el agent.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentMount {
+    /// `mounted` o `remounting`.
+    pub state: String,
+    pub provider: Option<String>,
+    pub mount_point: Option<String>,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+/// Último estado reportado de una ejecución (columna JSON `agents.runs`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentRun {
+    pub run_id: String,
+    pub container_id: String,
+    pub state: String,
+    pub pid: Option<u32>,
+    pub started_at_ms: u64,
+    pub ended_at_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub restarts: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AgentRecord {
+    pub id: String,
+    pub hostname: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub containers: Vec<AgentContainer>,
+    pub runs: Vec<AgentRun>,
+    /// `online` u `offline`; un agent sin heartbeat reciente cuenta como offline.
+    pub status: String,
+    pub last_seen_ms: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AgentRegistration {
+    pub id: String,
+    pub hostname: String,
+    pub version: String,
+    pub capabilities: Vec<String>,
+    pub containers: Vec<AgentContainer>,
+}
+
+pub const AGENT_ONLINE: &str = "online";
+pub const AGENT_OFFLINE: &str = "offline";
+
+/// Orden para un agent, tal como llega por `POST /api/agents/:id/commands`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AgentCommand {
+    Launch {
+        container_id: String,
+        #[serde(default)]
+        profile: Option<String>,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    Stop {
+        run_id: String,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    RefreshRegistry,
+    PullPackage {
+        container_id: String,
+        url: String,
+        /// SHA-256 del paquete en hexadecimal; el agent rechaza los que no coinciden.
+        sha256: String,
+    },
+    CollectDiagnostics,
+}
+
+/// Estado de una orden: `pending` (en cola, el agent no está conectado),
+/// `sent`, `acknowledged` (el agent la está ejecutando), `succeeded` o `failed`.
+pub mod command_status {
+    pub const PENDING: &str = "pending";
+    pub const SENT: &str = "sent";
+    pub const ACKNOWLEDGED: &str = "acknowledged";
+    pub const SUCCEEDED: &str = "succeeded";
+    pub const FAILED: &str = "failed";
+}
+
+/// Contenedor que un agent debe tener instalado (`PUT /api/agents/:id/assignments/:container_id`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assignment {
+    /// Versión exigida; si falta se usa la del contenedor en el backend.
+    #[serde(default)]
+    pub version: Option<String>,
+    pub package_url: String,
+    /// SHA-256 del paquete en hexadecimal; el agent no instala paquetes sin él.
+    pub sha256: String,
+}
+
+/// Estado de sincronización: `pending` hasta el primer informe del agent y
+/// después `installing`, `in_sync`, `drifted` (hay otra versión que el agent no
+/// administra) o `failed`.
+pub mod sync_status {
+    pub const PENDING: &str = "pending";
+    pub const INSTALLING: &str = "installing";
+    pub const IN_SYNC: &str = "in_sync";
+    pub const DRIFTED: &str = "drifted";
+    pub const FAILED: &str = "failed";
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AssignmentRecord {
+    pub agent_id: String,
+    pub container_id: String,
+    pub version: Option<String>,
+    pub package_url: String,
+    pub sha256: String,
+    pub sync_status: String,
+    pub sync_message: Option<String>,
+    pub installed_version: Option<String>,
+    pub updated_ms: i64,
+}
+
+/// Estado de un contenedor asignado según el último informe del agent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContainerSyncStatus {
+    pub container_id: String,
+    pub status: String,
+    pub version: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    pub id: String,
+    pub agent_id: String,
+    pub command: AgentCommand,
+    pub status: String,
+    pub message: Option<String>,
+    /// Respuesta del agent, si la envió.
+    pub output: Option<serde_json::Value>,
+    pub created_ms: i64,
+    pub updated_ms: i64,
+}
+
 #[derive(Debug, Default)]
 pub struct ListFilter {
     pub status: Option<String>,
@@ -50,6 +209,92 @@ impl<'r> sqlx::FromRow<'r, AnyRow> for ContainerRecord {
     }
 }
 
+fn json<T: serde::de::DeserializeOwned>(row: &AnyRow, column: &str) -> Result<T, sqlx::Error> {
+    let raw: String = row.try_get(column)?;
+    serde_json::from_str(&raw).map_err(|err| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: Box::new(err),
+    })
+}
+
+// El driver Any decodifica los INTEGER de SQLite como i32, así que los instantes
+// en milisegundos se seleccionan como texto (`CAST(... AS TEXT)`) para no truncarlos.
+fn millis(row: &AnyRow, column: &str) -> Result<i64, sqlx::Error> {
+    let raw: String = row.try_get(column)?;
+    raw.parse().map_err(|err| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: Box::new(err),
+    })
+}
+
+impl<'r> sqlx::FromRow<'r, AnyRow> for AgentRecord {
+    fn from_row(row: &'r AnyRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            hostname: row.try_get("hostname")?,
+            version: row.try_get("version")?,
+            capabilities: json(row, "capabilities")?,
+            containers: json(row, "containers")?,
+            runs: json(row, "runs")?,
+            status: row.try_get("status")?,
+            last_seen_ms: millis(row, "last_seen_ms")?,
+        })
+    }
+}
+
+impl<'r> sqlx::FromRow<'r, AnyRow> for CommandRecord {
+    fn from_row(row: &'r AnyRow) -> Result<Self, sqlx::Error> {
+        let message: String = row.try_get("message")?;
+        let output: String = row.try_get("output")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            agent_id: row.try_get("agent_id")?,
+            command: json(row, "command")?,
+            status: row.try_get("status")?,
+            message: Some(message).filter(|message| !message.is_empty()),
+            output: if output.is_empty() {
+                None
+            } else {
+                Some(json(row, "output")?)
+            },
+            created_ms: millis(row, "created_ms")?,
+            updated_ms: millis(row, "updated_ms")?,
+        })
+    }
+}
+
+impl<'r> sqlx::FromRow<'r, AnyRow> for AssignmentRecord {
+    fn from_row(row: &'r AnyRow) -> Result<Self, sqlx::Error> {
+        let optional = |column: &str| -> Result<Option<String>, sqlx::Error> {
+            let value: String = row.try_get(column)?;
+            Ok(Some(value).filter(|value| !value.is_empty()))
+        };
+        Ok(Self {
+            agent_id: row.try_get("agent_id")?,
+            container_id: row.try_get("container_id")?,
+            version: optional("version")?,
+            package_url: row.try_get("package_url")?,
+            sha256: row.try_get("sha256")?,
+            sync_status: row.try_get("sync_status")?,
+            sync_message: optional("sync_message")?,
+            installed_version: optional("installed_version")?,
+            updated_ms: millis(row, "updated_ms")?,
+        })
+    }
+}
+
+const AGENT_COLUMNS: &str = "id, hostname, version, capabilities, containers, runs, status, \
+     CAST(last_seen_ms AS TEXT) AS last_seen_ms";
+
+const COMMAND_COLUMNS: &str = "id, agent_id, command, status, COALESCE(message, '') AS message, \
+     COALESCE(output, '') AS output, CAST(created_ms AS TEXT) AS created_ms, \
+     CAST(updated_ms AS TEXT) AS updated_ms";
+
+const ASSIGNMENT_COLUMNS: &str = "agent_id, container_id, COALESCE(version, '') AS version, \
+     package_url, sha256, sync_status, \
+     COALESCE(sync_message, '') AS sync_message, \
+     COALESCE(installed_version, '') AS installed_version, CAST(updated_ms AS TEXT) AS updated_ms";
+
 impl Store {
     pub async fn open(database_url: &str) -> Result<Self> {
         install_default_drivers();
@@ -148,4 +393,336 @@ impl Store {
             .await?;
         Ok(result.rows_affected() > 0)
     }
+
+    /// Alta o re-registro de un agent; queda online desde `now_ms`.
+    pub async fn register_agent(&self, agent: &AgentRegistration, now_ms: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO agents (id, hostname, version, capabilities, containers, runs, status, last_seen_ms) \
+             VALUES (?, ?, ?, ?, ?, '[]', ?, ?) \
+             ON CONFLICT (id) DO UPDATE SET hostname = excluded.hostname, version = excluded.version, \
+             capabilities = excluded.capabilities, containers = excluded.containers, \
+             status = excluded.status, last_seen_ms = excluded.last_seen_ms",
+        )
+        .bind(&agent.id)
+        .bind(&agent.hostname)
+        .bind(&agent.version)
+        .bind(serde_json::to_string(&agent.capabilities)?)
+        .bind(serde_json::to_string(&agent.containers)?)
+        .bind(AGENT_ONLINE)
+        .bind(now_ms)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Guarda el heartbeat. Devuelve `false` si el agent no está registrado.
+    pub async fn record_heartbeat(
+        &self,
+        id: &str,
+        containers: &[AgentContainer],
+        runs: &[AgentRun],
+        now_ms: i64,
+    ) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE agents SET containers = ?, runs = ?, status = ?, last_seen_ms = ? WHERE id = ?",
+        )
+        .bind(serde_json::to_string(containers)?)
+        .bind(serde_json::to_string(runs)?)
+        .bind(AGENT_ONLINE)
+        .bind(now_ms)
+        .bind(id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn mark_agent_offline(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE agents SET status = ? WHERE id = ?")
+            .bind(AGENT_OFFLINE)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Lista los agents; los que llevan más de `stale_after` sin heartbeat se
+    /// devuelven como offline aunque su stream no se haya cerrado.
+    pub async fn list_agents(
+        &self,
+        now_ms: i64,
+        stale_after: Duration,
+    ) -> Result<Vec<AgentRecord>> {
+        let mut agents = sqlx::query_as::<_, AgentRecord>(&format!(
+            "SELECT {AGENT_COLUMNS} FROM agents ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+        for agent in &mut agents {
+            agent.apply_timeout(now_ms, stale_after);
+        }
+        Ok(agents)
+    }
+
+    pub async fn get_agent(
+        &self,
+        id: &str,
+        now_ms: i64,
+        stale_after: Duration,
+    ) -> Result<Option<AgentRecord>> {
+        let agent = sqlx::query_as::<_, AgentRecord>(&format!(
+            "SELECT {AGENT_COLUMNS} FROM agents WHERE id = ?"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(agent.map(|mut agent| {
+            agent.apply_timeout(now_ms, stale_after);
+            agent
+        }))
+    }
+
+    /// Guarda una orden nueva en estado `pending`.
+    pub async fn create_command(
+        &self,
+        agent_id: &str,
+        command: &AgentCommand,
+        now_ms: i64,
+    ) -> Result<CommandRecord> {
+        let record = CommandRecord {
+            id: Uuid::new_v4().to_string(),
+            agent_id: agent_id.to_string(),
+            command: command.clone(),
+            status: command_status::PENDING.into(),
+            message: None,
+            output: None,
+            created_ms: now_ms,
+            updated_ms: now_ms,
+        };
+        sqlx::query(
+            "INSERT INTO agent_commands (id, agent_id, command, status, created_ms, updated_ms) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&record.id)
+        .bind(&record.agent_id)
+        .bind(serde_json::to_string(&record.command)?)
+        .bind(&record.status)
+        .bind(now_ms)
+        .bind(now_ms)
+        .execute(&self.pool)
+        .await?;
+        Ok(record)
+    }
+
+    /// Actualiza el estado de una orden del agent indicado. Devuelve `false` si
+    /// la orden no existe o pertenece a otro agent.
+    pub async fn update_command(
+        &self,
+        agent_id: &str,
+        id: &str,
+        status: &str,
+        message: Option<&str>,
+        output: Option<&serde_json::Value>,
+        now_ms: i64,
+    ) -> Result<bool> {
+        let output = output.map(serde_json::to_string).transpose()?;
+        let result = sqlx::query(
+            "UPDATE agent_commands SET status = ?, message = COALESCE(?, message), \
+             output = COALESCE(?, output), updated_ms = ? WHERE id = ? AND agent_id = ?",
+        )
+        .bind(status)
+        .bind(message)
+        .bind(output)
+        .bind(now_ms)
+        .bind(id)
+        .bind(agent_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Pasa una orden de `pending` a `sent` y cuenta el envío. Devuelve `false`
+    /// si otro envío ya la reclamó.
+    pub async fn claim_command(&self, agent_id: &str, id: &str, now_ms: i64) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE agent_commands SET status = ?, attempts = attempts + 1, updated_ms = ? \
+             WHERE id = ? AND agent_id = ? AND status = ?",
+        )
+        .bind(command_status::SENT)
+        .bind(now_ms)
+        .bind(id)
+        .bind(agent_id)
+        .bind(command_status::PENDING)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Devuelve a la cola las órdenes enviadas que el agent no llegó a confirmar.
+    pub async fn requeue_unacknowledged(&self, agent_id: &str, now_ms: i64) -> Result<u64> {
+        let result = sqlx::query(
+            "UPDATE agent_commands SET status = ?, updated_ms = ? WHERE agent_id = ? AND status = ?",
+        )
+        .bind(command_status::PENDING)
+        .bind(now_ms)
+        .bind(agent_id)
+        .bind(command_status::SENT)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Órdenes enviadas o confirmadas sin resultado desde antes de `cutoff_ms`:
+    /// las que ya se enviaron `max_attempts` veces fallan y el resto vuelve a la
+    /// cola. Devuelve cuántas se reencolaron.
+    pub async fn expire_commands(
+        &self,
+        agent_id: &str,
+        cutoff_ms: i64,
+        max_attempts: u32,
+        now_ms: i64,
+    ) -> Result<u64> {
+        sqlx::query(
+            "UPDATE agent_commands SET status = ?, message = ?, updated_ms = ? \
+             WHERE agent_id = ? AND status IN (?, ?) AND updated_ms < ? AND attempts >= ?",
+        )
+        .bind(command_status::FAILED)
+        .bind("El agent no informó el resultado de la orden")
+        .bind(now_ms)
+        .bind(agent_id)
+        .bind(command_status::SENT)
+        .bind(command_status::ACKNOWLEDGED)
+        .bind(cutoff_ms)
+        .bind(i64::from(max_attempts))
+        .execute(&self.pool)
+        .await?;
+        let result = sqlx::query(
+            "UPDATE agent_commands SET status = ?, updated_ms = ? \
+             WHERE agent_id = ? AND status IN (?, ?) AND updated_ms < ?",
+        )
+        .bind(command_status::PENDING)
+        .bind(now_ms)
+        .bind(agent_id)
+        .bind(command_status::SENT)
+        .bind(command_status::ACKNOWLEDGED)
+        .bind(cutoff_ms)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Órdenes de un agent, de la más antigua a la más reciente; con `status`
+    /// solo las que están en ese estado.
+    pub async fn list_commands(
+        &self,
+        agent_id: &str,
+        status: Option<&str>,
+    ) -> Result<Vec<CommandRecord>> {
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {COMMAND_COLUMNS} FROM agent_commands WHERE agent_id = "
+        ));
+        builder.push_bind(agent_id);
+        if let Some(status) = status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        builder.push(" ORDER BY created_ms, id");
+        let rows = builder
+            .build_query_as::<CommandRecord>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows)
+    }
+
+    pub async fn get_command(&self, agent_id: &str, id: &str) -> Result<Option<CommandRecord>> {
+        let row = sqlx::query_as::<_, CommandRecord>(&format!(
+            "SELECT {COMMAND_COLUMNS} FROM agent_commands WHERE id = ? AND agent_id = ?"
+        ))
+        .bind(id)
+        .bind(agent_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    /// Asigna (o reasigna) un contenedor a un agent; la sincronización vuelve a
+    /// `pending` hasta el siguiente informe del agent.
+    pub async fn assign_container(
+        &self,
+        agent_id: &str,
+        container_id: &str,
+        assignment: &Assignment,
+        now_ms: i64,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO agent_assignments (agent_id, container_id, version, package_url, sha256, \
+             sync_status, updated_ms) VALUES (?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (agent_id, container_id) DO UPDATE SET version = excluded.version, \
+             package_url = excluded.package_url, sha256 = excluded.sha256, \
+             sync_status = excluded.sync_status, sync_message = NULL, \
+             updated_ms = excluded.updated_ms",
+        )
+        .bind(agent_id)
+        .bind(container_id)
+        .bind(&assignment.version)
+        .bind(&assignment.package_url)
+        .bind(&assignment.sha256)
+        .bind(sync_status::PENDING)
+        .bind(now_ms)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn unassign_container(&self, agent_id: &str, container_id: &str) -> Result<bool> {
+        let result =
+            sqlx::query("DELETE FROM agent_assignments WHERE agent_id = ? AND container_id = ?")
+                .bind(agent_id)
+                .bind(container_id)
+                .execute(&self.pool)
+                .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_assignments(&self, agent_id: &str) -> Result<Vec<AssignmentRecord>> {
+        let rows = sqlx::query_as::<_, AssignmentRecord>(&format!(
+            "SELECT {ASSIGNMENT_COLUMNS} FROM agent_assignments WHERE agent_id = ? \
+             ORDER BY container_id"
+        ))
+        .bind(agent_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Guarda el informe de sincronización del agent. Los contenedores que ya no
+    /// están asignados se ignoran.
+    pub async fn record_sync(
+        &self,
+        agent_id: &str,
+        containers: &[ContainerSyncStatus],
+        now_ms: i64,
+    ) -> Result<()> {
+        for container in containers {
+            sqlx::query(
+                "UPDATE agent_assignments SET sync_status = ?, sync_message = ?, \
+                 installed_version = ?, updated_ms = ? WHERE agent_id = ? AND container_id = ?",
+            )
+            .bind(&container.status)
+            .bind(&container.message)
+            .bind(&container.version)
+            .bind(now_ms)
+            .bind(agent_id)
+            .bind(&container.container_id)
+            .execute(&self.pool)
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+impl AgentRecord {
+    fn apply_timeout(&mut self, now_ms: i64, stale_after: Duration) {
+        if now_ms.saturating_sub(self.last_seen_ms) > stale_after.as_millis() as i64 {
+            self.status = AGENT_OFFLINE.to_string();
+        }
+    }
 }
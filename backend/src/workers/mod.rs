@@ -3,7 +3,11 @@ use std::time::Duration;
 use anyhow::Result;
 use tracing::{info, warn};
 
-use crate::{queue::TaskQueue, store::Store};
+use crate::{queue::TaskQueue, shutdown::Shutdown, store::Store};
+
+/// Espera máxima de cada lectura de la cola; acota lo que tarda el worker en
+/// ver el apagado cuando no hay tareas.
+const DEQUEUE_WAIT: Duration = Duration::from_secs(5);
 
 pub struct InstallWorker {
     queue: TaskQueue,
@@ -15,12 +19,14 @@ impl InstallWorker {
         Self { queue, store }
     }
 
-    pub async fn run(self) -> Result<()> {
+    /// Procesa tareas hasta que se dispara `shutdown`; la tarea en curso se
+    /// termina antes de salir.
+    pub async fn run(self, shutdown: Shutdown) -> Result<()> {
         info!("Worker de instalación iniciado; esperando tareas");
-        loop {
+        while !shutdown.is_triggered() {
             if let Some(payload) = self
                 .queue
-                .dequeue("containers:create", Duration::from_secs(30))
+                .dequeue("containers:create", DEQUEUE_WAIT)
                 .await?
             {
                 info!(task = payload.as_str(), "Procesando tarea de creación");
@@ -39,5 +45,7 @@ impl InstallWorker {
                 }
             }
         }
+        info!("Worker de instalación detenido");
+        Ok(())
     }
 }
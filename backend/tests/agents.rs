@@ -0,0 +1,626 @@
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use backend::{
+    agents::{AgentGrpc, MAX_COMMAND_ATTEMPTS},
+    app::{build_router, AppState},
+    proto::{
+        agent_message, agent_service_client::AgentServiceClient,
+        agent_service_server::AgentServiceServer, backend_message, command_request, AgentHeartbeat,
+        AgentMessage, BackendMessage, CommandAck, CommandRequest, CommandResult, ContainerSync,
+        DesiredState, HostedContainer, MountStatus, RegisterAgentRequest, RunStatus, SyncReport,
+    },
+    store::{AgentRecord, CommandRecord, Store},
+};
+use http_body_util::BodyExt;
+use std::time::Duration;
+use tokio::{net::TcpListener, sync::mpsc};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{transport::Channel, Code, Streaming};
+use tower::ServiceExt;
+
+async fn spawn_grpc(state: &AppState, timeout: Duration) -> AgentServiceClient<Channel> {
+    serve(agent_grpc(state, timeout)).await
+}
+
+fn agent_grpc(state: &AppState, timeout: Duration) -> AgentGrpc {
+    AgentGrpc::new(state.store.clone(), state.agents.clone(), timeout)
+        .with_shutdown(state.shutdown.clone())
+}
+
+async fn serve(service: AgentGrpc) -> AgentServiceClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(AgentServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    AgentServiceClient::connect(format!("http://{addr}"))
+        .await
+        .unwrap()
+}
+
+async fn send_command(
+    state: AppState,
+    agent_id: &str,
+    command: serde_json::Value,
+) -> (StatusCode, Option<CommandRecord>) {
+    let response = build_router(state)
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri(format!("/api/agents/{agent_id}/commands"))
+                .header("content-type", "application/json")
+                .body(Body::from(command.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).ok())
+}
+
+async fn get_command(state: AppState, agent_id: &str, command_id: &str) -> CommandRecord {
+    let response = build_router(state)
+        .oneshot(
+            Request::builder()
+                .uri(format!("/api/agents/{agent_id}/commands/{command_id}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Espera a que la orden llegue al estado indicado.
+async fn wait_for_status(state: &AppState, agent_id: &str, command_id: &str, status: &str) {
+    for _ in 0..50 {
+        if get_command(state.clone(), agent_id, command_id)
+            .await
+            .status
+            == status
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("la orden {command_id} no llegó a {status}");
+}
+
+async fn next_command(stream: &mut Streaming<BackendMessage>) -> CommandRequest {
+    loop {
+        match stream.message().await.unwrap().unwrap().payload {
+            Some(backend_message::Payload::Command(command)) => return command,
+            Some(backend_message::Payload::Ack(_) | backend_message::Payload::Desired(_)) => {
+                continue
+            }
+            None => panic!("mensaje vacío del backend"),
+        }
+    }
+}
+
+async fn next_desired(stream: &mut Streaming<BackendMessage>) -> DesiredState {
+    loop {
+        match stream.message().await.unwrap().unwrap().payload {
+            Some(backend_message::Payload::Desired(desired)) => return desired,
+            Some(_) => continue,
+            None => panic!("mensaje vacío del backend"),
+        }
+    }
+}
+
+async fn call(
+    state: AppState,
+    method: &str,
+    uri: &str,
+    body: Option<serde_json::Value>,
+) -> (StatusCode, serde_json::Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if body.is_some() {
+        request = request.header("content-type", "application/json");
+    }
+    let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+    let response = build_router(state)
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (
+        status,
+        serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null),
+    )
+}
+
+async fn list_agents(state: AppState) -> Vec<AgentRecord> {
+    let response = build_router(state)
+        .oneshot(
+            Request::builder()
+                .uri("/api/agents")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    serde_json::from_slice(&body).unwrap()
+}
+
+fn hosted() -> Vec<HostedContainer> {
+    vec![HostedContainer {
+        id: "demo".into(),
+        name: "Demo".into(),
+        version: "1.0".into(),
+        prepared: true,
+        mount: None,
+        error: String::new(),
+    }]
+}
+
+fn register_request(id: &str) -> RegisterAgentRequest {
+    RegisterAgentRequest {
+        agent_id: id.into(),
+        hostname: "pc-01".into(),
+        version: "0.1.0".into(),
+        capabilities: vec!["launch".into()],
+        containers: hosted(),
+    }
+}
+
+fn heartbeat(id: &str) -> AgentMessage {
+    AgentMessage {
+        payload: Some(agent_message::Payload::Heartbeat(AgentHeartbeat {
+            agent_id: id.into(),
+            containers: vec![HostedContainer {
+                mount: Some(MountStatus {
+                    state: "remounting".into(),
+                    attempts: 2,
+                    error: "El proceso del volumen terminó".into(),
+                    ..Default::default()
+                }),
+                error: "No se pudo montar el contenedor".into(),
+                ..hosted().remove(0)
+            }],
+            runs: vec![RunStatus {
+                run_id: "run-1".into(),
+                container_id: "demo".into(),
+                state: "running".into(),
+                pid: Some(42),
+                started_at_ms: 1,
+                ..Default::default()
+            }],
+        })),
+    }
+}
+
+#[tokio::test]
+async fn agents_register_heartbeat_and_go_offline_on_disconnect() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store.clone(), None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+
+    let registered = client
+        .register(register_request("agent-1"))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(registered.agent_id, "agent-1");
+    assert_eq!(registered.heartbeat_interval_secs, 10);
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut acks = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-1")).await.unwrap();
+    let ack = acks.message().await.unwrap().unwrap();
+    assert!(matches!(
+        ack.payload,
+        Some(backend_message::Payload::Ack(_))
+    ));
+
+    let agents = list_agents(state.clone()).await;
+    assert_eq!(agents.len(), 1);
+    assert_eq!(agents[0].status, "online");
+    assert_eq!(agents[0].capabilities, vec!["launch".to_string()]);
+    assert_eq!(agents[0].runs[0].pid, Some(42));
+    let mount = agents[0].containers[0].mount.as_ref().unwrap();
+    assert_eq!(mount.state, "remounting");
+    assert_eq!(mount.attempts, 2);
+    assert_eq!(mount.provider, None);
+    assert_eq!(
+        agents[0].containers[0].error.as_deref(),
+        Some("No se pudo montar el contenedor")
+    );
+
+    drop(tx);
+    for _ in 0..50 {
+        if list_agents(state.clone()).await[0].status == "offline" {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("el agent siguió online tras cerrar el stream");
+}
+
+#[tokio::test]
+async fn closing_a_replaced_stream_keeps_the_agent_online() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store, None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+    client.register(register_request("agent-5")).await.unwrap();
+
+    let (old_tx, old_rx) = mpsc::channel(4);
+    let mut old = client
+        .heartbeat(ReceiverStream::new(old_rx))
+        .await
+        .unwrap()
+        .into_inner();
+    old_tx.send(heartbeat("agent-5")).await.unwrap();
+    old.message().await.unwrap().unwrap();
+
+    // El agent reconecta antes de que el stream viejo termine de cerrarse.
+    let (new_tx, new_rx) = mpsc::channel(4);
+    let mut new = client
+        .heartbeat(ReceiverStream::new(new_rx))
+        .await
+        .unwrap()
+        .into_inner();
+    new_tx.send(heartbeat("agent-5")).await.unwrap();
+    new.message().await.unwrap().unwrap();
+    drop(old_tx);
+    while old.message().await.is_ok_and(|message| message.is_some()) {}
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(list_agents(state.clone()).await[0].status, "online");
+
+    let (status, sent) = send_command(
+        state.clone(),
+        "agent-5",
+        serde_json::json!({"kind": "refresh_registry"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(sent.unwrap().status, "sent");
+    assert!(matches!(
+        next_command(&mut new).await.action,
+        Some(command_request::Action::Refresh(_))
+    ));
+}
+
+#[tokio::test]
+async fn shutdown_closes_heartbeat_streams() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store, None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+
+    client.register(register_request("agent-4")).await.unwrap();
+    let (tx, rx) = mpsc::channel(4);
+    let mut stream = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-4")).await.unwrap();
+    stream.message().await.unwrap().unwrap();
+
+    state.shutdown.trigger();
+    let status = loop {
+        match stream.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("el stream terminó sin estado"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), Code::Unavailable);
+    for _ in 0..50 {
+        if list_agents(state.clone()).await[0].status == "offline" {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("el agent siguió online tras el apagado");
+}
+
+#[tokio::test]
+async fn silent_agents_time_out_and_unknown_agents_are_rejected() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let timeout = Duration::from_millis(200);
+    let state = AppState::new("test".into(), store.clone(), None).with_agent_timeout(timeout);
+    let mut client = spawn_grpc(&state, timeout).await;
+
+    client.register(register_request("agent-2")).await.unwrap();
+    assert_eq!(list_agents(state.clone()).await[0].status, "online");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(list_agents(state).await[0].status, "offline");
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut acks = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("ghost")).await.unwrap();
+    let status = acks.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+
+    let err = client
+        .register(RegisterAgentRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn commands_queue_until_the_agent_connects_and_track_acks() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store, None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+    client.register(register_request("agent-3")).await.unwrap();
+
+    let (status, queued) = send_command(
+        state.clone(),
+        "agent-3",
+        serde_json::json!({"kind": "launch", "container_id": "demo", "profile": "safe"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let queued = queued.unwrap();
+    assert_eq!(queued.status, "pending");
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut inbound = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-3")).await.unwrap();
+    let command = next_command(&mut inbound).await;
+    assert_eq!(command.command_id, queued.id);
+    assert!(matches!(
+        command.action,
+        Some(command_request::Action::Launch(ref launch))
+            if launch.container_id == "demo" && launch.profile.as_deref() == Some("safe")
+    ));
+
+    tx.send(AgentMessage {
+        payload: Some(agent_message::Payload::Ack(CommandAck {
+            command_id: queued.id.clone(),
+        })),
+    })
+    .await
+    .unwrap();
+    wait_for_status(&state, "agent-3", &queued.id, "acknowledged").await;
+
+    let (status, sent) = send_command(
+        state.clone(),
+        "agent-3",
+        serde_json::json!({"kind": "collect_diagnostics"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let sent = sent.unwrap();
+    assert_eq!(sent.status, "sent");
+    let command = next_command(&mut inbound).await;
+    assert!(matches!(
+        command.action,
+        Some(command_request::Action::Diagnostics(_))
+    ));
+
+    tx.send(AgentMessage {
+        payload: Some(agent_message::Payload::Result(CommandResult {
+            command_id: sent.id.clone(),
+            success: true,
+            message: String::new(),
+            output: r#"{"type":"diagnostics","diagnostics":[]}"#.into(),
+        })),
+    })
+    .await
+    .unwrap();
+    wait_for_status(&state, "agent-3", &sent.id, "succeeded").await;
+    let done = get_command(state.clone(), "agent-3", &sent.id).await;
+    assert_eq!(done.output.unwrap()["type"], "diagnostics");
+
+    let (status, _) = send_command(
+        state.clone(),
+        "ghost",
+        serde_json::json!({"kind": "refresh_registry"}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send_command(
+        state,
+        "agent-3",
+        serde_json::json!({"kind": "stop", "run_id": " "}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn commands_claimed_elsewhere_do_not_hold_back_the_queue() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store.clone(), None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+    client.register(register_request("agent-5")).await.unwrap();
+
+    let (_, first) = send_command(
+        state.clone(),
+        "agent-5",
+        serde_json::json!({"kind": "refresh_registry"}),
+    )
+    .await;
+    let (_, second) = send_command(
+        state.clone(),
+        "agent-5",
+        serde_json::json!({"kind": "collect_diagnostics"}),
+    )
+    .await;
+    let (first, second) = (first.unwrap(), second.unwrap());
+    // Otro envío se adelanta con la primera mientras el agent conecta.
+    assert!(store.claim_command("agent-5", &first.id, 0).await.unwrap());
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut inbound = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-5")).await.unwrap();
+    assert_eq!(next_command(&mut inbound).await.command_id, second.id);
+}
+
+#[tokio::test]
+async fn commands_without_result_are_resent_until_they_fail() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let state = AppState::new("test".into(), store, None);
+    let mut client =
+        serve(agent_grpc(&state, Duration::from_secs(30)).with_command_timeout(Duration::ZERO))
+            .await;
+    client.register(register_request("agent-6")).await.unwrap();
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut inbound = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-6")).await.unwrap();
+    let (_, sent) = send_command(
+        state.clone(),
+        "agent-6",
+        serde_json::json!({"kind": "launch", "container_id": "demo"}),
+    )
+    .await;
+    let sent = sent.unwrap();
+    assert_eq!(next_command(&mut inbound).await.command_id, sent.id);
+
+    // El agent confirma pero nunca informa el resultado: cada heartbeat la
+    // reenvía con el mismo id hasta agotar los envíos.
+    for _ in 1..MAX_COMMAND_ATTEMPTS {
+        tx.send(AgentMessage {
+            payload: Some(agent_message::Payload::Ack(CommandAck {
+                command_id: sent.id.clone(),
+            })),
+        })
+        .await
+        .unwrap();
+        wait_for_status(&state, "agent-6", &sent.id, "acknowledged").await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        tx.send(heartbeat("agent-6")).await.unwrap();
+        assert_eq!(next_command(&mut inbound).await.command_id, sent.id);
+    }
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    tx.send(heartbeat("agent-6")).await.unwrap();
+    wait_for_status(&state, "agent-6", &sent.id, "failed").await;
+    let failed = get_command(state, "agent-6", &sent.id).await;
+    assert_eq!(
+        failed.message.as_deref(),
+        Some("El agent no informó el resultado de la orden")
+    );
+}
+
+#[tokio::test]
+async fn assignments_push_desired_state_and_record_agent_sync() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let container = store.create("Demo 2", Some("2.0".into())).await.unwrap();
+    let state = AppState::new("test".into(), store, None);
+    let mut client = spawn_grpc(&state, Duration::from_secs(30)).await;
+    client.register(register_request("agent-4")).await.unwrap();
+
+    let uri = format!("/api/agents/agent-4/assignments/{}", container.id);
+    let sha256 = "ab".repeat(32);
+    let (status, assigned) = call(
+        state.clone(),
+        "PUT",
+        &uri,
+        Some(serde_json::json!({"package_url": "http://packages/demo.ctnr", "sha256": sha256})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(assigned["version"], "2.0");
+    assert_eq!(assigned["sha256"], sha256);
+    assert_eq!(assigned["sync_status"], "pending");
+
+    let (tx, rx) = mpsc::channel(4);
+    let mut inbound = client
+        .heartbeat(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    tx.send(heartbeat("agent-4")).await.unwrap();
+    let desired = next_desired(&mut inbound).await;
+    assert_eq!(desired.containers.len(), 1);
+    assert_eq!(desired.containers[0].container_id, container.id);
+    assert_eq!(desired.containers[0].version, "2.0");
+    assert_eq!(desired.containers[0].sha256, sha256);
+
+    tx.send(AgentMessage {
+        payload: Some(agent_message::Payload::Sync(SyncReport {
+            containers: vec![ContainerSync {
+                container_id: container.id.clone(),
+                status: "in_sync".into(),
+                version: "2.0".into(),
+                message: String::new(),
+            }],
+        })),
+    })
+    .await
+    .unwrap();
+    let mut sync = serde_json::Value::Null;
+    for _ in 0..50 {
+        sync = call(
+            state.clone(),
+            "GET",
+            "/api/agents/agent-4/assignments",
+            None,
+        )
+        .await
+        .1;
+        if sync["assignments"][0]["sync_status"] == "in_sync" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(sync["assignments"][0]["sync_status"], "in_sync");
+    assert_eq!(sync["assignments"][0]["installed_version"], "2.0");
+    // El agent aloja `demo`, que no está asignado.
+    assert_eq!(sync["unmanaged"], serde_json::json!(["demo"]));
+
+    let (status, _) = call(state.clone(), "DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(next_desired(&mut inbound).await.containers.is_empty());
+
+    let (status, _) = call(
+        state.clone(),
+        "PUT",
+        "/api/agents/agent-4/assignments/missing",
+        Some(serde_json::json!({"package_url": "http://packages/x.ctnr", "sha256": sha256})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    for body in [
+        serde_json::json!({"package_url": " ", "sha256": sha256}),
+        serde_json::json!({"package_url": "http://packages/demo.ctnr", "sha256": ""}),
+        serde_json::json!({"package_url": "http://packages/demo.ctnr", "sha256": "no-es-hex"}),
+    ] {
+        let (status, _) = call(state.clone(), "PUT", &uri, Some(body)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    // Sin `sha256` la petición ni siquiera se deserializa.
+    let (status, _) = call(
+        state,
+        "PUT",
+        &uri,
+        Some(serde_json::json!({"package_url": "http://packages/demo.ctnr"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+}
@@ -0,0 +1,80 @@
+use axum::{body::Body, http::Request};
+use backend::{
+    app::{build_router, AppState},
+    shutdown::{supervise, Shutdown},
+    store::Store,
+};
+use http_body_util::BodyExt;
+use std::time::{Duration, Instant};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn a_failing_task_stops_the_others() {
+    let shutdown = Shutdown::default();
+    let waiting = shutdown.clone();
+    let tasks = vec![
+        (
+            "http",
+            tokio::spawn(async move {
+                waiting.wait().await;
+                Ok(())
+            }),
+        ),
+        (
+            "grpc",
+            tokio::spawn(async { Err(anyhow::anyhow!("puerto ocupado")) }),
+        ),
+    ];
+
+    let err = supervise(tasks, &shutdown, Duration::from_secs(5))
+        .await
+        .unwrap_err();
+    assert!(shutdown.is_triggered());
+    assert!(format!("{err:#}").contains("puerto ocupado"));
+}
+
+#[tokio::test]
+async fn tasks_that_do_not_drain_are_aborted() {
+    let shutdown = Shutdown::default();
+    let tasks = vec![(
+        "stuck",
+        tokio::spawn(std::future::pending::<anyhow::Result<()>>()),
+    )];
+    shutdown.trigger();
+
+    let started = Instant::now();
+    supervise(tasks, &shutdown, Duration::from_millis(200))
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn sse_streams_end_with_a_shutdown_event() {
+    let store = Store::open("sqlite::memory:").await.unwrap();
+    let shutdown = Shutdown::default();
+    let state = AppState::new("test".into(), store, None).with_shutdown(shutdown.clone());
+    let response = build_router(state)
+        .oneshot(
+            Request::builder()
+                .uri("/api/events/containers")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    shutdown.trigger();
+    let body = tokio::time::timeout(Duration::from_secs(5), response.into_body().collect())
+        .await
+        .expect("el stream SSE debe cerrarse al apagar")
+        .unwrap()
+        .to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        body.trim_end().ends_with("event: shutdown\ndata: {}"),
+        "{body}"
+    );
+}
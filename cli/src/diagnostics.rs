@@ -0,0 +1,58 @@
+use crate::local;
+use agent::{
+    control::ControlClient,
+    registry::{ContainerRegistry, LoadDiagnostic},
+};
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Muestra los contenedores cargados y sus diagnósticos. Sin `roots` se los
+/// pide al agent, que conoce también los manifiestos rotos que mantiene con su
+/// última versión válida; con `roots` revisa esas carpetas localmente.
+pub async fn run(client: &ControlClient, roots: &[PathBuf]) -> Result<()> {
+    let (mut loaded, diagnostics) = if roots.is_empty() {
+        let containers = local::containers(client).await?;
+        let loaded = containers.into_iter().map(|c| (c.id, c.root)).collect();
+        (loaded, local::diagnostics(client).await?)
+    } else {
+        let registry = ContainerRegistry::load_roots(roots).await?;
+        let loaded = registry
+            .list()
+            .into_iter()
+            .map(|c| (c.manifest.id, c.root))
+            .collect::<Vec<_>>();
+        (loaded, registry.diagnostics().to_vec())
+    };
+    loaded.sort();
+
+    println!("Contenedores cargados: {}", loaded.len());
+    for (id, root) in &loaded {
+        println!("- {id} ({})", root.display());
+    }
+    print(&diagnostics);
+    Ok(())
+}
+
+pub fn print(diagnostics: &[LoadDiagnostic]) {
+    if diagnostics.is_empty() {
+        println!("Sin diagnósticos.");
+        return;
+    }
+    println!("Diagnósticos:");
+    for diagnostic in diagnostics {
+        let status = if diagnostic.retained {
+            "se mantiene la última versión válida"
+        } else if diagnostic.quarantines() {
+            "cuarentena"
+        } else {
+            "aviso"
+        };
+        println!(
+            "- [{status}] {:?} {} ({}): {}",
+            diagnostic.kind,
+            diagnostic.container_id.as_deref().unwrap_or("-"),
+            diagnostic.path.display(),
+            diagnostic.message
+        );
+    }
+}
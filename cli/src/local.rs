@@ -0,0 +1,258 @@
+use agent::{
+    control::{self, ContainerSummary, ControlClient, ControlRequest, ControlResponse},
+    host::ShutdownReport,
+    registry::LoadDiagnostic,
+    runtime::HookPlan,
+    supervisor::RunRecord,
+};
+use anyhow::{anyhow, bail, Result};
+use clap::Subcommand;
+use std::{path::PathBuf, time::Duration};
+
+/// Consultas al agent local (`ctnr agent ...`).
+#[derive(Subcommand)]
+pub enum AgentCommands {
+    /// Contenedores cargados por el agent y sus perfiles
+    Containers,
+    /// Plan de hooks (entorno, redirecciones, capas) de un contenedor
+    Plan { container: String },
+    /// Diagnósticos de la última carga del registro
+    Diagnostics,
+    /// Relee los manifiestos sin esperar a que el agent detecte cambios
+    Refresh,
+    /// Apaga el agent: detiene ejecuciones, desmonta volúmenes y desactiva hooks
+    Shutdown {
+        /// Segundos por proceso antes de forzar kill (por defecto, el del agent)
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+}
+
+/// Cliente del API local del agent en esta PC.
+pub fn client(endpoint: Option<&PathBuf>) -> ControlClient {
+    ControlClient::new(endpoint.cloned().unwrap_or_else(control::default_endpoint))
+}
+
+async fn call(client: &ControlClient, request: ControlRequest) -> Result<ControlResponse> {
+    match client.request(&request).await? {
+        ControlResponse::Error { message } => bail!(message),
+        response => Ok(response),
+    }
+}
+
+fn unexpected(response: ControlResponse) -> anyhow::Error {
+    anyhow!("Respuesta inesperada del agent: {response:?}")
+}
+
+pub async fn launch(
+    client: &ControlClient,
+    container: &str,
+    profile: Option<String>,
+    executable: Option<String>,
+    args: Vec<String>,
+) -> Result<String> {
+    let request = ControlRequest::Launch {
+        container_id: container.to_string(),
+        profile,
+        executable,
+        args,
+    };
+    match call(client, request).await? {
+        ControlResponse::Launched { run_id } => Ok(run_id),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn containers(client: &ControlClient) -> Result<Vec<ContainerSummary>> {
+    match call(client, ControlRequest::ListContainers).await? {
+        ControlResponse::Containers { containers } => Ok(containers),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn plan(client: &ControlClient, container: &str) -> Result<HookPlan> {
+    let request = ControlRequest::GetPlan {
+        container_id: container.to_string(),
+    };
+    match call(client, request).await? {
+        ControlResponse::Plan { plan } => Ok(plan),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn status(
+    client: &ControlClient,
+    run_id: Option<String>,
+    container: Option<String>,
+) -> Result<Vec<RunRecord>> {
+    let request = ControlRequest::Status {
+        run_id,
+        container_id: container,
+    };
+    match call(client, request).await? {
+        ControlResponse::Runs { runs } => Ok(runs),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn stop(client: &ControlClient, run_id: &str, timeout: Duration) -> Result<RunRecord> {
+    let request = ControlRequest::Stop {
+        run_id: run_id.to_string(),
+        timeout_ms: Some(timeout.as_millis() as u64),
+    };
+    match call(client, request).await? {
+        ControlResponse::Runs { mut runs } if runs.len() == 1 => Ok(runs.remove(0)),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn diagnostics(client: &ControlClient) -> Result<Vec<LoadDiagnostic>> {
+    match call(client, ControlRequest::Diagnostics).await? {
+        ControlResponse::Diagnostics { diagnostics } => Ok(diagnostics),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn refresh(client: &ControlClient) -> Result<usize> {
+    match call(client, ControlRequest::Refresh).await? {
+        ControlResponse::Refreshed { changes } => Ok(changes),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn shutdown(client: &ControlClient, timeout: Option<Duration>) -> Result<ShutdownReport> {
+    let request = ControlRequest::Shutdown {
+        timeout_ms: timeout.map(|timeout| timeout.as_millis() as u64),
+    };
+    match call(client, request).await? {
+        ControlResponse::ShutDown { report } => Ok(report),
+        other => Err(unexpected(other)),
+    }
+}
+
+pub async fn run_agent_command(client: &ControlClient, command: &AgentCommands) -> Result<()> {
+    match command {
+        AgentCommands::Containers => {
+            let containers = containers(client).await?;
+            if containers.is_empty() {
+                println!("El agent no tiene contenedores cargados.");
+            }
+            for container in containers {
+                let state = if container.prepared {
+                    "listo"
+                } else {
+                    "sin preparar"
+                };
+                println!(
+                    "- [{state}] {} {} ({}) perfiles: {}",
+                    container.id,
+                    container.version.as_deref().unwrap_or("latest"),
+                    container.name,
+                    if container.profiles.is_empty() {
+                        "-".to_string()
+                    } else {
+                        container.profiles.join(", ")
+                    }
+                );
+                if let Some(mount) = &container.mount {
+                    match &mount.mount_point {
+                        Some(mount_point) => println!(
+                            "    volumen: {} en {} ({})",
+                            mount.state.as_str(),
+                            mount_point.display(),
+                            mount.provider.as_deref().unwrap_or("-")
+                        ),
+                        None => println!(
+                            "    volumen: {} tras {} intentos: {}",
+                            mount.state.as_str(),
+                            mount.attempts,
+                            mount.error.as_deref().unwrap_or("-")
+                        ),
+                    }
+                }
+                if let Some(error) = &container.error {
+                    println!("    error: {error}");
+                }
+            }
+        }
+        AgentCommands::Plan { container } => {
+            let plan = plan(client, container).await?;
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        }
+        AgentCommands::Diagnostics => {
+            crate::diagnostics::print(&diagnostics(client).await?);
+        }
+        AgentCommands::Refresh => {
+            let changes = refresh(client).await?;
+            println!("Registro recargado: {changes} cambio(s).");
+        }
+        AgentCommands::Shutdown { timeout } => {
+            let report = shutdown(client, timeout.map(Duration::from_secs)).await?;
+            println!("Agent apagado.");
+            print_runs(&report.runs);
+            if !report.unmounted.is_empty() {
+                println!("Volúmenes desmontados: {}", report.unmounted.join(", "));
+            }
+            for error in &report.errors {
+                println!("- error: {error}");
+            }
+            if !report.errors.is_empty() {
+                bail!("El apagado terminó con {} error(es)", report.errors.len());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn print_runs(runs: &[RunRecord]) {
+    if runs.is_empty() {
+        println!("No hay ejecuciones.");
+    }
+    for run in runs {
+        let outcome = match (run.exit_code, run.signal) {
+            (Some(code), _) => format!("código {code}"),
+            (None, Some(signal)) => format!("señal {signal}"),
+            (None, None) => "-".to_string(),
+        };
+        println!(
+            "{} {:<10} {:<12} pid {:<7} reinicios {} salida {}",
+            run.run_id,
+            run.container_id,
+            format!("{:?}", run.state).to_lowercase(),
+            run.pid.map_or("-".to_string(), |pid| pid.to_string()),
+            run.restarts,
+            outcome
+        );
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use agent::control::{ControlAccess, ControlServer};
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn client_surfaces_agent_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let endpoint = dir.path().join("agent.sock");
+        let server = ControlServer::bind(&endpoint, ControlAccess::Owner).unwrap();
+        let (tx, mut rx) = mpsc::channel(4);
+        let serve = tokio::spawn(server.serve(tx));
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                let response = match command.request {
+                    ControlRequest::Status { .. } => ControlResponse::Runs { runs: vec![] },
+                    _ => ControlResponse::error("El contenedor demo no está preparado"),
+                };
+                let _ = command.reply.send(response);
+            }
+        });
+
+        let client = client(Some(&endpoint));
+        assert!(status(&client, None, None).await.unwrap().is_empty());
+        let err = plan(&client, "demo").await.unwrap_err();
+        assert_eq!(err.to_string(), "El contenedor demo no está preparado");
+        serve.abort();
+    }
+}
@@ -0,0 +1,47 @@
+use agent::{
+    logs::{self, LogLine, LOG_DIR},
+    registry::ContainerRegistry,
+};
+use anyhow::{anyhow, Result};
+use std::{path::PathBuf, time::Duration};
+
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Muestra los logs de un contenedor local leyendo `<contenedor>/logs/`.
+pub async fn run(
+    roots: &[PathBuf],
+    container_id: &str,
+    run_id: Option<&str>,
+    follow: bool,
+) -> Result<()> {
+    let registry = ContainerRegistry::load_roots(roots).await?;
+    let container = registry
+        .get(container_id)
+        .ok_or_else(|| anyhow!("No se encontró el contenedor {container_id}"))?;
+    let dir = container.root.join(LOG_DIR);
+
+    let (lines, mut follower) = logs::read_and_follow(&dir, run_id)?;
+    for line in lines {
+        print_line(&line);
+    }
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(FOLLOW_INTERVAL) => {}
+        }
+        for line in follower.poll()? {
+            if run_id.is_none_or(|id| line.run_id == id) {
+                print_line(&line);
+            }
+        }
+    }
+}
+
+fn print_line(line: &LogLine) {
+    let run = line.run_id.get(..8).unwrap_or(&line.run_id);
+    println!("{run} {:<6} {}", line.stream.to_string(), line.text);
+}
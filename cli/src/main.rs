@@ -1,6 +1,14 @@
+mod diagnostics;
+mod local;
+mod logs;
+mod reg;
+mod runtimes;
+
+use agent::config::{AgentArgs, AgentConfig};
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use serde::Deserialize;
+use std::{path::PathBuf, time::Duration};
 
 #[derive(Parser)]
 #[command(name = "ctnr", version)]
@@ -12,6 +20,15 @@ struct Cli {
     /// Endpoint del backend (por defecto localhost:8080)
     #[arg(global = true, long, default_value = "http://127.0.0.1:8080")]
     api: String,
+
+    /// Socket / named pipe del agent local
+    #[arg(global = true, long, env = "CTNR_AGENT_ENDPOINT")]
+    agent: Option<PathBuf>,
+
+    /// Configuración del agent local (también `AGENT_CONFIG`); de ella salen
+    /// las carpetas de contenedores y runtimes que no se indican
+    #[arg(global = true, long)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
@@ -20,6 +37,76 @@ enum Commands {
     List,
     /// Crea un contenedor placeholder
     Create { name: String },
+    /// Lanza un perfil (u otro ejecutable) de un contenedor en el agent local
+    Run {
+        container: String,
+        /// Perfil de `launch` del manifiesto (por defecto, el declarado como default)
+        #[arg(long, conflicts_with = "exec")]
+        profile: Option<String>,
+        /// Ejecutable relativo a la carpeta del contenedor
+        #[arg(long)]
+        exec: Option<String>,
+        /// Argumentos para el proceso (después de `--`)
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Estado de las ejecuciones del agent local
+    Ps {
+        /// Solo las de este contenedor
+        #[arg(long)]
+        container: Option<String>,
+        /// Solo esta ejecución
+        #[arg(long = "run")]
+        run_id: Option<String>,
+    },
+    /// Detiene una ejecución (cierre ordenado y kill pasado el timeout)
+    Stop {
+        run_id: String,
+        /// Segundos de gracia antes de forzar el cierre
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+    /// Consulta el agent local (contenedores, planes, diagnósticos)
+    Agent {
+        #[command(subcommand)]
+        command: local::AgentCommands,
+    },
+    /// Diagnósticos del agent local: manifiestos rotos y contenedores en cuarentena
+    Diagnostics {
+        /// Revisa estas carpetas localmente en lugar de preguntar al agent (repetible)
+        #[arg(long = "containers")]
+        roots: Vec<PathBuf>,
+    },
+    /// Muestra stdout/stderr capturados de las ejecuciones de un contenedor local
+    Logs {
+        container: String,
+        /// Sigue mostrando líneas nuevas hasta Ctrl+C
+        #[arg(long, short)]
+        follow: bool,
+        /// Solo las líneas de esta ejecución
+        #[arg(long = "run")]
+        run_id: Option<String>,
+        /// Carpetas de contenedores (repetible; por defecto, las del agent)
+        #[arg(long = "containers")]
+        roots: Vec<PathBuf>,
+    },
+    /// Administra los runtimes base compartidos instalados en esta PC
+    Runtime {
+        #[command(subcommand)]
+        command: runtimes::RuntimeCommands,
+        /// Almacén local de runtimes (por defecto, el del agent)
+        #[arg(long)]
+        store: Option<PathBuf>,
+        /// Carpetas de contenedores usadas para calcular referencias (repetible;
+        /// por defecto, las del agent)
+        #[arg(long)]
+        containers: Vec<PathBuf>,
+    },
+    /// Lee y edita hives del registro (`.hiv`) sin necesidad de Windows
+    Reg {
+        #[command(subcommand)]
+        command: reg::RegCommands,
+    },
 }
 
 #[tokio::main]
@@ -28,10 +115,82 @@ async fn main() -> Result<()> {
     match &cli.command {
         Commands::List => list_containers(&cli.api).await?,
         Commands::Create { name } => create_container(&cli.api, name).await?,
+        Commands::Run {
+            container,
+            profile,
+            exec,
+            args,
+        } => {
+            let client = local::client(cli.agent.as_ref());
+            let run_id = local::launch(
+                &client,
+                container,
+                profile.clone(),
+                exec.clone(),
+                args.clone(),
+            )
+            .await?;
+            println!("Proceso lanzado en {container}: run {run_id}");
+        }
+        Commands::Ps { container, run_id } => {
+            let client = local::client(cli.agent.as_ref());
+            let runs = local::status(&client, run_id.clone(), container.clone()).await?;
+            local::print_runs(&runs);
+        }
+        Commands::Stop { run_id, timeout } => {
+            let client = local::client(cli.agent.as_ref());
+            let run = local::stop(&client, run_id, Duration::from_secs(*timeout)).await?;
+            local::print_runs(&[run]);
+        }
+        Commands::Agent { command } => {
+            let client = local::client(cli.agent.as_ref());
+            local::run_agent_command(&client, command).await?;
+        }
+        Commands::Diagnostics { roots } => {
+            let client = local::client(cli.agent.as_ref());
+            diagnostics::run(&client, roots).await?;
+        }
+        Commands::Logs {
+            container,
+            follow,
+            run_id,
+            roots,
+        } => {
+            let roots = if roots.is_empty() {
+                agent_config(&cli)?.container_roots
+            } else {
+                roots.clone()
+            };
+            logs::run(&roots, container, run_id.as_deref(), *follow).await?
+        }
+        Commands::Runtime {
+            command,
+            store,
+            containers,
+        } => {
+            let config = agent_config(&cli)?;
+            let store = store.clone().unwrap_or(config.runtimes_dir);
+            let containers = if containers.is_empty() {
+                config.container_roots
+            } else {
+                containers.clone()
+            };
+            runtimes::run(command, &store, &containers).await?
+        }
+        Commands::Reg { command } => reg::run(command).await?,
     }
     Ok(())
 }
 
+/// Configuración del agent local, para usar sus mismas carpetas sin depender
+/// del directorio de trabajo.
+fn agent_config(cli: &Cli) -> Result<AgentConfig> {
+    AgentConfig::load(&AgentArgs {
+        config: cli.config.clone(),
+        ..AgentArgs::default()
+    })
+}
+
 async fn list_containers(api: &str) -> Result<()> {
     let containers = fetch_containers(api).await?;
     if containers.is_empty() {
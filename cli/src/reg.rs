@@ -0,0 +1,410 @@
+use agent::{
+    regfile::{ExportMode, RegFile, RegFileVersion},
+    registry::ContainerRegistry,
+    runtimes::RuntimeStore,
+    winreg::{RegValue, VirtualRegistry},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use regf::{Hive, Key};
+use std::{fmt::Write, io::Write as _, path::PathBuf};
+
+#[derive(Subcommand)]
+pub enum RegCommands {
+    /// Muestra todas las claves y valores de un hive, o los de una rama
+    Dump {
+        hive: PathBuf,
+        /// Clave desde la que empezar (`Software\Vendor`)
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Muestra los valores de una clave, o solo uno
+    Get {
+        hive: PathBuf,
+        key: String,
+        /// Nombre del valor (`""` para el predeterminado)
+        value: Option<String>,
+    },
+    /// Crea o reemplaza un valor; crea el hive y las claves que falten
+    Set {
+        hive: PathBuf,
+        key: String,
+        /// Nombre del valor (`""` para el predeterminado)
+        name: String,
+        /// Datos: texto, número (decimal o `0x…`), hex o una cadena por elemento
+        data: Vec<String>,
+        #[arg(long = "type", value_enum, default_value_t = ValueType::Sz)]
+        kind: ValueType,
+    },
+    /// Aplica un `.reg` (REGEDIT4 o 5.00) sobre el registro de un contenedor
+    Import {
+        container: String,
+        file: PathBuf,
+        #[command(flatten)]
+        local: LocalContainers,
+    },
+    /// Exporta el registro de un contenedor como `.reg`
+    Export {
+        container: String,
+        /// Archivo destino (por defecto, la salida estándar)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Rama a exportar (`HKEY_CURRENT_USER\Software\Vendor`)
+        #[arg(long)]
+        key: Option<String>,
+        /// Solo los cambios del contenedor sobre la base, con sus borrados
+        #[arg(long)]
+        changes: bool,
+        /// Formato REGEDIT4 (ANSI) en lugar de 5.00 (UTF-16LE)
+        #[arg(long)]
+        regedit4: bool,
+        #[command(flatten)]
+        local: LocalContainers,
+    },
+}
+
+#[derive(Args)]
+pub struct LocalContainers {
+    /// Carpetas de contenedores (repetible)
+    #[arg(long = "containers", default_value = "containers")]
+    roots: Vec<PathBuf>,
+    /// Almacén local de runtimes (snapshot base del registro)
+    #[arg(long, default_value = "runtimes")]
+    runtimes: PathBuf,
+}
+
+impl LocalContainers {
+    async fn registry(&self, container_id: &str) -> Result<VirtualRegistry> {
+        let containers = ContainerRegistry::load_roots(&self.roots).await?;
+        let container = containers
+            .get(container_id)
+            .ok_or_else(|| anyhow!("No se encontró el contenedor {container_id}"))?;
+        VirtualRegistry::for_container(container, &RuntimeStore::new(&self.runtimes))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValueType {
+    Sz,
+    ExpandSz,
+    Dword,
+    Qword,
+    Binary,
+    MultiSz,
+}
+
+pub async fn run(command: &RegCommands) -> Result<()> {
+    match command {
+        RegCommands::Dump { hive, key } => {
+            let hive = Hive::read(hive)?;
+            let path = key.as_deref().unwrap_or("");
+            let key = find(&hive, path)?;
+            print!("{}", dump(key, path));
+        }
+        RegCommands::Get { hive, key, value } => {
+            let hive = Hive::read(hive)?;
+            let found = find(&hive, key)?;
+            let mut out = format!("{}\n", display_path(key));
+            match value {
+                Some(name) => {
+                    let value = found
+                        .value(name)
+                        .with_context(|| format!("{key} no tiene el valor {name:?}"))?;
+                    write_value(&mut out, value);
+                }
+                None => found.values.iter().for_each(|v| write_value(&mut out, v)),
+            }
+            print!("{out}");
+        }
+        RegCommands::Set {
+            hive: path,
+            key,
+            name,
+            data,
+            kind,
+        } => {
+            let value = parse_value(*kind, data)?;
+            let mut hive = if path.exists() {
+                Hive::read(path)?
+            } else {
+                Hive::new()
+            };
+            hive.root
+                .create_key(key)
+                .set_value(name, value.kind(), value.to_bytes());
+            hive.write(path)?;
+            println!("{}\\{name} = {value}", display_path(key));
+        }
+        RegCommands::Import {
+            container,
+            file,
+            local,
+        } => {
+            let raw = std::fs::read(file)
+                .with_context(|| format!("No se pudo leer {}", file.display()))?;
+            let reg =
+                RegFile::parse(&raw).with_context(|| format!("{} inválido", file.display()))?;
+            let mut registry = local.registry(container).await?;
+            let summary = reg.apply(&mut registry)?;
+            registry.save()?;
+            println!(
+                "Importado en {container}: {} claves, {} valores; borradas {} claves y {} valores",
+                summary.keys, summary.values, summary.deleted_keys, summary.deleted_values
+            );
+        }
+        RegCommands::Export {
+            container,
+            output,
+            key,
+            changes,
+            regedit4,
+            local,
+        } => {
+            let registry = local.registry(container).await?;
+            let mode = if *changes {
+                ExportMode::Changes
+            } else {
+                ExportMode::Full
+            };
+            let version = if *regedit4 {
+                RegFileVersion::Regedit4
+            } else {
+                RegFileVersion::V5
+            };
+            let bytes = RegFile::export(&registry, key.as_deref(), mode, version)?.encode();
+            match output {
+                Some(path) => std::fs::write(path, bytes)
+                    .with_context(|| format!("No se pudo guardar {}", path.display()))?,
+                None => std::io::stdout().write_all(&bytes)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find<'a>(hive: &'a Hive, path: &str) -> Result<&'a Key> {
+    hive.root
+        .key(path)
+        .with_context(|| format!("La clave {} no existe", display_path(path)))
+}
+
+fn display_path(path: &str) -> String {
+    format!("\\{}", path.trim_matches('\\'))
+}
+
+/// Rama completa con el formato de `reg query /s`.
+fn dump(key: &Key, path: &str) -> String {
+    let mut out = String::new();
+    dump_into(&mut out, key, path.trim_matches('\\'));
+    out
+}
+
+fn dump_into(out: &mut String, key: &Key, path: &str) {
+    let _ = writeln!(out, "{}", display_path(path));
+    key.values.iter().for_each(|value| write_value(out, value));
+    out.push('\n');
+    for subkey in &key.subkeys {
+        let child = if path.is_empty() {
+            subkey.name.clone()
+        } else {
+            format!("{path}\\{}", subkey.name)
+        };
+        dump_into(out, subkey, &child);
+    }
+}
+
+fn write_value(out: &mut String, value: &regf::Value) {
+    let data = RegValue::from_bytes_lossless(value.kind, &value.data);
+    let name = if value.name.is_empty() {
+        "(Predeterminado)"
+    } else {
+        &value.name
+    };
+    let _ = writeln!(out, "    {name}    {}    {data}", data.type_name());
+}
+
+fn parse_value(kind: ValueType, data: &[String]) -> Result<RegValue> {
+    let single = || -> Result<&str> {
+        match data {
+            [] => Ok(""),
+            [one] => Ok(one),
+            _ => bail!("Este tipo admite un solo dato"),
+        }
+    };
+    Ok(match kind {
+        ValueType::Sz => RegValue::Sz(single()?.to_string()),
+        ValueType::ExpandSz => RegValue::ExpandSz(single()?.to_string()),
+        ValueType::Dword => RegValue::Dword(parse_number(single()?)?.try_into()?),
+        ValueType::Qword => RegValue::Qword(parse_number(single()?)?),
+        ValueType::Binary => {
+            let hex: String = data
+                .concat()
+                .chars()
+                .filter(|c| !matches!(c, ',' | ' '))
+                .collect();
+            RegValue::Binary(hex::decode(hex).context("Datos binarios inválidos")?)
+        }
+        ValueType::MultiSz => RegValue::MultiSz(data.to_vec()),
+    })
+}
+
+fn parse_number(text: &str) -> Result<u64> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.with_context(|| format!("Número inválido: {text}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn set_writes_hives_that_get_and_dump_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("HKCU.hiv");
+        let set = |key: &str, name: &str, kind, data: &[&str]| RegCommands::Set {
+            hive: path.clone(),
+            key: key.into(),
+            name: name.into(),
+            data: args(data),
+            kind,
+        };
+        let commands = [
+            set(r"Software\Vendor\App", "", ValueType::Sz, &["App"]),
+            set(
+                r"Software\Vendor\App",
+                "Version",
+                ValueType::Dword,
+                &["0x2a"],
+            ),
+            set(
+                r"Software\Vendor\App",
+                "Blob",
+                ValueType::Binary,
+                &["de,ad", "be ef"],
+            ),
+            set(
+                r"Software\Vendor\App\Paths",
+                "List",
+                ValueType::MultiSz,
+                &["a", "b"],
+            ),
+            set(r"Software\Vendor\App", "version", ValueType::Dword, &["7"]),
+        ];
+        for command in &commands {
+            run(command).await.unwrap();
+        }
+
+        let hive = Hive::read(&path).unwrap();
+        assert_eq!(
+            dump(find(&hive, r"software\vendor").unwrap(), r"Software\Vendor"),
+            "\\Software\\Vendor\n\n\
+             \\Software\\Vendor\\App\n    \
+             (Predeterminado)    REG_SZ    App\n    \
+             version    REG_DWORD    0x7\n    \
+             Blob    REG_BINARY    DEADBEEF\n\n\
+             \\Software\\Vendor\\App\\Paths\n    \
+             List    REG_MULTI_SZ    a\\0b\n\n"
+        );
+        assert!(find(&hive, r"Software\Missing").is_err());
+    }
+
+    #[tokio::test]
+    async fn imports_and_exports_a_container_registry() {
+        let dir = tempfile::tempdir().unwrap();
+        let containers = dir.path().join("containers");
+        std::fs::create_dir_all(containers.join("demo")).unwrap();
+        std::fs::write(
+            containers.join("demo/config.yml"),
+            "id: demo\nname: Demo\nversion: \"1\"\nruntime:\n  build: \"rt@1\"\n",
+        )
+        .unwrap();
+        // Snapshot base del runtime como hive regf.
+        let runtimes = dir.path().join("runtimes");
+        std::fs::create_dir_all(runtimes.join("rt@1/Registry")).unwrap();
+        let mut base = Hive::new();
+        let app = base.root.create_key(r"Software\Vendor\App");
+        app.set_value("Telemetry", 4, 1u32.to_le_bytes().to_vec());
+        app.set_value(
+            "Name",
+            1,
+            "App\0".encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        );
+        base.write(&runtimes.join("rt@1/Registry/HKCU.hiv"))
+            .unwrap();
+
+        let local = || LocalContainers {
+            roots: vec![containers.clone()],
+            runtimes: runtimes.clone(),
+        };
+        let file = dir.path().join("changes.reg");
+        std::fs::write(
+            &file,
+            "REGEDIT4\r\n\r\n[HKEY_CURRENT_USER\\Software\\Vendor\\App]\r\n\"Telemetry\"=-\r\n\"Channel\"=\"beta\"\r\n",
+        )
+        .unwrap();
+        run(&RegCommands::Import {
+            container: "demo".into(),
+            file,
+            local: local(),
+        })
+        .await
+        .unwrap();
+        assert!(containers.join("demo/user/Registry/HKCU.json").exists());
+
+        let export = |changes, output: &str| {
+            let output = dir.path().join(output);
+            let command = RegCommands::Export {
+                container: "demo".into(),
+                output: Some(output.clone()),
+                key: None,
+                changes,
+                regedit4: true,
+                local: local(),
+            };
+            (command, output)
+        };
+        let (full, full_path) = export(false, "full.reg");
+        let (changes, changes_path) = export(true, "changes-out.reg");
+        run(&full).await.unwrap();
+        run(&changes).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(full_path).unwrap(),
+            "REGEDIT4\r\n\r\n\
+             [HKEY_CURRENT_USER\\Software]\r\n\r\n\
+             [HKEY_CURRENT_USER\\Software\\Vendor]\r\n\r\n\
+             [HKEY_CURRENT_USER\\Software\\Vendor\\App]\r\n\
+             \"Channel\"=\"beta\"\r\n\
+             \"Name\"=\"App\"\r\n\r\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(changes_path).unwrap(),
+            "REGEDIT4\r\n\r\n\
+             [HKEY_CURRENT_USER\\Software\\Vendor\\App]\r\n\
+             \"Telemetry\"=-\r\n\
+             \"Channel\"=\"beta\"\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn value_data_is_parsed_per_type() {
+        assert_eq!(
+            parse_value(ValueType::Qword, &args(&["18446744073709551615"])).unwrap(),
+            RegValue::Qword(u64::MAX)
+        );
+        assert_eq!(
+            parse_value(ValueType::ExpandSz, &[]).unwrap(),
+            RegValue::ExpandSz(String::new())
+        );
+        assert!(parse_value(ValueType::Dword, &args(&["0x100000000"])).is_err());
+        assert!(parse_value(ValueType::Dword, &args(&["diez"])).is_err());
+        assert!(parse_value(ValueType::Sz, &args(&["a", "b"])).is_err());
+        assert!(parse_value(ValueType::Binary, &args(&["zz"])).is_err());
+    }
+}
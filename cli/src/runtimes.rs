@@ -0,0 +1,133 @@
+use agent::{
+    registry::ContainerRegistry,
+    runtimes::{RuntimeRef, RuntimeStore},
+};
+use anyhow::Result;
+use clap::Subcommand;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+#[derive(Subcommand)]
+pub enum RuntimeCommands {
+    /// Lista los runtimes instalados y los contenedores que los usan
+    List,
+    /// Instala un runtime desde una carpeta desempaquetada
+    Install {
+        /// Referencia `nombre@versión`
+        reference: RuntimeRef,
+        /// Carpeta con el contenido del runtime
+        source: PathBuf,
+    },
+    /// Elimina los runtimes que ningún contenedor referencia
+    Gc {
+        /// Solo muestra qué se eliminaría
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub async fn run(command: &RuntimeCommands, store: &Path, containers: &[PathBuf]) -> Result<()> {
+    let store = RuntimeStore::new(store);
+    match command {
+        RuntimeCommands::List => {
+            let references = referenced_by(containers).await?;
+            let installed = store.list()?;
+            if installed.is_empty() {
+                println!("No hay runtimes instalados en {}.", store.root().display());
+            }
+            for runtime in installed {
+                let users = references
+                    .get(&runtime.reference)
+                    .map(|ids| ids.join(", "))
+                    .unwrap_or_else(|| "sin referencias".into());
+                println!("- {} ({})", runtime.reference, users);
+            }
+        }
+        RuntimeCommands::Install { reference, source } => {
+            let runtime = store.install(reference, source)?;
+            println!(
+                "Runtime {} instalado en {}",
+                runtime.reference,
+                runtime.root.display()
+            );
+        }
+        RuntimeCommands::Gc { dry_run } => {
+            let referenced: HashSet<RuntimeRef> =
+                referenced_by(containers).await?.into_keys().collect();
+            let removed = store.gc(&referenced, *dry_run)?;
+            if removed.is_empty() {
+                println!("No hay runtimes sin referencias.");
+            }
+            for runtime in removed {
+                if *dry_run {
+                    println!("Se eliminaría {}", runtime.reference);
+                } else {
+                    println!("Eliminado {}", runtime.reference);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Agrupa los contenedores locales por el runtime declarado en `runtime.build`.
+async fn referenced_by(containers: &[PathBuf]) -> Result<BTreeMap<RuntimeRef, Vec<String>>> {
+    let registry = ContainerRegistry::load_roots(containers).await?;
+    let mut references: BTreeMap<RuntimeRef, Vec<String>> = BTreeMap::new();
+    for container in registry.list() {
+        let Some(build) = container.manifest.runtime.build.as_deref() else {
+            continue;
+        };
+        if let Ok(reference) = build.parse() {
+            references
+                .entry(reference)
+                .or_default()
+                .push(container.manifest.id.clone());
+        }
+    }
+    for ids in references.values_mut() {
+        ids.sort();
+    }
+    Ok(references)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[tokio::test]
+    async fn gc_keeps_runtimes_referenced_by_manifests() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let containers = dir.path().join("containers");
+        fs::create_dir_all(containers.join("demo"))?;
+        fs::write(
+            containers.join("demo/config.yml"),
+            "id: demo\nname: Demo\nversion: \"1\"\nruntime:\n  build: \"rt@1\"\n",
+        )?;
+
+        let source = dir.path().join("source");
+        fs::create_dir_all(&source)?;
+        let store_dir = dir.path().join("runtimes");
+        for reference in ["rt@1", "rt@2"] {
+            let command = RuntimeCommands::Install {
+                reference: reference.parse()?,
+                source: source.clone(),
+            };
+            run(&command, &store_dir, std::slice::from_ref(&containers)).await?;
+        }
+
+        run(
+            &RuntimeCommands::Gc { dry_run: false },
+            &store_dir,
+            &[containers],
+        )
+        .await?;
+        let remaining = RuntimeStore::new(&store_dir).list()?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].reference.to_string(), "rt@1");
+        Ok(())
+    }
+}
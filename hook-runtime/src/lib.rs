@@ -0,0 +1,60 @@
+//! Runtime de hooks (`ctnr_hooks.dll`) que el agent inyecta en cada proceso de
+//! un contenedor.
+//!
+//! El launcher crea el proceso suspendido, carga esta biblioteca y llama a
+//! [`ctnr_hook_init`] desde un hilo remoto antes de reanudarlo (ver
+//! `agent::hooks::wire`). Los hooks quedan activos hasta que el proceso termina.
+
+use agent::hooks::{apply_from_env, wire, NativeHookPipeline};
+use std::{ffi::c_void, sync::OnceLock};
+
+static RESULT: OnceLock<u32> = OnceLock::new();
+
+/// Lee el plan de [`wire::PLAN_ENV`] y activa los hooks con él. Devuelve
+/// [`wire::INIT_OK`], [`wire::INIT_NO_PLAN`] o [`wire::INIT_FAILED`]; el error
+/// se escribe en stderr, que el agent guarda en el log del contenedor. Las
+/// llamadas repetidas devuelven el resultado de la primera.
+#[no_mangle]
+pub extern "system" fn ctnr_hook_init(_parameter: *mut c_void) -> u32 {
+    *RESULT.get_or_init(|| {
+        // El pipeline vive tanto como el proceso: sus detours no se retiran.
+        let pipeline = Box::leak(Box::new(NativeHookPipeline::new()));
+        match apply_from_env(pipeline) {
+            Ok(true) => wire::INIT_OK,
+            Ok(false) => wire::INIT_NO_PLAN,
+            Err(err) => {
+                eprintln!("ctnr: {err:#}");
+                wire::INIT_FAILED
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use agent::{
+        overlay::{Layer, LayerKind, LayerStack},
+        runtime::HookPlan,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn applies_the_plan_named_in_the_environment_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("demo.bin");
+        let plan = HookPlan {
+            env: HashMap::new(),
+            mounts: vec![],
+            redirects: vec![],
+            overlay: LayerStack::new(Layer::new("demo", LayerKind::Container, dir.path())),
+            registry: None,
+        };
+        wire::write_plan(&path, &plan).unwrap();
+        std::env::set_var(wire::PLAN_ENV, &path);
+
+        assert_eq!(ctnr_hook_init(std::ptr::null_mut()), wire::INIT_OK);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ctnr_hook_init(std::ptr::null_mut()), wire::INIT_OK);
+    }
+}
@@ -0,0 +1,181 @@
+//! Lectura y escritura de hives del registro de Windows (formato `regf`) sin
+//! depender de Windows, para preparar e inspeccionar `user/Registry/*.hiv` desde
+//! Linux o a partir de instalaciones capturadas.
+//!
+//! Se soportan el base block con su checksum, los hbins y las celdas `nk`, `vk`,
+//! `sk`, las listas de subclaves `lf`/`lh`/`li`/`ri` y los datos grandes `db`.
+//! El writer genera hives 1.5 con listas `lh` (agrupadas en `ri` si son largas)
+//! y un único descriptor de seguridad compartido por todas las claves.
+
+mod reader;
+mod writer;
+
+use anyhow::{Context, Result};
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Tamaño del base block y unidad de los hbins.
+pub const BLOCK_SIZE: usize = 4096;
+/// Datos de un valor por encima de este tamaño van en una celda `db`.
+pub const BIG_DATA_SEGMENT: usize = 16344;
+
+/// Valor de una clave: nombre (`""` es el valor por defecto), tipo `REG_*` y
+/// datos en crudo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub name: String,
+    pub kind: u32,
+    pub data: Vec<u8>,
+}
+
+/// Clave con sus valores y subclaves. Los nombres se comparan sin distinguir
+/// mayúsculas, como en Windows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Key {
+    pub name: String,
+    /// Última escritura (FILETIME: intervalos de 100 ns desde 1601).
+    pub last_written: u64,
+    pub values: Vec<Value>,
+    pub subkeys: Vec<Key>,
+}
+
+impl Key {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            last_written: filetime_now(),
+            ..Default::default()
+        }
+    }
+
+    pub fn subkey(&self, name: &str) -> Option<&Key> {
+        self.subkeys.iter().find(|key| same_name(&key.name, name))
+    }
+
+    pub fn subkey_mut(&mut self, name: &str) -> Option<&mut Key> {
+        self.subkeys
+            .iter_mut()
+            .find(|key| same_name(&key.name, name))
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .find(|value| same_name(&value.name, name))
+    }
+
+    /// Clave en `path` (`Software\Vendor`), relativa a esta.
+    pub fn key(&self, path: &str) -> Option<&Key> {
+        components(path).try_fold(self, |key, name| key.subkey(name))
+    }
+
+    /// Crea las claves que falten hasta `path`.
+    pub fn create_key(&mut self, path: &str) -> &mut Key {
+        components(path).fold(self, |key, name| {
+            match key.subkeys.iter().position(|k| same_name(&k.name, name)) {
+                Some(index) => &mut key.subkeys[index],
+                None => {
+                    key.subkeys.push(Key::new(name));
+                    key.subkeys.last_mut().expect("recién insertada")
+                }
+            }
+        })
+    }
+
+    /// Crea o reemplaza un valor y actualiza la fecha de la clave.
+    pub fn set_value(&mut self, name: &str, kind: u32, data: Vec<u8>) {
+        let value = Value {
+            name: name.to_string(),
+            kind,
+            data,
+        };
+        match self.values.iter_mut().find(|v| same_name(&v.name, name)) {
+            Some(existing) => *existing = value,
+            None => self.values.push(value),
+        }
+        self.last_written = filetime_now();
+    }
+
+    pub fn remove_value(&mut self, name: &str) -> Option<Value> {
+        let index = self.values.iter().position(|v| same_name(&v.name, name))?;
+        self.last_written = filetime_now();
+        Some(self.values.remove(index))
+    }
+
+    pub fn remove_subkey(&mut self, name: &str) -> Option<Key> {
+        let index = self.subkeys.iter().position(|k| same_name(&k.name, name))?;
+        self.last_written = filetime_now();
+        Some(self.subkeys.remove(index))
+    }
+}
+
+/// Hive completo: la clave raíz y el nombre guardado en el base block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hive {
+    pub root: Key,
+    /// Ruta del hive (informativo); se guardan sus últimos 31 caracteres.
+    pub file_name: String,
+}
+
+impl Default for Hive {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hive {
+    pub fn new() -> Self {
+        Self {
+            root: Key::new("ROOT"),
+            file_name: String::new(),
+        }
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        reader::parse(data)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        writer::write(self)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read(path).with_context(|| format!("No se pudo leer {}", path.display()))?;
+        Self::from_bytes(&data).with_context(|| format!("Hive inválido en {}", path.display()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_bytes())
+            .with_context(|| format!("No se pudo guardar {}", path.display()))
+    }
+}
+
+/// Hora actual como FILETIME.
+pub fn filetime_now() -> u64 {
+    const UNIX_TO_FILETIME: u64 = 116_444_736_000_000_000;
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    UNIX_TO_FILETIME + (since_unix.as_nanos() / 100) as u64
+}
+
+/// Mayúscula de un carácter como la aplica Windows a los nombres: un carácter
+/// por otro (sin expandir `ß` a `SS`).
+fn upcase(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(single), None) => single,
+        _ => c,
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.chars().map(upcase).eq(b.chars().map(upcase))
+}
+
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('\\').filter(|name| !name.is_empty())
+}
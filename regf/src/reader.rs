@@ -0,0 +1,293 @@
+use crate::{Hive, Key, Value, BIG_DATA_SEGMENT, BLOCK_SIZE};
+use anyhow::{bail, ensure, Context, Result};
+
+/// Límite de anidamiento para no recorrer ciclos de un hive corrupto.
+const MAX_DEPTH: usize = 512;
+
+const NK_FIXED: usize = 0x4c;
+const VK_FIXED: usize = 0x14;
+const KEY_COMP_NAME: u16 = 0x20;
+const VALUE_COMP_NAME: u16 = 0x01;
+const DATA_INLINE: u32 = 0x8000_0000;
+const NO_CELL: u32 = u32::MAX;
+
+pub(crate) fn parse(data: &[u8]) -> Result<Hive> {
+    ensure!(
+        data.len() >= BLOCK_SIZE,
+        "Hive truncado: falta el base block"
+    );
+    let base = &data[..BLOCK_SIZE];
+    ensure!(&base[..4] == b"regf", "Firma regf ausente");
+    let stored = u32_at(base, 0x1fc)?;
+    let computed = checksum(base);
+    ensure!(
+        stored == computed,
+        "Checksum del base block incorrecto ({stored:#x}, se esperaba {computed:#x})"
+    );
+    ensure!(
+        u32_at(base, 0x04)? == u32_at(base, 0x08)?,
+        "El hive no se cerró limpiamente (secuencias distintas); hay que aplicar sus logs"
+    );
+    let (major, minor) = (u32_at(base, 0x14)?, u32_at(base, 0x18)?);
+    ensure!(
+        major == 1 && (3..=6).contains(&minor),
+        "Versión de hive no soportada: {major}.{minor}"
+    );
+    ensure!(
+        u32_at(base, 0x1c)? == 0,
+        "No es un hive primario (¿log de transacciones?)"
+    );
+
+    let bins_size = u32_at(base, 0x28)? as usize;
+    let bins = data
+        .get(BLOCK_SIZE..BLOCK_SIZE + bins_size)
+        .context("Hive truncado: faltan hbins")?;
+    check_bins(bins)?;
+
+    let reader = Reader { bins, minor };
+    let root = reader.key(u32_at(base, 0x24)?, 0)?;
+    Ok(Hive {
+        root,
+        file_name: utf16_until_nul(&base[0x30..0x70]),
+    })
+}
+
+/// XOR de las primeras 127 palabras del base block, con 0 y -1 reservados.
+pub(crate) fn checksum(base: &[u8]) -> u32 {
+    let sum = base[..0x1fc]
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().expect("4 bytes")))
+        .fold(0, |acc, word| acc ^ word);
+    match sum {
+        u32::MAX => u32::MAX - 1,
+        0 => 1,
+        sum => sum,
+    }
+}
+
+fn check_bins(bins: &[u8]) -> Result<()> {
+    ensure!(!bins.is_empty(), "Hive sin hbins");
+    let mut pos = 0;
+    while pos < bins.len() {
+        let header = bins.get(pos..pos + 32).context("hbin truncado")?;
+        ensure!(&header[..4] == b"hbin", "Firma hbin ausente en {pos:#x}");
+        ensure!(
+            u32_at(header, 4)? as usize == pos,
+            "hbin en {pos:#x} con offset incorrecto"
+        );
+        let size = u32_at(header, 8)? as usize;
+        ensure!(
+            size >= BLOCK_SIZE && size.is_multiple_of(BLOCK_SIZE) && pos + size <= bins.len(),
+            "hbin en {pos:#x} con tamaño inválido ({size:#x})"
+        );
+        pos += size;
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    /// Datos a partir del primer hbin; los offsets de celda son relativos a él.
+    bins: &'a [u8],
+    minor: u32,
+}
+
+impl<'a> Reader<'a> {
+    /// Contenido de la celda ocupada en `offset` (sin la cabecera de tamaño).
+    fn cell(&self, offset: u32) -> Result<&'a [u8]> {
+        let start = offset as usize;
+        let size = i32::from_le_bytes(
+            self.bins
+                .get(start..start + 4)
+                .with_context(|| format!("Celda fuera del hive en {offset:#x}"))?
+                .try_into()
+                .expect("4 bytes"),
+        );
+        ensure!(size < 0, "La celda {offset:#x} está libre");
+        let size = size.unsigned_abs() as usize;
+        ensure!(size >= 4, "Celda {offset:#x} con tamaño inválido");
+        self.bins
+            .get(start + 4..start + size)
+            .with_context(|| format!("Celda {offset:#x} fuera del hive"))
+    }
+
+    fn key(&self, offset: u32, depth: usize) -> Result<Key> {
+        ensure!(depth <= MAX_DEPTH, "Claves anidadas demasiado profundas");
+        let cell = self.cell(offset)?;
+        ensure!(
+            cell.len() >= NK_FIXED && &cell[..2] == b"nk",
+            "Se esperaba una celda nk en {offset:#x}"
+        );
+        let flags = u16_at(cell, 0x02)?;
+        let subkey_count = u32_at(cell, 0x14)? as usize;
+        let value_count = u32_at(cell, 0x24)? as usize;
+        let name_len = u16_at(cell, 0x48)? as usize;
+        let name = cell
+            .get(NK_FIXED..NK_FIXED + name_len)
+            .with_context(|| format!("Nombre de clave truncado en {offset:#x}"))?;
+        let name = decode_name(name, flags & KEY_COMP_NAME != 0);
+
+        // Los contadores vienen del hive: se comparan con sus listas antes de
+        // reservar memoria para ellos.
+        let mut values = Vec::new();
+        if value_count > 0 {
+            let list = self.cell(u32_at(cell, 0x28)?)?;
+            ensure!(
+                value_count <= list.len() / 4,
+                "{name}: declara {value_count} valores y su lista solo tiene sitio para {}",
+                list.len() / 4
+            );
+            values.reserve_exact(value_count);
+            for index in 0..value_count {
+                let value = self
+                    .value(u32_at(list, index * 4)?)
+                    .with_context(|| format!("Valor {index} de {name}"))?;
+                values.push(value);
+            }
+        }
+
+        let mut subkeys = Vec::new();
+        if subkey_count > 0 {
+            let mut offsets = Vec::new();
+            self.subkey_offsets(u32_at(cell, 0x1c)?, &mut offsets, true)?;
+            ensure!(
+                offsets.len() == subkey_count,
+                "{name}: la lista tiene {} subclaves y la clave declara {subkey_count}",
+                offsets.len()
+            );
+            subkeys.reserve_exact(subkey_count);
+            for child in offsets {
+                subkeys.push(self.key(child, depth + 1)?);
+            }
+        }
+
+        Ok(Key {
+            name,
+            last_written: u64_at(cell, 0x04)?,
+            values,
+            subkeys,
+        })
+    }
+
+    /// Offsets de las `nk` de una lista `lf`/`lh`/`li`, o de las listas de un `ri`.
+    fn subkey_offsets(&self, offset: u32, out: &mut Vec<u32>, allow_index: bool) -> Result<()> {
+        ensure!(offset != NO_CELL, "Falta la lista de subclaves");
+        let cell = self.cell(offset)?;
+        ensure!(
+            cell.len() >= 4,
+            "Lista de subclaves truncada en {offset:#x}"
+        );
+        let count = u16_at(cell, 2)? as usize;
+        match &cell[..2] {
+            b"lf" | b"lh" => {
+                for index in 0..count {
+                    out.push(u32_at(cell, 4 + index * 8)?);
+                }
+            }
+            b"li" => {
+                for index in 0..count {
+                    out.push(u32_at(cell, 4 + index * 4)?);
+                }
+            }
+            b"ri" if allow_index => {
+                for index in 0..count {
+                    self.subkey_offsets(u32_at(cell, 4 + index * 4)?, out, false)?;
+                }
+            }
+            other => bail!(
+                "Lista de subclaves desconocida en {offset:#x}: {}",
+                String::from_utf8_lossy(other)
+            ),
+        }
+        Ok(())
+    }
+
+    fn value(&self, offset: u32) -> Result<Value> {
+        let cell = self.cell(offset)?;
+        ensure!(
+            cell.len() >= VK_FIXED && &cell[..2] == b"vk",
+            "Se esperaba una celda vk en {offset:#x}"
+        );
+        let name_len = u16_at(cell, 0x02)? as usize;
+        let size = u32_at(cell, 0x04)?;
+        let data_offset = u32_at(cell, 0x08)?;
+        let kind = u32_at(cell, 0x0c)?;
+        let flags = u16_at(cell, 0x10)?;
+        let name = cell
+            .get(VK_FIXED..VK_FIXED + name_len)
+            .with_context(|| format!("Nombre de valor truncado en {offset:#x}"))?;
+        let name = decode_name(name, flags & VALUE_COMP_NAME != 0);
+
+        let len = (size & !DATA_INLINE) as usize;
+        let data = if size & DATA_INLINE != 0 {
+            ensure!(len <= 4, "{name}: dato en línea de {len} bytes");
+            data_offset.to_le_bytes()[..len].to_vec()
+        } else if len == 0 {
+            Vec::new()
+        } else {
+            let cell = self.cell(data_offset)?;
+            if len > BIG_DATA_SEGMENT && self.minor >= 4 && cell.starts_with(b"db") {
+                self.big_data(cell, len)?
+            } else {
+                cell.get(..len)
+                    .with_context(|| format!("{name}: datos truncados"))?
+                    .to_vec()
+            }
+        };
+        Ok(Value { name, kind, data })
+    }
+
+    fn big_data(&self, cell: &[u8], len: usize) -> Result<Vec<u8>> {
+        let segments = u16_at(cell, 2)? as usize;
+        let list = self.cell(u32_at(cell, 4)?)?;
+        // Ningún dato puede ocupar más que los hbins que lo contienen.
+        ensure!(
+            len <= self.bins.len(),
+            "Datos db de {len} bytes en un hive de {}",
+            self.bins.len()
+        );
+        let mut data = Vec::with_capacity(len);
+        for index in 0..segments {
+            let segment = self.cell(u32_at(list, index * 4)?)?;
+            let take = (len - data.len()).min(BIG_DATA_SEGMENT).min(segment.len());
+            data.extend_from_slice(&segment[..take]);
+        }
+        ensure!(data.len() == len, "Datos db incompletos");
+        Ok(data)
+    }
+}
+
+fn decode_name(raw: &[u8], compressed: bool) -> String {
+    if compressed {
+        raw.iter().map(|byte| char::from(*byte)).collect()
+    } else {
+        let units: Vec<u16> = raw
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+fn utf16_until_nul(raw: &[u8]) -> String {
+    let units: Vec<u16> = raw
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+    let bytes = data.get(at..at + 2).context("Estructura truncada")?;
+    Ok(u16::from_le_bytes(bytes.try_into().expect("2 bytes")))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+    let bytes = data.get(at..at + 4).context("Estructura truncada")?;
+    Ok(u32::from_le_bytes(bytes.try_into().expect("4 bytes")))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64> {
+    let bytes = data.get(at..at + 8).context("Estructura truncada")?;
+    Ok(u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+}
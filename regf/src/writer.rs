@@ -0,0 +1,316 @@
+use crate::{reader::checksum, upcase, Hive, Key, Value, BIG_DATA_SEGMENT, BLOCK_SIZE};
+
+const HBIN_HEADER: usize = 32;
+const NK_FIXED: usize = 0x4c;
+const VK_FIXED: usize = 0x14;
+const KEY_HIVE_ENTRY: u16 = 0x04;
+const KEY_NO_DELETE: u16 = 0x08;
+const KEY_COMP_NAME: u16 = 0x20;
+const VALUE_COMP_NAME: u16 = 0x01;
+const DATA_INLINE: u32 = 0x8000_0000;
+const NO_CELL: u32 = u32::MAX;
+/// Entradas por lista `lh`; más subclaves se reparten en varias bajo un `ri`.
+const LH_MAX: usize = 511;
+
+/// Descriptor de seguridad autorrelativo compartido por todas las claves:
+/// propietario Administradores, grupo SYSTEM y control total para Everyone
+/// heredable por las subclaves.
+#[rustfmt::skip]
+const SECURITY_DESCRIPTOR: [u8; 76] = [
+    // Cabecera: revisión 1, SE_SELF_RELATIVE | SE_DACL_PRESENT y offsets de
+    // propietario, grupo, SACL (ninguna) y DACL.
+    0x01, 0x00, 0x04, 0x80, 0x14, 0x00, 0x00, 0x00, 0x24, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00,
+    // Propietario: S-1-5-32-544.
+    0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x20, 0x00, 0x00, 0x00,
+    0x20, 0x02, 0x00, 0x00,
+    // Grupo: S-1-5-18.
+    0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x12, 0x00, 0x00, 0x00,
+    // DACL: revisión 2, 28 bytes, una ACE.
+    0x02, 0x00, 0x1c, 0x00, 0x01, 0x00, 0x00, 0x00,
+    // ACCESS_ALLOWED, OI|CI, KEY_ALL_ACCESS para S-1-1-0.
+    0x00, 0x03, 0x14, 0x00, 0x3f, 0x00, 0x0f, 0x00,
+    0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+];
+
+pub(crate) fn write(hive: &Hive) -> Vec<u8> {
+    let mut bins = Bins::new();
+    let security = bins.alloc(20 + SECURITY_DESCRIPTOR.len());
+    let mut writer = Writer {
+        bins,
+        security,
+        references: 0,
+    };
+    let root = writer.key(&hive.root, 0, KEY_HIVE_ENTRY | KEY_NO_DELETE);
+
+    let mut sk = Vec::with_capacity(20 + SECURITY_DESCRIPTOR.len());
+    sk.extend_from_slice(b"sk\0\0");
+    sk.extend_from_slice(&security.to_le_bytes());
+    sk.extend_from_slice(&security.to_le_bytes());
+    sk.extend_from_slice(&writer.references.to_le_bytes());
+    sk.extend_from_slice(&(SECURITY_DESCRIPTOR.len() as u32).to_le_bytes());
+    sk.extend_from_slice(&SECURITY_DESCRIPTOR);
+    writer.bins.fill(security, &sk);
+    let bins = writer.bins.finish();
+
+    let mut base = vec![0u8; BLOCK_SIZE];
+    base[..4].copy_from_slice(b"regf");
+    put_u32(&mut base, 0x04, 1);
+    put_u32(&mut base, 0x08, 1);
+    base[0x0c..0x14].copy_from_slice(&hive.root.last_written.to_le_bytes());
+    put_u32(&mut base, 0x14, 1);
+    put_u32(&mut base, 0x18, 5);
+    put_u32(&mut base, 0x1c, 0);
+    put_u32(&mut base, 0x20, 1);
+    put_u32(&mut base, 0x24, root);
+    put_u32(&mut base, 0x28, bins.len() as u32);
+    put_u32(&mut base, 0x2c, 1);
+    let file_name: Vec<u16> = hive.file_name.encode_utf16().collect();
+    let file_name = &file_name[file_name.len().saturating_sub(31)..];
+    for (index, unit) in file_name.iter().enumerate() {
+        base[0x30 + index * 2..0x32 + index * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    let sum = checksum(&base);
+    put_u32(&mut base, 0x1fc, sum);
+
+    base.extend_from_slice(&bins);
+    base
+}
+
+struct Writer {
+    bins: Bins,
+    security: u32,
+    references: u32,
+}
+
+impl Writer {
+    fn key(&mut self, key: &Key, parent: u32, flags: u16) -> u32 {
+        let (name, compressed) = encode_name(&key.name);
+        let offset = self.bins.alloc(NK_FIXED + name.len());
+        self.references += 1;
+
+        let values: Vec<u32> = key.values.iter().map(|value| self.value(value)).collect();
+        let values_list = if values.is_empty() {
+            NO_CELL
+        } else {
+            self.bins.put(&offsets_bytes(&values))
+        };
+
+        let mut subkeys: Vec<&Key> = key.subkeys.iter().collect();
+        subkeys.sort_by_cached_key(|key| sort_name(&key.name));
+        let children: Vec<(u32, u32)> = subkeys
+            .iter()
+            .map(|child| (self.key(child, offset, 0), name_hash(&child.name)))
+            .collect();
+        let subkeys_list = self.subkey_list(&children);
+
+        let mut nk = vec![0u8; NK_FIXED];
+        nk[..2].copy_from_slice(b"nk");
+        let flags = flags | if compressed { KEY_COMP_NAME } else { 0 };
+        nk[0x02..0x04].copy_from_slice(&flags.to_le_bytes());
+        nk[0x04..0x0c].copy_from_slice(&key.last_written.to_le_bytes());
+        put_u32(&mut nk, 0x10, parent);
+        put_u32(&mut nk, 0x14, children.len() as u32);
+        put_u32(&mut nk, 0x1c, subkeys_list);
+        put_u32(&mut nk, 0x20, NO_CELL);
+        put_u32(&mut nk, 0x24, values.len() as u32);
+        put_u32(&mut nk, 0x28, values_list);
+        put_u32(&mut nk, 0x2c, self.security);
+        put_u32(&mut nk, 0x30, NO_CELL);
+        let longest = |names: &mut dyn Iterator<Item = &str>| {
+            names
+                .map(|name| name.encode_utf16().count() as u32 * 2)
+                .max()
+                .unwrap_or(0)
+        };
+        put_u32(
+            &mut nk,
+            0x34,
+            longest(&mut key.subkeys.iter().map(|k| k.name.as_str())),
+        );
+        put_u32(
+            &mut nk,
+            0x3c,
+            longest(&mut key.values.iter().map(|v| v.name.as_str())),
+        );
+        let largest_data = key.values.iter().map(|v| v.data.len()).max().unwrap_or(0);
+        put_u32(&mut nk, 0x40, largest_data as u32);
+        nk[0x48..0x4a].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        nk.extend_from_slice(&name);
+        self.bins.fill(offset, &nk);
+        offset
+    }
+
+    fn subkey_list(&mut self, children: &[(u32, u32)]) -> u32 {
+        if children.is_empty() {
+            return NO_CELL;
+        }
+        let lists: Vec<u32> = children
+            .chunks(LH_MAX)
+            .map(|chunk| {
+                let mut lh = Vec::with_capacity(4 + chunk.len() * 8);
+                lh.extend_from_slice(b"lh");
+                lh.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+                for (offset, hash) in chunk {
+                    lh.extend_from_slice(&offset.to_le_bytes());
+                    lh.extend_from_slice(&hash.to_le_bytes());
+                }
+                self.bins.put(&lh)
+            })
+            .collect();
+        if let [single] = lists[..] {
+            return single;
+        }
+        let mut ri = Vec::with_capacity(4 + lists.len() * 4);
+        ri.extend_from_slice(b"ri");
+        ri.extend_from_slice(&(lists.len() as u16).to_le_bytes());
+        ri.extend_from_slice(&offsets_bytes(&lists));
+        self.bins.put(&ri)
+    }
+
+    fn value(&mut self, value: &Value) -> u32 {
+        let (name, compressed) = encode_name(&value.name);
+        let len = value.data.len();
+        let (size, data) = if len <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..len].copy_from_slice(&value.data);
+            (len as u32 | DATA_INLINE, u32::from_le_bytes(inline))
+        } else if len > BIG_DATA_SEGMENT {
+            (len as u32, self.big_data(&value.data))
+        } else {
+            (len as u32, self.bins.put(&value.data))
+        };
+
+        let mut vk = vec![0u8; VK_FIXED];
+        vk[..2].copy_from_slice(b"vk");
+        vk[0x02..0x04].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        put_u32(&mut vk, 0x04, size);
+        put_u32(&mut vk, 0x08, data);
+        put_u32(&mut vk, 0x0c, value.kind);
+        let flags = if compressed { VALUE_COMP_NAME } else { 0 };
+        vk[0x10..0x12].copy_from_slice(&flags.to_le_bytes());
+        vk.extend_from_slice(&name);
+        self.bins.put(&vk)
+    }
+
+    fn big_data(&mut self, data: &[u8]) -> u32 {
+        let segments: Vec<u32> = data
+            .chunks(BIG_DATA_SEGMENT)
+            .map(|segment| self.bins.put(segment))
+            .collect();
+        let list = self.bins.put(&offsets_bytes(&segments));
+        let mut db = Vec::with_capacity(8);
+        db.extend_from_slice(b"db");
+        db.extend_from_slice(&(segments.len() as u16).to_le_bytes());
+        db.extend_from_slice(&list.to_le_bytes());
+        self.bins.put(&db)
+    }
+}
+
+/// Área de hbins. Las celdas se reservan en orden; la que no cabe en el hbin
+/// actual abre uno nuevo, y el hueco que queda se marca como celda libre.
+struct Bins {
+    data: Vec<u8>,
+    bin_start: usize,
+    cursor: usize,
+}
+
+impl Bins {
+    fn new() -> Self {
+        let mut bins = Self {
+            data: Vec::new(),
+            bin_start: 0,
+            cursor: 0,
+        };
+        bins.open_bin(BLOCK_SIZE);
+        bins
+    }
+
+    fn open_bin(&mut self, size: usize) {
+        self.bin_start = self.data.len();
+        self.data.resize(self.bin_start + size, 0);
+        let header = &mut self.data[self.bin_start..self.bin_start + HBIN_HEADER];
+        header[..4].copy_from_slice(b"hbin");
+        put_u32(header, 4, self.bin_start as u32);
+        put_u32(header, 8, size as u32);
+        self.cursor = self.bin_start + HBIN_HEADER;
+    }
+
+    /// Marca como libre lo que queda del hbin actual.
+    fn close_bin(&mut self) {
+        let free = self.data.len() - self.cursor;
+        if free > 0 {
+            put_u32(&mut self.data, self.cursor, free as u32);
+        }
+        self.cursor = self.data.len();
+    }
+
+    /// Reserva una celda para `len` bytes de contenido; devuelve su offset.
+    fn alloc(&mut self, len: usize) -> u32 {
+        let size = (len + 4).next_multiple_of(8);
+        if self.cursor + size > self.data.len() {
+            self.close_bin();
+            self.open_bin((size + HBIN_HEADER).next_multiple_of(BLOCK_SIZE));
+        }
+        let offset = self.cursor;
+        self.data[offset..offset + 4].copy_from_slice(&(-(size as i32)).to_le_bytes());
+        self.cursor += size;
+        offset as u32
+    }
+
+    fn fill(&mut self, offset: u32, content: &[u8]) {
+        let start = offset as usize + 4;
+        self.data[start..start + content.len()].copy_from_slice(content);
+    }
+
+    fn put(&mut self, content: &[u8]) -> u32 {
+        let offset = self.alloc(content.len());
+        self.fill(offset, content);
+        offset
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.close_bin();
+        self.data
+    }
+}
+
+/// Nombre en Latin-1 si todos sus caracteres caben (nombre "comprimido"), si no
+/// en UTF-16LE.
+fn encode_name(name: &str) -> (Vec<u8>, bool) {
+    if name.chars().all(|c| (c as u32) < 0x100) {
+        (name.chars().map(|c| c as u8).collect(), true)
+    } else {
+        (
+            name.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            false,
+        )
+    }
+}
+
+/// Orden de las listas de subclaves: UTF-16 en mayúsculas.
+fn sort_name(name: &str) -> Vec<u16> {
+    name.chars()
+        .map(upcase)
+        .collect::<String>()
+        .encode_utf16()
+        .collect()
+}
+
+/// Hash de las entradas `lh`: `hash * 37 + carácter` sobre el nombre en mayúsculas.
+fn name_hash(name: &str) -> u32 {
+    sort_name(name).into_iter().fold(0u32, |hash, unit| {
+        hash.wrapping_mul(37).wrapping_add(unit as u32)
+    })
+}
+
+fn offsets_bytes(offsets: &[u32]) -> Vec<u8> {
+    offsets
+        .iter()
+        .flat_map(|offset| offset.to_le_bytes())
+        .collect()
+}
+
+fn put_u32(data: &mut [u8], at: usize, value: u32) {
+    data[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
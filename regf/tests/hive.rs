@@ -0,0 +1,195 @@
+use regf::{Hive, Key, Value, BIG_DATA_SEGMENT, BLOCK_SIZE};
+
+const REG_SZ: u32 = 1;
+const REG_BINARY: u32 = 3;
+const REG_DWORD: u32 = 4;
+
+fn utf16z(text: &str) -> Vec<u8> {
+    text.encode_utf16()
+        .chain([0])
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+fn fixture() -> Hive {
+    let mut hive = Hive::new();
+    hive.file_name = r"\??\C:\ctnr\user\Registry\HKCU.hiv".into();
+    let app = hive.root.create_key(r"Software\Vendor\App");
+    app.set_value("", REG_SZ, utf16z("App"));
+    app.set_value("Version", REG_DWORD, 2u32.to_le_bytes().to_vec());
+    app.set_value("Empty", REG_BINARY, Vec::new());
+    app.set_value("Path", REG_SZ, utf16z(r"C:\Program Files\App"));
+    app.set_value("Ünïcødé ✓", REG_SZ, utf16z("valor"));
+    hive.root.create_key(r"Software\Vendor\Ärger ✓");
+    hive.root.create_key(r"Software\aaa");
+    hive.root.create_key(r"Software\Zeta");
+    hive.root.create_key(r"Environment");
+    hive
+}
+
+#[test]
+fn written_hives_read_back_identically() {
+    let hive = fixture();
+    let bytes = hive.to_bytes();
+    assert_eq!(&bytes[..4], b"regf");
+    assert_eq!(bytes.len() % BLOCK_SIZE, 0);
+    assert_eq!(&bytes[BLOCK_SIZE..BLOCK_SIZE + 4], b"hbin");
+
+    let read = Hive::from_bytes(&bytes).unwrap();
+    // Solo se guardan los últimos 31 caracteres del nombre.
+    assert_eq!(read.file_name, r"\C:\ctnr\user\Registry\HKCU.hiv");
+    let app = read.root.key(r"software\VENDOR\app").unwrap();
+    assert_eq!(
+        app.value("version"),
+        Some(&Value {
+            name: "Version".into(),
+            kind: REG_DWORD,
+            data: 2u32.to_le_bytes().to_vec(),
+        })
+    );
+    assert_eq!(app.value("").unwrap().data, utf16z("App"));
+    assert_eq!(app.value("empty").unwrap().data, Vec::<u8>::new());
+    assert_eq!(app.value("ÜNÏCØDÉ ✓").unwrap().data, utf16z("valor"));
+
+    // Las subclaves se guardan ordenadas sin distinguir mayúsculas.
+    let names: Vec<_> = read
+        .root
+        .key("Software")
+        .unwrap()
+        .subkeys
+        .iter()
+        .map(|key| key.name.as_str())
+        .collect();
+    assert_eq!(names, ["aaa", "Vendor", "Zeta"]);
+
+    // Escribir lo leído produce los mismos bytes.
+    assert_eq!(read.to_bytes(), bytes);
+}
+
+#[test]
+fn large_values_and_long_subkey_lists_round_trip() {
+    let mut hive = Hive::new();
+    let big: Vec<u8> = (0..BIG_DATA_SEGMENT * 2 + 100).map(|i| i as u8).collect();
+    let medium = vec![0xab; 9000];
+    let blobs = hive.root.create_key("Blobs");
+    blobs.set_value("Big", REG_BINARY, big.clone());
+    blobs.set_value("Medium", REG_BINARY, medium.clone());
+    let many = hive.root.create_key("Many");
+    for index in 0..1200 {
+        many.create_key(&format!("Key{index:04}"));
+    }
+
+    let bytes = hive.to_bytes();
+    let read = Hive::from_bytes(&bytes).unwrap();
+    let blobs = read.root.subkey("blobs").unwrap();
+    assert_eq!(blobs.value("Big").unwrap().data, big);
+    assert_eq!(blobs.value("Medium").unwrap().data, medium);
+    let many = read.root.subkey("Many").unwrap();
+    assert_eq!(many.subkeys.len(), 1200);
+    assert_eq!(many.subkeys[1199].name, "Key1199");
+    assert!(bytes.windows(4).any(|window| window == b"ri\x03\x00"));
+    assert!(bytes.windows(2).any(|window| window == b"db"));
+    assert_eq!(read, hive);
+}
+
+#[test]
+fn reads_lf_and_li_subkey_lists() {
+    let mut hive = Hive::new();
+    hive.root.create_key("Solo");
+    let bytes = hive.to_bytes();
+    let at = bytes
+        .windows(4)
+        .position(|window| window == b"lh\x01\x00")
+        .unwrap();
+
+    // `lf` solo cambia la firma; `li` guarda offsets sin hash, y con una única
+    // entrada la lista `lh` también es un `li` válido.
+    for signature in [b"lf", b"li"] {
+        let mut patched = bytes.clone();
+        patched[at..at + 2].copy_from_slice(signature);
+        let read = Hive::from_bytes(&patched).unwrap();
+        assert!(read.root.subkey("solo").is_some());
+    }
+}
+
+#[test]
+fn corrupt_hives_are_rejected() {
+    let bytes = fixture().to_bytes();
+
+    let mut bad_checksum = bytes.clone();
+    bad_checksum[0x30] ^= 0xff;
+    let err = Hive::from_bytes(&bad_checksum).unwrap_err();
+    assert!(err.to_string().contains("Checksum"), "{err}");
+
+    assert!(Hive::from_bytes(&bytes[..BLOCK_SIZE + 100]).is_err());
+    assert!(Hive::from_bytes(b"no es un hive").is_err());
+
+    let mut bad_bin = bytes.clone();
+    bad_bin[BLOCK_SIZE..BLOCK_SIZE + 4].copy_from_slice(b"nope");
+    assert!(Hive::from_bytes(&bad_bin).is_err());
+}
+
+/// Copia de `bytes` con `patch` aplicado al contenido de la `nk` raíz.
+fn patch_root(bytes: &[u8], patch: impl FnOnce(&mut [u8])) -> Vec<u8> {
+    let root = u32::from_le_bytes(bytes[0x24..0x28].try_into().unwrap()) as usize;
+    let mut patched = bytes.to_vec();
+    patch(&mut patched[BLOCK_SIZE + root + 4..]);
+    patched
+}
+
+#[test]
+fn malformed_counts_fail_without_huge_allocations() {
+    let bytes = fixture().to_bytes();
+
+    // Contadores de valores y subclaves que no caben en sus listas.
+    let values = patch_root(&bytes, |nk| {
+        nk[0x24..0x28].copy_from_slice(&u32::MAX.to_le_bytes());
+        nk.copy_within(0x1c..0x20, 0x28);
+    });
+    let err = Hive::from_bytes(&values).unwrap_err();
+    assert!(err.to_string().contains("valores"), "{err:#}");
+    let subkeys = patch_root(&bytes, |nk| {
+        nk[0x14..0x18].copy_from_slice(&u32::MAX.to_le_bytes())
+    });
+    let err = Hive::from_bytes(&subkeys).unwrap_err();
+    assert!(err.to_string().contains("subclaves"), "{err:#}");
+
+    // Un valor `db` que dice ocupar casi 2 GiB.
+    let mut hive = Hive::new();
+    let big = vec![0x5a; BIG_DATA_SEGMENT * 2];
+    hive.root.set_value("Big", REG_BINARY, big);
+    let mut bytes = hive.to_bytes();
+    let vk = bytes
+        .windows(0x17)
+        .position(|window| window.starts_with(b"vk\x03\x00") && window.ends_with(b"Big"))
+        .unwrap();
+    bytes[vk + 4..vk + 8].copy_from_slice(&0x7fff_fff0u32.to_le_bytes());
+    let err = Hive::from_bytes(&bytes).unwrap_err();
+    assert!(format!("{err:#}").contains("db"), "{err:#}");
+}
+
+#[test]
+fn hives_are_edited_through_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("HKCU.hiv");
+    fixture().write(&path).unwrap();
+
+    let mut hive = Hive::read(&path).unwrap();
+    let app = hive.root.create_key(r"Software\Vendor\App");
+    assert!(app.remove_value("path").is_some());
+    app.set_value("VERSION", REG_DWORD, 3u32.to_le_bytes().to_vec());
+    assert!(hive
+        .root
+        .subkey_mut("software")
+        .unwrap()
+        .remove_subkey("ZETA")
+        .is_some());
+    hive.write(&path).unwrap();
+
+    let hive = Hive::read(&path).unwrap();
+    let app: &Key = hive.root.key(r"Software\Vendor\App").unwrap();
+    assert!(app.value("Path").is_none());
+    assert_eq!(app.value("Version").unwrap().name, "VERSION");
+    assert_eq!(app.value("Version").unwrap().data, 3u32.to_le_bytes());
+    assert!(hive.root.key(r"Software\Zeta").is_none());
+}